@@ -1,10 +1,25 @@
-#version 410
+#version 450
 #extension GL_ARB_separate_shader_objects : enable
 
+#ifdef LIT
+#include "include/lighting.glsl"
+#endif
+
 layout (location = 0) in vec4 frag_color;
+#ifdef LIT
+layout (location = 1) in vec3 frag_position;
+layout (location = 2) in vec3 frag_normal;
+layout (location = 3) flat in float frag_receive_shadows;
+#endif
 
 layout (location = 0) out vec4 color;
 
 void main() {
+#ifdef LIT
+    vec3 normal = normalize(frag_normal);
+    bool receive_shadows = frag_receive_shadows > 0.5;
+    color = vec4(shade(frag_color.rgb, frag_position, normal, receive_shadows), frag_color.a);
+#else
     color = frag_color;
+#endif
 }
\ No newline at end of file
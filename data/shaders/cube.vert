@@ -1,12 +1,29 @@
-#version 410
+#version 450
 #extension GL_ARB_separate_shader_objects : enable
 
+#include "include/uniforms.glsl"
+#include "include/instance.glsl"
+
 layout (location = 0) in vec4 pos;
 layout (location = 1) in vec4 color;
+#ifdef LIT
+layout (location = 2) in vec4 normal;
+#endif
 
 layout (location = 0) out vec4 frag_color;
+#ifdef LIT
+layout (location = 1) out vec3 frag_position;
+layout (location = 2) out vec3 frag_normal;
+layout (location = 3) flat out float frag_receive_shadows;
+#endif
 
 void main() {
-    frag_color = color;
-    gl_Position = pos;
+    frag_color = color * tint;
+    vec4 world_pos = model * pos;
+#ifdef LIT
+    frag_position = world_pos.xyz;
+    frag_normal = mat3(model) * normal.xyz;
+    frag_receive_shadows = receive_shadows;
+#endif
+    gl_Position = camera.projection * camera.view * world_pos;
 }
\ No newline at end of file
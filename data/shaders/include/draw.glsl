@@ -0,0 +1,10 @@
+#pragma once
+
+#define MAX_PARAMETERS 16
+
+// Written per draw, bound with a dynamic offset. The material's parameters
+// are in name order, the shaders are compiled with PARAMETER_<NAME> defined
+// as each one's index.
+layout (set = 1, binding = 0) uniform Draw {
+    vec4 parameters[MAX_PARAMETERS];
+} draw;
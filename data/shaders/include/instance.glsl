@@ -0,0 +1,9 @@
+#pragma once
+
+// Per instance, from the instance vertex buffer. Locations come after the
+// ones vertex attributes use.
+layout (location = 8) in mat4 model;
+// Multiplies the vertex colors.
+layout (location = 12) in vec4 tint;
+// 1 if shadows fall on the instance, 0 if not.
+layout (location = 13) in float receive_shadows;
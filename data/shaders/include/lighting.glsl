@@ -0,0 +1,91 @@
+#pragma once
+
+#include "uniforms.glsl"
+
+#define SHININESS 32.0
+#define SPECULAR 0.25
+
+// How much of a light reaches a surface point past its shadow casters,
+// from 0 to 1. Directional lights use the first cascade reaching as far as
+// the point, points outside every map are lit.
+float visibility(Light light, vec3 position, vec3 normal) {
+    int first = int(light.shadow.x);
+    int count = int(light.shadow.y);
+    float depth = -(camera.view * vec4(position, 1.0)).z;
+    int index = -1;
+    for (int i = first; i < first + count; i++) {
+        if (depth <= lighting.shadow_maps[i].split.x) {
+            index = i;
+            break;
+        }
+    }
+    if (index < 0) {
+        return 1.0;
+    }
+
+    vec3 offset = position + normal * SHADOW_NORMAL_OFFSET;
+    vec4 pos = lighting.shadow_maps[index].view_projection * vec4(offset, 1.0);
+    vec2 uv = pos.xy / pos.w * 0.5 + 0.5;
+    float z = pos.z / pos.w;
+    bool outside = any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)));
+    if (pos.w <= 0.0 || outside || z > 1.0) {
+        return 1.0;
+    }
+
+    // Percentage closer filtering over the nearest 3x3 texels.
+    float lit = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            vec2 sample_uv = uv + vec2(x, y) / SHADOW_MAP_SIZE;
+            lit += texture(shadow_map_array, vec4(sample_uv, index, z - SHADOW_BIAS));
+        }
+    }
+    return lit / 9.0;
+}
+
+// Blinn-Phong lighting of a surface point by every light of the frame,
+// frames without lights leave the color as it is. Points not receiving
+// shadows are lit as if nothing was in the way.
+vec3 shade(vec3 albedo, vec3 position, vec3 normal, bool receive_shadows) {
+    if (lighting.light_count == 0) {
+        return albedo;
+    }
+
+    vec3 view = normalize(lighting.camera_position.xyz - position);
+    vec3 result = vec3(0.0);
+    for (uint i = 0; i < lighting.light_count; i++) {
+        Light light = lighting.lights[i];
+        int kind = int(light.position.w);
+        if (kind == LIGHT_AMBIENT) {
+            result += albedo * light.color.rgb;
+            continue;
+        }
+
+        vec3 to_light = -light.direction.xyz;
+        float attenuation = 1.0;
+        if (kind != LIGHT_DIRECTIONAL) {
+            vec3 offset = light.position.xyz - position;
+            float distance = length(offset);
+            float range = light.direction.w;
+            float falloff = clamp(1.0 - distance * distance / (range * range), 0.0, 1.0);
+            to_light = offset / distance;
+            attenuation = falloff * falloff;
+            if (kind == LIGHT_SPOT) {
+                float cos_angle = dot(-to_light, light.direction.xyz);
+                attenuation *= smoothstep(light.spot.y, light.spot.x, cos_angle);
+            }
+        }
+
+        if (receive_shadows && light.shadow.y > 0.0) {
+            attenuation *= visibility(light, position, normal);
+        }
+
+        float diffuse = max(dot(normal, to_light), 0.0);
+        float specular = 0.0;
+        if (diffuse > 0.0) {
+            specular = SPECULAR * pow(max(dot(normal, normalize(to_light + view)), 0.0), SHININESS);
+        }
+        result += (albedo * diffuse + specular) * light.color.rgb * attenuation;
+    }
+    return result;
+}
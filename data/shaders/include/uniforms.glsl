@@ -0,0 +1,51 @@
+#pragma once
+
+#define MAX_LIGHTS 16
+
+#define LIGHT_AMBIENT 0
+#define LIGHT_DIRECTIONAL 1
+#define LIGHT_POINT 2
+#define LIGHT_SPOT 3
+
+#define MAX_SHADOW_MAPS 8
+#define SHADOW_MAP_SIZE 1024.0
+#define SHADOW_BIAS 0.0005
+#define SHADOW_NORMAL_OFFSET 0.02
+
+// Written once per frame.
+layout (set = 0, binding = 0) uniform Camera {
+    mat4 view;
+    mat4 projection;
+} camera;
+
+struct Light {
+    // w is one of the LIGHT_* kinds.
+    vec4 position;
+    // w is the range of point and spot lights.
+    vec4 direction;
+    // Color times intensity.
+    vec4 color;
+    // Cosines of a spot light's inner and outer angle.
+    vec4 spot;
+    // First shadow map and number of maps, no maps for lights without
+    // shadows.
+    vec4 shadow;
+};
+
+struct ShadowMap {
+    // From world space into the map's clip space.
+    mat4 view_projection;
+    // x is the camera depth up to which a cascade is used.
+    vec4 split;
+};
+
+// Written once per frame, in world space.
+layout (set = 0, binding = 1) uniform Lighting {
+    vec4 camera_position;
+    uint light_count;
+    Light lights[MAX_LIGHTS];
+    ShadowMap shadow_maps[MAX_SHADOW_MAPS];
+} lighting;
+
+// A layer per shadow map, compared against the depth passed in.
+layout (set = 0, binding = 2) uniform sampler2DArrayShadow shadow_map_array;
@@ -0,0 +1,6 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Shadow maps only keep depth, pipelines drawing them skip this stage.
+void main() {
+}
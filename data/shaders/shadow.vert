@@ -0,0 +1,14 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "include/instance.glsl"
+
+layout (location = 0) in vec4 pos;
+
+layout (push_constant) uniform ShadowPass {
+    mat4 view_projection;
+} shadow_pass;
+
+void main() {
+    gl_Position = shadow_pass.view_projection * model * pos;
+}
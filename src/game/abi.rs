@@ -0,0 +1,302 @@
+use std::mem;
+use std::fmt;
+use std::error::Error;
+use std::hash::Hasher;
+
+use super::state::State;
+use super::component;
+use super::asset;
+use super::super::renderer::{Attribute, Camera, Indices, Instance, Light, Material,
+                             MaterialTexture, MipLevel, Parameter, RenderSettings, RenderState,
+                             Renderer, Vertex, VertexData, VertexLayout};
+
+/// Bump whenever the exported function signatures change in a way the
+/// layout fingerprints can't see.
+pub const ABI_VERSION: u32 = 1;
+
+/// Lists the `Renderer` methods as the strings hashed into the fingerprint,
+/// along with a function that stops compiling once a listed signature and
+/// the trait's drift apart.
+macro_rules! renderer_methods {
+    ($(fn $name: ident($($arg: ty),*) -> $ret: ty;)*) => {
+        const RENDERER_METHODS: &[&str] = &[
+            $(stringify!(fn $name(&mut self, $($arg),*) -> $ret)),*
+        ];
+
+        #[allow(dead_code)]
+        fn check_renderer_methods<R: Renderer + ?Sized>() {
+            $(let _: fn(&mut R, $($arg),*) -> $ret = R::$name;)*
+        }
+    }
+}
+
+// The game calls the host's renderer through a vtable, so its methods'
+// order and signatures are part of the ABI. Keep this in the same order as
+// `renderer::Renderer`.
+renderer_methods! {
+    fn begin_frame() -> Result<(), Box<Error>>;
+    fn end_frame() -> Result<(), Box<Error>>;
+    fn set_camera(&Camera) -> Result<(), Box<Error>>;
+    fn set_lights(&[Light]) -> Result<(), Box<Error>>;
+    fn update_model(u32, &VertexData, Indices) -> Result<(), Box<Error>>;
+    fn update_material(u32, &Material) -> Result<(), Box<Error>>;
+    fn draw_model(u32, u32, &Instance) -> Result<(), Box<Error>>;
+    fn update_resolution(u32, u32) -> Result<(), Box<Error>>;
+    fn change_settings(&RenderSettings) -> Result<(), Box<Error>>;
+}
+
+/// Returned by the game library so the host can tell whether the types it
+/// passes across the dylib boundary still agree with the library's.
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct AbiInfo {
+    pub version: u32,
+    pub state_layout: u64,
+    pub renderer_layout: u64,
+}
+
+impl AbiInfo {
+    pub fn current() -> AbiInfo {
+        AbiInfo {
+            version: ABI_VERSION,
+            state_layout: state_layout(),
+            renderer_layout: renderer_layout(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AbiMismatch {
+    pub host: AbiInfo,
+    pub library: AbiInfo,
+}
+
+impl fmt::Display for AbiMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Game library ABI doesn't match the host (version {} vs {}, state {:016x} vs {:016x}, renderer {:016x} vs {:016x})",
+            self.library.version,
+            self.host.version,
+            self.library.state_layout,
+            self.host.state_layout,
+            self.library.renderer_layout,
+            self.host.renderer_layout
+        )
+    }
+}
+
+impl Error for AbiMismatch {
+    fn description(&self) -> &str {
+        "game library ABI mismatch"
+    }
+}
+
+/// Feeds a type's size and alignment plus the name and offset of each listed
+/// field into the hasher.
+macro_rules! hash_layout {
+    ($hasher: expr, $ty: ty { $($field: ident),* }) => {
+        {
+            $hasher.write(stringify!($ty).as_bytes());
+            $hasher.write_usize(mem::size_of::<$ty>());
+            $hasher.write_usize(mem::align_of::<$ty>());
+            $(
+                $hasher.write(stringify!($field).as_bytes());
+                $hasher.write_usize(mem::offset_of!($ty, $field));
+            )*
+        }
+    }
+}
+
+fn state_layout() -> u64 {
+    let mut hasher = FnvHasher::new();
+
+    hash_layout!(hasher, State {
+        delta_time,
+        physics_components,
+        graphics_components,
+        camera_components,
+        light_components,
+        sound_components,
+        ai_components,
+        entities,
+        next_entity_id,
+        model_ids,
+        material_ids
+    });
+    hash_layout!(hasher, component::Physics { pos, momentum, inv_mass });
+    hash_layout!(hasher, component::Graphics { mesh, material, cast_shadows, receive_shadows });
+    hash_layout!(hasher, component::Camera { projection, near, far, target, up });
+    hash_layout!(hasher, component::Light {
+        kind,
+        direction,
+        color,
+        intensity,
+        range,
+        cast_shadows
+    });
+    hash_layout!(hasher, component::Sound { pos });
+    hash_layout!(hasher, component::AI { pos });
+    hash_layout!(hasher, component::Entity { pos });
+    hash_layout!(hasher, asset::Mesh {
+        vertices,
+        indices,
+        path,
+        loading_state
+    });
+    hash_layout!(hasher, asset::Material {
+        vertex_shader,
+        fragment_shader,
+        textures,
+        parameters,
+        render_state,
+        path,
+        loading_state
+    });
+    hash_layout!(hasher, asset::Texture {
+        mip_levels,
+        color_space,
+        path,
+        loading_state
+    });
+
+    hasher.finish()
+}
+
+fn renderer_layout() -> u64 {
+    let mut hasher = FnvHasher::new();
+
+    hash_layout!(hasher, &mut Renderer {});
+    hash_layout!(hasher, Vertex { pos, color });
+    hash_layout!(hasher, Attribute { semantic, format, offset });
+    // Their fields are private, so they report their own offsets.
+    hash_layout!(hasher, VertexData {});
+    hash_fields(&mut hasher, &VertexData::field_offsets());
+    hash_layout!(hasher, VertexLayout {});
+    hash_fields(&mut hasher, &VertexLayout::field_offsets());
+    hash_layout!(hasher, Indices {});
+    hash_indices(&mut hasher);
+    hash_layout!(hasher, Camera { view, projection, near, far });
+    hash_layout!(hasher, Instance { model, tint, cast_shadows, receive_shadows });
+    hash_layout!(hasher, Light {
+        kind,
+        position,
+        direction,
+        color,
+        intensity,
+        range,
+        cast_shadows
+    });
+    hash_layout!(hasher, RenderState { blend, depth, cull, wireframe });
+    hash_layout!(hasher, Material {
+        vertex_shader,
+        fragment_shader,
+        textures,
+        parameters,
+        render_state
+    });
+    hash_layout!(hasher, MaterialTexture { name, color_space, mip_levels });
+    hash_layout!(hasher, MipLevel { width, height, pixels });
+    hash_layout!(hasher, Parameter {});
+    hash_layout!(hasher, RenderSettings {
+        samples,
+        present_mode,
+        resolution_scale,
+        depth_format
+    });
+    for method in RENDERER_METHODS {
+        hasher.write(method.as_bytes());
+    }
+
+    hasher.finish()
+}
+
+fn hash_fields(hasher: &mut FnvHasher, fields: &[(&str, usize)]) {
+    for &(name, offset) in fields {
+        hasher.write(name.as_bytes());
+        hasher.write_usize(offset);
+    }
+}
+
+/// `offset_of!` can't reach into enum variants, so the payload offsets are
+/// measured on sample values instead.
+fn hash_indices(hasher: &mut FnvHasher) {
+    let samples = [("U16", Indices::U16(&[])), ("U32", Indices::U32(&[]))];
+    for &(name, ref indices) in &samples {
+        let payload = match *indices {
+            Indices::None => continue,
+            Indices::U16(ref payload) => payload as *const _ as *const u8,
+            Indices::U32(ref payload) => payload as *const _ as *const u8,
+        };
+        hasher.write(name.as_bytes());
+        hasher.write_usize(payload as usize - indices as *const _ as usize);
+    }
+}
+
+/// FNV-1a, used instead of `DefaultHasher` whose output isn't guaranteed to
+/// stay the same between builds.
+struct FnvHasher(u64);
+
+impl FnvHasher {
+    fn new() -> FnvHasher {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fingerprint of a struct with the given fields, which all get the
+    /// same type name so only the fields tell them apart.
+    macro_rules! fingerprint {
+        ($($field: ident: $ty: ty),*) => {
+            {
+                #[allow(dead_code)]
+                #[repr(C)]
+                struct Layout {
+                    $($field: $ty),*
+                }
+                let mut hasher = FnvHasher::new();
+                hash_layout!(hasher, Layout { $($field),* });
+                hasher.finish()
+            }
+        }
+    }
+
+    #[test]
+    fn fingerprints_are_stable() {
+        assert_eq!(AbiInfo::current(), AbiInfo::current());
+        assert_eq!(fingerprint!(a: u32, b: u64), fingerprint!(a: u32, b: u64));
+    }
+
+    #[test]
+    fn changing_a_field_changes_the_fingerprint() {
+        let original = fingerprint!(a: u32, b: u32);
+
+        // A different type, which moves nothing but the size.
+        assert_ne!(original, fingerprint!(a: u32, b: u64));
+        // The same size and offsets, under another name.
+        assert_ne!(original, fingerprint!(a: u32, c: u32));
+        // Reordered.
+        assert_ne!(original, fingerprint!(b: u32, a: u32));
+        // Added and removed.
+        assert_ne!(original, fingerprint!(a: u32, b: u32, c: u8));
+        assert_ne!(original, fingerprint!(a: u32));
+        // A different alignment with the same size.
+        assert_ne!(fingerprint!(a: [u8; 8]), fingerprint!(a: u64));
+    }
+}
@@ -0,0 +1,419 @@
+use std::error::Error;
+
+use super::texture::MipLevel;
+
+const HEADER_SIZE: usize = 128;
+
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+
+#[derive(Clone, Copy)]
+enum BlockFormat {
+    Dxt1,
+    Dxt3,
+    Dxt5,
+}
+
+/// Decodes the top level of a DDS file into RGBA8. Stored mips are ignored
+/// since the texture rebuilds its own chain.
+pub fn decode(bytes: &[u8]) -> Result<MipLevel, Box<Error>> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != b"DDS " || read_u32(bytes, 4) != 124 {
+        return Err("Invalid DDS header".into());
+    }
+
+    let height = read_u32(bytes, 12);
+    let width = read_u32(bytes, 16);
+    let pf_flags = read_u32(bytes, 80);
+    let four_cc = &bytes[84..88];
+    let data = &bytes[HEADER_SIZE..];
+
+    if width == 0 || height == 0 {
+        return Err("DDS texture has zero size".into());
+    }
+
+    let pixels = if pf_flags & DDPF_FOURCC != 0 {
+        let format = match four_cc {
+            b"DXT1" => BlockFormat::Dxt1,
+            b"DXT3" => BlockFormat::Dxt3,
+            b"DXT5" => BlockFormat::Dxt5,
+            _ => {
+                return Err(format!(
+                    "Unsupported DDS compression: {}",
+                    String::from_utf8_lossy(four_cc)
+                ).into())
+            }
+        };
+        decode_blocks(data, width, height, format)?
+    } else if pf_flags & DDPF_RGB != 0 {
+        let alpha_mask = if pf_flags & DDPF_ALPHAPIXELS != 0 {
+            read_u32(bytes, 104)
+        } else {
+            0
+        };
+        let masks = [
+            read_u32(bytes, 92),
+            read_u32(bytes, 96),
+            read_u32(bytes, 100),
+            alpha_mask,
+        ];
+        decode_uncompressed(data, width, height, read_u32(bytes, 88), masks)?
+    } else {
+        return Err("Unsupported DDS pixel format".into());
+    };
+
+    Ok(MipLevel {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn decode_uncompressed(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    bit_count: u32,
+    masks: [u32; 4],
+) -> Result<Vec<u8>, Box<Error>> {
+    let bytes_per_pixel = match bit_count {
+        16 | 24 | 32 => (bit_count / 8) as usize,
+        _ => return Err(format!("Unsupported DDS bit count: {}", bit_count).into()),
+    };
+    let pixel_count = byte_size(&[width as usize, height as usize])?;
+    let data_size = byte_size(&[pixel_count, bytes_per_pixel])?;
+    if data.len() < data_size {
+        return Err("DDS pixel data is truncated".into());
+    }
+
+    let mut pixels = Vec::with_capacity(byte_size(&[pixel_count, 4])?);
+    for pixel in data[..data_size].chunks(bytes_per_pixel) {
+        let value = pixel
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &byte)| acc | (byte as u32) << (i * 8));
+
+        for (channel, &mask) in masks.iter().enumerate() {
+            pixels.push(if mask == 0 {
+                if channel == 3 { 255 } else { 0 }
+            } else {
+                extract_channel(value, mask)
+            });
+        }
+    }
+
+    Ok(pixels)
+}
+
+fn extract_channel(value: u32, mask: u32) -> u8 {
+    let shift = mask.trailing_zeros();
+    // Widened since a mask can cover the whole 32 bits.
+    let max = (mask >> shift) as u64;
+    (((value & mask) >> shift) as u64 * 255 / max) as u8
+}
+
+fn decode_blocks(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: BlockFormat,
+) -> Result<Vec<u8>, Box<Error>> {
+    let block_size = match format {
+        BlockFormat::Dxt1 => 8,
+        BlockFormat::Dxt3 | BlockFormat::Dxt5 => 16,
+    };
+    let blocks_x = (width as usize - 1) / 4 + 1;
+    let blocks_y = (height as usize - 1) / 4 + 1;
+    if data.len() < byte_size(&[blocks_x, blocks_y, block_size])? {
+        return Err("DDS block data is truncated".into());
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = vec![0u8; byte_size(&[width, height, 4])?];
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let offset = (by * blocks_x + bx) * block_size;
+            let block = &data[offset..offset + block_size];
+
+            let texels = match format {
+                BlockFormat::Dxt1 => decode_color_block(block, true),
+                BlockFormat::Dxt3 => {
+                    let mut texels = decode_color_block(&block[8..], false);
+                    for i in 0..16 {
+                        let nibble = (block[i / 2] >> ((i % 2) * 4)) & 0xf;
+                        texels[i][3] = nibble * 17;
+                    }
+                    texels
+                }
+                BlockFormat::Dxt5 => {
+                    let mut texels = decode_color_block(&block[8..], false);
+                    let alphas = decode_alpha_block(&block[..8]);
+                    for i in 0..16 {
+                        texels[i][3] = alphas[i];
+                    }
+                    texels
+                }
+            };
+
+            for (i, texel) in texels.iter().enumerate() {
+                let x = bx * 4 + i % 4;
+                let y = by * 4 + i / 4;
+                if x < width && y < height {
+                    let index = (y * width + x) * 4;
+                    pixels[index..index + 4].copy_from_slice(texel);
+                }
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+fn decode_color_block(block: &[u8], allow_transparent: bool) -> [[u8; 4]; 16] {
+    let c0 = read_u16(block, 0);
+    let c1 = read_u16(block, 2);
+    let (r0, g0, b0) = unpack_565(c0);
+    let (r1, g1, b1) = unpack_565(c1);
+
+    let mut palette = [[r0, g0, b0, 255], [r1, g1, b1, 255], [0; 4], [0; 4]];
+    if c0 > c1 || !allow_transparent {
+        palette[2] = [
+            lerp(r0, r1, 1, 3),
+            lerp(g0, g1, 1, 3),
+            lerp(b0, b1, 1, 3),
+            255,
+        ];
+        palette[3] = [
+            lerp(r0, r1, 2, 3),
+            lerp(g0, g1, 2, 3),
+            lerp(b0, b1, 2, 3),
+            255,
+        ];
+    } else {
+        palette[2] = [
+            lerp(r0, r1, 1, 2),
+            lerp(g0, g1, 1, 2),
+            lerp(b0, b1, 1, 2),
+            255,
+        ];
+    }
+
+    let indices = read_u32(block, 4);
+    let mut texels = [[0u8; 4]; 16];
+    for i in 0..16 {
+        texels[i] = palette[((indices >> (i * 2)) & 0x3) as usize];
+    }
+    texels
+}
+
+fn decode_alpha_block(block: &[u8]) -> [u8; 16] {
+    let a0 = block[0];
+    let a1 = block[1];
+
+    let mut palette = [a0, a1, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = lerp(a0, a1, i as u32, 7);
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = lerp(a0, a1, i as u32, 5);
+        }
+    }
+
+    let bits = block[2..8]
+        .iter()
+        .enumerate()
+        .fold(0u64, |acc, (i, &byte)| acc | (byte as u64) << (i * 8));
+
+    let mut alphas = [0u8; 16];
+    for i in 0..16 {
+        alphas[i] = palette[((bits >> (i * 3)) & 0x7) as usize];
+    }
+    alphas
+}
+
+fn unpack_565(color: u16) -> (u8, u8, u8) {
+    let r = ((color >> 11) & 0x1f) as u32;
+    let g = ((color >> 5) & 0x3f) as u32;
+    let b = (color & 0x1f) as u32;
+    ((r * 255 / 31) as u8, (g * 255 / 63) as u8, (b * 255 / 31) as u8)
+}
+
+fn lerp(a: u8, b: u8, step: u32, steps: u32) -> u8 {
+    ((a as u32 * (steps - step) + b as u32 * step) / steps) as u8
+}
+
+/// Multiplies sizes taken from the header, which can't be trusted to fit.
+fn byte_size(factors: &[usize]) -> Result<usize, Box<Error>> {
+    factors
+        .iter()
+        .try_fold(1usize, |acc, &factor| acc.checked_mul(factor))
+        .ok_or_else(|| "DDS texture is too large".into())
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    (0..4).fold(0, |acc, i| acc | (bytes[offset + i] as u32) << (i * 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: u32, height: u32, pf_flags: u32, four_cc: &[u8; 4]) -> Vec<u8> {
+        let mut bytes = vec![0u8; HEADER_SIZE];
+        bytes[0..4].copy_from_slice(b"DDS ");
+        write_u32(&mut bytes, 4, 124);
+        write_u32(&mut bytes, 12, height);
+        write_u32(&mut bytes, 16, width);
+        write_u32(&mut bytes, 80, pf_flags);
+        bytes[84..88].copy_from_slice(four_cc);
+        bytes
+    }
+
+    fn rgb_header(width: u32, height: u32, bit_count: u32, masks: [u32; 4]) -> Vec<u8> {
+        let alpha = if masks[3] != 0 { DDPF_ALPHAPIXELS } else { 0 };
+        let mut bytes = header(width, height, DDPF_RGB | alpha, b"\0\0\0\0");
+        write_u32(&mut bytes, 88, bit_count);
+        for (i, &mask) in masks.iter().enumerate() {
+            write_u32(&mut bytes, 92 + i * 4, mask);
+        }
+        bytes
+    }
+
+    fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        for i in 0..4 {
+            bytes[offset + i] = (value >> (i * 8)) as u8;
+        }
+    }
+
+    fn texel(level: &MipLevel, x: u32, y: u32) -> [u8; 4] {
+        let index = ((y * level.width + x) * 4) as usize;
+        let mut texel = [0; 4];
+        texel.copy_from_slice(&level.pixels[index..index + 4]);
+        texel
+    }
+
+    /// Red and blue endpoints, with the first row using every palette entry.
+    const COLOR_BLOCK: [u8; 8] = [0x00, 0xf8, 0x1f, 0x00, 0xe4, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn decodes_dxt1() {
+        let mut bytes = header(4, 4, DDPF_FOURCC, b"DXT1");
+        bytes.extend_from_slice(&COLOR_BLOCK);
+        let level = decode(&bytes).unwrap();
+
+        assert_eq!((level.width, level.height), (4, 4));
+        assert_eq!(texel(&level, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(texel(&level, 1, 0), [0, 0, 255, 255]);
+        assert_eq!(texel(&level, 2, 0), [170, 0, 85, 255]);
+        assert_eq!(texel(&level, 3, 0), [85, 0, 170, 255]);
+        assert_eq!(texel(&level, 0, 1), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn decodes_dxt1_transparent_colors() {
+        // c0 <= c1 switches to three colors and transparent black.
+        let texels = decode_color_block(&[0x1f, 0x00, 0x00, 0xf8, 0xe4, 0, 0, 0], true);
+        assert_eq!(texels[0], [0, 0, 255, 255]);
+        assert_eq!(texels[1], [255, 0, 0, 255]);
+        assert_eq!(texels[2], [127, 0, 127, 255]);
+        assert_eq!(texels[3], [0, 0, 0, 0]);
+
+        // DXT3 and DXT5 color blocks always use four colors.
+        let texels = decode_color_block(&[0x1f, 0x00, 0x00, 0xf8, 0xe4, 0, 0, 0], false);
+        assert_eq!(texels[3], [170, 0, 85, 255]);
+    }
+
+    #[test]
+    fn decodes_dxt3_explicit_alpha() {
+        let mut bytes = header(4, 4, DDPF_FOURCC, b"DXT3");
+        bytes.extend_from_slice(&[0xf0, 0x5a, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&COLOR_BLOCK);
+        let level = decode(&bytes).unwrap();
+
+        assert_eq!(texel(&level, 0, 0), [255, 0, 0, 0]);
+        assert_eq!(texel(&level, 1, 0), [0, 0, 255, 255]);
+        assert_eq!(texel(&level, 2, 0), [170, 0, 85, 170]);
+        assert_eq!(texel(&level, 3, 0), [85, 0, 170, 85]);
+    }
+
+    #[test]
+    fn decodes_dxt5_interpolated_alpha() {
+        // Indices 0, 1, 2, 7 for the first four texels.
+        let mut bytes = header(4, 4, DDPF_FOURCC, b"DXT5");
+        bytes.extend_from_slice(&[255, 0, 0x88, 0x0e, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&COLOR_BLOCK);
+        let level = decode(&bytes).unwrap();
+
+        assert_eq!(texel(&level, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(texel(&level, 1, 0)[3], 0);
+        assert_eq!(texel(&level, 2, 0)[3], 218);
+        assert_eq!(texel(&level, 3, 0)[3], 36);
+
+        // a0 <= a1 uses six steps plus fully transparent and opaque.
+        let alphas = decode_alpha_block(&[0, 255, 0x88, 0x0e, 0, 0, 0, 0]);
+        assert_eq!(&alphas[0..4], &[0, 255, 51, 255]);
+        let alphas = decode_alpha_block(&[10, 20, 0x3e, 0, 0, 0, 0, 0]);
+        assert_eq!(&alphas[0..3], &[0, 255, 10]);
+    }
+
+    #[test]
+    fn crops_partial_blocks() {
+        let mut bytes = header(2, 3, DDPF_FOURCC, b"DXT1");
+        bytes.extend_from_slice(&COLOR_BLOCK);
+        let level = decode(&bytes).unwrap();
+
+        assert_eq!((level.width, level.height), (2, 3));
+        assert_eq!(level.pixels.len(), 2 * 3 * 4);
+        assert_eq!(texel(&level, 1, 0), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn decodes_masked_rgb() {
+        let mut bytes = rgb_header(2, 1, 16, [0xf800, 0x07e0, 0x001f, 0]);
+        bytes.extend_from_slice(&[0xff, 0xff, 0x1f, 0x00]);
+        let level = decode(&bytes).unwrap();
+        assert_eq!(texel(&level, 0, 0), [255, 255, 255, 255]);
+        assert_eq!(texel(&level, 1, 0), [0, 0, 255, 255]);
+
+        let masks = [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000];
+        let mut bytes = rgb_header(1, 1, 32, masks);
+        bytes.extend_from_slice(&[0x30, 0x20, 0x10, 0x80]);
+        let level = decode(&bytes).unwrap();
+        assert_eq!(texel(&level, 0, 0), [0x10, 0x20, 0x30, 0x80]);
+
+        let mut bytes = rgb_header(1, 1, 24, [0x0000ff, 0x00ff00, 0xff0000, 0]);
+        bytes.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(texel(&decode(&bytes).unwrap(), 0, 0), [1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn extracts_full_width_masks() {
+        assert_eq!(extract_channel(0xffff_ffff, 0xffff_ffff), 255);
+        assert_eq!(extract_channel(0x8000_0000, 0xffff_ffff), 127);
+        assert_eq!(extract_channel(0x0000_0000, 0xffff_ffff), 0);
+    }
+
+    #[test]
+    fn rejects_oversized_and_truncated_textures() {
+        let bytes = header(0xffff_ffff, 0xffff_ffff, DDPF_FOURCC, b"DXT5");
+        assert!(decode(&bytes).is_err());
+
+        let mut bytes = rgb_header(0xffff_ffff, 0xffff_ffff, 32, [0xff, 0xff00, 0xff0000, 0]);
+        bytes.extend_from_slice(&[0; 16]);
+        assert!(decode(&bytes).is_err());
+
+        let mut bytes = header(8, 4, DDPF_FOURCC, b"DXT1");
+        bytes.extend_from_slice(&COLOR_BLOCK);
+        assert!(decode(&bytes).is_err());
+
+        assert!(decode(&header(0, 4, DDPF_FOURCC, b"DXT1")).is_err());
+        assert!(decode(&header(4, 4, DDPF_FOURCC, b"ATI2")).is_err());
+        assert!(decode(b"DDS ").is_err());
+    }
+}
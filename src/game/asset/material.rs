@@ -0,0 +1,249 @@
+use ron;
+
+use std::path::{Path, PathBuf};
+use std::error::Error;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::prelude::*;
+
+use renderer::{self, MaterialTexture, RenderState};
+
+use super::{intern_path, LoadingState};
+use super::texture::{ColorSpace, Texture};
+
+pub use renderer::Parameter;
+
+#[derive(Debug, Clone)]
+pub struct TextureSlot {
+    pub name: String,
+    pub texture: Texture,
+}
+
+/// Only the path survives serialization; everything else is reloaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Material {
+    #[serde(skip)]
+    pub vertex_shader: PathBuf,
+    #[serde(skip)]
+    pub fragment_shader: PathBuf,
+    #[serde(skip)]
+    pub textures: Vec<TextureSlot>,
+    /// Kept sorted by name so the uniform block layout is stable between loads.
+    #[serde(skip)]
+    pub parameters: BTreeMap<String, Parameter>,
+    #[serde(skip)]
+    pub render_state: RenderState,
+    #[serde(deserialize_with = "super::deserialize_path")]
+    pub path: &'static Path,
+    #[serde(skip)]
+    pub loading_state: LoadingState,
+}
+
+/// On-disk RON representation of a material.
+#[derive(Debug, Deserialize)]
+struct MaterialFile {
+    vertex_shader: String,
+    fragment_shader: String,
+    #[serde(default)]
+    textures: Vec<TextureFile>,
+    #[serde(default)]
+    parameters: BTreeMap<String, Parameter>,
+    #[serde(default)]
+    render_state: RenderState,
+}
+
+#[derive(Debug, Deserialize)]
+struct TextureFile {
+    name: String,
+    path: String,
+    #[serde(default = "default_color_space")]
+    color_space: ColorSpace,
+}
+
+fn default_color_space() -> ColorSpace {
+    ColorSpace::Srgb
+}
+
+impl Material {
+    pub fn new(path: &'static Path) -> Material {
+        Material {
+            vertex_shader: PathBuf::new(),
+            fragment_shader: PathBuf::new(),
+            textures: vec![],
+            parameters: BTreeMap::new(),
+            render_state: RenderState::default(),
+            path,
+            loading_state: LoadingState::Unloaded,
+        }
+    }
+
+    pub fn load(&mut self) -> Result<(), Box<Error>> {
+        let source = {
+            let mut file = File::open(self.path)?;
+            let mut source = String::new();
+            file.read_to_string(&mut source)?;
+            source
+        };
+
+        let material: MaterialFile = ron::de::from_str(&source)
+            .map_err(|err| format!("{}: {}", self.path.display(), err))?;
+
+        let mut textures = vec![];
+        for slot in material.textures {
+            let mut texture = Texture::new(intern_path(PathBuf::from(slot.path)), slot.color_space);
+            texture.load()?;
+            textures.push(TextureSlot {
+                name: slot.name,
+                texture,
+            });
+        }
+
+        self.vertex_shader = PathBuf::from(material.vertex_shader);
+        self.fragment_shader = PathBuf::from(material.fragment_shader);
+        self.textures = textures;
+        self.parameters = material.parameters;
+        self.render_state = material.render_state;
+        self.loading_state = LoadingState::Loaded;
+
+        Ok(())
+    }
+
+    /// Drops everything read from disk, keeping only the path to reload from.
+    pub fn unload(&mut self) {
+        *self = Material::new(self.path);
+    }
+
+    pub fn texture(&self, name: &str) -> Option<&Texture> {
+        self.textures
+            .iter()
+            .find(|slot| slot.name == name)
+            .map(|slot| &slot.texture)
+    }
+
+    /// The material as renderers take it through `update_material`.
+    pub fn to_renderer(&self) -> renderer::Material<'_> {
+        renderer::Material {
+            vertex_shader: &self.vertex_shader,
+            fragment_shader: &self.fragment_shader,
+            textures: self.textures
+                .iter()
+                .map(|slot| MaterialTexture {
+                    name: &slot.name,
+                    color_space: slot.texture.color_space,
+                    mip_levels: &slot.texture.mip_levels,
+                })
+                .collect(),
+            parameters: self.parameters
+                .iter()
+                .map(|(name, &parameter)| (name.as_str(), parameter))
+                .collect(),
+            render_state: self.render_state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::ptr;
+    use std::sync::Arc;
+
+    /// Files in the temp directory named after the process and test, so
+    /// concurrent runs don't share them. Removed when dropped.
+    struct TempFiles {
+        test: &'static str,
+        paths: Vec<PathBuf>,
+    }
+
+    impl TempFiles {
+        fn new(test: &'static str) -> TempFiles {
+            TempFiles {
+                test,
+                paths: vec![],
+            }
+        }
+
+        fn path(&mut self, name: &str) -> PathBuf {
+            let file_name = format!("{}_{}_{}", process::id(), self.test, name);
+            let path = env::temp_dir().join(format!("xtreme_game_material_{}", file_name));
+            self.paths.push(path.clone());
+            path
+        }
+    }
+
+    impl Drop for TempFiles {
+        fn drop(&mut self) {
+            for path in &self.paths {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Writes a material using a 1x1 uncompressed DDS texture to `path`.
+    fn write_material(path: &Path, texture: &Path) {
+        let mut dds = vec![0u8; 128];
+        dds[0..4].copy_from_slice(b"DDS ");
+        let fields = [(4, 124), (12, 1), (16, 1), (80, 0x40), (88, 32), (92, 0xff)];
+        for &(offset, value) in &fields {
+            dds[offset..offset + 4].copy_from_slice(&[value as u8, (value >> 8) as u8, 0, 0]);
+        }
+        dds.extend_from_slice(&[0x80, 0, 0, 0]);
+        fs::write(texture, dds).unwrap();
+
+        let source = format!(
+            "(vertex_shader: \"a.vert\", fragment_shader: \"a.frag\", \
+             textures: [(name: \"albedo\", path: {:?})], \
+             parameters: {{ \"tint\": Float(0.5) }})",
+            texture
+        );
+        fs::write(path, source).unwrap();
+    }
+
+    #[test]
+    fn shares_textures_and_paths_between_materials() {
+        let mut files = TempFiles::new("shares");
+        let texture = files.path("texture.dds");
+        let first_path = files.path("a.ron");
+        let second_path = files.path("b.ron");
+        write_material(&first_path, &texture);
+        write_material(&second_path, &texture);
+
+        let mut first = Material::new(intern_path(first_path));
+        let mut second = Material::new(intern_path(second_path));
+        first.load().unwrap();
+        second.load().unwrap();
+
+        let first_texture = first.texture("albedo").unwrap();
+        let second_texture = second.texture("albedo").unwrap();
+        assert_eq!(first_texture.mip_levels[0].pixels, vec![0x80, 0, 0, 255]);
+        assert!(Arc::ptr_eq(&first_texture.mip_levels, &second_texture.mip_levels));
+        assert!(ptr::addr_eq(first_texture.path, second_texture.path));
+
+        // Reloading a material doesn't leak its texture paths again.
+        let path = first_texture.path;
+        first.load().unwrap();
+        assert!(ptr::addr_eq(first.texture("albedo").unwrap().path, path));
+    }
+
+    #[test]
+    fn unload_drops_everything_read_from_disk() {
+        let mut files = TempFiles::new("unload");
+        let texture = files.path("texture.dds");
+        let path = files.path("material.ron");
+        write_material(&path, &texture);
+
+        let mut material = Material::new(intern_path(path));
+        material.load().unwrap();
+        assert_eq!(material.parameters.len(), 1);
+        material.unload();
+
+        assert!(material.textures.is_empty());
+        assert!(material.parameters.is_empty());
+        assert_eq!(material.vertex_shader, PathBuf::new());
+        assert_eq!(material.loading_state, LoadingState::Unloaded);
+    }
+}
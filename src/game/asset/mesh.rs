@@ -3,32 +3,34 @@ use cgmath::Vector3;
 use std::path::Path;
 use std::error::Error;
 
-use renderer::Vertex;
+use renderer::{AttributeFormat, Semantic, Vertex, VertexData, VertexLayout};
 use super::LoadingState;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Mesh {
-    pub vertices: Vec<Vertex>,
+    #[serde(skip)]
+    pub vertices: VertexData,
+    #[serde(skip)]
     pub indices: Vec<u16>,
+    #[serde(deserialize_with = "super::deserialize_path")]
     pub path: &'static Path,
+    #[serde(skip)]
     pub loading_state: LoadingState,
-    pub descriptors_changed: bool,
 }
 
 impl Mesh {
     pub fn new(path: &'static Path) -> Mesh {
         Mesh {
-            vertices: vec![],
+            vertices: VertexData::default(),
             indices: vec![],
             path,
             loading_state: LoadingState::Unloaded,
-            descriptors_changed: false,
         }
     }
 
     pub fn load(&mut self) -> Result<(), Box<Error>> {
         self.loading_state = LoadingState::Loaded;
-        self.vertices = vec![
+        let corners = [
             Vertex::new([0.0, 0.0, 0.5, 1.0], [0.2, 0.2, 0.0, 1.0]),
             Vertex::new([0.0, 0.5, 0.0, 1.0], [0.0, 0.6, 0.0, 1.0]),
             Vertex::new([0.5, 0.0, 0.0, 1.0], [0.2, 0.8, 0.0, 1.0]),
@@ -39,9 +41,76 @@ impl Mesh {
             Vertex::new([0.5, 0.0, 0.5, 1.0], [0.7, 0.2, 1.0, 1.0]),
             Vertex::new([0.0, 0.5, 0.5, 1.0], [0.2, 0.2, 0.8, 1.0]),
         ];
+        // Corners of each face, clockwise seen from outside, and its normal.
+        let faces = [
+            ([0, 3, 1, 7], [-1.0, 0.0, 0.0]),
+            ([5, 2, 6, 4], [1.0, 0.0, 0.0]),
+            ([2, 3, 0, 6], [0.0, -1.0, 0.0]),
+            ([7, 1, 5, 4], [0.0, 1.0, 0.0]),
+            ([1, 3, 2, 5], [0.0, 0.0, -1.0]),
+            ([6, 0, 7, 4], [0.0, 0.0, 1.0]),
+        ];
+
+        let layout = VertexLayout::new(&[
+            (Semantic::Position, AttributeFormat::Float4),
+            (Semantic::Color, AttributeFormat::Float4),
+            (Semantic::Normal, AttributeFormat::Snorm8x4),
+        ])?;
+        let mut vertices = VertexData::new(layout, faces.len() * 4);
+        let mut indices = Vec::with_capacity(faces.len() * 6);
+        for (face, &(ref quad, normal)) in faces.iter().enumerate() {
+            let first = face * 4;
+            for (i, &corner) in quad.iter().enumerate() {
+                let vertex = &corners[corner];
+                vertices.set(first + i, Semantic::Position, vertex.pos);
+                vertices.set(first + i, Semantic::Color, vertex.color);
+                vertices.set(first + i, Semantic::Normal, [normal[0], normal[1], normal[2], 0.0]);
+            }
+            let first = first as u16;
+            indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+        self.vertices = vertices;
+        self.indices = indices;
 
         Ok(())
     }
 
     pub fn unload(&mut self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::InnerSpace;
+
+    fn position(mesh: &Mesh, index: u16) -> Vector3<f32> {
+        let [x, y, z, _] = mesh.vertices.get(index as usize, Semantic::Position).unwrap();
+        Vector3::new(x, y, z)
+    }
+
+    /// Front faces are the clockwise ones, so culling back faces has to
+    /// leave the outside of the cube.
+    #[test]
+    fn cube_faces_are_clockwise_seen_from_outside() {
+        let mut mesh = Mesh::new(Path::new("/"));
+        mesh.load().unwrap();
+
+        let center = Vector3::new(0.25, 0.25, 0.25);
+        assert_eq!(mesh.indices.len(), 36);
+        for triangle in mesh.indices.chunks(3) {
+            let a = position(&mesh, triangle[0]);
+            let b = position(&mesh, triangle[1]);
+            let c = position(&mesh, triangle[2]);
+            let [x, y, z, _] = mesh.vertices
+                .get(triangle[0] as usize, Semantic::Normal)
+                .unwrap();
+            let normal = Vector3::new(x, y, z);
+
+            assert!((a - center).dot(normal) > 0.0, "{:?} points inwards", triangle);
+            // Counter-clockwise triangles' cross products point at the eye.
+            let facing = (b - a).cross(c - a).dot(normal);
+            assert!(facing < 0.0, "{:?} is counter-clockwise from outside", triangle);
+        }
+    }
+}
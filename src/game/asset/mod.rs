@@ -1,9 +1,43 @@
 pub mod mesh;
+pub mod texture;
+pub mod material;
+mod dds;
+
+use serde::{Deserialize, Deserializer};
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 pub use self::mesh::Mesh;
+pub use self::texture::Texture;
+pub use self::material::Material;
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Default)]
 pub enum LoadingState {
+    #[default]
     Unloaded,
     Loaded,
 }
+
+/// Asset paths live for the whole run, so paths read at runtime are leaked
+/// to get the `&'static Path` the assets store. Each distinct path is only
+/// leaked once per load of the game library, however often it's read.
+pub fn intern_path(path: PathBuf) -> &'static Path {
+    static PATHS: Mutex<BTreeSet<&'static Path>> = Mutex::new(BTreeSet::new());
+
+    let mut paths = PATHS.lock().unwrap();
+    if let Some(&interned) = paths.get(path.as_path()) {
+        return interned;
+    }
+    let interned: &'static Path = Box::leak(path.into_boxed_path());
+    paths.insert(interned);
+    interned
+}
+
+pub fn deserialize_path<'de, D>(deserializer: D) -> Result<&'static Path, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    PathBuf::deserialize(deserializer).map(intern_path)
+}
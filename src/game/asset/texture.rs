@@ -0,0 +1,275 @@
+use image;
+
+use std::path::Path;
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, Weak};
+
+use super::LoadingState;
+use super::dds;
+
+pub use renderer::{ColorSpace, MipLevel};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TextureFormat {
+    Png,
+    Tga,
+    Dds,
+}
+
+/// Mip chains decoded so far, so textures loaded from the same file share
+/// theirs. An entry lives as long as a texture holds on to it.
+static DECODED: Mutex<BTreeMap<(&'static Path, ColorSpace), Weak<Vec<MipLevel>>>> =
+    Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Texture {
+    /// Shared with every other texture loaded from the same path.
+    #[serde(skip)]
+    pub mip_levels: Arc<Vec<MipLevel>>,
+    pub color_space: ColorSpace,
+    #[serde(deserialize_with = "super::deserialize_path")]
+    pub path: &'static Path,
+    #[serde(skip)]
+    pub loading_state: LoadingState,
+}
+
+impl Texture {
+    pub fn new(path: &'static Path, color_space: ColorSpace) -> Texture {
+        Texture {
+            mip_levels: Arc::default(),
+            color_space,
+            path,
+            loading_state: LoadingState::Unloaded,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.mip_levels.first().map_or(0, |level| level.width)
+    }
+
+    pub fn height(&self) -> u32 {
+        self.mip_levels.first().map_or(0, |level| level.height)
+    }
+
+    pub fn load(&mut self) -> Result<(), Box<Error>> {
+        let key = (self.path, self.color_space);
+        if let Some(mip_levels) = DECODED.lock().unwrap().get(&key).and_then(Weak::upgrade) {
+            self.mip_levels = mip_levels;
+            self.loading_state = LoadingState::Loaded;
+            return Ok(());
+        }
+
+        let bytes = {
+            let mut file = File::open(self.path)?;
+            let mut bytes = vec![];
+            file.read_to_end(&mut bytes)?;
+            bytes
+        };
+
+        let base_level = decode(&bytes, detect_format(self.path, &bytes)?)?;
+
+        self.mip_levels = Arc::new(generate_mip_chain(base_level, self.color_space)?);
+        self.loading_state = LoadingState::Loaded;
+
+        let mut decoded = DECODED.lock().unwrap();
+        decoded.retain(|_, mip_levels| mip_levels.upgrade().is_some());
+        decoded.insert(key, Arc::downgrade(&self.mip_levels));
+
+        Ok(())
+    }
+
+    pub fn unload(&mut self) {
+        self.mip_levels = Arc::default();
+        self.loading_state = LoadingState::Unloaded;
+    }
+}
+
+fn detect_format(path: &Path, bytes: &[u8]) -> Result<TextureFormat, Box<Error>> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Ok(TextureFormat::Png);
+    }
+    if bytes.starts_with(b"DDS ") {
+        return Ok(TextureFormat::Dds);
+    }
+
+    // TGA has no magic bytes, so fall back to the file extension.
+    let extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+
+    match extension.as_ref().map(|ext| ext.as_str()) {
+        Some("tga") => Ok(TextureFormat::Tga),
+        _ => Err(format!("Unsupported texture format: {}", path.display()).into()),
+    }
+}
+
+fn decode(bytes: &[u8], format: TextureFormat) -> Result<MipLevel, Box<Error>> {
+    let image_format = match format {
+        TextureFormat::Png => image::ImageFormat::PNG,
+        TextureFormat::Tga => image::ImageFormat::TGA,
+        TextureFormat::Dds => return dds::decode(bytes),
+    };
+
+    let rgba = image::load_from_memory_with_format(bytes, image_format)?.to_rgba();
+    let (width, height) = rgba.dimensions();
+
+    Ok(MipLevel {
+        width,
+        height,
+        pixels: rgba.into_raw(),
+    })
+}
+
+/// Builds the full mip chain down to 1x1 with a 2x2 box filter. sRGB
+/// textures are filtered in linear space so mips don't darken. Fails for
+/// textures without any pixels, which have no 1x1 level to stop at.
+pub fn generate_mip_chain(
+    base_level: MipLevel,
+    color_space: ColorSpace,
+) -> Result<Vec<MipLevel>, Box<Error>> {
+    if base_level.width == 0 || base_level.height == 0 {
+        return Err(format!(
+            "Texture has no pixels: {}x{}",
+            base_level.width, base_level.height
+        ).into());
+    }
+
+    let mut levels = vec![base_level];
+
+    loop {
+        let next = {
+            let prev = levels.last().unwrap();
+            if prev.width == 1 && prev.height == 1 {
+                break;
+            }
+            downsample(prev, color_space)
+        };
+        levels.push(next);
+    }
+
+    Ok(levels)
+}
+
+fn downsample(src: &MipLevel, color_space: ColorSpace) -> MipLevel {
+    let width = (src.width / 2).max(1);
+    let height = (src.height / 2).max(1);
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+
+    for y in 0..height {
+        for x in 0..width {
+            // Odd sizes drop their last row/column, 1-pixel wide sources
+            // are clamped to reuse it.
+            let xs = [(x * 2).min(src.width - 1), (x * 2 + 1).min(src.width - 1)];
+            let ys = [(y * 2).min(src.height - 1), (y * 2 + 1).min(src.height - 1)];
+
+            for channel in 0..4 {
+                let mut sum = 0.0;
+                for &sy in &ys {
+                    for &sx in &xs {
+                        let value = src.pixels[((sy * src.width + sx) * 4 + channel) as usize];
+                        sum += to_linear(value, channel, color_space);
+                    }
+                }
+                pixels.push(from_linear(sum / 4.0, channel, color_space));
+            }
+        }
+    }
+
+    MipLevel {
+        width,
+        height,
+        pixels,
+    }
+}
+
+fn to_linear(value: u8, channel: u32, color_space: ColorSpace) -> f32 {
+    let c = value as f32 / 255.0;
+    if color_space == ColorSpace::Linear || channel == 3 {
+        return c;
+    }
+
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(value: f32, channel: u32, color_space: ColorSpace) -> u8 {
+    let c = if color_space == ColorSpace::Linear || channel == 3 {
+        value
+    } else if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+
+    (c.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(width: u32, height: u32, pixels: &[[u8; 4]]) -> MipLevel {
+        MipLevel {
+            width,
+            height,
+            pixels: pixels.iter().flat_map(|pixel| pixel.iter().cloned()).collect(),
+        }
+    }
+
+    #[test]
+    fn builds_chain_down_to_one_pixel() {
+        let base = level(8, 2, &[[0; 4]; 16]);
+        let sizes: Vec<_> = generate_mip_chain(base, ColorSpace::Linear)
+            .unwrap()
+            .iter()
+            .map(|level| (level.width, level.height, level.pixels.len()))
+            .collect();
+
+        assert_eq!(sizes, vec![(8, 2, 64), (4, 1, 16), (2, 1, 8), (1, 1, 4)]);
+    }
+
+    #[test]
+    fn averages_linear_textures_directly() {
+        let base = level(2, 2, &[[0, 0, 0, 0], [255, 255, 255, 255], [0; 4], [255; 4]]);
+        let levels = generate_mip_chain(base, ColorSpace::Linear).unwrap();
+
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[1].pixels, vec![128, 128, 128, 128]);
+    }
+
+    #[test]
+    fn averages_srgb_textures_in_linear_space() {
+        let base = level(2, 2, &[[0, 0, 0, 0], [255, 255, 255, 255], [0; 4], [255; 4]]);
+        let levels = generate_mip_chain(base, ColorSpace::Srgb).unwrap();
+
+        // Half of linear white is brighter than half of the encoded value,
+        // alpha is always linear.
+        assert_eq!(levels[1].pixels, vec![188, 188, 188, 128]);
+    }
+
+    #[test]
+    fn odd_sizes_drop_last_column() {
+        let base = level(3, 1, &[[0; 4], [0; 4], [200, 100, 50, 255]]);
+        let levels = generate_mip_chain(base, ColorSpace::Linear).unwrap();
+
+        assert_eq!((levels[1].width, levels[1].height), (1, 1));
+        assert_eq!(levels[1].pixels, vec![0, 0, 0, 0]);
+
+        let base = level(1, 3, &[[0; 4], [0; 4], [200, 100, 50, 255]]);
+        let levels = generate_mip_chain(base, ColorSpace::Linear).unwrap();
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[1].pixels, vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_empty_textures() {
+        assert!(generate_mip_chain(level(0, 4, &[]), ColorSpace::Linear).is_err());
+        assert!(generate_mip_chain(level(4, 0, &[]), ColorSpace::Linear).is_err());
+    }
+}
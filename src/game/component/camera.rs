@@ -0,0 +1,52 @@
+use cgmath::{Matrix4, Point3, Vector3};
+
+use renderer::{self, Projection};
+
+/// Views the scene from its entity's `Physics` position. The first entity
+/// with a camera is the one frames are drawn from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Camera {
+    pub projection: Projection,
+    pub near: f32,
+    pub far: f32,
+    /// Point the camera looks at.
+    pub target: Vector3<f32>,
+    pub up: Vector3<f32>,
+}
+
+impl Camera {
+    pub fn perspective(fov_y: f32, near: f32, far: f32) -> Camera {
+        Camera {
+            projection: Projection::Perspective { fov_y },
+            near,
+            far,
+            target: Vector3::new(0.0, 0.0, 0.0),
+            up: Vector3::new(0.0, 1.0, 0.0),
+        }
+    }
+
+    pub fn orthographic(height: f32, near: f32, far: f32) -> Camera {
+        Camera {
+            projection: Projection::Orthographic { height },
+            ..Camera::perspective(60.0, near, far)
+        }
+    }
+
+    pub fn looking_at(mut self, target: Vector3<f32>) -> Camera {
+        self.target = target;
+        self
+    }
+
+    pub fn to_renderer(&self, pos: Vector3<f32>) -> renderer::Camera {
+        renderer::Camera {
+            view: Matrix4::look_at(
+                Point3::new(pos.x, pos.y, pos.z),
+                Point3::new(self.target.x, self.target.y, self.target.z),
+                self.up,
+            ),
+            projection: self.projection,
+            near: self.near,
+            far: self.far,
+        }
+    }
+}
@@ -8,15 +8,29 @@ pub enum LoadingState {
     Loaded,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Graphics {
     pub mesh: asset::Mesh,
+    pub material: asset::Material,
+    /// Whether the entity shadows others.
+    #[serde(default = "default_shadows")]
+    pub cast_shadows: bool,
+    /// Whether shadows fall on the entity.
+    #[serde(default = "default_shadows")]
+    pub receive_shadows: bool,
 }
 
 impl Graphics {
     pub fn new() -> Graphics {
         Graphics {
             mesh: asset::Mesh::new(Path::new("/")),
+            material: asset::Material::new(Path::new("data/materials/default.ron")),
+            cast_shadows: true,
+            receive_shadows: true,
         }
     }
 }
+
+fn default_shadows() -> bool {
+    true
+}
@@ -0,0 +1,83 @@
+use cgmath::{InnerSpace, Vector3};
+
+use renderer::{self, LightKind};
+
+/// Lights the scene from its entity's `Physics` position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Way directional and spot lights shine.
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance point and spot lights reach.
+    pub range: f32,
+    /// Only directional and spot lights cast shadows.
+    #[serde(default)]
+    pub cast_shadows: bool,
+}
+
+impl Light {
+    pub fn ambient(intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Ambient,
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            color: [1.0, 1.0, 1.0],
+            intensity,
+            range: 0.0,
+            cast_shadows: false,
+        }
+    }
+
+    pub fn directional(direction: Vector3<f32>) -> Light {
+        Light {
+            kind: LightKind::Directional,
+            direction,
+            ..Light::ambient(1.0)
+        }
+    }
+
+    pub fn point(range: f32) -> Light {
+        Light {
+            kind: LightKind::Point,
+            range,
+            ..Light::ambient(1.0)
+        }
+    }
+
+    /// Angles are in degrees off the cone's axis.
+    pub fn spot(direction: Vector3<f32>, range: f32, inner_angle: f32, outer_angle: f32) -> Light {
+        Light {
+            kind: LightKind::Spot {
+                inner_angle,
+                outer_angle,
+            },
+            direction,
+            range,
+            ..Light::ambient(1.0)
+        }
+    }
+
+    pub fn with_color(mut self, color: [f32; 3], intensity: f32) -> Light {
+        self.color = color;
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_shadows(mut self) -> Light {
+        self.cast_shadows = true;
+        self
+    }
+
+    pub fn to_renderer(&self, pos: Vector3<f32>) -> renderer::Light {
+        renderer::Light {
+            kind: self.kind,
+            position: pos,
+            direction: self.direction.normalize(),
+            color: self.color,
+            intensity: self.intensity,
+            range: self.range,
+            cast_shadows: self.cast_shadows,
+        }
+    }
+}
@@ -1,22 +1,26 @@
 pub mod graphics;
 pub mod physics;
+pub mod camera;
+pub mod light;
 
 use cgmath::Vector3;
 
 pub use self::graphics::Graphics;
 pub use self::physics::Physics;
+pub use self::camera::Camera;
+pub use self::light::Light;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sound {
     pub pos: Vector3<f32>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AI {
     pub pos: Vector3<f32>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Entity {
     pub pos: Vector3<f32>,
 }
@@ -1,6 +1,6 @@
 use cgmath::Vector3;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Physics {
     pub pos: Vector3<f32>,
     pub momentum: Vector3<f32>,
@@ -6,15 +6,12 @@ pub struct Entity<'a> {
     game_state: &'a mut State,
 }
 
-static mut GLOBAL_ID: usize = 0;
-
 impl<'a> Entity<'a> {
+    /// Takes the state's next free id, so every state numbers its entities
+    /// from 0.
     pub fn new(game_state: &mut State) -> Entity {
-        let id = unsafe {
-            let id = GLOBAL_ID;
-            GLOBAL_ID += 1;
-            id
-        };
+        let id = game_state.next_entity_id;
+        game_state.next_entity_id += 1;
 
         Entity { game_state, id }
     }
@@ -29,6 +26,16 @@ impl<'a> Entity<'a> {
         self
     }
 
+    pub fn with_camera(&mut self, component: component::Camera) -> &mut Entity<'a> {
+        self.game_state.camera_components[self.id] = Some(component);
+        self
+    }
+
+    pub fn with_light(&mut self, component: component::Light) -> &mut Entity<'a> {
+        self.game_state.light_components[self.id] = Some(component);
+        self
+    }
+
     pub fn build(&mut self) -> usize {
         self.id
     }
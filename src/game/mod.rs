@@ -3,6 +3,7 @@ pub mod system;
 pub mod component;
 pub mod entity;
 pub mod asset;
+pub mod abi;
 
 use std::path::Path;
 
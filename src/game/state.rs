@@ -1,16 +1,38 @@
+use ron;
+
 use std::time;
 use std::fmt;
 use std::default::Default;
+use std::path::Path;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
 
 use super::component;
 
+#[derive(Serialize, Deserialize)]
 pub struct State {
     pub delta_time: time::Duration,
     pub physics_components: Vec<Option<component::Physics>>,
     pub graphics_components: Vec<Option<component::Graphics>>,
+    #[serde(default = "default_components")]
+    pub camera_components: Vec<Option<component::Camera>>,
+    #[serde(default = "default_components")]
+    pub light_components: Vec<Option<component::Light>>,
     pub sound_components: Vec<Option<component::Sound>>,
     pub ai_components: Vec<Option<component::AI>>,
     pub entities: Vec<Option<component::Entity>>,
+    /// Id `Entity::new` gives the next entity.
+    #[serde(default)]
+    pub next_entity_id: usize,
+    /// Renderer model id of each mesh, by path. Entities with the same mesh
+    /// share a model so it's uploaded once and drawn instanced.
+    #[serde(skip)]
+    pub model_ids: HashMap<&'static Path, u32>,
+    /// Renderer material id of each material, by path.
+    #[serde(skip)]
+    pub material_ids: HashMap<&'static Path, u32>,
 }
 
 // impl fmt::Debug for State {
@@ -24,10 +46,114 @@ impl Default for State {
         State {
             physics_components: vec![None; 2048],
             graphics_components: vec![None; 2048],
+            camera_components: vec![None; 2048],
+            light_components: vec![None; 2048],
             sound_components: vec![None; 2048],
             ai_components: vec![None; 2048],
             entities: vec![None; 2048],
+            next_entity_id: 0,
             delta_time: time::Duration::from_millis(16),
+            model_ids: HashMap::new(),
+            material_ids: HashMap::new(),
         }
     }
 }
+
+/// Lets snapshots from before a component list existed still load.
+fn default_components<T: Clone>() -> Vec<Option<T>> {
+    vec![None; 2048]
+}
+
+impl State {
+    /// Writes the state in a layout independent format, so a build with a
+    /// different `State` can pick it up with `load_snapshot`.
+    pub fn save_snapshot(&self, path: &Path) -> Result<(), Box<Error>> {
+        let snapshot = ron::ser::to_string(self)?;
+        let mut file = File::create(path)?;
+        file.write_all(snapshot.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load_snapshot(path: &Path) -> Result<State, Box<Error>> {
+        let mut snapshot = String::new();
+        File::open(path)?.read_to_string(&mut snapshot)?;
+        let state = ron::de::from_str(&snapshot)?;
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::Vector3;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::super::asset;
+
+    #[test]
+    fn snapshots_round_trip() {
+        let mut state = State {
+            delta_time: time::Duration::from_millis(7),
+            ..State::default()
+        };
+        let mut physics = component::Physics::new();
+        physics.pos = Vector3::new(1.0, 2.0, 3.0);
+        physics.momentum = Vector3::new(0.0, -4.5, 0.0);
+        state.physics_components[3] = Some(physics);
+        let mut graphics = component::Graphics::new();
+        graphics.mesh = asset::Mesh::new(Path::new("data/meshes/cube.mesh"));
+        graphics.receive_shadows = false;
+        state.graphics_components[3] = Some(graphics);
+        state.camera_components[0] = Some(
+            component::Camera::orthographic(10.0, 0.1, 100.0)
+                .looking_at(Vector3::new(0.0, 1.0, 0.0)),
+        );
+        state.light_components[1] = Some(
+            component::Light::spot(Vector3::new(0.0, -1.0, 0.0), 20.0, 15.0, 30.0)
+                .with_color([1.0, 0.5, 0.25], 2.0),
+        );
+        state.entities[3] = Some(component::Entity {
+            pos: Vector3::new(1.0, 2.0, 3.0),
+        });
+        state.model_ids.insert(Path::new("data/meshes/cube.mesh"), 1);
+
+        let path = env::temp_dir().join(format!("xtreme_game_state_test-{}.ron", process::id()));
+        state.save_snapshot(&path).unwrap();
+        let loaded = State::load_snapshot(&path);
+        let _ = fs::remove_file(&path);
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.delta_time, state.delta_time);
+        let debug = |state: &State| {
+            format!(
+                "{:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+                state.physics_components,
+                state.graphics_components,
+                state.camera_components,
+                state.light_components,
+                state.sound_components,
+                state.ai_components,
+                state.entities
+            )
+        };
+        assert_eq!(debug(&loaded), debug(&state));
+        // Ids belong to the renderer the state was saved with.
+        assert!(loaded.model_ids.is_empty());
+        assert!(loaded.material_ids.is_empty());
+    }
+
+    #[test]
+    fn loading_a_broken_snapshot_fails() {
+        let path = env::temp_dir().join(format!("xtreme_game_state_broken-{}.ron", process::id()));
+        File::create(&path).unwrap().write_all(b"(delta_time: ").unwrap();
+        let loaded = State::load_snapshot(&path);
+        let _ = fs::remove_file(&path);
+        assert!(loaded.is_err());
+
+        assert!(State::load_snapshot(Path::new("data/no_such_snapshot.ron")).is_err());
+    }
+}
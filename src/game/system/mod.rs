@@ -1,9 +1,13 @@
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+
 use std::error::Error;
+use std::path::Path;
 
 use super::state::State;
 use super::component;
 use super::asset;
-use super::super::renderer::Renderer;
+use super::asset::material::Parameter;
+use super::super::renderer::{self, Indices, Instance, Renderer};
 
 pub fn process_physics(state: &State, next_state: &mut State) {
     for (i, obj) in state.physics_components.iter().enumerate() {
@@ -23,20 +27,243 @@ fn integrate(component: &Option<component::Physics>, dt: f32) -> Option<componen
     }
 }
 
+/// A model queued for drawing this frame.
+struct Draw {
+    id: u32,
+    material_id: u32,
+    material: &'static Path,
+    mesh: &'static Path,
+    instance: Instance,
+}
+
 pub fn draw_entities(renderer: &mut Renderer, state: &mut State) -> Result<(), Box<Error>> {
+    if let Some(camera) = active_camera(state) {
+        renderer.set_camera(&camera)?;
+    }
+    renderer.set_lights(&lights(state))?;
+
+    let model_ids = &mut state.model_ids;
+    let material_ids = &mut state.material_ids;
+    let mut draws = vec![];
     for (id, graphics_component) in state.graphics_components.iter_mut().enumerate() {
         if let &mut Some(ref mut component) = graphics_component {
             if component.mesh.loading_state == asset::LoadingState::Unloaded {
                 component.mesh.load()?;
-                renderer.update_model(id as u32, &component.mesh.vertices)?;
             }
+            let model_id = match model_ids.get(component.mesh.path) {
+                Some(&model_id) => model_id,
+                None => {
+                    let model_id = model_ids.len() as u32;
+                    let indices = match component.mesh.indices.len() {
+                        0 => Indices::None,
+                        _ => Indices::U16(&component.mesh.indices),
+                    };
+                    renderer.update_model(model_id, &component.mesh.vertices, indices)?;
+                    model_ids.insert(component.mesh.path, model_id);
+                    model_id
+                }
+            };
 
-            if component.mesh.descriptors_changed {
-                renderer
-                    .update_descriptors(id as u32, &component.mesh.vertices)?;
+            if component.material.loading_state == asset::LoadingState::Unloaded {
+                component.material.load()?;
             }
+            let material_id = match material_ids.get(component.material.path) {
+                Some(&material_id) => material_id,
+                None => {
+                    let material_id = material_ids.len() as u32;
+                    renderer.update_material(material_id, &component.material.to_renderer())?;
+                    material_ids.insert(component.material.path, material_id);
+                    material_id
+                }
+            };
+
+            let model = match state.physics_components[id] {
+                Some(ref physics) => Matrix4::from_translation(physics.pos),
+                None => Matrix4::identity(),
+            };
+            let tint = match component.material.parameters.get("tint") {
+                Some(&Parameter::Vec4(r, g, b, a)) => [r, g, b, a],
+                _ => [1.0, 1.0, 1.0, 1.0],
+            };
+            draws.push(Draw {
+                id: model_id,
+                material_id,
+                material: component.material.path,
+                mesh: component.mesh.path,
+                instance: Instance {
+                    model,
+                    tint,
+                    cast_shadows: component.cast_shadows,
+                    receive_shadows: component.receive_shadows,
+                },
+            });
         }
     }
 
+    // Instances sharing a material and mesh are queued back to back so the
+    // backends can draw them together.
+    draws.sort_by(|a, b| (a.material, a.mesh).cmp(&(b.material, b.mesh)));
+    for draw in &draws {
+        renderer.draw_model(draw.id, draw.material_id, &draw.instance)?;
+    }
+
     Ok(())
 }
+
+/// Every entity's light, placed at its `Physics` position.
+fn lights(state: &State) -> Vec<renderer::Light> {
+    state
+        .light_components
+        .iter()
+        .enumerate()
+        .filter_map(|(id, light)| light.as_ref().map(|light| (id, light)))
+        .map(|(id, light)| {
+            let pos = match state.physics_components[id] {
+                Some(ref physics) => physics.pos,
+                None => Vector3::new(0.0, 0.0, 0.0),
+            };
+            light.to_renderer(pos)
+        })
+        .collect()
+}
+
+/// The first entity with a camera, placed at its `Physics` position.
+fn active_camera(state: &State) -> Option<renderer::Camera> {
+    state
+        .camera_components
+        .iter()
+        .enumerate()
+        .filter_map(|(id, camera)| camera.as_ref().map(|camera| (id, camera)))
+        .next()
+        .map(|(id, camera)| {
+            let pos = match state.physics_components[id] {
+                Some(ref physics) => physics.pos,
+                None => Vector3::new(0.0, 0.0, 0.0),
+            };
+            camera.to_renderer(pos)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::super::entity::Entity;
+    use super::super::asset::intern_path;
+    use super::super::super::renderer::{Camera, Light, Material, Parameter, RenderSettings,
+                                        VertexData};
+
+    /// Keeps what the materials and draws it was given looked like.
+    #[derive(Default)]
+    struct RecordingRenderer {
+        materials: Vec<(u32, PathBuf, PathBuf, Vec<(String, Parameter)>)>,
+        draws: Vec<(u32, u32)>,
+    }
+
+    impl Renderer for RecordingRenderer {
+        fn begin_frame(&mut self) -> Result<(), Box<Error>> {
+            Ok(())
+        }
+
+        fn end_frame(&mut self) -> Result<(), Box<Error>> {
+            Ok(())
+        }
+
+        fn set_camera(&mut self, _camera: &Camera) -> Result<(), Box<Error>> {
+            Ok(())
+        }
+
+        fn set_lights(&mut self, _lights: &[Light]) -> Result<(), Box<Error>> {
+            Ok(())
+        }
+
+        fn update_model(
+            &mut self,
+            _id: u32,
+            _vertices: &VertexData,
+            _indices: Indices,
+        ) -> Result<(), Box<Error>> {
+            Ok(())
+        }
+
+        fn update_material(&mut self, id: u32, material: &Material) -> Result<(), Box<Error>> {
+            self.materials.push((
+                id,
+                material.vertex_shader.to_path_buf(),
+                material.fragment_shader.to_path_buf(),
+                material
+                    .parameters
+                    .iter()
+                    .map(|&(name, parameter)| (name.to_string(), parameter))
+                    .collect(),
+            ));
+            Ok(())
+        }
+
+        fn draw_model(
+            &mut self,
+            id: u32,
+            material: u32,
+            _instance: &Instance,
+        ) -> Result<(), Box<Error>> {
+            self.draws.push((id, material));
+            Ok(())
+        }
+
+        fn update_resolution(&mut self, _width: u32, _height: u32) -> Result<(), Box<Error>> {
+            Ok(())
+        }
+
+        fn change_settings(&mut self, _settings: &RenderSettings) -> Result<(), Box<Error>> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn passes_materials_to_the_renderer() {
+        let path = env::temp_dir().join("xtreme_game_system_test_material.ron");
+        fs::write(
+            &path,
+            "(vertex_shader: \"data/shaders/water.vert\", \
+             fragment_shader: \"data/shaders/water.frag\", \
+             parameters: { \"wave_height\": Float(0.25), \"deep_color\": Vec3(0.0, 0.1, 0.4) })",
+        ).unwrap();
+
+        let mut state = State::default();
+        for _ in 0..2 {
+            let mut graphics = component::Graphics::new();
+            graphics.material = asset::Material::new(intern_path(path.clone()));
+            Entity::new(&mut state).with_graphics(graphics).build();
+        }
+        let mut graphics = component::Graphics::new();
+        graphics.material = asset::Material::new(Path::new("data/materials/default.ron"));
+        Entity::new(&mut state).with_graphics(graphics).build();
+
+        let mut renderer = RecordingRenderer::default();
+        draw_entities(&mut renderer, &mut state).unwrap();
+
+        // Each material is handed over once, with its parameters by name.
+        assert_eq!(renderer.materials.len(), 2);
+        let (water, ref vertex_shader, ref fragment_shader, ref parameters) =
+            renderer.materials[0];
+        assert_eq!(vertex_shader, Path::new("data/shaders/water.vert"));
+        assert_eq!(fragment_shader, Path::new("data/shaders/water.frag"));
+        assert_eq!(
+            parameters,
+            &vec![
+                ("deep_color".to_string(), Parameter::Vec3(0.0, 0.1, 0.4)),
+                ("wave_height".to_string(), Parameter::Float(0.25)),
+            ]
+        );
+        let (default, ref vertex_shader, _, _) = renderer.materials[1];
+        assert_eq!(vertex_shader, Path::new("data/shaders/cube.vert"));
+
+        let mut materials: Vec<_> = renderer.draws.iter().map(|&(_, material)| material).collect();
+        materials.sort();
+        assert_eq!(materials, vec![water, water, default]);
+    }
+}
@@ -1,23 +1,36 @@
-#[macro_use]
-extern crate ash;
 extern crate cgmath;
-extern crate glsl_to_spirv;
+extern crate image;
+extern crate ron;
+extern crate serde;
 #[macro_use]
-extern crate lazy_static;
-extern crate winit;
+extern crate serde_derive;
 
 pub mod game;
 pub mod renderer;
 
+/// Only the software backend is built into the game library, so the tests
+/// can render without a GPU. The Vulkan backend lives in the host.
+pub mod render_backends {
+    pub mod software;
+}
+
 use std::error::Error;
 
+use game::abi::AbiInfo;
 use game::state::State;
 use game::system;
 use renderer::Renderer;
 
+/// Checked by the host before any other symbol is used, so it has to stay
+/// `extern "C"` and keep its signature across every build.
+#[no_mangle]
+pub extern "C" fn abi_info() -> AbiInfo {
+    AbiInfo::current()
+}
+
 #[no_mangle]
 pub fn render(state: &mut State, renderer: &mut Renderer) -> Result<(), Box<Error>> {
-    renderer.begin_frame();
+    renderer.begin_frame()?;
     system::draw_entities(renderer, state)?;
     renderer.end_frame()
 }
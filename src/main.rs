@@ -1,34 +1,77 @@
 #[macro_use]
 extern crate ash;
 extern crate libloading;
+extern crate libc;
 extern crate cgmath;
 extern crate winit;
 extern crate glsl_to_spirv;
+extern crate image;
+extern crate ron;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 
 pub mod os_platform;
 pub mod game;
 pub mod renderer;
 pub mod render_backends;
 
-use winit::{Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 
 use std::mem;
 use std::cmp;
 use std::time;
+use std::env;
+use std::process;
+use std::fs;
+use std::path::PathBuf;
+use std::error::Error;
 
-use os_platform::code_reload::GameLib;
-use render_backends::vulkan::VulkanRenderer;
-use renderer::Renderer;
+use game::abi::AbiMismatch;
+use game::state::State;
+use os_platform::code_reload::{self, GameLib};
+use renderer::{DepthFormat, PresentMode, RenderSettings, Renderer};
+use render_backends::vulkan::{SurfaceLost, VulkanRenderer};
 
-#[cfg(target_os = "windows")]
-const LIB_PATH: &str = "./target/debug/xtreme_game.dll";
-#[cfg(target_os = "linux")]
-const LIB_PATH: &str = "./target/debug/libxtreme_game.so";
-#[cfg(target_os = "macos")]
-const LIB_PATH: &str = "./target/debug/libxtreme_game.dylib";
+pub fn main() {
+    let args: Vec<String> = env::args().collect();
+    let migrate_state = args.iter().any(|arg| arg == "--migrate-state");
+    let restore_path = arg_value(&args, "--restore-state").map(PathBuf::from);
+    // Snapshot left by `restart_with_state`, removed once it's read back.
+    let migrated_path = arg_value(&args, "--migrated-state").map(PathBuf::from);
+    // Taken up front since on Linux the path gets a " (deleted)" suffix once
+    // a rebuild replaces the executable.
+    let exe_path = env::current_exe().ok();
+    let lib_path = code_reload::find_game_lib(arg_value(&args, "--game-lib"));
 
+    // Loaded before the window opens so a missing or broken library just
+    // ends the run.
+    let mut game = match GameLib::new(&lib_path) {
+        Ok(game) => game,
+        Err(err) => {
+            println!("Couldn't load the game library {}: {}", lib_path.display(), err);
+            process::exit(1);
+        }
+    };
+
+    let (mut state, mut next_state) = match migrated_path.as_ref().or(restore_path.as_ref()) {
+        Some(path) => (State::load_snapshot(path).unwrap(), State::default()),
+        None => game::init(),
+    };
+    if let Some(path) = migrated_path {
+        if let Err(err) = fs::remove_file(&path) {
+            println!("Couldn't remove {}: {}", path.display(), err);
+        }
+    }
+
+    if let Some(size) = arg_value(&args, "--headless") {
+        if let Err(err) = render_headless(&game, &mut state, size) {
+            println!("Couldn't render headless: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
 
-pub fn main() {
     let mut events_loop = winit::EventsLoop::new();
     let window = winit::WindowBuilder::new()
         .with_title("Xtreme Game")
@@ -36,23 +79,28 @@ pub fn main() {
         .build(&events_loop)
         .unwrap();
 
-    let mut renderer = VulkanRenderer::new(&window, 1024, 768).unwrap();
-
-    let mut game = GameLib::new(LIB_PATH);
-    let mut last_modified = std::fs::metadata(LIB_PATH).unwrap().modified().unwrap();
+    let mut renderer = VulkanRenderer::new(&window).unwrap();
 
-    let (mut state, mut next_state) = game::init();
+    if let Err(err) = renderer.change_settings(&render_settings(&args)) {
+        println!("Couldn't apply the render settings: {}", err);
+    }
 
     let mut curr_time = time::Instant::now();
     let mut time_accumulator = time::Duration::new(0, 0);
 
     let mut game_is_running = true;
     while game_is_running {
-        if let Ok(Ok(modified)) = std::fs::metadata(LIB_PATH).map(|m| m.modified()) {
-            if modified > last_modified {
-                drop(game);
-                game = GameLib::new(LIB_PATH);
-                last_modified = modified;
+        match game.reload_if_changed() {
+            Ok(true) => println!("Reloaded {}", game.path().display()),
+            Ok(false) => (),
+            Err(err) => {
+                println!("{}", err);
+                if migrate_state && err.is::<AbiMismatch>() {
+                    match restart_with_state(exe_path.as_ref(), &state) {
+                        Ok(()) => game_is_running = false,
+                        Err(err) => println!("Couldn't migrate state: {}", err),
+                    }
+                }
             }
         }
 
@@ -71,6 +119,23 @@ pub fn main() {
                 event: WindowEvent::Closed,
                 ..
             } => game_is_running = false,
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::F12),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                    ..
+                },
+                ..
+            } => renderer.capture_frame(),
+            Event::WindowEvent {
+                event: WindowEvent::Resized(width, height),
+                ..
+            } => if let Err(err) = renderer.update_resolution(width, height) {
+                println!("Couldn't resize: {}", err);
+            },
             _ => (),
         });
 
@@ -87,8 +152,115 @@ pub fn main() {
 
         let alpha = time_accumulator.subsec_nanos() as f64 / state.delta_time.subsec_nanos() as f64;
         game.interpolate(&state, &mut next_state, alpha);
-        println!("after interpolate");
-        game.render(&state, &mut renderer).unwrap();
-        println!("after render");
+        // A frame that fails is dropped, the next one gets another try.
+        if let Err(err) = game.render(&mut state, &mut renderer) {
+            if !err.is::<SurfaceLost>() {
+                println!("Couldn't render: {}", err);
+            } else if let Err(err) = renderer.recreate_surface(&window) {
+                println!("Couldn't recreate the surface: {}", err);
+            }
+        }
+
+        match renderer.take_captured_frame() {
+            Some(Ok(frame)) => match save_screenshot(&frame) {
+                Ok(path) => println!("Saved screenshot {}", path.display()),
+                Err(err) => println!("Couldn't save screenshot: {}", err),
+            },
+            Some(Err(err)) => println!("Couldn't capture the frame: {}", err),
+            None => (),
+        }
+    }
+}
+
+/// Carries the state over a `State` layout change by snapshotting it and
+/// starting the freshly built host, which reads the snapshot back in with
+/// the new layout and removes it.
+fn restart_with_state(exe_path: Option<&PathBuf>, state: &State) -> Result<(), Box<Error>> {
+    let exe_path = exe_path.ok_or("Couldn't find the host executable")?;
+    let snapshot_path = env::temp_dir().join(format!("xtreme_game-{}.ron", process::id()));
+    state.save_snapshot(&snapshot_path)?;
+
+    // Keep the user's flags but replace any snapshot we were started with.
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    for flag in &["--restore-state", "--migrated-state"] {
+        if let Some(i) = args.iter().position(|arg| arg == flag) {
+            let end = cmp::min(i + 2, args.len());
+            args.drain(i..end);
+        }
+    }
+
+    let spawned = process::Command::new(exe_path)
+        .args(&args)
+        .arg("--migrated-state")
+        .arg(&snapshot_path)
+        .spawn();
+    if let Err(err) = spawned {
+        let _ = fs::remove_file(&snapshot_path);
+        return Err(err.into());
     }
+
+    Ok(())
+}
+
+/// Draws one frame of `state` without a window at `size`, given as
+/// `<width>x<height>`, and saves it as a screenshot. Works on devices that
+/// can't present, like software drivers.
+fn render_headless(game: &GameLib, state: &mut State, size: &str) -> Result<(), Box<Error>> {
+    let mut dimensions = size.split('x').map(|dimension| dimension.parse::<u32>());
+    let (width, height) = match (dimensions.next(), dimensions.next(), dimensions.next()) {
+        (Some(Ok(width)), Some(Ok(height)), None) if width > 0 && height > 0 => (width, height),
+        _ => return Err(format!("Expected <width>x<height> for --headless, got {}", size).into()),
+    };
+
+    let mut renderer = VulkanRenderer::headless(width, height)?;
+    game.render(state, &mut renderer)?;
+    let frame = renderer.read_render_target(0)?;
+
+    let path = save_screenshot(&frame)?;
+    println!("Saved screenshot {}", path.display());
+    Ok(())
+}
+
+fn save_screenshot(frame: &image::RgbaImage) -> Result<PathBuf, Box<Error>> {
+    let timestamp = time::SystemTime::now().duration_since(time::UNIX_EPOCH)?;
+    let path = PathBuf::from(format!("screenshot-{}.png", timestamp.as_secs()));
+    frame.save(&path)?;
+    Ok(path)
+}
+
+/// Settings from `--msaa <samples>`, `--present-mode <vsync|mailbox|immediate>`,
+/// `--resolution-scale <scale>` and `--depth-bits <16|24|32>`, defaults for
+/// the ones missing or not understood.
+fn render_settings(args: &[String]) -> RenderSettings {
+    let defaults = RenderSettings::default();
+    let present_mode = match arg_value(args, "--present-mode") {
+        Some("vsync") => PresentMode::Vsync,
+        Some("mailbox") => PresentMode::Mailbox,
+        Some("immediate") => PresentMode::Immediate,
+        _ => defaults.present_mode,
+    };
+    let depth_format = match arg_value(args, "--depth-bits") {
+        Some("16") => DepthFormat::D16,
+        Some("24") => DepthFormat::D24,
+        Some("32") => DepthFormat::D32,
+        _ => defaults.depth_format,
+    };
+
+    RenderSettings {
+        samples: arg_value(args, "--msaa")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(defaults.samples),
+        present_mode,
+        resolution_scale: arg_value(args, "--resolution-scale")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(defaults.resolution_scale),
+        depth_format,
+    }
+}
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
 }
@@ -1,43 +1,470 @@
-use libloading::Library;
-
-use std::path::Path;
-use std::fs;
-use std::error::Error;
-
-use game::state::State;
-use super::super::renderer::Renderer;
-
-pub struct GameLib(Library);
-
-impl GameLib {
-    pub fn new(lib_path: &str) -> GameLib {
-        let lib_copy_path = Path::new(lib_path).with_extension("module");
-        let _ = fs::copy(lib_path, lib_copy_path.to_owned());
-        GameLib(Library::new(lib_copy_path).unwrap())
-    }
-
-    pub fn render(&self, state: &State, renderer: &mut Renderer) -> Result<(), Box<Error>> {
-        unsafe {
-            let func = self.0
-                .get::<fn(&State, &mut Renderer) -> Result<(), Box<Error>>>(b"render")
-                .unwrap();
-            func(state, renderer)
-        }
-    }
-
-    pub fn update(&self, state: &State, next_state: &mut State) {
-        unsafe {
-            let func = self.0.get::<fn(&State, &mut State)>(b"update").unwrap();
-            func(state, next_state)
-        }
-    }
-
-    pub fn interpolate(&self, state: &State, next_state: &mut State, alpha: f64) {
-        unsafe {
-            let func = self.0
-                .get::<fn(&State, &mut State, alpha: f64)>(b"interpolate")
-                .unwrap();
-            func(state, next_state, alpha)
-        }
-    }
-}
+use libloading::Library;
+
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::env;
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::process;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant, SystemTime};
+use std::error::Error;
+
+use game::abi::{AbiInfo, AbiMismatch};
+use game::state::State;
+use super::super::renderer::Renderer;
+
+/// How long the library file has to stay untouched before we trust that the
+/// compiler has finished writing it.
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRIES: u32 = 5;
+
+const LIB_NAME: &str = "xtreme_game";
+const LIB_PATH_ENV: &str = "XTREME_GAME_LIB";
+
+type AbiInfoFn = extern "C" fn() -> AbiInfo;
+type RenderFn = fn(&mut State, &mut Renderer) -> Result<(), Box<Error>>;
+type UpdateFn = fn(&State, &mut State);
+type InterpolateFn = fn(&State, &mut State, f64);
+
+/// Entry points resolved once per load instead of on every call.
+struct GameApi {
+    render: RenderFn,
+    update: UpdateFn,
+    interpolate: InterpolateFn,
+}
+
+struct LoadedLib {
+    api: GameApi,
+    // Keeps the code behind `api` mapped. Only `None` while dropping.
+    library: Option<Library>,
+    copy_path: PathBuf,
+}
+
+impl Drop for LoadedLib {
+    fn drop(&mut self) {
+        // Windows won't delete a file that is still loaded.
+        drop(self.library.take());
+        let _ = fs::remove_file(&self.copy_path);
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+struct PendingBuild {
+    stamp: FileStamp,
+    seen_at: Instant,
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+pub struct GameLib {
+    lib_path: PathBuf,
+    loaded: LoadedLib,
+    loaded_stamp: FileStamp,
+    pending: Option<PendingBuild>,
+    copy_count: u32,
+}
+
+impl GameLib {
+    pub fn new<P: AsRef<Path>>(lib_path: P) -> Result<GameLib, Box<Error>> {
+        let lib_path = lib_path.as_ref().to_owned();
+        remove_stale_copies(&env::temp_dir());
+        let stamp = file_stamp(&lib_path)?;
+        let loaded = load(&lib_path, 0)?;
+
+        Ok(GameLib {
+            lib_path,
+            loaded,
+            loaded_stamp: stamp,
+            pending: None,
+            copy_count: 1,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.lib_path
+    }
+
+    /// Polls the library file and swaps in a new build once it has settled.
+    /// Returns `Ok(true)` after a successful reload. On error the previous
+    /// library stays active and the load is retried on later calls.
+    pub fn reload_if_changed(&mut self) -> Result<bool, Box<Error>> {
+        let now = Instant::now();
+
+        let stamp = match file_stamp(&self.lib_path) {
+            Ok(stamp) => stamp,
+            // The file disappears briefly while the linker replaces it.
+            Err(_) => return Ok(false),
+        };
+
+        if stamp == self.loaded_stamp {
+            self.pending = None;
+            return Ok(false);
+        }
+
+        let is_new_build = self.pending
+            .as_ref()
+            .is_none_or(|pending| pending.stamp != stamp);
+
+        if is_new_build {
+            self.pending = Some(PendingBuild {
+                stamp,
+                seen_at: now,
+                attempts: 0,
+                next_attempt: now + SETTLE_TIME,
+            });
+            return Ok(false);
+        }
+
+        let pending = self.pending.as_mut().unwrap();
+        if now < pending.next_attempt || now - pending.seen_at < SETTLE_TIME
+            || pending.attempts >= MAX_RETRIES
+        {
+            return Ok(false);
+        }
+
+        pending.attempts += 1;
+        pending.next_attempt = now + RETRY_DELAY;
+
+        let copy_id = self.copy_count;
+        self.copy_count += 1;
+
+        match load(&self.lib_path, copy_id) {
+            Ok(loaded) => {
+                self.loaded = loaded;
+                self.loaded_stamp = stamp;
+                self.pending = None;
+                Ok(true)
+            }
+            Err(err) => {
+                if err.is::<AbiMismatch>() {
+                    // Retrying can't fix a layout change, only a new build can.
+                    pending.attempts = MAX_RETRIES;
+                    return Err(err);
+                }
+
+                let giving_up = if pending.attempts >= MAX_RETRIES {
+                    ", giving up until the next build"
+                } else {
+                    ", retrying"
+                };
+                Err(format!(
+                    "Couldn't reload {} (attempt {}/{}{}): {}",
+                    self.lib_path.display(),
+                    pending.attempts,
+                    MAX_RETRIES,
+                    giving_up,
+                    err
+                ).into())
+            }
+        }
+    }
+
+    pub fn render(&self, state: &mut State, renderer: &mut Renderer) -> Result<(), Box<Error>> {
+        (self.loaded.api.render)(state, renderer)
+    }
+
+    pub fn update(&self, state: &State, next_state: &mut State) {
+        (self.loaded.api.update)(state, next_state)
+    }
+
+    pub fn interpolate(&self, state: &State, next_state: &mut State, alpha: f64) {
+        (self.loaded.api.interpolate)(state, next_state, alpha)
+    }
+}
+
+fn file_stamp(path: &Path) -> Result<FileStamp, Box<Error>> {
+    let metadata = fs::metadata(path)?;
+    Ok(FileStamp {
+        modified: metadata.modified()?,
+        len: metadata.len(),
+    })
+}
+
+/// Finds the game library. An explicit path (from the command line) wins,
+/// then `XTREME_GAME_LIB`, then the library built next to the running
+/// executable, and finally `$CARGO_TARGET_DIR/<profile>` or
+/// `./target/<profile>`.
+pub fn find_game_lib(explicit_path: Option<&str>) -> PathBuf {
+    search_game_lib(explicit_path, env::current_exe().ok())
+}
+
+fn search_game_lib(explicit_path: Option<&str>, exe: Option<PathBuf>) -> PathBuf {
+    if let Some(path) = explicit_path {
+        return PathBuf::from(path);
+    }
+    if let Some(path) = env::var_os(LIB_PATH_ENV) {
+        return PathBuf::from(path);
+    }
+
+    let file_name = format!("{}{}{}", DLL_PREFIX, LIB_NAME, DLL_SUFFIX);
+
+    // Cargo puts the library and the host binary in the same directory, which
+    // already accounts for the profile and any custom target dir.
+    let next_to_exe = exe.and_then(|exe| exe.parent().map(|dir| dir.join(&file_name)));
+    if let Some(path) = next_to_exe {
+        if path.exists() {
+            return path;
+        }
+    }
+
+    let profile = if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    };
+    let target_dir = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("target"));
+
+    target_dir.join(profile).join(file_name)
+}
+
+/// Removes the library copies `load` left in `dir` when a process died
+/// before its `LoadedLib`s were dropped. Copies of running processes,
+/// including this one, are kept.
+fn remove_stale_copies(dir: &Path) {
+    let prefix = format!("{}{}-", DLL_PREFIX, LIB_NAME);
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let file_name = entry.file_name();
+        let pid = file_name
+            .to_str()
+            .filter(|name| name.starts_with(&prefix) && name.ends_with(DLL_SUFFIX))
+            .map(|name| &name[prefix.len()..name.len() - DLL_SUFFIX.len()])
+            .and_then(copy_pid);
+        if let Some(pid) = pid {
+            if pid != process::id() && !is_running(pid) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/// Whether a process with the id exists. A process we aren't allowed to
+/// signal still exists.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // Zero and negative ids would signal whole process groups.
+    let pid = match i32::try_from(pid) {
+        Ok(pid) if pid > 0 => pid,
+        _ => return false,
+    };
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || ::std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Windows won't delete a library another instance still has loaded, so
+/// every copy can be tried.
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+/// Process id in the `<pid>-<copy id>` part of a copy's name.
+fn copy_pid(name: &str) -> Option<u32> {
+    let mut parts = name.splitn(2, '-');
+    let pid = parts.next()?.parse().ok()?;
+    parts.next()?.parse::<u32>().ok()?;
+    Some(pid)
+}
+
+/// Loads a copy of the library so the original can be overwritten by the
+/// next build. Every load gets its own uniquely named copy in the temp dir:
+/// loaders cache libraries by path, and the old library has to keep running
+/// until the new one has loaded completely. Libraries whose ABI doesn't match
+/// the host are refused before any game code is called.
+fn load(lib_path: &Path, copy_id: u32) -> Result<LoadedLib, Box<Error>> {
+    let copy_path = env::temp_dir().join(format!(
+        "{}{}-{}-{}{}",
+        DLL_PREFIX,
+        LIB_NAME,
+        process::id(),
+        copy_id,
+        DLL_SUFFIX
+    ));
+    fs::copy(lib_path, &copy_path)
+        .map_err(|err| format!("Couldn't copy {}: {}", lib_path.display(), err))?;
+
+    let library = match Library::new(&copy_path) {
+        Ok(library) => library,
+        Err(err) => {
+            let _ = fs::remove_file(&copy_path);
+            return Err(format!("Couldn't load {}: {}", lib_path.display(), err).into());
+        }
+    };
+
+    let api = match unsafe { resolve_api(&library) } {
+        Ok(api) => api,
+        Err(err) => {
+            drop(library);
+            let _ = fs::remove_file(&copy_path);
+            return Err(err);
+        }
+    };
+
+    Ok(LoadedLib {
+        api,
+        library: Some(library),
+        copy_path,
+    })
+}
+
+unsafe fn resolve_api(library: &Library) -> Result<GameApi, Box<Error>> {
+    let library_abi = (*symbol::<AbiInfoFn>(library, "abi_info")?)();
+    let host_abi = AbiInfo::current();
+    if library_abi != host_abi {
+        return Err(Box::new(AbiMismatch {
+            host: host_abi,
+            library: library_abi,
+        }));
+    }
+
+    Ok(GameApi {
+        render: *symbol::<RenderFn>(library, "render")?,
+        update: *symbol::<UpdateFn>(library, "update")?,
+        interpolate: *symbol::<InterpolateFn>(library, "interpolate")?,
+    })
+}
+
+unsafe fn symbol<'lib, T>(
+    library: &'lib Library,
+    name: &str,
+) -> Result<::libloading::Symbol<'lib, T>, Box<Error>> {
+    library
+        .get::<T>(name.as_bytes())
+        .map_err(|err| format!("Missing symbol `{}`: {}", name, err).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsString;
+    use std::sync::Mutex;
+
+    /// Tests changing the environment hold this, the test threads share it.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Sets or clears the variables for the duration of `f`, restoring them
+    /// afterwards.
+    fn with_env<F: FnOnce()>(vars: &[(&str, Option<&str>)], f: F) {
+        let _guard = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let saved: Vec<(&str, Option<OsString>)> =
+            vars.iter().map(|&(name, _)| (name, env::var_os(name))).collect();
+        for &(name, value) in vars {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+        f();
+        for (name, value) in saved {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+    }
+
+    /// A fresh directory in the temp dir, named after the test.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("xtreme_game_code_reload_{}", test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn lib_file_name() -> String {
+        format!("{}{}{}", DLL_PREFIX, LIB_NAME, DLL_SUFFIX)
+    }
+
+    #[test]
+    fn explicit_path_comes_first() {
+        with_env(&[(LIB_PATH_ENV, Some("from_env"))], || {
+            let exe = temp_dir("explicit").join("host");
+            fs::write(exe.with_file_name(lib_file_name()), b"").unwrap();
+
+            let path = search_game_lib(Some("explicit"), Some(exe));
+            assert_eq!(path, PathBuf::from("explicit"));
+        });
+    }
+
+    #[test]
+    fn environment_comes_before_the_exe_dir() {
+        with_env(&[(LIB_PATH_ENV, Some("from_env"))], || {
+            let exe = temp_dir("environment").join("host");
+            fs::write(exe.with_file_name(lib_file_name()), b"").unwrap();
+
+            assert_eq!(search_game_lib(None, Some(exe)), PathBuf::from("from_env"));
+        });
+    }
+
+    #[test]
+    fn exe_dir_comes_before_the_target_dir() {
+        let dir = temp_dir("exe_dir");
+        let exe = dir.join("host");
+        with_env(
+            &[(LIB_PATH_ENV, None), ("CARGO_TARGET_DIR", Some("custom_target"))],
+            || {
+                fs::write(dir.join(lib_file_name()), b"").unwrap();
+                assert_eq!(search_game_lib(None, Some(exe.clone())), dir.join(lib_file_name()));
+
+                // Only a library that's there counts.
+                fs::remove_file(dir.join(lib_file_name())).unwrap();
+                let profile = if cfg!(debug_assertions) {
+                    "debug"
+                } else {
+                    "release"
+                };
+                let expected = Path::new("custom_target").join(profile).join(lib_file_name());
+                assert_eq!(search_game_lib(None, Some(exe.clone())), expected);
+            },
+        );
+        with_env(&[(LIB_PATH_ENV, None), ("CARGO_TARGET_DIR", None)], || {
+            let path = search_game_lib(None, Some(exe));
+            assert!(path.starts_with("target"), "{}", path.display());
+        });
+    }
+
+    #[test]
+    fn removes_copies_left_by_exited_processes() {
+        let dir = temp_dir("stale_copies");
+        let copy = |pid: u32, copy_id: &str| {
+            format!("{}{}-{}-{}{}", DLL_PREFIX, LIB_NAME, pid, copy_id, DLL_SUFFIX)
+        };
+        // Beyond the largest pid any platform hands out.
+        let exited_pid = i32::MAX as u32;
+        let stale = [copy(exited_pid, "0"), copy(exited_pid, "12")];
+        let mut kept = vec![
+            copy(process::id(), "0"),
+            copy(exited_pid, "latest"),
+            lib_file_name(),
+            format!("{}-{}.ron", LIB_NAME, exited_pid),
+        ];
+        if cfg!(unix) {
+            // Init, running but not ours to signal unless we're root.
+            kept.push(copy(1, "0"));
+        }
+        for name in stale.iter().chain(&kept) {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        remove_stale_copies(&dir);
+
+        for name in &stale {
+            assert!(!dir.join(name).exists(), "{} wasn't removed", name);
+        }
+        for name in &kept {
+            assert!(dir.join(name).exists(), "{} was removed", name);
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod vulkan;
+pub mod software;
@@ -0,0 +1,687 @@
+use image;
+use image::png::PNGEncoder;
+use cgmath::{ElementWise, InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use std::error::Error;
+use std::collections::HashMap;
+use std::path::Path;
+use std::fs::File;
+use std::io::BufWriter;
+
+use renderer::{Blend, Camera, Cull, Depth, FrameShadows, Indices, Instance, Light, LightKind,
+               Material, RenderSettings, RenderState, Renderer, Semantic, ShadowMap, VertexData,
+               MAX_LIGHTS, MAX_PARAMETERS, SHADOW_BIAS, SHADOW_MAP_SIZE,
+               SHADOW_NORMAL_OFFSET, sort_blended_last};
+
+/// CPU rasterizer drawing into an in-memory RGBA8 framebuffer. It follows the
+/// Vulkan pipelines' fixed state (clockwise front faces, less-or-equal depth
+/// test, the same blend factors) so both backends produce the same picture.
+/// Wireframes are approximated by the pixels within one pixel of an edge.
+/// Lit models are shaded per pixel with the same Blinn-Phong terms and
+/// shadow lookups as `data/shaders/include/lighting.glsl`. Of a material only
+/// the render state is used, every model is shaded like the default shaders
+/// shade it.
+pub struct SoftwareRenderer {
+    width: u32,
+    height: u32,
+    color: Vec<u8>,
+    depth: Vec<f32>,
+    models: HashMap<u32, Model>,
+    /// Render state of each material.
+    materials: HashMap<u32, RenderState>,
+    draw_list: Vec<(u32, Instance, RenderState)>,
+    camera: Option<Camera>,
+    lights: Vec<Light>,
+    /// Shadow maps of the frame being drawn, depths row by row like the
+    /// framebuffer's.
+    shadows: FrameShadows,
+    shadow_maps: Vec<Vec<f32>>,
+    clear_color: [u8; 4],
+}
+
+struct Model {
+    vertices: VertexData,
+    /// Vertex indices, three per triangle. Models without indices get
+    /// `0..vertices.len()`.
+    indices: Vec<u32>,
+}
+
+/// Vertex after the perspective divide, in framebuffer coordinates.
+#[derive(Clone, Copy)]
+struct ScreenVertex {
+    x: f32,
+    y: f32,
+    z: f32,
+    inv_w: f32,
+    color: [f32; 4],
+    /// World space position and normal, for models with normals.
+    world: Vector3<f32>,
+    normal: Option<Vector3<f32>>,
+}
+
+impl SoftwareRenderer {
+    pub fn new(width: u32, height: u32) -> SoftwareRenderer {
+        let pixel_count = width as usize * height as usize;
+
+        SoftwareRenderer {
+            width,
+            height,
+            color: vec![0; pixel_count * 4],
+            depth: vec![1.0; pixel_count],
+            models: HashMap::new(),
+            materials: HashMap::new(),
+            draw_list: vec![],
+            camera: None,
+            lights: vec![],
+            shadows: FrameShadows::default(),
+            shadow_maps: vec![],
+            clear_color: [0, 0, 0, 0],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// RGBA8 pixels of the last finished frame, row by row from the top.
+    pub fn pixels(&self) -> &[u8] {
+        &self.color
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), Box<Error>> {
+        let file = BufWriter::new(File::create(path)?);
+        PNGEncoder::new(file).encode(
+            &self.color,
+            self.width,
+            self.height,
+            image::ColorType::RGBA(8),
+        )?;
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        for pixel in self.color.chunks_mut(4) {
+            pixel.copy_from_slice(&self.clear_color);
+        }
+        for depth in &mut self.depth {
+            *depth = 1.0;
+        }
+    }
+
+    /// Projection times view, like the vertex shader applies them.
+    fn view_projection(&self) -> Matrix4<f32> {
+        match self.camera {
+            Some(ref camera) => {
+                let aspect = self.width as f32 / self.height as f32;
+                camera.projection_matrix(aspect) * camera.view
+            }
+            None => Matrix4::identity(),
+        }
+    }
+
+    /// Colors are multiplied by the instance's tint, models without colors
+    /// are drawn in the tint the way a shader would default a missing color.
+    fn to_screen(
+        &self,
+        transform: &Matrix4<f32>,
+        instance: &Instance,
+        vertices: &VertexData,
+        index: usize,
+    ) -> Option<ScreenVertex> {
+        let local = Vector4::from(vertices.get(index, Semantic::Position)?);
+        let pos = transform * local;
+        let w = pos.w;
+        // There is no clipping, so anything behind the eye is dropped.
+        if w <= 0.0 {
+            return None;
+        }
+
+        let inv_w = 1.0 / w;
+        let mut color = vertices
+            .get(index, Semantic::Color)
+            .unwrap_or([1.0, 1.0, 1.0, 1.0]);
+        for (channel, tint) in color.iter_mut().zip(&instance.tint) {
+            *channel *= tint;
+        }
+        let normal = vertices.get(index, Semantic::Normal).map(|normal| {
+            let normal = instance.model * Vector4::new(normal[0], normal[1], normal[2], 0.0);
+            normal.truncate()
+        });
+        Some(ScreenVertex {
+            x: (pos.x * inv_w * 0.5 + 0.5) * self.width as f32,
+            y: (pos.y * inv_w * 0.5 + 0.5) * self.height as f32,
+            z: pos.z * inv_w,
+            inv_w,
+            color,
+            world: (instance.model * local).truncate(),
+            normal,
+        })
+    }
+
+    fn draw_triangle(
+        &mut self,
+        a: ScreenVertex,
+        b: ScreenVertex,
+        c: ScreenVertex,
+        state: &RenderState,
+        eye: Vector3<f32>,
+        receive_shadows: bool,
+    ) {
+        // Clockwise triangles have a positive area with y pointing down, the
+        // rest are back faces.
+        let front_facing = edge(&a, &b, c.x, c.y) > 0.0;
+        let culled = match state.cull {
+            Cull::Back => !front_facing,
+            Cull::Front => front_facing,
+            Cull::None => false,
+        };
+        if culled {
+            return;
+        }
+        // Back faces that are drawn are wound the other way round so the
+        // edge functions below stay positive inside.
+        let (b, c) = if front_facing { (b, c) } else { (c, b) };
+        let area = edge(&a, &b, c.x, c.y);
+        if area <= 0.0 {
+            return;
+        }
+        let edge_lengths = [length(&b, &c), length(&c, &a), length(&a, &b)];
+
+        let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+        let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as u32;
+        let max_x = (a.x.max(b.x).max(c.x).ceil() as u32).min(self.width);
+        let max_y = (a.y.max(b.y).max(c.y).ceil() as u32).min(self.height);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let px = x as f32 + 0.5;
+                let py = y as f32 + 0.5;
+
+                let wa = edge(&b, &c, px, py) / area;
+                let wb = edge(&c, &a, px, py) / area;
+                let wc = edge(&a, &b, px, py) / area;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                if state.wireframe {
+                    let distance = (wa * area / edge_lengths[0])
+                        .min(wb * area / edge_lengths[1])
+                        .min(wc * area / edge_lengths[2]);
+                    if distance >= 1.0 {
+                        continue;
+                    }
+                }
+
+                let z = wa * a.z + wb * b.z + wc * c.z;
+                let index = (y * self.width + x) as usize;
+                if !(0.0..=1.0).contains(&z) {
+                    continue;
+                }
+                if state.depth != Depth::Off && z > self.depth[index] {
+                    continue;
+                }
+                if state.depth == Depth::TestAndWrite {
+                    self.depth[index] = z;
+                }
+
+                // Colors are interpolated perspective correctly, like the GPU does.
+                let inv_w = wa * a.inv_w + wb * b.inv_w + wc * c.inv_w;
+                let wa = wa * a.inv_w / inv_w;
+                let wb = wb * b.inv_w / inv_w;
+                let wc = wc * c.inv_w / inv_w;
+                let mut color = [0.0; 4];
+                for (channel, value) in color.iter_mut().enumerate() {
+                    *value = wa * a.color[channel] + wb * b.color[channel] + wc * c.color[channel];
+                }
+                if let (Some(na), Some(nb), Some(nc)) = (a.normal, b.normal, c.normal) {
+                    if !self.lights.is_empty() {
+                        let position = a.world * wa + b.world * wb + c.world * wc;
+                        let normal = (na * wa + nb * wb + nc * wc).normalize();
+                        let mut visibility = [1.0; MAX_LIGHTS];
+                        if receive_shadows {
+                            for (light, visible) in
+                                visibility.iter_mut().enumerate().take(self.lights.len())
+                            {
+                                *visible = self.visibility(light, position, normal);
+                            }
+                        }
+                        let lit = shade(&self.lights, &visibility, eye, &color, position, normal);
+                        color[..3].copy_from_slice(&lit);
+                    }
+                }
+                for channel in &mut color {
+                    *channel = channel.clamp(0.0, 1.0);
+                }
+                if state.blend == Blend::Alpha {
+                    let alpha = color[3];
+                    let dst = &self.color[index * 4..index * 4 + 4];
+                    for (channel, (value, &dst)) in color.iter_mut().zip(dst).enumerate() {
+                        // Color is weighted by the source alpha, alpha itself
+                        // is added on top of what's left.
+                        let src = if channel == 3 { 1.0 } else { alpha } * *value;
+                        *value = src + dst as f32 / 255.0 * (1.0 - alpha);
+                    }
+                }
+                let pixel = &mut self.color[index * 4..index * 4 + 4];
+                for (dst, value) in pixel.iter_mut().zip(&color) {
+                    *dst = (value * 255.0 + 0.5) as u8;
+                }
+            }
+        }
+    }
+
+    /// Draws the depth of every shadow casting instance as seen from `map`.
+    fn render_shadow_map(
+        &self,
+        map: &ShadowMap,
+        draw_list: &[(u32, Instance, RenderState)],
+    ) -> Vec<f32> {
+        let size = SHADOW_MAP_SIZE as f32;
+        let mut depth = vec![1.0; (SHADOW_MAP_SIZE * SHADOW_MAP_SIZE) as usize];
+        for &(id, ref instance, _) in draw_list {
+            if !instance.cast_shadows {
+                continue;
+            }
+            let transform = map.view_projection * instance.model;
+            let model = &self.models[&id];
+            let to_map = |index: u32| {
+                let pos = model.vertices.get(index as usize, Semantic::Position)?;
+                let pos = transform * Vector4::from(pos);
+                if pos.w <= 0.0 {
+                    return None;
+                }
+                Some([
+                    (pos.x / pos.w * 0.5 + 0.5) * size,
+                    (pos.y / pos.w * 0.5 + 0.5) * size,
+                    pos.z / pos.w,
+                ])
+            };
+            for triangle in model.indices.chunks(3).filter(|triangle| triangle.len() == 3) {
+                let corners = (to_map(triangle[0]), to_map(triangle[1]), to_map(triangle[2]));
+                if let (Some(a), Some(b), Some(c)) = corners {
+                    rasterize_depth(&mut depth, SHADOW_MAP_SIZE, a, b, c);
+                }
+            }
+        }
+        depth
+    }
+
+    /// Fraction of `light` reaching `position` past the shadow casters,
+    /// filtered over the 3x3 nearest texels.
+    fn visibility(&self, light: usize, position: Vector3<f32>, normal: Vector3<f32>) -> f32 {
+        let (first, count) = self.shadows.lights[light];
+        let depth = match self.camera {
+            Some(ref camera) => -(camera.view * position.extend(1.0)).z,
+            None => 0.0,
+        };
+        // The first cascade reaching far enough, spot lights have one map.
+        let index = (first..first + count).find(|&index| depth <= self.shadows.maps[index].split);
+        let index = match index {
+            Some(index) => index,
+            None => return 1.0,
+        };
+
+        let offset = position + normal * SHADOW_NORMAL_OFFSET;
+        let pos = self.shadows.maps[index].view_projection * offset.extend(1.0);
+        let x = pos.x / pos.w * 0.5 + 0.5;
+        let y = pos.y / pos.w * 0.5 + 0.5;
+        let z = pos.z / pos.w;
+        if pos.w <= 0.0 || !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) || z > 1.0 {
+            return 1.0;
+        }
+
+        let size = SHADOW_MAP_SIZE as i32;
+        let map = &self.shadow_maps[index];
+        let texel_x = (x * size as f32) as i32;
+        let texel_y = (y * size as f32) as i32;
+        let mut lit = 0.0;
+        for dy in -1..2 {
+            for dx in -1..2 {
+                let sample_x = (texel_x + dx).clamp(0, size - 1);
+                let sample_y = (texel_y + dy).clamp(0, size - 1);
+                if z - SHADOW_BIAS <= map[(sample_y * size + sample_x) as usize] {
+                    lit += 1.0;
+                }
+            }
+        }
+        lit / 9.0
+    }
+}
+
+/// Writes the nearest depth of a triangle, given in texels and depth, into
+/// a square depth map. Both windings are drawn.
+fn rasterize_depth(depth: &mut [f32], size: u32, a: [f32; 3], b: [f32; 3], c: [f32; 3]) {
+    let edge = |a: [f32; 3], b: [f32; 3], px: f32, py: f32| {
+        (b[0] - a[0]) * (py - a[1]) - (b[1] - a[1]) * (px - a[0])
+    };
+    let area = edge(a, b, c[0], c[1]);
+    if area == 0.0 {
+        return;
+    }
+
+    let min_x = a[0].min(b[0]).min(c[0]).floor().max(0.0) as u32;
+    let min_y = a[1].min(b[1]).min(c[1]).floor().max(0.0) as u32;
+    let max_x = (a[0].max(b[0]).max(c[0]).ceil().max(0.0) as u32).min(size);
+    let max_y = (a[1].max(b[1]).max(c[1]).ceil().max(0.0) as u32).min(size);
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let px = x as f32 + 0.5;
+            let py = y as f32 + 0.5;
+            let wa = edge(b, c, px, py) / area;
+            let wb = edge(c, a, px, py) / area;
+            let wc = edge(a, b, px, py) / area;
+            if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                continue;
+            }
+
+            let z = wa * a[2] + wb * b[2] + wc * c[2];
+            let index = (y * size + x) as usize;
+            if z >= 0.0 && z < depth[index] {
+                depth[index] = z;
+            }
+        }
+    }
+}
+
+/// Blinn-Phong lighting of a surface point, returning its color.
+fn shade(
+    lights: &[Light],
+    visibility: &[f32],
+    eye: Vector3<f32>,
+    albedo: &[f32; 4],
+    position: Vector3<f32>,
+    normal: Vector3<f32>,
+) -> [f32; 3] {
+    let albedo = Vector3::new(albedo[0], albedo[1], albedo[2]);
+    let view = (eye - position).normalize();
+    let mut result = Vector3::new(0.0, 0.0, 0.0);
+    for (light, &visible) in lights.iter().zip(visibility) {
+        let radiance = Vector3::from(light.color) * light.intensity;
+        let (to_light, attenuation) = match light.kind {
+            LightKind::Ambient => {
+                result += albedo.mul_element_wise(radiance);
+                continue;
+            }
+            LightKind::Directional => (-light.direction, 1.0),
+            LightKind::Point | LightKind::Spot { .. } => {
+                let offset = light.position - position;
+                let distance = offset.magnitude();
+                let falloff =
+                    (1.0 - distance * distance / (light.range * light.range)).clamp(0.0, 1.0);
+                let mut attenuation = falloff * falloff;
+                if let Some((cos_inner, cos_outer)) = light.kind.spot_cosines() {
+                    let cos_angle = (-offset / distance).dot(light.direction);
+                    attenuation *= smoothstep(cos_outer, cos_inner, cos_angle);
+                }
+                (offset / distance, attenuation)
+            }
+        };
+        let diffuse = normal.dot(to_light).max(0.0);
+        let specular = if diffuse > 0.0 {
+            SPECULAR * normal.dot((to_light + view).normalize()).max(0.0).powf(SHININESS)
+        } else {
+            0.0
+        };
+        let reflected = albedo * diffuse + Vector3::new(specular, specular, specular);
+        result += reflected.mul_element_wise(radiance) * attenuation * visible;
+    }
+    result.into()
+}
+
+/// Shininess exponent and strength of the specular highlight.
+const SHININESS: f32 = 32.0;
+const SPECULAR: f32 = 0.25;
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    // Equal edges would divide zero by zero right at the edge.
+    if edge0 == edge1 {
+        return if x > edge0 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn edge(a: &ScreenVertex, b: &ScreenVertex, px: f32, py: f32) -> f32 {
+    (b.x - a.x) * (py - a.y) - (b.y - a.y) * (px - a.x)
+}
+
+fn length(a: &ScreenVertex, b: &ScreenVertex) -> f32 {
+    ((b.x - a.x) * (b.x - a.x) + (b.y - a.y) * (b.y - a.y)).sqrt()
+}
+
+impl Renderer for SoftwareRenderer {
+    fn begin_frame(&mut self) -> Result<(), Box<Error>> {
+        self.draw_list.clear();
+        self.camera = None;
+        self.lights.clear();
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> Result<(), Box<Error>> {
+        // Like a minimized window, there's nothing to draw into and no
+        // aspect ratio to project with.
+        if self.width == 0 || self.height == 0 {
+            return Ok(());
+        }
+
+        self.clear();
+
+        let view_projection = self.view_projection();
+        let eye = match self.camera {
+            Some(ref camera) => camera.position(),
+            None => Vector3::new(0.0, 0.0, 0.0),
+        };
+        let mut draw_list = self.draw_list.clone();
+        sort_blended_last(&mut draw_list, eye, |&(_, ref instance, state)| {
+            (state.blend, instance)
+        });
+
+        let aspect = self.width as f32 / self.height as f32;
+        self.shadows = FrameShadows::new(&self.lights, self.camera.as_ref(), aspect);
+        self.shadow_maps = self.shadows
+            .maps
+            .iter()
+            .map(|map| self.render_shadow_map(map, &draw_list))
+            .collect();
+        for (id, instance, state) in draw_list {
+            let transform = view_projection * instance.model;
+            let triangles: Vec<ScreenVertex> = {
+                let model = &self.models[&id];
+                let vertices = &model.vertices;
+                // Triangles with a vertex behind the eye are dropped whole.
+                model
+                    .indices
+                    .chunks(3)
+                    .filter(|triangle| triangle.len() == 3)
+                    .filter_map(|triangle| {
+                        let a =
+                            self.to_screen(&transform, &instance, vertices, triangle[0] as usize)?;
+                        let b =
+                            self.to_screen(&transform, &instance, vertices, triangle[1] as usize)?;
+                        let c =
+                            self.to_screen(&transform, &instance, vertices, triangle[2] as usize)?;
+                        Some(vec![a, b, c])
+                    })
+                    .flatten()
+                    .collect()
+            };
+
+            for triangle in triangles.chunks(3) {
+                self.draw_triangle(
+                    triangle[0],
+                    triangle[1],
+                    triangle[2],
+                    &state,
+                    eye,
+                    instance.receive_shadows,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_camera(&mut self, camera: &Camera) -> Result<(), Box<Error>> {
+        self.camera = Some(*camera);
+        Ok(())
+    }
+
+    fn set_lights(&mut self, lights: &[Light]) -> Result<(), Box<Error>> {
+        self.lights = lights.iter().take(MAX_LIGHTS).cloned().collect();
+        Ok(())
+    }
+
+    fn update_model(
+        &mut self,
+        id: u32,
+        vertices: &VertexData,
+        indices: Indices,
+    ) -> Result<(), Box<Error>> {
+        let indices: Vec<u32> = match indices {
+            Indices::None => (0..vertices.len() as u32).collect(),
+            Indices::U16(indices) => indices.iter().map(|&index| index as u32).collect(),
+            Indices::U32(indices) => indices.to_vec(),
+        };
+        if let Some(&index) = indices.iter().find(|&&index| index as usize >= vertices.len()) {
+            return Err(format!(
+                "Model {} has index {} but only {} vertices",
+                id,
+                index,
+                vertices.len()
+            ).into());
+        }
+
+        self.models.insert(
+            id,
+            Model {
+                vertices: vertices.clone(),
+                indices,
+            },
+        );
+        Ok(())
+    }
+
+    fn update_material(&mut self, id: u32, material: &Material) -> Result<(), Box<Error>> {
+        if material.parameters.len() > MAX_PARAMETERS {
+            return Err(format!(
+                "Material {} has {} parameters, at most {} are supported",
+                id,
+                material.parameters.len(),
+                MAX_PARAMETERS
+            ).into());
+        }
+        self.materials.insert(id, material.render_state);
+        Ok(())
+    }
+
+    fn draw_model(
+        &mut self,
+        id: u32,
+        material: u32,
+        instance: &Instance,
+    ) -> Result<(), Box<Error>> {
+        if !self.models.contains_key(&id) {
+            return Err(format!("Model {} isn't loaded", id).into());
+        }
+        let state = *self.materials
+            .get(&material)
+            .ok_or_else(|| format!("Material {} isn't loaded", material))?;
+        self.draw_list.push((id, *instance, state));
+        Ok(())
+    }
+
+    fn update_resolution(&mut self, width: u32, height: u32) -> Result<(), Box<Error>> {
+        let pixel_count = width as usize * height as usize;
+        self.width = width;
+        self.height = height;
+        self.color = vec![0; pixel_count * 4];
+        self.depth = vec![1.0; pixel_count];
+        Ok(())
+    }
+
+    /// Frames are always drawn with one sample at the full resolution and
+    /// aren't presented, so there is nothing to change.
+    fn change_settings(&mut self, _settings: &RenderSettings) -> Result<(), Box<Error>> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use renderer::Vertex;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+
+    /// Draws one clockwise triangle covering the whole 2x2 framebuffer at
+    /// the given depth and w, with no camera so positions are clip space.
+    fn draw(renderer: &mut SoftwareRenderer, z: f32, w: f32, color: [f32; 4], state: RenderState) {
+        let id = renderer.models.len() as u32;
+        let corners = [[-1.0, -1.0], [3.0, -1.0], [-1.0, 3.0]];
+        let vertices: Vec<_> = corners
+            .iter()
+            .map(|corner| Vertex::new([corner[0] * w, corner[1] * w, z * w, w], color))
+            .collect();
+        renderer
+            .update_model(id, &VertexData::from_vertices(&vertices), Indices::None)
+            .unwrap();
+        renderer.materials.insert(id, state);
+        renderer
+            .draw_model(id, id, &Instance::new(Matrix4::identity()))
+            .unwrap();
+    }
+
+    fn render(draws: &[(f32, f32, [f32; 4], RenderState)]) -> Vec<u8> {
+        let mut renderer = SoftwareRenderer::new(2, 2);
+        renderer.begin_frame().unwrap();
+        for &(z, w, color, state) in draws {
+            draw(&mut renderer, z, w, color, state);
+        }
+        renderer.end_frame().unwrap();
+        renderer.pixels()[..4].to_vec()
+    }
+
+    #[test]
+    fn drops_triangles_outside_the_view_volume() {
+        let opaque = RenderState::default();
+
+        assert_eq!(render(&[(0.5, 1.0, RED, opaque)]), vec![255, 0, 0, 255]);
+        // In front of the near plane, beyond the far plane and behind the eye.
+        assert_eq!(render(&[(-0.5, 1.0, RED, opaque)]), vec![0, 0, 0, 0]);
+        assert_eq!(render(&[(1.5, 1.0, RED, opaque)]), vec![0, 0, 0, 0]);
+        assert_eq!(render(&[(0.5, -1.0, RED, opaque)]), vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn keeps_the_nearest_fragment() {
+        let opaque = RenderState::default();
+        let near = (0.25, 1.0, RED, opaque);
+        let far = (0.75, 1.0, GREEN, opaque);
+
+        assert_eq!(render(&[near, far]), vec![255, 0, 0, 255]);
+        assert_eq!(render(&[far, near]), vec![255, 0, 0, 255]);
+
+        let depth_off = RenderState {
+            depth: Depth::Off,
+            ..opaque
+        };
+        assert_eq!(render(&[near, (0.75, 1.0, GREEN, depth_off)]), vec![0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn blends_by_source_alpha() {
+        let opaque = RenderState::default();
+        let blended = RenderState {
+            blend: Blend::Alpha,
+            ..opaque
+        };
+        let glass = (0.25, 1.0, [0.0, 1.0, 0.0, 0.5], blended);
+
+        assert_eq!(render(&[glass, (0.75, 1.0, RED, opaque)]), vec![128, 128, 0, 255]);
+    }
+}
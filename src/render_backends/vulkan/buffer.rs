@@ -0,0 +1,825 @@
+use ash::vk;
+use ash::version::{DeviceV1_0, InstanceV1_0};
+
+use std::ptr;
+use std::error::Error;
+
+use super::VK_INSTANCE;
+use super::VkDevice;
+use super::find_memorytype_index;
+
+/// Size of the device memory blocks buffers are sub-allocated from. Buffers
+/// larger than this get a block of their own.
+const BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Sub-allocates buffers from a few large blocks of device memory per memory
+/// type, since drivers only allow a limited number of `allocate_memory`
+/// calls. Buffers are referred to by index, which stays the same for the
+/// buffer's lifetime, even when `defragment` moves it.
+pub struct Allocator {
+    buffers: Vec<Option<Buffer>>,
+    /// Blocks per memory type. Released blocks leave a `None` behind so the
+    /// block indices in allocations stay valid.
+    blocks: Vec<Vec<Option<Block>>>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// Queue families buffers are shared between, see `new`.
+    queue_families: Vec<u32>,
+}
+
+pub struct Buffer {
+    pub buf: vk::Buffer,
+    pub size: u64,
+    usage: vk::BufferUsageFlags,
+    allocation: Allocation,
+    /// Start of the buffer in the block's persistent mapping, null unless
+    /// the memory is host visible.
+    mapped: *mut u8,
+}
+
+impl Buffer {
+    /// Pointer to the buffer's contents if its memory is host visible. Stays
+    /// valid until the buffer is freed or moved.
+    pub fn mapped(&self) -> Option<*mut u8> {
+        if self.mapped.is_null() {
+            None
+        } else {
+            Some(self.mapped)
+        }
+    }
+
+    pub fn usage(&self) -> vk::BufferUsageFlags {
+        self.usage
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Allocation {
+    memory_type: u32,
+    block: usize,
+    offset: u64,
+    size: u64,
+    alignment: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Range {
+    offset: u64,
+    size: u64,
+}
+
+/// First fit free list handing out ranges of `[0, size)`.
+pub struct FreeList {
+    size: u64,
+    /// Free ranges sorted by offset. Neighbouring ranges are always merged.
+    free: Vec<Range>,
+}
+
+struct Block {
+    memory: vk::DeviceMemory,
+    ranges: FreeList,
+    allocation_count: u32,
+    /// The whole block stays mapped if it is host visible, mapping the same
+    /// memory twice isn't allowed and buffers share blocks.
+    mapped: *mut u8,
+}
+
+/// Memory use of one memory heap, see `Allocator::heap_stats`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    pub block_count: u32,
+    pub allocation_count: u32,
+    /// Device memory allocated for blocks.
+    pub reserved_bytes: u64,
+    /// The part of `reserved_bytes` used by buffers, alignment included.
+    pub used_bytes: u64,
+}
+
+impl FreeList {
+    pub fn new(size: u64) -> FreeList {
+        FreeList {
+            size,
+            free: vec![Range { offset: 0, size }],
+        }
+    }
+
+    /// Padding in front of the aligned offset stays free.
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        self.find(size, alignment).map(|(i, offset)| {
+            let range = self.free[i];
+            let end = range.offset + range.size;
+
+            let mut remaining = vec![];
+            if offset > range.offset {
+                remaining.push(Range {
+                    offset: range.offset,
+                    size: offset - range.offset,
+                });
+            }
+            if offset + size < end {
+                remaining.push(Range {
+                    offset: offset + size,
+                    size: end - offset - size,
+                });
+            }
+            self.free.splice(i..i + 1, remaining);
+
+            offset
+        })
+    }
+
+    pub fn can_allocate(&self, size: u64, alignment: u64) -> bool {
+        self.find(size, alignment).is_some()
+    }
+
+    pub fn free(&mut self, offset: u64, size: u64) {
+        let i = self.free
+            .iter()
+            .position(|range| range.offset > offset)
+            .unwrap_or(self.free.len());
+        self.free.insert(i, Range { offset, size });
+
+        if i + 1 < self.free.len() && offset + size == self.free[i + 1].offset {
+            self.free[i].size += self.free[i + 1].size;
+            self.free.remove(i + 1);
+        }
+        if i > 0 && self.free[i - 1].offset + self.free[i - 1].size == offset {
+            self.free[i - 1].size += self.free[i].size;
+            self.free.remove(i);
+        }
+    }
+
+    /// Makes the list cover `[0, size)`, the new space at the end is free.
+    pub fn grow(&mut self, size: u64) {
+        if size > self.size {
+            let old_size = self.size;
+            self.size = size;
+            self.free(old_size, size - old_size);
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn used(&self) -> u64 {
+        self.size - self.free.iter().map(|range| range.size).sum::<u64>()
+    }
+
+    /// First free range `size` bytes fit in and the aligned offset in it.
+    fn find(&self, size: u64, alignment: u64) -> Option<(usize, u64)> {
+        self.free.iter().enumerate().find_map(|(i, range)| {
+            let offset = align_up(range.offset, alignment);
+            if offset + size <= range.offset + range.size {
+                Some((i, offset))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl Block {
+    fn new(
+        device: &VkDevice,
+        memory_type: u32,
+        size: u64,
+        host_visible: bool,
+    ) -> Result<Block, Box<Error>> {
+        let allocate_info = vk::MemoryAllocateInfo {
+            s_type: vk::StructureType::MemoryAllocateInfo,
+            p_next: ptr::null(),
+            allocation_size: size,
+            memory_type_index: memory_type,
+        };
+        let memory = unsafe { device.allocate_memory(&allocate_info, None)? };
+
+        let mapped = if host_visible {
+            let mapped = unsafe { device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty()) };
+            match mapped {
+                Ok(mapped) => mapped as *mut u8,
+                Err(err) => {
+                    unsafe { device.free_memory(memory, None) };
+                    return Err(Box::new(err));
+                }
+            }
+        } else {
+            ptr::null_mut()
+        };
+
+        Ok(Block {
+            memory,
+            ranges: FreeList::new(size),
+            allocation_count: 0,
+            mapped,
+        })
+    }
+
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let offset = self.ranges.allocate(size, alignment)?;
+        self.allocation_count += 1;
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: u64, size: u64) {
+        self.ranges.free(offset, size);
+        self.allocation_count -= 1;
+    }
+
+    fn size(&self) -> u64 {
+        self.ranges.size()
+    }
+
+    fn used(&self) -> u64 {
+        self.ranges.used()
+    }
+
+    unsafe fn destroy(&self, device: &VkDevice) {
+        if !self.mapped.is_null() {
+            device.unmap_memory(self.memory);
+        }
+        device.free_memory(self.memory, None);
+    }
+}
+
+impl Allocator {
+    /// Buffers are created for concurrent use by all `queue_families`, so
+    /// uploads on a transfer queue need no ownership transfers.
+    pub fn new(physical_device: vk::PhysicalDevice, queue_families: &[u32]) -> Allocator {
+        let memory_properties = VK_INSTANCE.get_physical_device_memory_properties(physical_device);
+
+        Allocator {
+            buffers: vec![],
+            blocks: (0..memory_properties.memory_type_count)
+                .map(|_| vec![])
+                .collect(),
+            memory_properties,
+            queue_families: queue_families.to_vec(),
+        }
+    }
+
+    pub fn buffer(&self, index: usize) -> &Buffer {
+        self.buffers[index]
+            .as_ref()
+            .expect("Buffer has been freed")
+    }
+
+    pub fn create_buffer(
+        &mut self,
+        device: &VkDevice,
+        buffer_size: u64,
+        usage: vk::BufferUsageFlags,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<usize, Box<Error>> {
+        let buffer = new_buffer(device, buffer_size, usage, &self.queue_families)?;
+
+        let memory_requirements = device.get_buffer_memory_requirements(buffer);
+        let allocation = find_memorytype_index(
+            &memory_requirements,
+            &self.memory_properties,
+            properties,
+        ).map_err(|err| err.into())
+            .and_then(|memory_type| {
+                self.allocate(
+                    device,
+                    memory_type,
+                    memory_requirements.size,
+                    memory_requirements.alignment,
+                )
+            });
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { device.destroy_buffer(buffer, None) };
+                return Err(err);
+            }
+        };
+
+        let buffer = self.bind(device, buffer, buffer_size, usage, allocation)?;
+
+        match self.buffers.iter().position(|slot| slot.is_none()) {
+            Some(index) => {
+                self.buffers[index] = Some(buffer);
+                Ok(index)
+            }
+            None => {
+                self.buffers.push(Some(buffer));
+                Ok(self.buffers.len() - 1)
+            }
+        }
+    }
+
+    /// Destroys the buffer and returns its memory to the block. The GPU must
+    /// be done with it.
+    pub fn free_buffer(&mut self, device: &VkDevice, index: usize) {
+        if let Some(buffer) = self.buffers[index].take() {
+            unsafe { device.destroy_buffer(buffer.buf, None) };
+            self.free(buffer.allocation);
+        }
+    }
+
+    /// Returns blocks without any buffers in them to the driver.
+    pub fn release_empty_blocks(&mut self, device: &VkDevice) {
+        for blocks in &mut self.blocks {
+            for block in take_empty_blocks(blocks) {
+                unsafe { block.destroy(device) };
+            }
+        }
+    }
+
+    /// Defragmentation hook. For every memory type with more than one block,
+    /// tries to move all buffers out of the emptiest block into the others
+    /// and releases it. `copy` gets the old and the new buffer and has to have
+    /// copied the contents when it returns; host visible buffers are copied
+    /// by the allocator. Device local buffers need both transfer usages to be
+    /// moved. The GPU must not be using any buffer while this runs. Returns
+    /// how many buffers were moved.
+    pub fn defragment<F>(&mut self, device: &VkDevice, mut copy: F) -> Result<u32, Box<Error>>
+    where
+        F: FnMut(&Buffer, &Buffer) -> Result<(), Box<Error>>,
+    {
+        let mut moved = 0;
+
+        for memory_type in 0..self.blocks.len() as u32 {
+            let emptiest = match defragment_source(&self.blocks[memory_type as usize]) {
+                Some(block) => block,
+                None => continue,
+            };
+
+            let host_visible = self.is_host_visible(memory_type);
+            let transfer_usage = vk::BUFFER_USAGE_TRANSFER_SRC_BIT | vk::BUFFER_USAGE_TRANSFER_DST_BIT;
+
+            let indices: Vec<usize> = self.buffers
+                .iter()
+                .enumerate()
+                .filter_map(|(i, slot)| slot.as_ref().map(|buffer| (i, buffer)))
+                .filter(|&(_, buffer)| {
+                    buffer.allocation.memory_type == memory_type
+                        && buffer.allocation.block == emptiest
+                })
+                .map(|(i, _)| i)
+                .collect();
+
+            let movable = indices.iter().all(|&i| {
+                host_visible || self.buffer(i).usage.subset(transfer_usage)
+            });
+            if !movable {
+                continue;
+            }
+
+            for index in indices {
+                let (size, usage, old_allocation) = {
+                    let buffer = self.buffer(index);
+                    (buffer.size, buffer.usage, buffer.allocation)
+                };
+
+                let allocation = match allocate_in_blocks(
+                    &mut self.blocks[memory_type as usize],
+                    memory_type,
+                    old_allocation.size,
+                    old_allocation.alignment,
+                    Some(emptiest),
+                ) {
+                    Some(allocation) => allocation,
+                    None => break,
+                };
+
+                let new_buffer = new_buffer(device, size, usage, &self.queue_families)
+                    .and_then(|buffer| self.bind(device, buffer, size, usage, allocation));
+                let new_buffer = match new_buffer {
+                    Ok(buffer) => buffer,
+                    Err(err) => {
+                        self.free(allocation);
+                        return Err(err);
+                    }
+                };
+
+                let copied = if host_visible {
+                    unsafe {
+                        ptr::copy_nonoverlapping(
+                            self.buffer(index).mapped,
+                            new_buffer.mapped,
+                            size as usize,
+                        )
+                    };
+                    Ok(())
+                } else {
+                    copy(self.buffer(index), &new_buffer)
+                };
+                if let Err(err) = copied {
+                    unsafe { device.destroy_buffer(new_buffer.buf, None) };
+                    self.free(allocation);
+                    return Err(err);
+                }
+
+                let old_buffer = self.buffers[index].take().unwrap();
+                unsafe { device.destroy_buffer(old_buffer.buf, None) };
+                self.free(old_buffer.allocation);
+                self.buffers[index] = Some(new_buffer);
+                moved += 1;
+            }
+        }
+
+        self.release_empty_blocks(device);
+
+        Ok(moved)
+    }
+
+    /// Usage per memory heap, indexed by heap index.
+    pub fn heap_stats(&self) -> Vec<HeapStats> {
+        let mut stats =
+            vec![HeapStats::default(); self.memory_properties.memory_heap_count as usize];
+
+        for (memory_type, blocks) in self.blocks.iter().enumerate() {
+            let heap = self.memory_properties.memory_types[memory_type].heap_index as usize;
+            for block in blocks.iter().filter_map(|slot| slot.as_ref()) {
+                stats[heap].block_count += 1;
+                stats[heap].allocation_count += block.allocation_count;
+                stats[heap].reserved_bytes += block.size();
+                stats[heap].used_bytes += block.used();
+            }
+        }
+
+        stats
+    }
+
+    /// Frees every buffer and block. Nothing may be in use by the GPU.
+    pub unsafe fn destroy(&mut self, device: &VkDevice) {
+        for buffer in self.buffers.drain(..).flatten() {
+            device.destroy_buffer(buffer.buf, None);
+        }
+        for blocks in &mut self.blocks {
+            for block in blocks.drain(..).flatten() {
+                block.destroy(device);
+            }
+        }
+    }
+
+    fn is_host_visible(&self, memory_type: u32) -> bool {
+        self.memory_properties.memory_types[memory_type as usize]
+            .property_flags
+            .subset(vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT)
+    }
+
+    fn allocate(
+        &mut self,
+        device: &VkDevice,
+        memory_type: u32,
+        size: u64,
+        alignment: u64,
+    ) -> Result<Allocation, Box<Error>> {
+        let blocks = &mut self.blocks[memory_type as usize];
+        if let Some(allocation) = allocate_in_blocks(blocks, memory_type, size, alignment, None) {
+            return Ok(allocation);
+        }
+
+        let host_visible = self.is_host_visible(memory_type);
+        let mut block = Block::new(device, memory_type, size.max(BLOCK_SIZE), host_visible)?;
+        let offset = block.allocate(size, alignment).unwrap();
+
+        let blocks = &mut self.blocks[memory_type as usize];
+        let index = match blocks.iter().position(|slot| slot.is_none()) {
+            Some(index) => {
+                blocks[index] = Some(block);
+                index
+            }
+            None => {
+                blocks.push(Some(block));
+                blocks.len() - 1
+            }
+        };
+
+        Ok(Allocation {
+            memory_type,
+            block: index,
+            offset,
+            size,
+            alignment,
+        })
+    }
+
+    fn free(&mut self, allocation: Allocation) {
+        if let Some(ref mut block) = self.blocks[allocation.memory_type as usize][allocation.block] {
+            block.free(allocation.offset, allocation.size);
+        }
+    }
+
+    fn bind(
+        &mut self,
+        device: &VkDevice,
+        buffer: vk::Buffer,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        allocation: Allocation,
+    ) -> Result<Buffer, Box<Error>> {
+        let (memory, mapped) = {
+            let block = self.blocks[allocation.memory_type as usize][allocation.block]
+                .as_ref()
+                .unwrap();
+            let mapped = if block.mapped.is_null() {
+                ptr::null_mut()
+            } else {
+                unsafe { block.mapped.offset(allocation.offset as isize) }
+            };
+            (block.memory, mapped)
+        };
+
+        if let Err(err) = unsafe { device.bind_buffer_memory(buffer, memory, allocation.offset) } {
+            unsafe { device.destroy_buffer(buffer, None) };
+            self.free(allocation);
+            return Err(Box::new(err));
+        }
+
+        Ok(Buffer {
+            buf: buffer,
+            size,
+            usage,
+            allocation,
+            mapped,
+        })
+    }
+}
+
+fn new_buffer(
+    device: &VkDevice,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+    queue_families: &[u32],
+) -> Result<vk::Buffer, Box<Error>> {
+    let sharing_mode = if queue_families.len() > 1 {
+        vk::SharingMode::Concurrent
+    } else {
+        vk::SharingMode::Exclusive
+    };
+    let buffer_info = vk::BufferCreateInfo {
+        s_type: vk::StructureType::BufferCreateInfo,
+        p_next: ptr::null(),
+        flags: vk::BufferCreateFlags::empty(),
+        size,
+        usage,
+        sharing_mode,
+        queue_family_index_count: queue_families.len() as u32,
+        p_queue_family_indices: queue_families.as_ptr(),
+    };
+
+    let buffer = unsafe { device.create_buffer(&buffer_info, None)? };
+
+    Ok(buffer)
+}
+
+/// Allocates from the first of the `memory_type` blocks with room, other
+/// than `skip_block`.
+fn allocate_in_blocks(
+    blocks: &mut [Option<Block>],
+    memory_type: u32,
+    size: u64,
+    alignment: u64,
+    skip_block: Option<usize>,
+) -> Option<Allocation> {
+    for (index, slot) in blocks.iter_mut().enumerate() {
+        if Some(index) == skip_block {
+            continue;
+        }
+        if let Some(ref mut block) = *slot {
+            if let Some(offset) = block.allocate(size, alignment) {
+                return Some(Allocation {
+                    memory_type,
+                    block: index,
+                    offset,
+                    size,
+                    alignment,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// The block `defragment` tries to empty: the least used one still holding
+/// buffers, as long as there is another block to move them to.
+fn defragment_source(blocks: &[Option<Block>]) -> Option<usize> {
+    let live_blocks = blocks.iter().filter(|slot| slot.is_some()).count();
+    if live_blocks < 2 {
+        return None;
+    }
+
+    blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(i, slot)| slot.as_ref().map(|block| (i, block)))
+        .filter(|&(_, block)| block.allocation_count > 0)
+        .min_by_key(|&(_, block)| block.used())
+        .map(|(i, _)| i)
+}
+
+/// Takes the blocks without allocations out of `blocks`, leaving `None`
+/// in their slots.
+fn take_empty_blocks(blocks: &mut [Option<Block>]) -> Vec<Block> {
+    blocks
+        .iter_mut()
+        .filter(|slot| slot.as_ref().is_some_and(|block| block.allocation_count == 0))
+        .map(|slot| slot.take().unwrap())
+        .collect()
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
+
+pub fn copy_buffer(
+    device: &VkDevice,
+    command_pool: vk::CommandPool,
+    present_queue: vk::Queue,
+    src: vk::Buffer,
+    dst: vk::Buffer,
+    size: u64,
+) -> Result<(), Box<Error>> {
+    let alloc_info = vk::CommandBufferAllocateInfo {
+        s_type: vk::StructureType::CommandBufferAllocateInfo,
+        p_next: ptr::null(),
+        level: vk::CommandBufferLevel::Primary,
+        command_pool,
+        command_buffer_count: 1,
+    };
+
+    let command_buffer = unsafe { device.allocate_command_buffers(&alloc_info)?[0] };
+
+    let copied = super::command::submit(
+        device,
+        command_buffer,
+        present_queue,
+        &[],
+        &[],
+        &[],
+        |device, command_buffer| {
+            let copy_regions = [
+                vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: 0,
+                    size,
+                },
+            ];
+            unsafe { device.cmd_copy_buffer(command_buffer, src, dst, &copy_regions) };
+        },
+    );
+
+    // Freed whether or not the copy went through, `submit` has waited for it.
+    unsafe { device.free_command_buffers(command_pool, &[command_buffer]) };
+
+    copied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: u64) -> Block {
+        Block {
+            memory: vk::DeviceMemory::null(),
+            ranges: FreeList::new(size),
+            allocation_count: 0,
+            mapped: ptr::null_mut(),
+        }
+    }
+
+    #[test]
+    fn allocates_first_fit() {
+        let mut list = FreeList::new(100);
+        assert_eq!(list.allocate(30, 1), Some(0));
+        assert_eq!(list.allocate(30, 1), Some(30));
+        assert_eq!(list.used(), 60);
+        assert_eq!(list.allocate(50, 1), None);
+        assert_eq!(list.allocate(40, 1), Some(60));
+        assert_eq!(list.used(), 100);
+        assert!(!list.can_allocate(1, 1));
+    }
+
+    #[test]
+    fn reuses_freed_ranges() {
+        let mut list = FreeList::new(100);
+        let first = list.allocate(40, 1).unwrap();
+        list.allocate(40, 1).unwrap();
+        list.free(first, 40);
+
+        assert_eq!(list.used(), 40);
+        assert_eq!(list.allocate(30, 1), Some(0));
+        assert_eq!(list.allocate(20, 1), Some(80));
+        assert_eq!(list.allocate(10, 1), Some(30));
+        assert_eq!(list.used(), 100);
+    }
+
+    #[test]
+    fn coalesces_neighbouring_ranges() {
+        let mut list = FreeList::new(90);
+        let offsets: Vec<u64> = (0..3).map(|_| list.allocate(30, 1).unwrap()).collect();
+
+        // Freed out of order so the middle range merges on both sides.
+        list.free(offsets[0], 30);
+        list.free(offsets[2], 30);
+        list.free(offsets[1], 30);
+
+        assert_eq!(list.used(), 0);
+        assert_eq!(list.free.len(), 1);
+        assert_eq!(list.allocate(90, 1), Some(0));
+    }
+
+    #[test]
+    fn aligns_offsets_and_keeps_padding_free() {
+        let mut list = FreeList::new(256);
+        assert_eq!(list.allocate(10, 1), Some(0));
+        assert_eq!(list.allocate(16, 64), Some(64));
+        assert_eq!(list.used(), 26);
+
+        // The padding between 10 and 64 is still there for small allocations.
+        assert_eq!(list.allocate(54, 1), Some(10));
+        assert_eq!(list.allocate(100, 128), Some(128));
+        assert!(!list.can_allocate(100, 128));
+    }
+
+    #[test]
+    fn grows_with_free_space_at_the_end() {
+        let mut list = FreeList::new(64);
+        list.allocate(64, 1).unwrap();
+        list.grow(128);
+
+        assert_eq!(list.size(), 128);
+        assert_eq!(list.used(), 64);
+        assert_eq!(list.allocate(64, 1), Some(64));
+    }
+
+    #[test]
+    fn aligns_up() {
+        assert_eq!(align_up(0, 256), 0);
+        assert_eq!(align_up(1, 256), 256);
+        assert_eq!(align_up(256, 256), 256);
+        assert_eq!(align_up(257, 4), 260);
+        assert_eq!(align_up(7, 1), 7);
+    }
+
+    #[test]
+    fn allocates_in_blocks_with_room() {
+        let mut blocks = vec![Some(block(64)), None, Some(block(128))];
+        blocks[0].as_mut().unwrap().allocate(48, 1).unwrap();
+
+        let allocation = allocate_in_blocks(&mut blocks, 3, 32, 16, None).unwrap();
+        assert_eq!(allocation.memory_type, 3);
+        assert_eq!(allocation.block, 2);
+        assert_eq!(allocation.offset, 0);
+        assert_eq!(blocks[2].as_ref().unwrap().allocation_count, 1);
+
+        let allocation = allocate_in_blocks(&mut blocks, 3, 16, 16, None).unwrap();
+        assert_eq!((allocation.block, allocation.offset), (0, 48));
+
+        assert!(allocate_in_blocks(&mut blocks, 3, 128, 1, None).is_none());
+    }
+
+    #[test]
+    fn defragments_the_least_used_block() {
+        let mut blocks = vec![Some(block(128)), None, Some(block(128)), Some(block(128))];
+        blocks[0].as_mut().unwrap().allocate(96, 1).unwrap();
+        blocks[2].as_mut().unwrap().allocate(16, 1).unwrap();
+        blocks[2].as_mut().unwrap().allocate(16, 1).unwrap();
+
+        // The empty block is released rather than defragmented.
+        let source = defragment_source(&blocks);
+        assert_eq!(source, Some(2));
+
+        // Its buffers go to the other blocks, never back into it.
+        for &offset in &[0, 16] {
+            let allocation = allocate_in_blocks(&mut blocks, 0, 16, 1, source).unwrap();
+            assert_eq!(allocation.block, 0);
+            blocks[2].as_mut().unwrap().free(offset, 16);
+        }
+        let allocation = allocate_in_blocks(&mut blocks, 0, 16, 1, source).unwrap();
+        assert_eq!(allocation.block, 3);
+
+        let released = take_empty_blocks(&mut blocks);
+        assert_eq!(released.len(), 1);
+        assert!(blocks[2].is_none());
+        assert!(blocks[0].is_some() && blocks[3].is_some());
+    }
+
+    #[test]
+    fn leaves_a_single_block_alone() {
+        let mut blocks = vec![None, Some(block(128))];
+        blocks[1].as_mut().unwrap().allocate(16, 1).unwrap();
+        assert_eq!(defragment_source(&blocks), None);
+
+        blocks.push(Some(block(128)));
+        assert_eq!(defragment_source(&blocks), Some(1));
+    }
+
+    #[test]
+    fn releases_only_empty_blocks() {
+        let mut blocks = vec![Some(block(64)), Some(block(64)), None];
+        let offset = blocks[0].as_mut().unwrap().allocate(8, 1).unwrap();
+
+        assert_eq!(take_empty_blocks(&mut blocks).len(), 1);
+        assert!(blocks[0].is_some());
+        assert!(blocks[1].is_none());
+
+        blocks[0].as_mut().unwrap().free(offset, 8);
+        assert_eq!(take_empty_blocks(&mut blocks).len(), 1);
+        assert!(blocks.iter().all(|slot| slot.is_none()));
+    }
+}
@@ -5,7 +5,6 @@ use std::ptr;
 use std::u64;
 use std::error::Error;
 
-use super::VK_INSTANCE;
 use super::VkDevice;
 use super::sync::create_fence;
 
@@ -44,13 +43,11 @@ pub fn alloc_buffers(
     Ok(command_buffers)
 }
 
-pub fn submit<F: FnOnce(&VkDevice, vk::CommandBuffer)>(
+/// Records `f` into `command_buffer` for a single submission, replacing
+/// whatever it held before.
+pub fn record<F: FnOnce(&VkDevice, vk::CommandBuffer)>(
     device: &VkDevice,
     command_buffer: vk::CommandBuffer,
-    submit_queue: vk::Queue,
-    wait_mask: &[vk::PipelineStageFlags],
-    wait_semaphores: &[vk::Semaphore],
-    signal_semaphores: &[vk::Semaphore],
     f: F,
 ) -> Result<(), Box<Error>> {
     unsafe {
@@ -76,7 +73,20 @@ pub fn submit<F: FnOnce(&VkDevice, vk::CommandBuffer)>(
         device.end_command_buffer(command_buffer)?;
     }
 
-    let submit_fence = create_fence(&device)?;
+    Ok(())
+}
+
+/// Submits a recorded command buffer without waiting for it. `fence` is
+/// signaled once it has executed.
+pub fn submit_with_fence(
+    device: &VkDevice,
+    command_buffer: vk::CommandBuffer,
+    submit_queue: vk::Queue,
+    wait_mask: &[vk::PipelineStageFlags],
+    wait_semaphores: &[vk::Semaphore],
+    signal_semaphores: &[vk::Semaphore],
+    fence: vk::Fence,
+) -> Result<(), Box<Error>> {
     let submit_info = vk::SubmitInfo {
         s_type: vk::StructureType::SubmitInfo,
         p_next: ptr::null(),
@@ -88,12 +98,72 @@ pub fn submit<F: FnOnce(&VkDevice, vk::CommandBuffer)>(
         signal_semaphore_count: signal_semaphores.len() as u32,
         p_signal_semaphores: signal_semaphores.as_ptr(),
     };
+
     unsafe {
-        device.queue_submit(submit_queue, &[submit_info], submit_fence)?;
+        device.queue_submit(submit_queue, &[submit_info], fence)?;
+    }
+
+    Ok(())
+}
+
+/// Records, submits and blocks until the commands have executed. Meant for
+/// one-off work like uploads, frames go through `submit_with_fence`.
+pub fn submit<F: FnOnce(&VkDevice, vk::CommandBuffer)>(
+    device: &VkDevice,
+    command_buffer: vk::CommandBuffer,
+    submit_queue: vk::Queue,
+    wait_mask: &[vk::PipelineStageFlags],
+    wait_semaphores: &[vk::Semaphore],
+    signal_semaphores: &[vk::Semaphore],
+    f: F,
+) -> Result<(), Box<Error>> {
+    record(device, command_buffer, f)?;
+
+    let submit_fence = create_fence(&device, false)?;
+    let submitted = submit_with_fence(
+        device,
+        command_buffer,
+        submit_queue,
+        wait_mask,
+        wait_semaphores,
+        signal_semaphores,
+        submit_fence,
+    ).and_then(|_| {
+        unsafe { device.wait_for_fences(&[submit_fence], true, u64::MAX)? };
+        Ok(())
+    });
 
-        device.wait_for_fences(&[submit_fence], true, u64::MAX)?;
+    unsafe {
         device.destroy_fence(submit_fence, None);
     }
 
+    submitted
+}
+
+/// Submits a batch without commands that only waits on `wait_semaphores`,
+/// signaling `fence` once it has.
+pub fn submit_waits(
+    device: &VkDevice,
+    submit_queue: vk::Queue,
+    wait_mask: &[vk::PipelineStageFlags],
+    wait_semaphores: &[vk::Semaphore],
+    fence: vk::Fence,
+) -> Result<(), Box<Error>> {
+    let submit_info = vk::SubmitInfo {
+        s_type: vk::StructureType::SubmitInfo,
+        p_next: ptr::null(),
+        wait_semaphore_count: wait_semaphores.len() as u32,
+        p_wait_semaphores: wait_semaphores.as_ptr(),
+        p_wait_dst_stage_mask: wait_mask.as_ptr(),
+        command_buffer_count: 0,
+        p_command_buffers: ptr::null(),
+        signal_semaphore_count: 0,
+        p_signal_semaphores: ptr::null(),
+    };
+
+    unsafe {
+        device.queue_submit(submit_queue, &[submit_info], fence)?;
+    }
+
     Ok(())
 }
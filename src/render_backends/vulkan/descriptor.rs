@@ -0,0 +1,342 @@
+use ash::vk;
+use ash::version::DeviceV1_0;
+
+use std::ptr;
+use std::error::Error;
+use std::collections::HashMap;
+
+use super::VkDevice;
+
+/// Sets every pool is sized for, descriptor counts are a multiple of it.
+const SETS_PER_POOL: u32 = 64;
+
+/// Set written once per frame, with the camera and lights.
+pub const FRAME_SET: u32 = 0;
+/// Bindings of the frame set's uniform buffers.
+pub const CAMERA_BINDING: u32 = 0;
+pub const LIGHTING_BINDING: u32 = 1;
+/// Binding of the frame set's shadow map array.
+pub const SHADOW_MAPS_BINDING: u32 = 2;
+/// Set written per draw, with the material parameters of the draw.
+/// Its uniform buffers are dynamic so draws share a set and differ by
+/// offset.
+pub const OBJECT_SET: u32 = 1;
+/// Binding of the object set's `Draw` block.
+pub const DRAW_BINDING: u32 = 0;
+/// Set written per material, with the textures its shaders sample. Its
+/// bindings are whatever samplers the shaders declare there, each bound to
+/// the material texture of the same name.
+pub const MATERIAL_SET: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BindingType {
+    UniformBuffer,
+    /// Uniform buffer bound with an offset given at bind time, for per-object
+    /// data packed into one buffer.
+    UniformBufferDynamic,
+    StorageBuffer,
+    CombinedImageSampler,
+}
+
+impl BindingType {
+    fn descriptor_type(&self) -> vk::DescriptorType {
+        match *self {
+            BindingType::UniformBuffer => vk::DescriptorType::UniformBuffer,
+            BindingType::UniformBufferDynamic => vk::DescriptorType::UniformBufferDynamic,
+            BindingType::StorageBuffer => vk::DescriptorType::StorageBuffer,
+            BindingType::CombinedImageSampler => vk::DescriptorType::CombinedImageSampler,
+        }
+    }
+}
+
+/// A binding as declared by a shader, layouts are built from these.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Binding {
+    pub binding: u32,
+    pub binding_type: BindingType,
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+}
+
+/// Bindings of the frame set. Every program's frame set has all of them,
+/// whichever ones its shaders read, so one set per frame fits every
+/// pipeline.
+pub fn frame_bindings() -> Vec<Binding> {
+    let uniforms = [CAMERA_BINDING, LIGHTING_BINDING].iter().map(|&binding| Binding {
+        binding,
+        binding_type: BindingType::UniformBuffer,
+        count: 1,
+        stages: vk::SHADER_STAGE_VERTEX_BIT | vk::SHADER_STAGE_FRAGMENT_BIT,
+    });
+    let shadow_maps = Binding {
+        binding: SHADOW_MAPS_BINDING,
+        binding_type: BindingType::CombinedImageSampler,
+        count: 1,
+        stages: vk::SHADER_STAGE_FRAGMENT_BIT,
+    };
+    uniforms.chain(Some(shadow_maps)).collect()
+}
+
+/// Bindings of the object set, which every program has like the frame set
+/// so the sets written per draw fit every pipeline.
+pub fn object_bindings() -> Vec<Binding> {
+    vec![
+        Binding {
+            binding: DRAW_BINDING,
+            binding_type: BindingType::UniformBufferDynamic,
+            count: 1,
+            stages: vk::SHADER_STAGE_VERTEX_BIT | vk::SHADER_STAGE_FRAGMENT_BIT,
+        },
+    ]
+}
+
+/// What a descriptor points at.
+#[derive(Debug, Clone, Copy)]
+pub enum Resource {
+    Buffer {
+        buffer: vk::Buffer,
+        offset: u64,
+        range: u64,
+    },
+    Image {
+        view: vk::ImageView,
+        sampler: vk::Sampler,
+        layout: vk::ImageLayout,
+    },
+}
+
+/// Creates each distinct set layout once, shaders declaring the same
+/// bindings share it.
+pub struct LayoutCache {
+    layouts: HashMap<Vec<Binding>, vk::DescriptorSetLayout>,
+}
+
+impl LayoutCache {
+    pub fn new() -> LayoutCache {
+        LayoutCache {
+            layouts: HashMap::new(),
+        }
+    }
+
+    pub fn get(
+        &mut self,
+        device: &VkDevice,
+        bindings: &[Binding],
+    ) -> Result<vk::DescriptorSetLayout, Box<Error>> {
+        let mut bindings = bindings.to_vec();
+        bindings.sort_by_key(|binding| binding.binding);
+
+        if let Some(&layout) = self.layouts.get(&bindings) {
+            return Ok(layout);
+        }
+
+        let layout_bindings: Vec<_> = bindings
+            .iter()
+            .map(|binding| vk::DescriptorSetLayoutBinding {
+                binding: binding.binding,
+                descriptor_type: binding.binding_type.descriptor_type(),
+                descriptor_count: binding.count,
+                stage_flags: binding.stages,
+                p_immutable_samplers: ptr::null(),
+            })
+            .collect();
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: vk::StructureType::DescriptorSetLayoutCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            binding_count: layout_bindings.len() as u32,
+            p_bindings: layout_bindings.as_ptr(),
+        };
+
+        let layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        self.layouts.insert(bindings, layout);
+
+        Ok(layout)
+    }
+
+    pub unsafe fn destroy(&mut self, device: &VkDevice) {
+        for (_, layout) in self.layouts.drain() {
+            device.destroy_descriptor_set_layout(layout, None);
+        }
+    }
+}
+
+/// Descriptor pools of one frame in flight. Sets are allocated while
+/// recording and all freed together by `reset` once the frame's fence has
+/// been signaled, another pool is added whenever the current ones run out.
+pub struct FramePools {
+    pools: Vec<vk::DescriptorPool>,
+    /// Index of the pool allocations are tried from.
+    current: usize,
+    /// Sets allocated from the current pool.
+    allocated: u32,
+}
+
+impl FramePools {
+    pub fn new() -> FramePools {
+        FramePools {
+            pools: vec![],
+            current: 0,
+            allocated: 0,
+        }
+    }
+
+    pub fn allocate(
+        &mut self,
+        device: &VkDevice,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, Box<Error>> {
+        loop {
+            if self.allocated == SETS_PER_POOL {
+                self.current += 1;
+                self.allocated = 0;
+            }
+            if self.current == self.pools.len() {
+                self.pools.push(create_pool(device)?);
+            }
+
+            let allocate_info = vk::DescriptorSetAllocateInfo {
+                s_type: vk::StructureType::DescriptorSetAllocateInfo,
+                p_next: ptr::null(),
+                descriptor_pool: self.pools[self.current],
+                descriptor_set_count: 1,
+                p_set_layouts: &layout,
+            };
+
+            match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
+                Ok(sets) => {
+                    self.allocated += 1;
+                    return Ok(sets[0]);
+                }
+                // Vulkan 1.0 has no dedicated error for a pool running out of
+                // descriptors, so any allocation failure moves on to the next
+                // pool unless it's a fresh one.
+                Err(vk::Result::ErrorFragmentedPool)
+                | Err(vk::Result::ErrorOutOfHostMemory)
+                | Err(vk::Result::ErrorOutOfDeviceMemory) if self.allocated > 0 =>
+                {
+                    self.current += 1;
+                    self.allocated = 0;
+                }
+                Err(err) => return Err(Box::new(err)),
+            }
+        }
+    }
+
+    /// Frees every set allocated since the last reset. The GPU must be done
+    /// with them.
+    pub fn reset(&mut self, device: &VkDevice) -> Result<(), Box<Error>> {
+        for &pool in &self.pools {
+            unsafe { device.reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())? };
+        }
+        self.current = 0;
+        self.allocated = 0;
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&mut self, device: &VkDevice) {
+        for pool in self.pools.drain(..) {
+            device.destroy_descriptor_pool(pool, None);
+        }
+    }
+}
+
+fn create_pool(device: &VkDevice) -> Result<vk::DescriptorPool, Box<Error>> {
+    let pool_sizes = [
+        vk::DescriptorPoolSize {
+            typ: vk::DescriptorType::UniformBuffer,
+            descriptor_count: SETS_PER_POOL,
+        },
+        vk::DescriptorPoolSize {
+            typ: vk::DescriptorType::UniformBufferDynamic,
+            descriptor_count: SETS_PER_POOL,
+        },
+        vk::DescriptorPoolSize {
+            typ: vk::DescriptorType::StorageBuffer,
+            descriptor_count: SETS_PER_POOL,
+        },
+        vk::DescriptorPoolSize {
+            typ: vk::DescriptorType::CombinedImageSampler,
+            descriptor_count: SETS_PER_POOL * 4,
+        },
+    ];
+
+    let pool_info = vk::DescriptorPoolCreateInfo {
+        s_type: vk::StructureType::DescriptorPoolCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        max_sets: SETS_PER_POOL,
+        pool_size_count: pool_sizes.len() as u32,
+        p_pool_sizes: pool_sizes.as_ptr(),
+    };
+
+    let pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+
+    Ok(pool)
+}
+
+/// Points the bindings of `set` at the given resources.
+pub fn write_set(
+    device: &VkDevice,
+    set: vk::DescriptorSet,
+    writes: &[(u32, BindingType, Resource)],
+) {
+    // The writes point into these, so they have to be filled first.
+    let buffer_infos: Vec<_> = writes
+        .iter()
+        .map(|&(_, _, resource)| match resource {
+            Resource::Buffer { buffer, offset, range } => vk::DescriptorBufferInfo {
+                buffer,
+                offset,
+                range,
+            },
+            Resource::Image { .. } => vk::DescriptorBufferInfo {
+                buffer: vk::Buffer::null(),
+                offset: 0,
+                range: 0,
+            },
+        })
+        .collect();
+    let image_infos: Vec<_> = writes
+        .iter()
+        .map(|&(_, _, resource)| match resource {
+            Resource::Image { view, sampler, layout } => vk::DescriptorImageInfo {
+                sampler,
+                image_view: view,
+                image_layout: layout,
+            },
+            Resource::Buffer { .. } => vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: vk::ImageView::null(),
+                image_layout: vk::ImageLayout::Undefined,
+            },
+        })
+        .collect();
+
+    let descriptor_writes: Vec<_> = writes
+        .iter()
+        .enumerate()
+        .map(|(i, &(binding, binding_type, resource))| {
+            let (p_buffer_info, p_image_info) = match resource {
+                Resource::Buffer { .. } => (&buffer_infos[i] as *const _, ptr::null()),
+                Resource::Image { .. } => (ptr::null(), &image_infos[i] as *const _),
+            };
+
+            vk::WriteDescriptorSet {
+                s_type: vk::StructureType::WriteDescriptorSet,
+                p_next: ptr::null(),
+                dst_set: set,
+                dst_binding: binding,
+                dst_array_element: 0,
+                descriptor_count: 1,
+                descriptor_type: binding_type.descriptor_type(),
+                p_image_info,
+                p_buffer_info,
+                p_texel_buffer_view: ptr::null(),
+            }
+        })
+        .collect();
+
+    unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+}
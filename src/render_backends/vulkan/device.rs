@@ -9,36 +9,48 @@ use std::error::Error;
 
 use super::VK_INSTANCE;
 
+/// Creates one queue from the graphics family and, if there is one, one from
+/// the dedicated transfer family. Swapchains are only enabled for devices
+/// that `present`, so headless ones work on drivers without them.
 pub fn new(
     queue_family_index: u32,
+    transfer_queue_family_index: Option<u32>,
     physical_device: vk::PhysicalDevice,
+    present: bool,
 ) -> Result<Device<V1_0>, Box<Error>> {
+    let supported_features = VK_INSTANCE.get_physical_device_features(physical_device);
     let features = vk::PhysicalDeviceFeatures {
         shader_clip_distance: 1,
-        ..Default::defaul()
+        // Wireframe pipelines, which are drawn filled without it.
+        fill_mode_non_solid: supported_features.fill_mode_non_solid,
+        ..Default::default()
     };
 
-    let queue_info = {
-        let priorities = [1.0];
-
-        vk::DeviceQueueCreateInfo {
+    let priorities = [1.0];
+    let queue_infos: Vec<_> = Some(queue_family_index)
+        .into_iter()
+        .chain(transfer_queue_family_index)
+        .map(|queue_family_index| vk::DeviceQueueCreateInfo {
             s_type: vk::StructureType::DeviceQueueCreateInfo,
             p_next: ptr::null(),
             flags: Default::default(),
-            queue_family_index: queue_family_index,
+            queue_family_index,
             p_queue_priorities: priorities.as_ptr(),
             queue_count: priorities.len() as u32,
-        }
-    };
+        })
+        .collect();
 
-    let device_extension_names = [Swapchain::name().as_ptr()];
+    let device_extension_names: Vec<_> = Some(Swapchain::name().as_ptr())
+        .into_iter()
+        .filter(|_| present)
+        .collect();
 
     let device_create_info = vk::DeviceCreateInfo {
         s_type: vk::StructureType::DeviceCreateInfo,
         p_next: ptr::null(),
         flags: Default::default(),
-        queue_create_info_count: 1,
-        p_queue_create_infos: &queue_info,
+        queue_create_info_count: queue_infos.len() as u32,
+        p_queue_create_infos: queue_infos.as_ptr(),
         enabled_layer_count: 0,
         pp_enabled_layer_names: ptr::null(),
         enabled_extension_count: device_extension_names.len() as u32,
@@ -0,0 +1,49 @@
+use ash::vk;
+use ash::version::DeviceV1_0;
+
+use std::ptr;
+use std::error::Error;
+
+use super::VkDevice;
+
+/// One framebuffer per view in `present_image_views`. Render passes with
+/// more than one sample draw into `multisampled_view` and resolve into the
+/// present image, see `render_pass::new`.
+pub fn new(
+    device: &VkDevice,
+    render_pass: vk::RenderPass,
+    surface_resolution: &vk::Extent2D,
+    present_image_views: &[vk::ImageView],
+    depth_image_view: vk::ImageView,
+    multisampled_view: Option<vk::ImageView>,
+) -> Result<Vec<vk::Framebuffer>, Box<Error>> {
+    let mut framebuffers = Vec::with_capacity(present_image_views.len());
+    for &present_image_view in present_image_views {
+        let attachments = match multisampled_view {
+            Some(view) => vec![view, depth_image_view, present_image_view],
+            None => vec![present_image_view, depth_image_view],
+        };
+        let create_info = vk::FramebufferCreateInfo {
+            s_type: vk::StructureType::FramebufferCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: surface_resolution.width,
+            height: surface_resolution.height,
+            layers: 1,
+        };
+        match unsafe { device.create_framebuffer(&create_info, None) } {
+            Ok(framebuffer) => framebuffers.push(framebuffer),
+            Err(err) => {
+                for &framebuffer in &framebuffers {
+                    unsafe { device.destroy_framebuffer(framebuffer, None) };
+                }
+                return Err(Box::new(err));
+            }
+        }
+    }
+
+    Ok(framebuffers)
+}
@@ -0,0 +1,922 @@
+use ash::vk;
+use ash::version::DeviceV1_0;
+use glsl_to_spirv;
+
+use std::ptr;
+use std::mem;
+use std::error::Error;
+use std::ffi::CString;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+
+use renderer::{AttributeFormat, Blend, Cull, Depth, Instance, Material, RenderState,
+               VertexLayout};
+
+use super::VkDevice;
+use super::descriptor::{self, Binding, BindingType, LayoutCache};
+use super::reflect::{self, ShaderInterface};
+use super::preprocess;
+
+/// Where each attribute of a mesh's vertices is, vertex shader inputs are
+/// matched to attributes by location.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VertexFormat {
+    pub stride: u32,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub format: vk::Format,
+    pub offset: u32,
+}
+
+impl VertexFormat {
+    /// Format of vertices in `layout`, each attribute at its semantic's
+    /// location.
+    pub fn new(layout: &VertexLayout) -> VertexFormat {
+        VertexFormat {
+            stride: layout.stride(),
+            attributes: layout
+                .attributes()
+                .iter()
+                .map(|attribute| VertexAttribute {
+                    location: attribute.semantic.location(),
+                    format: attribute_format(attribute.format),
+                    offset: attribute.offset,
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn attribute_format(format: AttributeFormat) -> vk::Format {
+    match format {
+        AttributeFormat::Float => vk::Format::R32Sfloat,
+        AttributeFormat::Float2 => vk::Format::R32g32Sfloat,
+        AttributeFormat::Float3 => vk::Format::R32g32b32Sfloat,
+        AttributeFormat::Float4 => vk::Format::R32g32b32a32Sfloat,
+        AttributeFormat::Half2 => vk::Format::R16g16Sfloat,
+        AttributeFormat::Half4 => vk::Format::R16g16b16a16Sfloat,
+        AttributeFormat::Unorm8x4 => vk::Format::R8g8b8a8Unorm,
+        AttributeFormat::Snorm8x4 => vk::Format::R8g8b8a8Snorm,
+        AttributeFormat::Unorm16x2 => vk::Format::R16g16Unorm,
+        AttributeFormat::Unorm16x4 => vk::Format::R16g16b16a16Unorm,
+        AttributeFormat::Snorm16x2 => vk::Format::R16g16Snorm,
+        AttributeFormat::Snorm16x4 => vk::Format::R16g16b16a16Snorm,
+        AttributeFormat::Snorm10x3 => vk::Format::A2b10g10r10SnormPack32,
+        AttributeFormat::Uint8x4 => vk::Format::R8g8b8a8Uint,
+        AttributeFormat::Uint16x4 => vk::Format::R16g16b16a16Uint,
+    }
+}
+
+/// Vertex buffer binding of the meshes' vertices.
+const VERTEX_BINDING: u32 = 0;
+/// Vertex buffer binding of the per-instance data.
+pub const INSTANCE_BINDING: u32 = 1;
+/// Shader input location of the instance's model matrix, one location per
+/// column. Comes after the locations `Semantic`s use.
+const INSTANCE_MODEL_LOCATION: u32 = 8;
+const INSTANCE_TINT_LOCATION: u32 = 12;
+const INSTANCE_RECEIVE_SHADOWS_LOCATION: u32 = 13;
+
+/// Per-instance vertex data, read by shaders at `INSTANCE_MODEL_LOCATION`,
+/// `INSTANCE_TINT_LOCATION` and `INSTANCE_RECEIVE_SHADOWS_LOCATION`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct InstanceData {
+    pub model: [[f32; 4]; 4],
+    pub tint: [f32; 4],
+    /// 1 if shadows fall on the instance, 0 if not.
+    pub receive_shadows: f32,
+}
+
+impl InstanceData {
+    pub fn new(instance: &Instance) -> InstanceData {
+        InstanceData {
+            model: instance.model.into(),
+            tint: instance.tint,
+            receive_shadows: if instance.receive_shadows { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+fn instance_format() -> VertexFormat {
+    let mut attributes: Vec<_> = (0..4)
+        .map(|column| VertexAttribute {
+            location: INSTANCE_MODEL_LOCATION + column,
+            format: vk::Format::R32g32b32a32Sfloat,
+            offset: mem::offset_of!(InstanceData, model) as u32 + column * 16,
+        })
+        .collect();
+    attributes.push(VertexAttribute {
+        location: INSTANCE_TINT_LOCATION,
+        format: vk::Format::R32g32b32a32Sfloat,
+        offset: mem::offset_of!(InstanceData, tint) as u32,
+    });
+    attributes.push(VertexAttribute {
+        location: INSTANCE_RECEIVE_SHADOWS_LOCATION,
+        format: vk::Format::R32Sfloat,
+        offset: mem::offset_of!(InstanceData, receive_shadows) as u32,
+    });
+
+    VertexFormat {
+        stride: mem::size_of::<InstanceData>() as u32,
+        attributes,
+    }
+}
+
+/// GLSL sources of a pipeline's stages and the defines both are compiled
+/// with. Each combination of defines is a separate permutation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShaderSet {
+    pub vertex: PathBuf,
+    pub fragment: PathBuf,
+    pub defines: BTreeMap<String, String>,
+}
+
+impl ShaderSet {
+    pub fn new<P: AsRef<Path>>(vertex: P, fragment: P) -> ShaderSet {
+        ShaderSet {
+            vertex: vertex.as_ref().to_path_buf(),
+            fragment: fragment.as_ref().to_path_buf(),
+            defines: BTreeMap::new(),
+        }
+    }
+
+    /// The permutation compiled with `name` defined as `value`.
+    pub fn with_define(mut self, name: &str, value: &str) -> ShaderSet {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// A material's shaders, with `PARAMETER_<NAME>` defined as the index of
+    /// each of its parameters in the `Draw` block.
+    pub fn for_material(material: &Material) -> ShaderSet {
+        material
+            .parameters
+            .iter()
+            .enumerate()
+            .fold(
+                ShaderSet::new(material.vertex_shader, material.fragment_shader),
+                |shaders, (index, &(name, _))| {
+                    let define = format!("PARAMETER_{}", name.to_uppercase());
+                    shaders.with_define(&define, &index.to_string())
+                },
+            )
+    }
+}
+
+impl Default for ShaderSet {
+    fn default() -> ShaderSet {
+        ShaderSet::new("data/shaders/cube.vert", "data/shaders/cube.frag")
+    }
+}
+
+/// Compiled shaders with their reflected interfaces and the layout they're
+/// bound through. Every pipeline made from the same shaders shares it.
+pub struct ShaderProgram {
+    vertex_path: PathBuf,
+    vertex_code: Vec<u32>,
+    vertex: ShaderInterface,
+    fragment_code: Vec<u32>,
+    pub layout: vk::PipelineLayout,
+    /// Layouts of the sets in order, from the shared `LayoutCache`.
+    pub set_layouts: Vec<vk::DescriptorSetLayout>,
+    pub textures: TextureBindings,
+}
+
+/// Binding and sampler name of each texture in the material set.
+pub type TextureBindings = Vec<(u32, String)>;
+
+impl ShaderProgram {
+    pub fn new(
+        device: &VkDevice,
+        layouts: &mut LayoutCache,
+        shaders: &ShaderSet,
+    ) -> Result<ShaderProgram, Box<Error>> {
+        let vertex_code = compile_shader(
+            &shaders.vertex,
+            &shaders.defines,
+            glsl_to_spirv::ShaderType::Vertex,
+        )?;
+        let fragment_code = compile_shader(
+            &shaders.fragment,
+            &shaders.defines,
+            glsl_to_spirv::ShaderType::Fragment,
+        )?;
+        let vertex = reflect::reflect(&vertex_code)
+            .map_err(|err| format!("{}: {}", shaders.vertex.display(), err))?;
+        let fragment = reflect::reflect(&fragment_code)
+            .map_err(|err| format!("{}: {}", shaders.fragment.display(), err))?;
+
+        check_stage_interface(&vertex, &fragment).map_err(|err| {
+            format!(
+                "{} and {}: {}",
+                shaders.vertex.display(),
+                shaders.fragment.display(),
+                err
+            )
+        })?;
+
+        let interfaces = [vertex, fragment];
+        let SetBindings { sets, textures } = set_bindings(&interfaces)?;
+        let set_layouts = sets
+            .iter()
+            .map(|bindings| layouts.get(device, bindings))
+            .collect::<Result<Vec<_>, _>>()?;
+        let push_constant_ranges = push_constant_ranges(&interfaces);
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo {
+            s_type: vk::StructureType::PipelineLayoutCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+        };
+        let layout = unsafe { device.create_pipeline_layout(&layout_create_info, None)? };
+
+        let [vertex, _] = interfaces;
+        Ok(ShaderProgram {
+            vertex_path: shaders.vertex.clone(),
+            vertex_code,
+            vertex,
+            fragment_code,
+            layout,
+            set_layouts,
+            textures,
+        })
+    }
+
+    /// The set layouts belong to the `LayoutCache`.
+    pub unsafe fn destroy(&self, device: &VkDevice) {
+        device.destroy_pipeline_layout(self.layout, None);
+    }
+}
+
+/// Fixed function state of a pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineState {
+    pub render: RenderState,
+    /// Runs only the vertex shader, for passes without color attachments
+    /// such as depth prepasses and shadow maps.
+    pub depth_only: bool,
+    /// Has to match the samples of the render pass's attachments.
+    pub samples: vk::SampleCountFlags,
+}
+
+impl PipelineState {
+    pub fn new(render: RenderState) -> PipelineState {
+        PipelineState {
+            render,
+            depth_only: false,
+            samples: vk::SAMPLE_COUNT_1_BIT,
+        }
+    }
+
+    pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> PipelineState {
+        self.samples = samples;
+        self
+    }
+}
+
+/// Creates a pipeline drawing meshes of `vertex_format` with `program` into
+/// subpass 0 of `render_pass`. Fails if the vertex shader reads attributes
+/// the format doesn't provide.
+pub fn new(
+    device: &VkDevice,
+    cache: vk::PipelineCache,
+    render_pass: vk::RenderPass,
+    program: &ShaderProgram,
+    vertex_format: &VertexFormat,
+    state: &PipelineState,
+) -> Result<vk::Pipeline, Box<Error>> {
+    let entry_name = CString::new("main")?;
+
+    // The create infos below point into `binding_descriptions` and
+    // `attribute_descriptions`, so they have to outlive the
+    // `create_graphics_pipelines` call.
+    let (binding_descriptions, attribute_descriptions) = vertex_input(&program.vertex, vertex_format)
+        .map_err(|err| format!("{}: {}", program.vertex_path.display(), err))?;
+
+    let vert_shader = unsafe { create_shader_module(device, &program.vertex_code)? };
+    let frag_shader = if state.depth_only {
+        None
+    } else {
+        match unsafe { create_shader_module(device, &program.fragment_code) } {
+            Ok(module) => Some(module),
+            Err(err) => {
+                unsafe { device.destroy_shader_module(vert_shader, None) };
+                return Err(err);
+            }
+        }
+    };
+
+    let vert_shader_stage_info = vk::PipelineShaderStageCreateInfo {
+        s_type: vk::StructureType::PipelineShaderStageCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        module: vert_shader,
+        p_name: entry_name.as_ptr(),
+        p_specialization_info: ptr::null(),
+        stage: vk::SHADER_STAGE_VERTEX_BIT,
+    };
+
+    let frag_shader_stage_info = frag_shader.map(|frag_shader| vk::PipelineShaderStageCreateInfo {
+        s_type: vk::StructureType::PipelineShaderStageCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        module: frag_shader,
+        p_name: entry_name.as_ptr(),
+        p_specialization_info: ptr::null(),
+        stage: vk::SHADER_STAGE_FRAGMENT_BIT,
+    });
+
+    let shader_stage_create_infos: Vec<_> = Some(vert_shader_stage_info)
+        .into_iter()
+        .chain(frag_shader_stage_info)
+        .collect();
+
+    let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo {
+        s_type: vk::StructureType::PipelineVertexInputStateCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        vertex_attribute_description_count: attribute_descriptions.len() as u32,
+        p_vertex_attribute_descriptions: attribute_descriptions.as_ptr(),
+        vertex_binding_description_count: binding_descriptions.len() as u32,
+        p_vertex_binding_descriptions: binding_descriptions.as_ptr(),
+    };
+
+    let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+        s_type: vk::StructureType::PipelineInputAssemblyStateCreateInfo,
+        flags: Default::default(),
+        p_next: ptr::null(),
+        primitive_restart_enable: 0,
+        topology: vk::PrimitiveTopology::TriangleList,
+    };
+
+    // Viewport and scissor are set while recording, so the same pipeline
+    // draws into the swapchain and into render targets of any size.
+    let viewport_state_info = vk::PipelineViewportStateCreateInfo {
+        s_type: vk::StructureType::PipelineViewportStateCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        scissor_count: 1,
+        p_scissors: ptr::null(),
+        viewport_count: 1,
+        p_viewports: ptr::null(),
+    };
+
+    let dynamic_states = [vk::DynamicState::Viewport, vk::DynamicState::Scissor];
+    let dynamic_state_info = vk::PipelineDynamicStateCreateInfo {
+        s_type: vk::StructureType::PipelineDynamicStateCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        dynamic_state_count: dynamic_states.len() as u32,
+        p_dynamic_states: dynamic_states.as_ptr(),
+    };
+
+    let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+        s_type: vk::StructureType::PipelineRasterizationStateCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        cull_mode: match state.render.cull {
+            Cull::Back => vk::CULL_MODE_BACK_BIT,
+            Cull::Front => vk::CULL_MODE_FRONT_BIT,
+            Cull::None => vk::CULL_MODE_NONE,
+        },
+        depth_bias_clamp: 0.0,
+        depth_bias_constant_factor: 0.0,
+        depth_bias_enable: 0,
+        depth_bias_slope_factor: 0.0,
+        depth_clamp_enable: 0,
+        front_face: vk::FrontFace::Clockwise,
+        line_width: 1.0,
+        polygon_mode: if state.render.wireframe {
+            vk::PolygonMode::Line
+        } else {
+            vk::PolygonMode::Fill
+        },
+        rasterizer_discard_enable: 0,
+    };
+
+    let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
+        s_type: vk::StructureType::PipelineMultisampleStateCreateInfo,
+        flags: Default::default(),
+        p_next: ptr::null(),
+        rasterization_samples: state.samples,
+        sample_shading_enable: 0,
+        min_sample_shading: 1.0,
+        p_sample_mask: ptr::null(),
+        alpha_to_one_enable: 0,
+        alpha_to_coverage_enable: 0,
+    };
+
+    let noop_stencil_state = vk::StencilOpState {
+        fail_op: vk::StencilOp::Keep,
+        pass_op: vk::StencilOp::Keep,
+        depth_fail_op: vk::StencilOp::Keep,
+        compare_op: vk::CompareOp::Always,
+        compare_mask: 0,
+        write_mask: 0,
+        reference: 0,
+    };
+
+    let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+        s_type: vk::StructureType::PipelineDepthStencilStateCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        depth_test_enable: (state.render.depth != Depth::Off) as u32,
+        depth_write_enable: (state.render.depth == Depth::TestAndWrite) as u32,
+        depth_compare_op: vk::CompareOp::LessOrEqual,
+        depth_bounds_test_enable: 0,
+        stencil_test_enable: 0,
+        front: noop_stencil_state,
+        back: noop_stencil_state,
+        max_depth_bounds: 1.0,
+        min_depth_bounds: 0.0,
+    };
+
+    let color_blend_attachment_states = if state.depth_only {
+        vec![]
+    } else {
+        vec![color_blend_attachment(state.render.blend)]
+    };
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+        s_type: vk::StructureType::PipelineColorBlendStateCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        logic_op_enable: 0,
+        logic_op: vk::LogicOp::Clear,
+        attachment_count: color_blend_attachment_states.len() as u32,
+        p_attachments: color_blend_attachment_states.as_ptr(),
+        blend_constants: [0.0, 0.0, 0.0, 0.0],
+    };
+
+    let graphics_pipelines = {
+        let graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo {
+            s_type: vk::StructureType::GraphicsPipelineCreateInfo,
+            p_next: ptr::null(),
+            flags: vk::PipelineCreateFlags::empty(),
+            stage_count: shader_stage_create_infos.len() as u32,
+            p_stages: shader_stage_create_infos.as_ptr(),
+            p_vertex_input_state: &vertex_input_state_info,
+            p_input_assembly_state: &vertex_input_assembly_state_info,
+            p_tessellation_state: ptr::null(),
+            p_viewport_state: &viewport_state_info,
+            p_rasterization_state: &rasterization_info,
+            p_multisample_state: &multisample_state_info,
+            p_depth_stencil_state: &depth_state_info,
+            p_color_blend_state: &color_blend_state,
+            p_dynamic_state: &dynamic_state_info,
+            layout: program.layout,
+            render_pass,
+            subpass: 0,
+            base_pipeline_handle: vk::Pipeline::null(),
+            base_pipeline_index: 0,
+        };
+
+        unsafe { device.create_graphics_pipelines(cache, &[graphics_pipeline_create_info], None) }
+    };
+
+    // The pipeline keeps what it needs from the modules.
+    unsafe {
+        device.destroy_shader_module(vert_shader, None);
+        if let Some(frag_shader) = frag_shader {
+            device.destroy_shader_module(frag_shader, None);
+        }
+    }
+
+    match graphics_pipelines {
+        Ok(graphics_pipelines) => Ok(graphics_pipelines[0]),
+        Err(_) => Err("Unable to create graphics pipeline".into()),
+    }
+}
+
+fn color_blend_attachment(blend: Blend) -> vk::PipelineColorBlendAttachmentState {
+    match blend {
+        Blend::Opaque => vk::PipelineColorBlendAttachmentState {
+            blend_enable: 0,
+            src_color_blend_factor: vk::BlendFactor::SrcColor,
+            dst_color_blend_factor: vk::BlendFactor::OneMinusDstColor,
+            color_blend_op: vk::BlendOp::Add,
+            src_alpha_blend_factor: vk::BlendFactor::Zero,
+            dst_alpha_blend_factor: vk::BlendFactor::Zero,
+            alpha_blend_op: vk::BlendOp::Add,
+            color_write_mask: vk::ColorComponentFlags::all(),
+        },
+        // Premultiplies color by alpha while blending, alpha itself is added
+        // on top of what's left.
+        Blend::Alpha => vk::PipelineColorBlendAttachmentState {
+            blend_enable: 1,
+            src_color_blend_factor: vk::BlendFactor::SrcAlpha,
+            dst_color_blend_factor: vk::BlendFactor::OneMinusSrcAlpha,
+            color_blend_op: vk::BlendOp::Add,
+            src_alpha_blend_factor: vk::BlendFactor::One,
+            dst_alpha_blend_factor: vk::BlendFactor::OneMinusSrcAlpha,
+            alpha_blend_op: vk::BlendOp::Add,
+            color_write_mask: vk::ColorComponentFlags::all(),
+        },
+    }
+}
+
+/// Builds the vertex input of the attributes the vertex shader reads, from
+/// the mesh's vertices or the per-instance data. Fails if neither has one or
+/// provides it as another type.
+fn vertex_input(
+    vertex_shader: &ShaderInterface,
+    format: &VertexFormat,
+) -> Result<(Vec<vk::VertexInputBindingDescription>, Vec<vk::VertexInputAttributeDescription>), Box<Error>> {
+    let instance_format = instance_format();
+    let mut attribute_descriptions = vec![];
+    for input in &vertex_shader.inputs {
+        let find = |format: &VertexFormat| {
+            format
+                .attributes
+                .iter()
+                .find(|attribute| attribute.location == input.location)
+                .cloned()
+        };
+        let (binding, attribute) = match (find(format), find(&instance_format)) {
+            (Some(attribute), _) => (VERTEX_BINDING, attribute),
+            (None, Some(attribute)) => (INSTANCE_BINDING, attribute),
+            (None, None) => {
+                return Err(format!(
+                    "Input `{}` at location {} isn't in the mesh's vertex format, which has locations {:?}",
+                    input.name,
+                    input.location,
+                    format
+                        .attributes
+                        .iter()
+                        .map(|attribute| attribute.location)
+                        .collect::<Vec<_>>()
+                ).into())
+            }
+        };
+
+        let (numeric_type, _) = reflect::format_components(attribute.format).ok_or_else(|| {
+            format!(
+                "Vertex format has {:?} at location {}, which isn't a supported attribute format",
+                attribute.format, attribute.location
+            )
+        })?;
+        // Missing components are filled in and extra ones dropped, but the
+        // type has to match.
+        if numeric_type != input.numeric_type {
+            return Err(format!(
+                "Input `{}` at location {} is {:?} but the mesh's vertex format has {:?}",
+                input.name, input.location, input.numeric_type, attribute.format
+            ).into());
+        }
+
+        attribute_descriptions.push(vk::VertexInputAttributeDescription {
+            location: attribute.location,
+            binding,
+            format: attribute.format,
+            offset: attribute.offset,
+        });
+    }
+
+    let mut binding_descriptions = vec![
+        vk::VertexInputBindingDescription {
+            binding: VERTEX_BINDING,
+            stride: format.stride,
+            input_rate: vk::VertexInputRate::Vertex,
+        },
+    ];
+    if attribute_descriptions
+        .iter()
+        .any(|attribute| attribute.binding == INSTANCE_BINDING)
+    {
+        binding_descriptions.push(vk::VertexInputBindingDescription {
+            binding: INSTANCE_BINDING,
+            stride: instance_format.stride,
+            input_rate: vk::VertexInputRate::Instance,
+        });
+    }
+
+    Ok((binding_descriptions, attribute_descriptions))
+}
+
+/// Checks that everything the fragment shader reads is written by the vertex
+/// shader.
+fn check_stage_interface(vertex: &ShaderInterface, fragment: &ShaderInterface) -> Result<(), Box<Error>> {
+    for input in &fragment.inputs {
+        let output = vertex
+            .outputs
+            .iter()
+            .find(|output| output.location == input.location)
+            .ok_or_else(|| {
+                format!(
+                    "Fragment input `{}` at location {} isn't written by the vertex shader",
+                    input.name, input.location
+                )
+            })?;
+
+        if output.numeric_type != input.numeric_type || output.components != input.components {
+            return Err(format!(
+                "Fragment input `{}` at location {} is {} {:?} components but the vertex shader writes {} {:?} components",
+                input.name,
+                input.location,
+                input.components,
+                input.numeric_type,
+                output.components,
+                output.numeric_type
+            ).into());
+        }
+    }
+
+    Ok(())
+}
+
+/// What `set_bindings` makes of a program's descriptors.
+struct SetBindings {
+    /// Bindings of each set, in order.
+    sets: Vec<Vec<Binding>>,
+    textures: TextureBindings,
+}
+
+/// Merges the descriptors of all stages into the bindings of each set.
+/// Uniform buffers in `descriptor::OBJECT_SET` are bound with dynamic
+/// offsets. The frame and object sets always have all of their bindings,
+/// other bindings in them are an error. `descriptor::MATERIAL_SET` may only
+/// hold samplers, returned with their names as the material's textures.
+fn set_bindings(interfaces: &[ShaderInterface]) -> Result<SetBindings, Box<Error>> {
+    let mut merged: BTreeMap<(u32, u32), (Binding, &str)> = BTreeMap::new();
+    for interface in interfaces {
+        for descriptor in &interface.descriptors {
+            let binding_type = match descriptor.binding_type {
+                BindingType::UniformBuffer if descriptor.set == descriptor::OBJECT_SET => {
+                    BindingType::UniformBufferDynamic
+                }
+                binding_type => binding_type,
+            };
+            let key = (descriptor.set, descriptor.binding);
+            if let Some(&mut (ref mut binding, name)) = merged.get_mut(&key) {
+                if binding.binding_type != binding_type || binding.count != descriptor.count {
+                    return Err(format!(
+                        "Set {} binding {} is `{}` in one stage and `{}` of another type in another",
+                        descriptor.set, descriptor.binding, name, descriptor.name
+                    ).into());
+                }
+                binding.stages |= interface.stage;
+                continue;
+            }
+
+            merged.insert(
+                key,
+                (
+                    Binding {
+                        binding: descriptor.binding,
+                        binding_type,
+                        count: descriptor.count,
+                        stages: interface.stage,
+                    },
+                    &descriptor.name,
+                ),
+            );
+        }
+    }
+
+    // Sets the shaders skip get empty layouts, the renderer always binds the
+    // frame and object sets.
+    let set_count = merged
+        .keys()
+        .map(|&(set, _)| set + 1)
+        .max()
+        .unwrap_or(0)
+        .max(descriptor::OBJECT_SET + 1);
+    let mut sets = vec![vec![]; set_count as usize];
+    sets[descriptor::FRAME_SET as usize] = descriptor::frame_bindings();
+    sets[descriptor::OBJECT_SET as usize] = descriptor::object_bindings();
+    let mut textures = vec![];
+    for ((set, _), (binding, name)) in merged {
+        let set_name = match set {
+            descriptor::FRAME_SET => "frame",
+            descriptor::OBJECT_SET => "object",
+            descriptor::MATERIAL_SET => {
+                if binding.binding_type != BindingType::CombinedImageSampler || binding.count != 1 {
+                    return Err(format!(
+                        "`{}` at set {} binding {} isn't a single sampler, the material set only \
+                         holds textures",
+                        name, set, binding.binding
+                    ).into());
+                }
+                textures.push((binding.binding, name.to_string()));
+                sets[set as usize].push(binding);
+                continue;
+            }
+            _ => {
+                return Err(format!(
+                    "`{}` is in set {}, past the material set",
+                    name, set
+                ).into())
+            }
+        };
+        let declared = sets[set as usize]
+            .iter()
+            .any(|fixed| fixed.binding == binding.binding && fixed.binding_type == binding.binding_type);
+        if !declared {
+            return Err(format!(
+                "`{}` at set {} binding {} isn't one of the {} set's bindings",
+                name, set, binding.binding, set_name
+            ).into());
+        }
+    }
+
+    Ok(SetBindings { sets, textures })
+}
+
+/// One range per stage with push constants.
+fn push_constant_ranges(interfaces: &[ShaderInterface]) -> Vec<vk::PushConstantRange> {
+    interfaces
+        .iter()
+        .filter_map(|interface| {
+            interface
+                .push_constants
+                .map(|(offset, size)| vk::PushConstantRange {
+                    stage_flags: interface.stage,
+                    offset,
+                    size,
+                })
+        })
+        .collect()
+}
+
+fn compile_shader(
+    path: &Path,
+    defines: &BTreeMap<String, String>,
+    shader_type: glsl_to_spirv::ShaderType,
+) -> Result<Vec<u32>, Box<Error>> {
+    let source = preprocess::preprocess(path, Path::new(preprocess::SHADER_ROOT), defines)?;
+
+    let mut spv_file = glsl_to_spirv::compile(&source.code, shader_type).map_err(|messages| {
+        let defines: Vec<_> = defines
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        format!(
+            "Couldn't compile {} with defines [{}]:\n{}",
+            path.display(),
+            defines.join(", "),
+            source.map_messages(&messages)
+        )
+    })?;
+
+    let mut spv_code = vec![];
+    spv_file.read_to_end(&mut spv_code)?;
+
+    reflect::words(&spv_code)
+}
+
+unsafe fn create_shader_module(device: &VkDevice, code: &[u32]) -> Result<vk::ShaderModule, Box<Error>> {
+    let shader_info = vk::ShaderModuleCreateInfo {
+        s_type: vk::StructureType::ShaderModuleCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        code_size: code.len() * 4,
+        p_code: code.as_ptr(),
+    };
+
+    let shader_module = device.create_shader_module(&shader_info, None)?;
+    Ok(shader_module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use renderer::Parameter;
+
+    #[test]
+    fn material_shaders_define_their_parameters() {
+        let material = Material {
+            vertex_shader: Path::new("data/shaders/water.vert"),
+            fragment_shader: Path::new("data/shaders/water.frag"),
+            textures: vec![],
+            parameters: vec![
+                ("deep_color", Parameter::Vec3(0.0, 0.1, 0.4)),
+                ("wave_height", Parameter::Float(0.25)),
+            ],
+            render_state: RenderState::default(),
+        };
+        let shaders = ShaderSet::for_material(&material);
+
+        assert_eq!(shaders.vertex, Path::new("data/shaders/water.vert"));
+        assert_eq!(shaders.fragment, Path::new("data/shaders/water.frag"));
+        assert_ne!(shaders, ShaderSet::default());
+        let defines: Vec<_> = shaders
+            .defines
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            defines,
+            vec![("PARAMETER_DEEP_COLOR", "0"), ("PARAMETER_WAVE_HEIGHT", "1")]
+        );
+    }
+
+    #[test]
+    fn instance_inputs_read_the_instance_binding() {
+        let inputs = [
+            ("pos", 0),
+            ("model", 8),
+            ("model", 9),
+            ("model", 10),
+            ("model", 11),
+            ("tint", 12),
+        ];
+        let vertex_shader = ShaderInterface {
+            stage: vk::SHADER_STAGE_VERTEX_BIT,
+            inputs: inputs
+                .iter()
+                .map(|&(name, location)| reflect::Variable {
+                    name: name.to_string(),
+                    location,
+                    numeric_type: reflect::NumericType::Float,
+                    components: 4,
+                })
+                .collect(),
+            outputs: vec![],
+            descriptors: vec![],
+            push_constants: None,
+        };
+        let format = VertexFormat::new(&VertexLayout::position_color());
+        let (bindings, attributes) = vertex_input(&vertex_shader, &format).unwrap();
+
+        assert_eq!(bindings.len(), 2);
+        assert_eq!(bindings[1].binding, INSTANCE_BINDING);
+        assert_eq!(bindings[1].input_rate, vk::VertexInputRate::Instance);
+        assert_eq!(bindings[1].stride as usize, mem::size_of::<InstanceData>());
+        let binding_of = |location| {
+            attributes
+                .iter()
+                .find(|attribute| attribute.location == location)
+                .map(|attribute| (attribute.binding, attribute.offset))
+        };
+        assert_eq!(binding_of(0), Some((VERTEX_BINDING, 0)));
+        assert_eq!(binding_of(9), Some((INSTANCE_BINDING, 16)));
+        assert_eq!(binding_of(12), Some((INSTANCE_BINDING, 64)));
+
+        // Without instance inputs the instance buffer isn't bound at all.
+        let mut vertex_only = vertex_shader.clone();
+        vertex_only.inputs.truncate(1);
+        let (bindings, _) = vertex_input(&vertex_only, &format).unwrap();
+        assert_eq!(bindings.len(), 1);
+    }
+
+    fn fragment_sampling(descriptors: &[(&str, u32, u32, BindingType)]) -> ShaderInterface {
+        ShaderInterface {
+            stage: vk::SHADER_STAGE_FRAGMENT_BIT,
+            inputs: vec![],
+            outputs: vec![],
+            descriptors: descriptors
+                .iter()
+                .map(|&(name, set, binding, binding_type)| reflect::Descriptor {
+                    name: name.to_string(),
+                    set,
+                    binding,
+                    binding_type,
+                    count: 1,
+                })
+                .collect(),
+            push_constants: None,
+        }
+    }
+
+    #[test]
+    fn material_set_samplers_become_textures() {
+        let interface = fragment_sampling(&[
+            ("normals", descriptor::MATERIAL_SET, 1, BindingType::CombinedImageSampler),
+            ("albedo", descriptor::MATERIAL_SET, 0, BindingType::CombinedImageSampler),
+            ("shadow_maps", descriptor::FRAME_SET, 2, BindingType::CombinedImageSampler),
+        ]);
+        let SetBindings { sets, textures } = set_bindings(&[interface]).unwrap();
+
+        assert_eq!(sets.len(), 3);
+        assert_eq!(sets[descriptor::FRAME_SET as usize], descriptor::frame_bindings());
+        assert_eq!(sets[descriptor::MATERIAL_SET as usize].len(), 2);
+        assert_eq!(
+            textures,
+            vec![(0, "albedo".to_string()), (1, "normals".to_string())]
+        );
+    }
+
+    #[test]
+    fn material_set_only_holds_samplers() {
+        let buffer = fragment_sampling(&[
+            ("settings", descriptor::MATERIAL_SET, 0, BindingType::UniformBuffer),
+        ]);
+        assert!(set_bindings(&[buffer]).is_err());
+
+        let past_material_set = fragment_sampling(&[
+            ("albedo", descriptor::MATERIAL_SET + 1, 0, BindingType::CombinedImageSampler),
+        ]);
+        assert!(set_bindings(&[past_material_set]).is_err());
+    }
+}
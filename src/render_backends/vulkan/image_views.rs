@@ -0,0 +1,311 @@
+use ash::vk;
+use ash::version::{DeviceV1_0, InstanceV1_0};
+
+use std::ptr;
+use std::error::Error;
+
+use super::VK_INSTANCE;
+use super::find_memorytype_index;
+
+pub fn new(
+    device: &DeviceV1_0,
+    images: &[vk::Image],
+    surface_format: &vk::SurfaceFormatKHR,
+) -> Result<Vec<vk::ImageView>, Box<Error>> {
+    let mut image_views = Vec::with_capacity(images.len());
+    for &image in images {
+        let create_view_info = vk::ImageViewCreateInfo {
+            s_type: vk::StructureType::ImageViewCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            view_type: vk::ImageViewType::Type2d,
+            format: surface_format.format,
+            components: vk::ComponentMapping {
+                r: vk::ComponentSwizzle::Identity,
+                g: vk::ComponentSwizzle::Identity,
+                b: vk::ComponentSwizzle::Identity,
+                a: vk::ComponentSwizzle::Identity,
+            },
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::IMAGE_ASPECT_COLOR_BIT,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image,
+        };
+        match unsafe { device.create_image_view(&create_view_info, None) } {
+            Ok(view) => image_views.push(view),
+            Err(err) => {
+                for &view in &image_views {
+                    unsafe { device.destroy_image_view(view, None) };
+                }
+                return Err(Box::new(err));
+            }
+        }
+    }
+
+    Ok(image_views)
+}
+
+pub fn new_depth_image(
+    device: &DeviceV1_0,
+    surface_resolution: &vk::Extent2D,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+    physical_device: vk::PhysicalDevice,
+) -> Result<(vk::Image, vk::DeviceMemory), Box<Error>> {
+    new_multisampled_image(
+        device,
+        surface_resolution,
+        format,
+        vk::IMAGE_USAGE_DEPTH_STENCIL_ATTACHMENT_BIT,
+        samples,
+        physical_device,
+    )
+}
+
+pub fn new_depth_view(
+    device: &DeviceV1_0,
+    depth_image: vk::Image,
+    format: vk::Format,
+) -> Result<vk::ImageView, Box<Error>> {
+    new_view(device, depth_image, format, vk::IMAGE_ASPECT_DEPTH_BIT)
+}
+
+/// Creates a device local 2D image with a single mip level and binds memory
+/// to it.
+pub fn new_image(
+    device: &DeviceV1_0,
+    extent: &vk::Extent2D,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    physical_device: vk::PhysicalDevice,
+) -> Result<(vk::Image, vk::DeviceMemory), Box<Error>> {
+    new_layered_image(device, extent, format, usage, 1, physical_device)
+}
+
+/// Like `new_image`, with `layers` array layers.
+pub fn new_layered_image(
+    device: &DeviceV1_0,
+    extent: &vk::Extent2D,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    layers: u32,
+    physical_device: vk::PhysicalDevice,
+) -> Result<(vk::Image, vk::DeviceMemory), Box<Error>> {
+    create_image(
+        device,
+        extent,
+        format,
+        usage,
+        ImageCounts {
+            mip_levels: 1,
+            layers,
+            samples: vk::SAMPLE_COUNT_1_BIT,
+        },
+        physical_device,
+    )
+}
+
+/// Like `new_image`, with `mip_levels` mip levels.
+pub fn new_mipmapped_image(
+    device: &DeviceV1_0,
+    extent: &vk::Extent2D,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    mip_levels: u32,
+    physical_device: vk::PhysicalDevice,
+) -> Result<(vk::Image, vk::DeviceMemory), Box<Error>> {
+    create_image(
+        device,
+        extent,
+        format,
+        usage,
+        ImageCounts {
+            mip_levels,
+            layers: 1,
+            samples: vk::SAMPLE_COUNT_1_BIT,
+        },
+        physical_device,
+    )
+}
+
+/// Like `new_image`, with `samples` samples per pixel.
+pub fn new_multisampled_image(
+    device: &DeviceV1_0,
+    extent: &vk::Extent2D,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    samples: vk::SampleCountFlags,
+    physical_device: vk::PhysicalDevice,
+) -> Result<(vk::Image, vk::DeviceMemory), Box<Error>> {
+    create_image(
+        device,
+        extent,
+        format,
+        usage,
+        ImageCounts {
+            mip_levels: 1,
+            layers: 1,
+            samples,
+        },
+        physical_device,
+    )
+}
+
+/// Subresources and samples of an image made by `create_image`.
+struct ImageCounts {
+    mip_levels: u32,
+    layers: u32,
+    samples: vk::SampleCountFlags,
+}
+
+fn create_image(
+    device: &DeviceV1_0,
+    extent: &vk::Extent2D,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    counts: ImageCounts,
+    physical_device: vk::PhysicalDevice,
+) -> Result<(vk::Image, vk::DeviceMemory), Box<Error>> {
+    let image_create_info = vk::ImageCreateInfo {
+        s_type: vk::StructureType::ImageCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        image_type: vk::ImageType::Type2d,
+        format,
+        extent: vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        },
+        mip_levels: counts.mip_levels,
+        array_layers: counts.layers,
+        samples: counts.samples,
+        tiling: vk::ImageTiling::Optimal,
+        usage,
+        sharing_mode: vk::SharingMode::Exclusive,
+        queue_family_index_count: 0,
+        p_queue_family_indices: ptr::null(),
+        initial_layout: vk::ImageLayout::Undefined,
+    };
+    let image = unsafe { device.create_image(&image_create_info, None)? };
+
+    let device_memory_properties =
+        VK_INSTANCE.get_physical_device_memory_properties(physical_device);
+    let image_memory_req = device.get_image_memory_requirements(image);
+    let image_memory_index = find_memorytype_index(
+        &image_memory_req,
+        &device_memory_properties,
+        vk::MEMORY_PROPERTY_DEVICE_LOCAL_BIT,
+    )?;
+
+    let image_allocate_info = vk::MemoryAllocateInfo {
+        s_type: vk::StructureType::MemoryAllocateInfo,
+        p_next: ptr::null(),
+        allocation_size: image_memory_req.size,
+        memory_type_index: image_memory_index,
+    };
+
+    let image_memory = unsafe {
+        let image_memory = device.allocate_memory(&image_allocate_info, None)?;
+        device.bind_image_memory(image, image_memory, 0)?;
+        image_memory
+    };
+
+    Ok((image, image_memory))
+}
+
+pub fn new_view(
+    device: &DeviceV1_0,
+    image: vk::Image,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+) -> Result<vk::ImageView, Box<Error>> {
+    new_layer_view(
+        device,
+        image,
+        format,
+        aspect_mask,
+        vk::ImageViewType::Type2d,
+        0,
+        1,
+    )
+}
+
+/// A view of `layer_count` layers of an image, starting at `base_layer`.
+pub fn new_layer_view(
+    device: &DeviceV1_0,
+    image: vk::Image,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+    view_type: vk::ImageViewType,
+    base_layer: u32,
+    layer_count: u32,
+) -> Result<vk::ImageView, Box<Error>> {
+    create_view(
+        device,
+        image,
+        format,
+        view_type,
+        vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: base_layer,
+            layer_count,
+        },
+    )
+}
+
+/// A view of all `mip_levels` mip levels of a 2D color image.
+pub fn new_mipmapped_view(
+    device: &DeviceV1_0,
+    image: vk::Image,
+    format: vk::Format,
+    mip_levels: u32,
+) -> Result<vk::ImageView, Box<Error>> {
+    create_view(
+        device,
+        image,
+        format,
+        vk::ImageViewType::Type2d,
+        vk::ImageSubresourceRange {
+            aspect_mask: vk::IMAGE_ASPECT_COLOR_BIT,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+    )
+}
+
+fn create_view(
+    device: &DeviceV1_0,
+    image: vk::Image,
+    format: vk::Format,
+    view_type: vk::ImageViewType,
+    subresource_range: vk::ImageSubresourceRange,
+) -> Result<vk::ImageView, Box<Error>> {
+    let image_view_info = vk::ImageViewCreateInfo {
+        s_type: vk::StructureType::ImageViewCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        view_type,
+        format,
+        components: vk::ComponentMapping {
+            r: vk::ComponentSwizzle::Identity,
+            g: vk::ComponentSwizzle::Identity,
+            b: vk::ComponentSwizzle::Identity,
+            a: vk::ComponentSwizzle::Identity,
+        },
+        subresource_range,
+        image,
+    };
+
+    let image_view = unsafe { device.create_image_view(&image_view_info, None)? };
+
+    Ok(image_view)
+}
@@ -6,7 +6,7 @@ use ash::extensions::{DebugReport, Surface, Win32Surface, XlibSurface};
 
 use std::ptr;
 use std::error::Error;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 
 pub fn new(entry: &Entry<V1_0>) -> Result<Instance<V1_0>, Box<Error>> {
     let app_name = CString::new("Xtreme Game")?;
@@ -21,9 +21,16 @@ pub fn new(entry: &Entry<V1_0>) -> Result<Instance<V1_0>, Box<Error>> {
         api_version: vk_make_version!(1, 0, 36),
     };
 
+    // Validation only runs where the layer is installed.
+    let available_layers: Vec<CString> = entry
+        .enumerate_instance_layer_properties()?
+        .iter()
+        .map(|layer| unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) }.to_owned())
+        .collect();
     let layer_names = [CString::new("VK_LAYER_LUNARG_standard_validation")?];
     let layers_names_raw: Vec<*const i8> = layer_names
         .iter()
+        .filter(|name| available_layers.contains(name))
         .map(|raw_name| raw_name.as_ptr())
         .collect();
 
@@ -35,11 +42,19 @@ pub fn new(entry: &Entry<V1_0>) -> Result<Instance<V1_0>, Box<Error>> {
         XlibSurface::name()
     };
 
-    let extension_names = vec![
-        Surface::name().as_ptr(),
-        os_surface.as_ptr(),
-        DebugReport::name().as_ptr(),
-    ];
+    // Drivers without surfaces, like software ones running headless, can
+    // still render offscreen, and ones without debug reports just don't
+    // report.
+    let available_extensions = available_extensions(entry)?;
+    let extension_names: Vec<*const i8> = [Surface::name(), os_surface, DebugReport::name()]
+        .iter()
+        .filter(|name| {
+            available_extensions
+                .iter()
+                .any(|available| available.as_c_str() == **name)
+        })
+        .map(|name| name.as_ptr())
+        .collect();
 
     let create_info = vk::InstanceCreateInfo {
         s_type: vk::StructureType::InstanceCreateInfo,
@@ -56,3 +71,20 @@ pub fn new(entry: &Entry<V1_0>) -> Result<Instance<V1_0>, Box<Error>> {
 
     Ok(instance)
 }
+
+/// Whether `new` enables `DebugReport`, which callbacks can only be set
+/// through if it did.
+pub fn has_debug_report(entry: &Entry<V1_0>) -> Result<bool, Box<Error>> {
+    Ok(available_extensions(entry)?
+        .iter()
+        .any(|available| available.as_c_str() == DebugReport::name()))
+}
+
+fn available_extensions(entry: &Entry<V1_0>) -> Result<Vec<CString>, Box<Error>> {
+    let extensions = entry
+        .enumerate_instance_extension_properties()?
+        .iter()
+        .map(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) }.to_owned())
+        .collect();
+    Ok(extensions)
+}
@@ -0,0 +1,446 @@
+use ash::vk;
+
+use std::slice;
+use std::error::Error;
+
+use renderer::{Indices, VertexData};
+use super::VkDevice;
+use super::graphics_pipeline::VertexFormat;
+use super::buffer::{Allocator, FreeList};
+use super::transfer::{UploadId, Uploader};
+
+const INITIAL_VERTEX_BUFFER_SIZE: u64 = 1024 * 1024;
+const INITIAL_INDEX_BUFFER_SIZE: u64 = 256 * 1024;
+/// Every vertex attribute format's size is a multiple of 4, so this keeps
+/// all of them aligned.
+const VERTEX_ALIGNMENT: u64 = 4;
+/// Index ranges are aligned to the larger index type so both can share the
+/// index buffer.
+const INDEX_ALIGNMENT: u64 = 4;
+
+/// Refers to a mesh uploaded to `MeshBuffers`. Stays valid through updates
+/// until the mesh is freed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshHandle(usize);
+
+/// Where a mesh lives in the shared buffers.
+#[derive(Debug, Clone, Copy)]
+pub struct Mesh {
+    /// Byte offset of the first vertex in the vertex buffer.
+    pub vertex_offset: u64,
+    pub vertex_count: u32,
+    pub vertex_stride: u32,
+    /// Index of the vertices' format in `MeshBuffers`.
+    vertex_format: usize,
+    /// Byte offset of the first index in the index buffer.
+    pub index_offset: u64,
+    /// Zero for meshes drawn without indices.
+    pub index_count: u32,
+    pub index_type: vk::IndexType,
+    /// Upload batch carrying the mesh's current data.
+    pub upload: UploadId,
+}
+
+impl Mesh {
+    fn vertex_size(&self) -> u64 {
+        self.vertex_count as u64 * self.vertex_stride as u64
+    }
+
+    fn index_size(&self) -> u64 {
+        self.index_count as u64 * index_type_size(self.index_type)
+    }
+}
+
+/// One vertex and one index buffer shared by every mesh, so drawing
+/// different meshes only needs different offsets. Both grow when they run
+/// out of space, which copies their contents into a bigger buffer. Data goes
+/// through an `Uploader`, so a mesh can only be drawn by submissions waiting
+/// on its semaphores.
+pub struct MeshBuffers {
+    vertex_buffer: usize,
+    vertex_ranges: FreeList,
+    index_buffer: usize,
+    index_ranges: FreeList,
+    meshes: Vec<Option<Mesh>>,
+    /// Vertex formats of the meshes, each stored once.
+    formats: Vec<VertexFormat>,
+}
+
+impl MeshBuffers {
+    pub fn new(device: &VkDevice, allocator: &mut Allocator) -> Result<MeshBuffers, Box<Error>> {
+        let vertex_buffer = new_device_buffer(
+            device,
+            allocator,
+            INITIAL_VERTEX_BUFFER_SIZE,
+            vk::BUFFER_USAGE_VERTEX_BUFFER_BIT,
+        )?;
+        let index_buffer = new_device_buffer(
+            device,
+            allocator,
+            INITIAL_INDEX_BUFFER_SIZE,
+            vk::BUFFER_USAGE_INDEX_BUFFER_BIT,
+        )?;
+
+        Ok(MeshBuffers {
+            vertex_buffer,
+            vertex_ranges: FreeList::new(INITIAL_VERTEX_BUFFER_SIZE),
+            index_buffer,
+            index_ranges: FreeList::new(INITIAL_INDEX_BUFFER_SIZE),
+            meshes: vec![],
+            formats: vec![],
+        })
+    }
+
+    pub fn vertex_buffer(&self, allocator: &Allocator) -> vk::Buffer {
+        allocator.buffer(self.vertex_buffer).buf
+    }
+
+    pub fn index_buffer(&self, allocator: &Allocator) -> vk::Buffer {
+        allocator.buffer(self.index_buffer).buf
+    }
+
+    pub fn mesh(&self, handle: MeshHandle) -> Option<&Mesh> {
+        self.meshes.get(handle.0).and_then(|slot| slot.as_ref())
+    }
+
+    /// Format pipelines drawing `mesh` read its vertices with.
+    pub fn vertex_format(&self, mesh: &Mesh) -> &VertexFormat {
+        &self.formats[mesh.vertex_format]
+    }
+
+    /// Whether the mesh fits without growing the buffers, which can't happen
+    /// while the GPU is using them.
+    pub fn has_room(&self, vertices: &VertexData, indices: Indices) -> bool {
+        let (vertex_size, index_size) = mesh_sizes(vertices, indices);
+        (vertex_size == 0 || self.vertex_ranges.can_allocate(vertex_size, VERTEX_ALIGNMENT))
+            && (index_size == 0 || self.index_ranges.can_allocate(index_size, INDEX_ALIGNMENT))
+    }
+
+    /// Queues copying the mesh into the shared buffers. Unless `has_room`,
+    /// the GPU must not be using the buffers since they get replaced.
+    pub fn upload(
+        &mut self,
+        device: &VkDevice,
+        allocator: &mut Allocator,
+        uploader: &mut Uploader,
+        vertices: &VertexData,
+        indices: Indices,
+    ) -> Result<MeshHandle, Box<Error>> {
+        let mut mesh = self.allocate(device, allocator, uploader, vertices, indices)?;
+        match self.write(device, allocator, uploader, &mesh, vertices, indices) {
+            Ok(upload) => mesh.upload = upload,
+            Err(err) => {
+                self.free_ranges(&mesh);
+                return Err(err);
+            }
+        }
+
+        let index = match self.meshes.iter().position(|slot| slot.is_none()) {
+            Some(index) => index,
+            None => {
+                self.meshes.push(None);
+                self.meshes.len() - 1
+            }
+        };
+        self.meshes[index] = Some(mesh);
+
+        Ok(MeshHandle(index))
+    }
+
+    /// Replaces the mesh's data. It stays in place if the sizes match and is
+    /// moved to new ranges otherwise. The GPU must be done drawing the mesh.
+    pub fn update(
+        &mut self,
+        device: &VkDevice,
+        allocator: &mut Allocator,
+        uploader: &mut Uploader,
+        handle: MeshHandle,
+        vertices: &VertexData,
+        indices: Indices,
+    ) -> Result<(), Box<Error>> {
+        let old_mesh = *self.mesh(handle)
+            .ok_or_else(|| format!("{:?} has been freed", handle))?;
+        // Copies in a batch aren't ordered, the old data has to land first.
+        uploader.wait(device, old_mesh.upload)?;
+
+        let fits = old_mesh.vertex_size() == vertices.bytes().len() as u64
+            && old_mesh.index_count as usize == indices.len()
+            && (indices.is_empty() || old_mesh.index_type == index_type(indices));
+        if fits {
+            let mut mesh = old_mesh;
+            mesh.vertex_count = vertices.len() as u32;
+            mesh.vertex_stride = vertices.layout().stride();
+            mesh.vertex_format = self.format_index(vertices);
+            mesh.upload = self.write(device, allocator, uploader, &mesh, vertices, indices)?;
+            self.meshes[handle.0] = Some(mesh);
+            return Ok(());
+        }
+
+        self.free_ranges(&old_mesh);
+        let mesh = self.allocate(device, allocator, uploader, vertices, indices)
+            .and_then(|mut mesh| {
+                match self.write(device, allocator, uploader, &mesh, vertices, indices) {
+                    Ok(upload) => {
+                        mesh.upload = upload;
+                        Ok(mesh)
+                    }
+                    Err(err) => {
+                        self.free_ranges(&mesh);
+                        Err(err)
+                    }
+                }
+            });
+        match mesh {
+            Ok(mesh) => {
+                self.meshes[handle.0] = Some(mesh);
+                Ok(())
+            }
+            Err(err) => {
+                self.meshes[handle.0] = None;
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns the mesh's ranges for reuse. The GPU must be done drawing it.
+    pub fn free(
+        &mut self,
+        device: &VkDevice,
+        uploader: &mut Uploader,
+        handle: MeshHandle,
+    ) -> Result<(), Box<Error>> {
+        if let Some(mesh) = self.meshes.get_mut(handle.0).and_then(|slot| slot.take()) {
+            // A new mesh in the same ranges mustn't race the old upload.
+            uploader.wait(device, mesh.upload)?;
+            self.free_ranges(&mesh);
+        }
+        Ok(())
+    }
+
+    /// Whether the mesh's data has reached the GPU buffers.
+    pub fn is_uploaded(
+        &self,
+        device: &VkDevice,
+        uploader: &mut Uploader,
+        handle: MeshHandle,
+    ) -> Result<bool, Box<Error>> {
+        let mesh = self.mesh(handle)
+            .ok_or_else(|| format!("{:?} has been freed", handle))?;
+        uploader.is_complete(device, mesh.upload)
+    }
+
+    /// Frees the buffers, all handles become invalid.
+    pub fn destroy(&mut self, device: &VkDevice, allocator: &mut Allocator) {
+        allocator.free_buffer(device, self.vertex_buffer);
+        allocator.free_buffer(device, self.index_buffer);
+        self.meshes.clear();
+    }
+
+    fn allocate(
+        &mut self,
+        device: &VkDevice,
+        allocator: &mut Allocator,
+        uploader: &mut Uploader,
+        vertices: &VertexData,
+        indices: Indices,
+    ) -> Result<Mesh, Box<Error>> {
+        let (vertex_size, index_size) = mesh_sizes(vertices, indices);
+
+        let vertex_offset = allocate_range(
+            device,
+            allocator,
+            uploader,
+            &mut self.vertex_buffer,
+            &mut self.vertex_ranges,
+            vertex_size,
+            VERTEX_ALIGNMENT,
+        )?;
+        let index_offset = allocate_range(
+            device,
+            allocator,
+            uploader,
+            &mut self.index_buffer,
+            &mut self.index_ranges,
+            index_size,
+            INDEX_ALIGNMENT,
+        );
+        let index_offset = match index_offset {
+            Ok(offset) => offset,
+            Err(err) => {
+                self.vertex_ranges.free(vertex_offset, vertex_size);
+                return Err(err);
+            }
+        };
+
+        Ok(Mesh {
+            vertex_offset,
+            vertex_count: vertices.len() as u32,
+            vertex_stride: vertices.layout().stride(),
+            vertex_format: self.format_index(vertices),
+            index_offset,
+            index_count: indices.len() as u32,
+            index_type: index_type(indices),
+            upload: UploadId::default(),
+        })
+    }
+
+    fn format_index(&mut self, vertices: &VertexData) -> usize {
+        let format = VertexFormat::new(vertices.layout());
+        match self.formats.iter().position(|known| *known == format) {
+            Some(index) => index,
+            None => {
+                self.formats.push(format);
+                self.formats.len() - 1
+            }
+        }
+    }
+
+    fn free_ranges(&mut self, mesh: &Mesh) {
+        if mesh.vertex_size() > 0 {
+            self.vertex_ranges.free(mesh.vertex_offset, mesh.vertex_size());
+        }
+        if mesh.index_size() > 0 {
+            self.index_ranges.free(mesh.index_offset, mesh.index_size());
+        }
+    }
+
+    fn write(
+        &self,
+        device: &VkDevice,
+        allocator: &Allocator,
+        uploader: &mut Uploader,
+        mesh: &Mesh,
+        vertices: &VertexData,
+        indices: Indices,
+    ) -> Result<UploadId, Box<Error>> {
+        let vertex_bytes = vertices.bytes();
+
+        let mut upload = UploadId::default();
+        if !vertex_bytes.is_empty() {
+            upload = uploader.upload(
+                device,
+                allocator,
+                vertex_bytes,
+                allocator.buffer(self.vertex_buffer).buf,
+                mesh.vertex_offset,
+            )?;
+        }
+        if !indices.is_empty() {
+            upload = uploader.upload(
+                device,
+                allocator,
+                index_bytes(indices),
+                allocator.buffer(self.index_buffer).buf,
+                mesh.index_offset,
+            )?;
+        }
+
+        Ok(upload)
+    }
+}
+
+pub fn index_type(indices: Indices) -> vk::IndexType {
+    match indices {
+        Indices::U32(_) => vk::IndexType::Uint32,
+        _ => vk::IndexType::Uint16,
+    }
+}
+
+fn mesh_sizes(vertices: &VertexData, indices: Indices) -> (u64, u64) {
+    (
+        vertices.bytes().len() as u64,
+        indices.len() as u64 * index_type_size(index_type(indices)),
+    )
+}
+
+fn index_type_size(index_type: vk::IndexType) -> u64 {
+    match index_type {
+        vk::IndexType::Uint32 => 4,
+        _ => 2,
+    }
+}
+
+fn index_bytes<'a>(indices: Indices<'a>) -> &'a [u8] {
+    unsafe {
+        match indices {
+            Indices::None => &[],
+            Indices::U16(indices) => {
+                slice::from_raw_parts(indices.as_ptr() as *const u8, indices.len() * 2)
+            }
+            Indices::U32(indices) => {
+                slice::from_raw_parts(indices.as_ptr() as *const u8, indices.len() * 4)
+            }
+        }
+    }
+}
+
+fn new_device_buffer(
+    device: &VkDevice,
+    allocator: &mut Allocator,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+) -> Result<usize, Box<Error>> {
+    // Transfer source so the contents can be moved when growing or
+    // defragmenting.
+    allocator.create_buffer(
+        device,
+        size,
+        usage | vk::BUFFER_USAGE_TRANSFER_SRC_BIT | vk::BUFFER_USAGE_TRANSFER_DST_BIT,
+        vk::MEMORY_PROPERTY_DEVICE_LOCAL_BIT,
+    )
+}
+
+/// Allocates `size` bytes from `ranges`, growing `buffer` if they don't fit.
+/// Empty ranges get offset 0 and aren't tracked.
+fn allocate_range(
+    device: &VkDevice,
+    allocator: &mut Allocator,
+    uploader: &mut Uploader,
+    buffer: &mut usize,
+    ranges: &mut FreeList,
+    size: u64,
+    alignment: u64,
+) -> Result<u64, Box<Error>> {
+    if size == 0 {
+        return Ok(0);
+    }
+    if let Some(offset) = ranges.allocate(size, alignment) {
+        return Ok(offset);
+    }
+
+    let old_size = ranges.size();
+    let mut new_size = old_size * 2;
+    while new_size < old_size + size + alignment {
+        new_size *= 2;
+    }
+
+    let (old_buf, usage) = {
+        let old_buffer = allocator.buffer(*buffer);
+        (old_buffer.buf, old_buffer.usage())
+    };
+    let new_buffer = allocator.create_buffer(device, new_size, usage, vk::MEMORY_PROPERTY_DEVICE_LOCAL_BIT)?;
+    // Pending writes to the old buffer have to land before it's copied, and
+    // the copy before the old buffer is freed.
+    let copied = uploader.wait_idle(device).and_then(|_| {
+        uploader.copy(
+            old_buf,
+            allocator.buffer(new_buffer).buf,
+            vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: 0,
+                size: old_size,
+            },
+        );
+        uploader.wait_idle(device)
+    });
+    if let Err(err) = copied {
+        allocator.free_buffer(device, new_buffer);
+        return Err(err);
+    }
+
+    allocator.free_buffer(device, *buffer);
+    *buffer = new_buffer;
+    ranges.grow(new_size);
+
+    Ok(ranges.allocate(size, alignment).unwrap())
+}
@@ -0,0 +1,2331 @@
+mod command;
+mod device;
+mod framebuffers;
+mod graphics_pipeline;
+mod pipelines;
+mod image_views;
+mod instance;
+mod physical_device;
+mod render_pass;
+mod semaphore;
+mod surface;
+mod swapchain;
+mod buffer;
+mod sync;
+mod render_target;
+mod descriptor;
+mod reflect;
+mod preprocess;
+mod uniform;
+mod mesh;
+mod transfer;
+mod shadow_map;
+mod scene_target;
+mod texture;
+
+use ash::vk;
+use ash::Entry;
+use ash::Instance;
+use ash::Device;
+use ash::version::{DeviceV1_0, InstanceV1_0, V1_0};
+use ash::extensions as ext;
+use winit;
+use image::RgbaImage;
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+
+use std::error::Error;
+use std::collections::HashMap;
+use std::ptr;
+use std::mem;
+use std::sync::LazyLock;
+use std::ffi::CStr;
+use std::path::Path;
+
+use renderer::{self, Blend, Camera, Cull, DepthFormat, FrameShadows, Indices, LightKind,
+               Material, PresentMode, RenderSettings, RenderState, Renderer, Semantic,
+               VertexData, VertexLayout, MAX_LIGHTS, MAX_PARAMETERS, MAX_RESOLUTION_SCALE,
+               MAX_SHADOW_MAPS, MIN_RESOLUTION_SCALE};
+use self::surface::Surface;
+use self::swapchain::Swapchain;
+use self::render_target::Readback;
+use self::mesh::{Mesh, MeshBuffers};
+use self::graphics_pipeline::{InstanceData, PipelineState, ShaderProgram, ShaderSet,
+                              VertexFormat};
+use self::pipelines::{PipelineKey, PipelineManager};
+use self::transfer::Uploader;
+use self::descriptor::{BindingType, FramePools, LayoutCache, Resource};
+use self::shadow_map::{ShadowMaps, ShadowPass};
+use self::scene_target::SceneTarget;
+use self::uniform::DynamicUniforms;
+use self::texture::Texture;
+
+pub use self::render_target::RenderTarget;
+pub use self::swapchain::SurfaceLost;
+pub use self::buffer::HeapStats;
+pub use self::mesh::MeshHandle;
+
+static VK_ENTRY: LazyLock<Entry<V1_0>> = LazyLock::new(|| Entry::new().unwrap());
+static VK_INSTANCE: LazyLock<Instance<V1_0>> =
+    LazyLock::new(|| instance::new(&VK_ENTRY).unwrap());
+
+type VkDevice = Device<V1_0>;
+
+/// How many frames the CPU may record ahead of the GPU.
+const FRAMES_IN_FLIGHT: usize = 2;
+
+/// Compiled pipelines are kept here between runs.
+const PIPELINE_CACHE_PATH: &str = "pipeline_cache.bin";
+
+/// Color format of headless renderers, which every device can render into
+/// and read back.
+const HEADLESS_FORMAT: vk::Format = vk::Format::R8g8b8a8Unorm;
+
+/// Resources owned by one of the frames in flight.
+struct FrameResources {
+    command_buffer: vk::CommandBuffer,
+    image_available: vk::Semaphore,
+    render_finished: vk::Semaphore,
+    /// Signaled once the GPU has executed the frame's commands.
+    fence: vk::Fence,
+    /// Copy of the frame requested through `capture_frame`, readable once
+    /// `fence` has been signaled.
+    readback: Option<Readback>,
+    /// Upload semaphores the frame waited on, handed back to the uploader
+    /// once `fence` has been signaled.
+    upload_semaphores: Vec<vk::Semaphore>,
+    /// Host visible buffer holding the frame's `CameraUniforms`.
+    uniform_buffer: usize,
+    /// Host visible buffer holding the frame's `LightingUniforms`.
+    lighting_buffer: usize,
+    /// Sets used by the frame, reset once `fence` has been signaled.
+    descriptor_pools: FramePools,
+    /// The frame's `Draw` blocks, bound per draw in the object set.
+    object_uniforms: DynamicUniforms,
+    /// Host visible vertex buffer with the frame's `InstanceData`, replaced
+    /// by a bigger one when a frame has more instances.
+    instance_buffer: usize,
+    /// Layers the frame draws its shadow maps into, sampled by its lighting.
+    shadow_maps: ShadowMaps,
+}
+
+/// Instances the instance buffers start out with room for.
+const INITIAL_INSTANCE_COUNT: u64 = 1024;
+
+/// Matches the `Camera` uniform block in the shaders, bound once per frame
+/// in set 0.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CameraUniforms {
+    view: [[f32; 4]; 4],
+    projection: [[f32; 4]; 4],
+}
+
+/// Matches the `Lighting` uniform block in the shaders, next to the camera
+/// in set 0.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LightingUniforms {
+    camera_position: [f32; 4],
+    light_count: u32,
+    _padding: [u32; 3],
+    lights: [LightUniforms; MAX_LIGHTS],
+    shadow_maps: [ShadowMapUniforms; MAX_SHADOW_MAPS],
+}
+
+/// Matches the `Light` struct in the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct LightUniforms {
+    /// `w` is the kind, see the `LIGHT_*` constants in the shaders.
+    position: [f32; 4],
+    /// `w` is the range.
+    direction: [f32; 4],
+    /// Color times intensity.
+    color: [f32; 4],
+    /// Cosines of a spot light's inner and outer angle.
+    spot: [f32; 4],
+    /// First shadow map and number of maps.
+    shadow: [f32; 4],
+}
+
+/// Matches the `ShadowMap` struct in the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct ShadowMapUniforms {
+    view_projection: [[f32; 4]; 4],
+    /// `x` is the split.
+    split: [f32; 4],
+}
+
+impl LightUniforms {
+    fn new(light: &renderer::Light, (first_map, map_count): (usize, usize)) -> LightUniforms {
+        let kind = match light.kind {
+            LightKind::Ambient => 0.0,
+            LightKind::Directional => 1.0,
+            LightKind::Point => 2.0,
+            LightKind::Spot { .. } => 3.0,
+        };
+        let (cos_inner, cos_outer) = light.kind.spot_cosines().unwrap_or((0.0, 0.0));
+        let color = light.color;
+        LightUniforms {
+            position: light.position.extend(kind).into(),
+            direction: light.direction.extend(light.range).into(),
+            color: [
+                color[0] * light.intensity,
+                color[1] * light.intensity,
+                color[2] * light.intensity,
+                1.0,
+            ],
+            spot: [cos_inner, cos_outer, 0.0, 0.0],
+            shadow: [first_map as f32, map_count as f32, 0.0, 0.0],
+        }
+    }
+}
+
+/// A material's parameters as the shaders' `Draw` block starts with them.
+type DrawParameters = [[f32; 4]; MAX_PARAMETERS];
+
+/// A material uploaded through `Renderer::update_material`.
+struct GpuMaterial {
+    /// Shaders models without normals are drawn with.
+    shaders: ShaderSet,
+    /// The `LIT` permutation of `shaders`, for models with normals.
+    lit_shaders: ShaderSet,
+    render_state: RenderState,
+    parameters: DrawParameters,
+    /// Uploaded textures by name, bound to the samplers of the same name in
+    /// the material set.
+    textures: Vec<(String, Texture)>,
+}
+
+impl GpuMaterial {
+    /// The GPU must be done with the material.
+    unsafe fn destroy(&self, device: &VkDevice) {
+        for (_, texture) in &self.textures {
+            texture.destroy(device);
+        }
+    }
+}
+
+/// Instances of a mesh drawn with the same pipeline and material, ready to be
+/// recorded as one instanced draw.
+struct Batch {
+    pipeline: vk::Pipeline,
+    /// Layout of `pipeline`, the batch's sets are bound through it.
+    layout: vk::PipelineLayout,
+    /// Material set of the batch, `None` if its shaders sample no textures.
+    material_set: Option<vk::DescriptorSet>,
+    handle: MeshHandle,
+    mesh: Mesh,
+    /// Object set holding the batch's `Draw` block and its dynamic offset.
+    object_set: vk::DescriptorSet,
+    object_offset: u32,
+    /// Index of the batch's first `InstanceData` in the instance buffer.
+    first_instance: u32,
+    instance_count: u32,
+}
+
+/// Instances of a mesh to draw with the same pipeline and material, before
+/// they are written to the frame's object uniforms.
+struct BatchInstances {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    material_set: Option<vk::DescriptorSet>,
+    handle: MeshHandle,
+    mesh: Mesh,
+    parameters: DrawParameters,
+    instances: Vec<InstanceData>,
+}
+
+/// What `prepare_frame` leaves for `record_frame` to draw.
+struct PreparedFrame {
+    frame_set: vk::DescriptorSet,
+    batches: Vec<Batch>,
+    /// View of each shadow map drawn this frame, by layer.
+    shadow_views: Vec<Matrix4<f32>>,
+    /// Shadow casters, drawn into every shadow map.
+    shadow_batches: Vec<Batch>,
+    /// Layout the shadow pipelines take their view through.
+    shadow_layout: vk::PipelineLayout,
+}
+
+impl FrameResources {
+    fn new(
+        device: &VkDevice,
+        allocator: &mut buffer::Allocator,
+        command_buffer: vk::CommandBuffer,
+        uniform_alignment: u64,
+        shadow_maps: ShadowMaps,
+    ) -> Result<FrameResources, Box<Error>> {
+        let uniform_buffer = allocator.create_buffer(
+            device,
+            mem::size_of::<CameraUniforms>() as u64,
+            vk::BUFFER_USAGE_UNIFORM_BUFFER_BIT,
+            vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT | vk::MEMORY_PROPERTY_HOST_COHERENT_BIT,
+        )?;
+        let lighting_buffer = allocator.create_buffer(
+            device,
+            mem::size_of::<LightingUniforms>() as u64,
+            vk::BUFFER_USAGE_UNIFORM_BUFFER_BIT,
+            vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT | vk::MEMORY_PROPERTY_HOST_COHERENT_BIT,
+        )?;
+
+        Ok(FrameResources {
+            command_buffer,
+            image_available: sync::create_semaphore(device)?,
+            render_finished: sync::create_semaphore(device)?,
+            fence: sync::create_fence(device, true)?,
+            readback: None,
+            upload_semaphores: vec![],
+            uniform_buffer,
+            lighting_buffer,
+            descriptor_pools: FramePools::new(),
+            object_uniforms: DynamicUniforms::new(
+                descriptor::DRAW_BINDING,
+                mem::size_of::<DrawParameters>() as u64,
+                uniform_alignment,
+            ),
+            instance_buffer: new_instance_buffer(device, allocator, INITIAL_INSTANCE_COUNT)?,
+            shadow_maps,
+        })
+    }
+
+    unsafe fn destroy(&mut self, device: &VkDevice, allocator: &mut buffer::Allocator) {
+        self.descriptor_pools.destroy(device);
+        self.object_uniforms.destroy(device, allocator);
+        allocator.free_buffer(device, self.instance_buffer);
+        self.shadow_maps.destroy(device);
+        allocator.free_buffer(device, self.uniform_buffer);
+        allocator.free_buffer(device, self.lighting_buffer);
+        device.destroy_semaphore(self.image_available, None);
+        device.destroy_semaphore(self.render_finished, None);
+        device.destroy_fence(self.fence, None);
+        if let Some(ref readback) = self.readback {
+            readback.destroy(device);
+        }
+    }
+}
+
+/// Where the frame between `begin_frame` and `end_frame` is drawn.
+#[derive(Clone, Copy)]
+enum FrameTarget {
+    /// Index of the acquired swapchain image.
+    Swapchain(u32),
+    /// Index into `render_targets`.
+    Offscreen(usize),
+    /// Nothing can be presented, e.g. while the window is minimized.
+    Skipped,
+}
+
+/// The window's surface and swapchain, with everything frames for it are
+/// drawn with. Headless renderers have none.
+struct Presenter {
+    surface: Surface,
+    swapchain: Swapchain,
+    /// Set when the swapchain no longer matches the surface, it is rebuilt
+    /// before the next frame.
+    outdated: bool,
+    images: Vec<vk::Image>,
+    image_views: Vec<vk::ImageView>,
+    /// Depth and the other images frames for the swapchain are drawn with,
+    /// sized after it.
+    scene: SceneTarget,
+    /// Fence of the frame last drawn into each swapchain image, or null.
+    images_in_flight: Vec<vk::Fence>,
+}
+
+impl Presenter {
+    fn new(
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        surface: Surface,
+        format: vk::SurfaceFormatKHR,
+        render_pass: vk::RenderPass,
+        settings: &RenderSettings,
+    ) -> Result<Presenter, Box<Error>> {
+        let swapchain = Swapchain::new(
+            device,
+            physical_device,
+            &surface,
+            format,
+            present_mode(settings.present_mode),
+            vk::SwapchainKHR::null(),
+        )?;
+        let SwapchainResources {
+            images,
+            image_views,
+            scene,
+        } = SwapchainResources::new(device, physical_device, &swapchain, render_pass, settings)?;
+
+        Ok(Presenter {
+            images_in_flight: vec![vk::Fence::null(); images.len()],
+            surface,
+            swapchain,
+            outdated: false,
+            images,
+            image_views,
+            scene,
+        })
+    }
+
+    /// Rebuilds the swapchain and everything sized after it. Does nothing
+    /// and returns `false` while the surface has no area, which is the case
+    /// when the window is minimized. The pipeline needs no rebuild since its
+    /// viewport is set at draw time.
+    fn rebuild(
+        &mut self,
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+        settings: &RenderSettings,
+        old_swapchain: vk::SwapchainKHR,
+    ) -> Result<bool, Box<Error>> {
+        let extent = self.surface.extent(physical_device)?;
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(false);
+        }
+
+        device.device_wait_idle()?;
+
+        let swapchain = Swapchain::new(
+            device,
+            physical_device,
+            &self.surface,
+            self.swapchain.format(),
+            present_mode(settings.present_mode),
+            old_swapchain,
+        )?;
+        // The old swapchain is only replaced once everything for the new one
+        // has been made.
+        let SwapchainResources {
+            images,
+            image_views,
+            scene,
+        } = SwapchainResources::new(device, physical_device, &swapchain, render_pass, settings)?;
+
+        // Everything is idle, so no image is in use anymore.
+        self.images_in_flight = vec![vk::Fence::null(); images.len()];
+
+        unsafe { self.destroy_swapchain(device) };
+
+        self.swapchain = swapchain;
+        self.images = images;
+        self.image_views = image_views;
+        self.scene = scene;
+
+        Ok(true)
+    }
+
+    /// The image captures of the frame for the swapchain image at
+    /// `image_index` copy, and its layout after the frame's render pass.
+    fn frame_image(&self, image_index: u32) -> (vk::Image, vk::ImageLayout) {
+        match self.scene.scaled_image() {
+            Some(image) => (image, vk::ImageLayout::TransferSrcOptimal),
+            None => (
+                self.images[image_index as usize],
+                vk::ImageLayout::PresentSrcKhr,
+            ),
+        }
+    }
+
+    fn can_capture(&self) -> bool {
+        self.scene.scaled_image().is_some() || self.swapchain.supports_readback()
+    }
+
+    unsafe fn destroy_swapchain(&self, device: &VkDevice) {
+        self.scene.destroy(device);
+        for &image_view in &self.image_views {
+            device.destroy_image_view(image_view, None);
+        }
+        self.swapchain.destroy();
+    }
+}
+
+/// What a `Presenter` draws into a swapchain with.
+struct SwapchainResources {
+    images: Vec<vk::Image>,
+    image_views: Vec<vk::ImageView>,
+    scene: SceneTarget,
+}
+
+impl SwapchainResources {
+    /// The images of `swapchain` with views of them and the scene target
+    /// drawing into them. The swapchain is destroyed along with whatever was
+    /// made for it if anything can't be made.
+    fn new(
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        swapchain: &Swapchain,
+        render_pass: vk::RenderPass,
+        settings: &RenderSettings,
+    ) -> Result<SwapchainResources, Box<Error>> {
+        let images = swapchain.images();
+        let image_views = images.and_then(|images| {
+            let image_views = image_views::new(device, &images, &swapchain.format())?;
+            Ok((images, image_views))
+        });
+        let (images, image_views) = match image_views {
+            Ok(created) => created,
+            Err(err) => {
+                unsafe { swapchain.destroy() };
+                return Err(err);
+            }
+        };
+
+        let scene = new_scene_target(
+            device,
+            physical_device,
+            render_pass,
+            swapchain,
+            &image_views,
+            settings,
+        );
+        match scene {
+            Ok(scene) => Ok(SwapchainResources {
+                images,
+                image_views,
+                scene,
+            }),
+            Err(err) => {
+                unsafe {
+                    for &image_view in &image_views {
+                        device.destroy_image_view(image_view, None);
+                    }
+                    swapchain.destroy();
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+pub struct VulkanRenderer {
+    device: VkDevice,
+
+    physical_device: vk::PhysicalDevice,
+    graphics_queue: vk::Queue,
+
+    /// `None` where the instance has no `DebugReport`.
+    debug_report_loader: Option<ext::DebugReport>,
+    debug_callback: Option<vk::DebugReportCallbackEXT>,
+
+    /// `None` for headless renderers.
+    presenter: Option<Presenter>,
+    /// Format frames are drawn in, the swapchain's or `HEADLESS_FORMAT`.
+    color_format: vk::Format,
+    /// Draws frames for the swapchain, made for the current `settings`.
+    render_pass: vk::RenderPass,
+    /// Applied through `change_settings`, limited to what the device can do.
+    settings: RenderSettings,
+    /// Changed while a frame was being recorded, applied by the next
+    /// `begin_frame`.
+    pending_settings: Option<RenderSettings>,
+    pipelines: PipelineManager,
+    /// Materials uploaded through `Renderer::update_material`, by id.
+    materials: HashMap<u32, GpuMaterial>,
+    /// Material textures are sampled through it.
+    texture_sampler: vk::Sampler,
+    /// Depth only shaders drawing casters into shadow maps.
+    shadow_shaders: ShaderSet,
+    shadow_pass: ShadowPass,
+    descriptor_layouts: LayoutCache,
+    /// Layouts of the default shaders' sets, starting with the per-frame one.
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+
+    command_pool: vk::CommandPool,
+    frames: Vec<FrameResources>,
+    /// Index into `frames` of the frame being recorded.
+    current_frame: usize,
+
+    /// Set in `begin_frame`, `None` outside a frame.
+    frame: Option<FrameTarget>,
+    /// Model, material and instance of every draw.
+    draw_list: Vec<(u32, u32, renderer::Instance)>,
+    /// Set through `set_camera`, reset every frame.
+    camera: Option<Camera>,
+    /// Set through `set_lights`, reset every frame.
+    lights: Vec<renderer::Light>,
+
+    render_targets: Vec<RenderTarget>,
+    /// Render target used by the next frames instead of the swapchain.
+    active_target: Option<usize>,
+    capture_requested: bool,
+    /// The last capture, or why it couldn't be made.
+    captured_frame: Option<Result<RgbaImage, Box<Error>>>,
+
+    allocator: buffer::Allocator,
+    uploader: Uploader,
+    meshes: MeshBuffers,
+    /// Meshes uploaded through `Renderer::update_model`, by model id.
+    models: HashMap<u32, MeshHandle>,
+}
+
+impl VulkanRenderer {
+    pub fn new(window: &winit::Window) -> Result<VulkanRenderer, Box<Error>> {
+        VulkanRenderer::with_surface(Some(Surface::new(window)?))
+    }
+
+    /// A renderer without a window or surface, drawing every frame into a
+    /// `width` by `height` render target read back with `read_render_target`
+    /// or `capture_frame`. Works on devices that can't present at all, like
+    /// software drivers.
+    pub fn headless(width: u32, height: u32) -> Result<VulkanRenderer, Box<Error>> {
+        let mut renderer = VulkanRenderer::with_surface(None)?;
+        let target = renderer.create_render_target(width, height)?;
+        renderer.set_render_target(Some(target))?;
+
+        Ok(renderer)
+    }
+
+    fn with_surface(surface: Option<Surface>) -> Result<VulkanRenderer, Box<Error>> {
+        let (debug_report_loader, debug_callback) = match set_debug_callback()? {
+            Some((loader, callback)) => (Some(loader), Some(callback)),
+            None => (None, None),
+        };
+
+        let physical_device = physical_device::new()?;
+
+        let surface_format = match surface {
+            Some(ref surface) => Some(surface.format(physical_device)?),
+            None => None,
+        };
+        let color_format = surface_format
+            .as_ref()
+            .map_or(HEADLESS_FORMAT, |format| format.format);
+
+        let graphics_queue_index =
+            physical_device::graphics_queue_index(physical_device, surface.as_ref())
+                .ok_or("Couldn't find a queue family supporting graphics and presentation")?;
+
+        let transfer_queue_index = physical_device::transfer_queue_index(physical_device);
+
+        let device = device::new(
+            graphics_queue_index,
+            transfer_queue_index,
+            physical_device,
+            surface.is_some(),
+        )?;
+
+        let graphics_queue = unsafe { device.get_device_queue(graphics_queue_index, 0) };
+        // Without a dedicated family uploads share the graphics queue.
+        let transfer_queue = match transfer_queue_index {
+            Some(index) => unsafe { device.get_device_queue(index, 0) },
+            None => graphics_queue,
+        };
+
+        let settings = RenderSettings::default();
+        let render_pass = new_render_pass(&device, color_format, &settings)?;
+        let presenter = match (surface, surface_format) {
+            (Some(surface), Some(format)) => Some(Presenter::new(
+                &device,
+                physical_device,
+                surface,
+                format,
+                render_pass,
+                &settings,
+            )?),
+            _ => None,
+        };
+
+        let properties = VK_INSTANCE.get_physical_device_properties(physical_device);
+        let features = VK_INSTANCE.get_physical_device_features(physical_device);
+        let mut pipelines = PipelineManager::new(
+            &device,
+            &properties,
+            features.fill_mode_non_solid != 0,
+            Path::new(PIPELINE_CACHE_PATH),
+        )?;
+
+        // The default shaders are compiled up front, the frame and object
+        // sets are allocated with their set layouts.
+        let mut descriptor_layouts = LayoutCache::new();
+        let shaders = ShaderSet::default();
+        let set_layouts = pipelines
+            .program(&device, &mut descriptor_layouts, &shaders)?
+            .set_layouts
+            .clone();
+        pipelines.get(
+            &device,
+            &mut descriptor_layouts,
+            &PipelineKey {
+                shaders: shaders.clone(),
+                vertex_format: VertexFormat::new(&VertexLayout::position_color()),
+                state: PipelineState::new(RenderState::default())
+                    .with_samples(sample_count(settings.samples)),
+                render_pass,
+            },
+        )?;
+
+        let command_pool = command::create_pool(&device, graphics_queue_index)?;
+
+        let shadow_pass = ShadowPass::new(&device)?;
+        let texture_sampler = texture::new_sampler(&device)?;
+
+        let queue_families: Vec<u32> = Some(graphics_queue_index)
+            .into_iter()
+            .chain(transfer_queue_index)
+            .collect();
+        let mut allocator = buffer::Allocator::new(physical_device, &queue_families);
+
+        let uniform_alignment = properties.limits.min_uniform_buffer_offset_alignment;
+
+        let frames = command::alloc_buffers(&device, command_pool, FRAMES_IN_FLIGHT as u32)?
+            .into_iter()
+            .map(|command_buffer| {
+                let shadow_maps = ShadowMaps::new(
+                    &device,
+                    physical_device,
+                    command_pool,
+                    graphics_queue,
+                    &shadow_pass,
+                )?;
+                FrameResources::new(
+                    &device,
+                    &mut allocator,
+                    command_buffer,
+                    uniform_alignment,
+                    shadow_maps,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let uploader = Uploader::new(
+            &device,
+            &mut allocator,
+            transfer_queue,
+            transfer_queue_index.unwrap_or(graphics_queue_index),
+        )?;
+
+        let meshes = MeshBuffers::new(&device, &mut allocator)?;
+
+        Ok(VulkanRenderer {
+            device,
+
+            physical_device,
+            graphics_queue,
+
+            debug_report_loader,
+            debug_callback,
+
+            presenter,
+            color_format,
+            render_pass,
+            settings,
+            pending_settings: None,
+            pipelines,
+            materials: HashMap::new(),
+            texture_sampler,
+            shadow_shaders: ShaderSet::new("data/shaders/shadow.vert", "data/shaders/shadow.frag"),
+            shadow_pass,
+            descriptor_layouts,
+            set_layouts,
+
+            command_pool,
+            frames,
+            current_frame: 0,
+
+            frame: None,
+            draw_list: vec![],
+            camera: None,
+            lights: vec![],
+
+            render_targets: vec![],
+            active_target: None,
+            capture_requested: false,
+            captured_frame: None,
+
+            allocator,
+            uploader,
+            meshes,
+            models: HashMap::new(),
+        })
+    }
+
+    /// Replaces the surface after a `SurfaceLost` error.
+    pub fn recreate_surface(&mut self, window: &winit::Window) -> Result<(), Box<Error>> {
+        let presenter = self.presenter
+            .as_mut()
+            .ok_or("Headless renderers have no surface")?;
+        let old_surface = mem::replace(&mut presenter.surface, Surface::new(window)?);
+
+        // The old swapchain belongs to the old surface, so it can't be passed
+        // on to the new one.
+        let rebuilt = presenter.rebuild(
+            &self.device,
+            self.physical_device,
+            self.render_pass,
+            &self.settings,
+            vk::SwapchainKHR::null(),
+        );
+        if let Ok(true) = rebuilt {
+            unsafe { old_surface.destroy() };
+            presenter.outdated = false;
+            return Ok(());
+        }
+
+        let new_surface = mem::replace(&mut presenter.surface, old_surface);
+        unsafe { new_surface.destroy() };
+        match rebuilt {
+            Err(err) => Err(err),
+            _ => Err("Can't recreate the surface while the window is minimized".into()),
+        }
+    }
+
+    fn acquire_swapchain_image(&mut self) -> Result<FrameTarget, Box<Error>> {
+        let presenter = self.presenter
+            .as_mut()
+            .ok_or("Headless renderers only draw into render targets")?;
+
+        if presenter.outdated {
+            let old_swapchain = presenter.swapchain.handle();
+            let rebuilt = presenter.rebuild(
+                &self.device,
+                self.physical_device,
+                self.render_pass,
+                &self.settings,
+                old_swapchain,
+            )?;
+            if !rebuilt {
+                return Ok(FrameTarget::Skipped);
+            }
+            presenter.outdated = false;
+        }
+
+        let image_available = self.frames[self.current_frame].image_available;
+        let image_index = match presenter.swapchain.acquire_next_image(image_available)? {
+            Some(image_index) => image_index,
+            None => {
+                presenter.outdated = true;
+                return Ok(FrameTarget::Skipped);
+            }
+        };
+
+        // With more frames in flight than swapchain images, or images coming
+        // back out of order, an older frame may still be drawing into it.
+        let image_fence = presenter.images_in_flight[image_index as usize];
+        if image_fence != vk::Fence::null() {
+            unsafe {
+                self.device
+                    .wait_for_fences(&[image_fence], true, u64::MAX)?
+            };
+        }
+
+        Ok(FrameTarget::Swapchain(image_index))
+    }
+
+    /// Waits until the GPU is done with the frame at `index` so its resources
+    /// can be reused, and picks up the capture it made.
+    fn finish_frame(&mut self, index: usize) -> Result<(), Box<Error>> {
+        unsafe {
+            self.device
+                .wait_for_fences(&[self.frames[index].fence], true, u64::MAX)?
+        };
+
+        self.uploader
+            .recycle_semaphores(&mut self.frames[index].upload_semaphores);
+
+        if let Some(readback) = self.frames[index].readback.take() {
+            let captured = readback.read(&self.device);
+            unsafe { readback.destroy(&self.device) };
+            self.captured_frame = Some(captured);
+        }
+
+        Ok(())
+    }
+
+    fn finish_all_frames(&mut self) -> Result<(), Box<Error>> {
+        for index in 0..self.frames.len() {
+            self.finish_frame(index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Records and submits the frame begun for `frame`. On error, the
+    /// frame's readback and the semaphores it was to wait on are left in its
+    /// `FrameResources` for `abandon_frame`.
+    fn submit_frame(&mut self, frame: FrameTarget) -> Result<(), Box<Error>> {
+        // `image` is what captures copy, frames drawn at another size than
+        // the swapchain's are captured at the size they were drawn at.
+        let (render_pass, samples, framebuffer, extent, format, image, final_layout) = match frame {
+            FrameTarget::Swapchain(image_index) => {
+                let presenter = self.presenter
+                    .as_ref()
+                    .ok_or("Swapchain frame without a swapchain")?;
+                let (image, final_layout) = presenter.frame_image(image_index);
+                (
+                    self.render_pass,
+                    sample_count(self.settings.samples),
+                    presenter.scene.framebuffer(image_index as usize),
+                    presenter.scene.extent(),
+                    self.color_format,
+                    image,
+                    final_layout,
+                )
+            }
+            FrameTarget::Offscreen(index) => {
+                let target = &self.render_targets[index];
+                (
+                    target.render_pass(),
+                    vk::SAMPLE_COUNT_1_BIT,
+                    target.framebuffer(),
+                    target.extent(),
+                    target.format(),
+                    target.color_image(),
+                    vk::ImageLayout::ShaderReadOnlyOptimal,
+                )
+            }
+            FrameTarget::Skipped => return Ok(()),
+        };
+
+        // A failed capture is reported through `take_captured_frame`, the
+        // frame is drawn and presented all the same.
+        let readback = if self.capture_requested {
+            self.capture_requested = false;
+            let readback = match frame {
+                FrameTarget::Swapchain(_)
+                    if !self.presenter.as_ref().is_some_and(Presenter::can_capture) =>
+                {
+                    Err("The swapchain doesn't allow copying frames".into())
+                }
+                _ => Readback::new(&self.device, self.physical_device, extent, format),
+            };
+            readback.map_err(|err| self.captured_frame = Some(Err(err))).ok()
+        } else {
+            None
+        };
+        // Stored right away so `abandon_frame` finds them if anything below
+        // fails.
+        self.frames[self.current_frame].readback = readback;
+
+        let prepared = self.prepare_frame(render_pass, samples, extent)?;
+
+        // Uploads queued since the last frame start now, the frame waits for
+        // them before reading vertices.
+        self.uploader.flush(&self.device)?;
+        let upload_semaphores = self.uploader.take_wait_semaphores();
+        self.frames[self.current_frame]
+            .upload_semaphores
+            .extend(upload_semaphores.iter().cloned());
+
+        // Scaled frames only touch the swapchain image in their final blit.
+        let scaled = self.presenter
+            .as_ref()
+            .and_then(|presenter| presenter.scene.scaled_image());
+        let present_stage = match scaled {
+            Some(_) => vk::PIPELINE_STAGE_TRANSFER_BIT,
+            None => vk::PIPELINE_STAGE_COLOR_ATTACHMENT_OUTPUT_BIT,
+        };
+        let resources = &self.frames[self.current_frame];
+        let (mut wait_semaphores, mut wait_mask, signal_semaphores) = match frame {
+            FrameTarget::Swapchain(_) => (
+                vec![resources.image_available],
+                vec![present_stage],
+                vec![resources.render_finished],
+            ),
+            _ => (vec![], vec![], vec![]),
+        };
+        wait_semaphores.extend(upload_semaphores.iter().cloned());
+        wait_mask.extend(upload_semaphores.iter().map(|_| vk::PIPELINE_STAGE_VERTEX_INPUT_BIT));
+
+        command::record(&self.device, resources.command_buffer, |device, command_buffer| {
+            self.record_frame(
+                device,
+                command_buffer,
+                render_pass,
+                framebuffer,
+                extent,
+                &prepared,
+            );
+            if let (FrameTarget::Swapchain(image_index), Some(presenter)) =
+                (frame, self.presenter.as_ref())
+            {
+                presenter.scene.record_blit(
+                    device,
+                    command_buffer,
+                    presenter.images[image_index as usize],
+                    presenter.swapchain.extent(),
+                );
+            }
+            if let Some(ref readback) = resources.readback {
+                readback.record_copy(device, command_buffer, image, final_layout);
+            }
+        })?;
+
+        // A failed submission leaves the fence unsignaled, `abandon_frame`
+        // signals it again.
+        unsafe { self.device.reset_fences(&[resources.fence])? };
+        command::submit_with_fence(
+            &self.device,
+            resources.command_buffer,
+            self.graphics_queue,
+            &wait_mask,
+            &wait_semaphores,
+            &signal_semaphores,
+            resources.fence,
+        )?;
+
+        Ok(())
+    }
+
+    /// Presents the swapchain image the frame at `index` was drawn for.
+    fn present_frame(&mut self, index: usize, image_index: u32) -> Result<(), Box<Error>> {
+        let fence = self.frames[index].fence;
+        let render_finished = self.frames[index].render_finished;
+        let presented = {
+            let presenter = self.presenter
+                .as_mut()
+                .ok_or("Swapchain frame without a swapchain")?;
+            presenter.images_in_flight[image_index as usize] = fence;
+
+            let presented = presenter.swapchain.present(
+                self.graphics_queue,
+                image_index,
+                &[render_finished],
+            );
+            // The image goes back with the swapchain if it wasn't presented.
+            match presented {
+                Ok(true) | Err(_) => presenter.outdated = true,
+                Ok(false) => {}
+            }
+            presented
+        };
+
+        match presented {
+            Ok(_) => Ok(()),
+            // Presents failing with a lost surface still wait on the
+            // semaphore, others leave it signaled.
+            Err(err) => {
+                if !err.is::<SurfaceLost>() {
+                    self.replace_frame_sync(index)?;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Hands back what the current frame held when it failed before being
+    /// submitted. An empty submission waits on its semaphores and signals
+    /// its fence in its place, and the swapchain is rebuilt to release the
+    /// image acquired for it.
+    fn abandon_frame(&mut self, frame: FrameTarget) {
+        let index = self.current_frame;
+        if let Some(readback) = self.frames[index].readback.take() {
+            unsafe { readback.destroy(&self.device) };
+            self.captured_frame = Some(Err("The captured frame couldn't be drawn".into()));
+        }
+
+        let resources = &self.frames[index];
+        let mut wait_semaphores = resources.upload_semaphores.clone();
+        if let FrameTarget::Swapchain(_) = frame {
+            wait_semaphores.push(resources.image_available);
+            if let Some(ref mut presenter) = self.presenter {
+                presenter.outdated = true;
+            }
+        }
+        let wait_mask = vec![vk::PIPELINE_STAGE_ALL_COMMANDS_BIT; wait_semaphores.len()];
+
+        let submitted = unsafe { self.device.reset_fences(&[resources.fence]) }
+            .map_err(Box::<Error>::from)
+            .and_then(|_| {
+                command::submit_waits(
+                    &self.device,
+                    self.graphics_queue,
+                    &wait_mask,
+                    &wait_semaphores,
+                    resources.fence,
+                )
+            });
+        // The frame's error is the one reported, this is only cleanup.
+        if submitted.is_err() {
+            let _ = self.replace_frame_sync(index);
+        }
+    }
+
+    /// Replaces the semaphores and fence of the frame at `index` after an
+    /// error left them in a state nothing would clear. The replacement
+    /// fence starts out signaled.
+    fn replace_frame_sync(&mut self, index: usize) -> Result<(), Box<Error>> {
+        self.device.device_wait_idle()?;
+
+        let image_available = sync::create_semaphore(&self.device)?;
+        let render_finished = sync::create_semaphore(&self.device)?;
+        let fence = sync::create_fence(&self.device, true)?;
+
+        let resources = &mut self.frames[index];
+        if let Some(ref mut presenter) = self.presenter {
+            for image_fence in &mut presenter.images_in_flight {
+                if *image_fence == resources.fence {
+                    *image_fence = vk::Fence::null();
+                }
+            }
+        }
+        unsafe {
+            self.device.destroy_semaphore(resources.image_available, None);
+            self.device.destroy_semaphore(resources.render_finished, None);
+            self.device.destroy_fence(resources.fence, None);
+            // They may be left signaled, so they can't go back to the uploader.
+            for semaphore in resources.upload_semaphores.drain(..) {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+        }
+        resources.image_available = image_available;
+        resources.render_finished = render_finished;
+        resources.fence = fence;
+
+        Ok(())
+    }
+
+    /// Recreates the render pass for supported `settings` and has the
+    /// swapchain rebuilt. Must not be called while a frame is recorded.
+    fn apply_settings(&mut self, settings: RenderSettings) -> Result<(), Box<Error>> {
+        if settings == self.settings {
+            return Ok(());
+        }
+
+        self.finish_all_frames()?;
+        self.device.device_wait_idle()?;
+
+        let render_pass = new_render_pass(&self.device, self.color_format, &settings)?;
+        unsafe {
+            self.pipelines.forget_render_pass(&self.device, self.render_pass);
+            self.device.destroy_render_pass(self.render_pass, None);
+        }
+        self.render_pass = render_pass;
+        self.settings = settings;
+        if let Some(ref mut presenter) = self.presenter {
+            presenter.outdated = true;
+        }
+
+        Ok(())
+    }
+
+    /// `settings` with what the device and surface can't do replaced by the
+    /// nearest they can.
+    fn supported_settings(&self, settings: &RenderSettings) -> Result<RenderSettings, Box<Error>> {
+        let limits = VK_INSTANCE
+            .get_physical_device_properties(self.physical_device)
+            .limits;
+        let sample_counts = limits.framebuffer_color_sample_counts
+            & limits.framebuffer_depth_sample_counts;
+        // The most samples the device has without going over the request.
+        let mut samples = 64;
+        while samples > 1
+            && (samples > settings.samples || !sample_counts.subset(sample_count(samples)))
+        {
+            samples /= 2;
+        }
+
+        let present_modes = match self.presenter {
+            Some(ref presenter) => presenter.surface.present_modes(self.physical_device)?,
+            // Nothing is presented, so any mode will do.
+            None => vec![present_mode(settings.present_mode)],
+        };
+        let present_mode = if present_modes.contains(&present_mode(settings.present_mode)) {
+            settings.present_mode
+        } else {
+            PresentMode::Vsync
+        };
+
+        // Scaled frames are blitted onto the swapchain images. A scale that
+        // isn't a number draws at full resolution.
+        let supports_blit = self.presenter
+            .as_ref()
+            .is_none_or(|presenter| presenter.swapchain.supports_blit());
+        let resolution_scale = if supports_blit && !settings.resolution_scale.is_nan() {
+            settings
+                .resolution_scale
+                .clamp(MIN_RESOLUTION_SCALE, MAX_RESOLUTION_SCALE)
+        } else {
+            1.0
+        };
+
+        let depth_features = VK_INSTANCE
+            .get_physical_device_format_properties(
+                self.physical_device,
+                depth_format(settings.depth_format),
+            )
+            .optimal_tiling_features;
+        let renders_depth = depth_features.subset(vk::FORMAT_FEATURE_DEPTH_STENCIL_ATTACHMENT_BIT);
+        let depth_format = if renders_depth {
+            settings.depth_format
+        } else {
+            DepthFormat::D16
+        };
+
+        Ok(RenderSettings {
+            samples,
+            present_mode,
+            resolution_scale,
+            depth_format,
+        })
+    }
+
+    /// Creates an offscreen color/depth target in the format frames are drawn
+    /// in and returns its index.
+    pub fn create_render_target(&mut self, width: u32, height: u32) -> Result<usize, Box<Error>> {
+        let target = RenderTarget::new(
+            &self.device,
+            self.physical_device,
+            vk::Extent2D { width, height },
+            self.color_format,
+        )?;
+        self.render_targets.push(target);
+
+        Ok(self.render_targets.len() - 1)
+    }
+
+    pub fn render_target(&self, index: usize) -> &RenderTarget {
+        &self.render_targets[index]
+    }
+
+    /// Makes the following frames render into a render target, or back into
+    /// the swapchain with `None`. Frames drawn into a target aren't presented.
+    pub fn set_render_target(&mut self, target: Option<usize>) -> Result<(), Box<Error>> {
+        match target {
+            Some(index) if index >= self.render_targets.len() => {
+                return Err(format!("Render target {} doesn't exist", index).into());
+            }
+            None if self.presenter.is_none() => {
+                return Err("Headless renderers only draw into render targets".into());
+            }
+            _ => (),
+        }
+        self.active_target = target;
+
+        Ok(())
+    }
+
+    /// Copies the last frame drawn into a render target back to host memory.
+    pub fn read_render_target(&self, index: usize) -> Result<RgbaImage, Box<Error>> {
+        let target = &self.render_targets[index];
+        let readback = Readback::new(
+            &self.device,
+            self.physical_device,
+            target.extent(),
+            target.format(),
+        )?;
+
+        let result = command::alloc_buffers(&self.device, self.command_pool, 1).and_then(
+            |command_buffers| {
+                let submitted = command::submit(
+                    &self.device,
+                    command_buffers[0],
+                    self.graphics_queue,
+                    &[],
+                    &[],
+                    &[],
+                    |device, command_buffer| {
+                        readback.record_copy(
+                            device,
+                            command_buffer,
+                            target.color_image(),
+                            vk::ImageLayout::ShaderReadOnlyOptimal,
+                        )
+                    },
+                );
+                unsafe {
+                    self.device
+                        .free_command_buffers(self.command_pool, &command_buffers)
+                };
+                submitted?;
+                readback.read(&self.device)
+            },
+        );
+
+        unsafe { readback.destroy(&self.device) };
+        result
+    }
+
+    /// Compacts GPU memory by moving buffers out of sparsely used blocks, see
+    /// `buffer::Allocator::defragment`. Returns how many buffers were moved.
+    pub fn defragment_memory(&mut self) -> Result<u32, Box<Error>> {
+        self.finish_all_frames()?;
+        self.uploader.wait_idle(&self.device)?;
+
+        let device = &self.device;
+        let command_pool = self.command_pool;
+        let queue = self.graphics_queue;
+        self.allocator.defragment(device, |old, new| {
+            buffer::copy_buffer(device, command_pool, queue, old.buf, new.buf, old.size)
+        })
+    }
+
+    /// Queues uploading a mesh into the shared vertex and index buffers. The
+    /// upload runs on the transfer queue, frames drawing the mesh wait for
+    /// it on the GPU, see `mesh_uploaded` to poll it.
+    pub fn upload_mesh(
+        &mut self,
+        vertices: &VertexData,
+        indices: Indices,
+    ) -> Result<MeshHandle, Box<Error>> {
+        self.check_mesh(vertices, indices)?;
+        // Growing the shared buffers replaces them under frames in flight.
+        if !self.meshes.has_room(vertices, indices) {
+            self.finish_all_frames()?;
+        }
+
+        self.meshes.upload(
+            &self.device,
+            &mut self.allocator,
+            &mut self.uploader,
+            vertices,
+            indices,
+        )
+    }
+
+    pub fn update_mesh(
+        &mut self,
+        handle: MeshHandle,
+        vertices: &VertexData,
+        indices: Indices,
+    ) -> Result<(), Box<Error>> {
+        self.check_mesh(vertices, indices)?;
+        self.finish_all_frames()?;
+
+        self.meshes.update(
+            &self.device,
+            &mut self.allocator,
+            &mut self.uploader,
+            handle,
+            vertices,
+            indices,
+        )
+    }
+
+    /// Fails for indices past the last vertex and for attribute formats the
+    /// device can't read vertices in, of the ones in `AttributeFormat` only
+    /// `Snorm10x3` is optional.
+    fn check_mesh(&self, vertices: &VertexData, indices: Indices) -> Result<(), Box<Error>> {
+        let max_index = match indices {
+            Indices::None => None,
+            Indices::U16(indices) => indices.iter().map(|&index| index as u32).max(),
+            Indices::U32(indices) => indices.iter().cloned().max(),
+        };
+        if let Some(index) = max_index.filter(|&index| index as usize >= vertices.len()) {
+            return Err(format!(
+                "Mesh has index {} but only {} vertices",
+                index,
+                vertices.len()
+            ).into());
+        }
+
+        for attribute in vertices.layout().attributes() {
+            let format = graphics_pipeline::attribute_format(attribute.format);
+            let properties = VK_INSTANCE.get_physical_device_format_properties(self.physical_device, format);
+            if !properties
+                .buffer_features
+                .subset(vk::FORMAT_FEATURE_VERTEX_BUFFER_BIT)
+            {
+                return Err(format!(
+                    "The device can't read {:?} vertex attributes as {:?}",
+                    attribute.semantic, attribute.format
+                ).into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn free_mesh(&mut self, handle: MeshHandle) -> Result<(), Box<Error>> {
+        self.finish_all_frames()?;
+        self.meshes.free(&self.device, &mut self.uploader, handle)
+    }
+
+    /// Polls whether the mesh's latest data has finished uploading.
+    pub fn mesh_uploaded(&mut self, handle: MeshHandle) -> Result<bool, Box<Error>> {
+        self.meshes.is_uploaded(&self.device, &mut self.uploader, handle)
+    }
+
+    /// GPU memory usage per heap.
+    pub fn memory_stats(&self) -> Vec<HeapStats> {
+        self.allocator.heap_stats()
+    }
+
+    /// Requests a copy of the next finished frame, available afterwards from
+    /// `take_captured_frame`.
+    pub fn capture_frame(&mut self) {
+        self.capture_requested = true;
+    }
+
+    /// The frame copied after `capture_frame`, or why it couldn't be copied.
+    /// Failed captures don't fail the frame they were requested for.
+    pub fn take_captured_frame(&mut self) -> Option<Result<RgbaImage, Box<Error>>> {
+        self.captured_frame.take()
+    }
+
+    /// Writes the current frame's uniforms, descriptor sets and instance
+    /// data, returning the per-frame set and the batches to draw. The frame's
+    /// fence has been waited on in `begin_frame`, so the GPU is done with the
+    /// old contents.
+    fn prepare_frame(
+        &mut self,
+        render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        extent: vk::Extent2D,
+    ) -> Result<PreparedFrame, Box<Error>> {
+        let aspect = extent.width as f32 / extent.height as f32;
+        let shadows = FrameShadows::new(&self.lights, self.camera.as_ref(), aspect);
+        self.write_camera_uniforms(extent)?;
+        self.write_lighting_uniforms(&shadows)?;
+
+        let materials = &self.materials;
+        let eye = match self.camera {
+            Some(ref camera) => camera.position(),
+            None => Vector3::new(0.0, 0.0, 0.0),
+        };
+        renderer::sort_blended_last(&mut self.draw_list, eye, |&(_, material, ref instance)| {
+            (materials[&material].render_state.blend, instance)
+        });
+
+        let device = &self.device;
+        let allocator = &mut self.allocator;
+        let meshes = &self.meshes;
+        let frame = &mut self.frames[self.current_frame];
+        frame.descriptor_pools.reset(device)?;
+        frame.object_uniforms.reset();
+
+        let frame_layout = self.set_layouts[descriptor::FRAME_SET as usize];
+        let frame_set = frame.descriptor_pools.allocate(device, frame_layout)?;
+        descriptor::write_set(
+            device,
+            frame_set,
+            &[
+                (
+                    descriptor::CAMERA_BINDING,
+                    BindingType::UniformBuffer,
+                    Resource::Buffer {
+                        buffer: allocator.buffer(frame.uniform_buffer).buf,
+                        offset: 0,
+                        range: mem::size_of::<CameraUniforms>() as u64,
+                    },
+                ),
+                (
+                    descriptor::LIGHTING_BINDING,
+                    BindingType::UniformBuffer,
+                    Resource::Buffer {
+                        buffer: allocator.buffer(frame.lighting_buffer).buf,
+                        offset: 0,
+                        range: mem::size_of::<LightingUniforms>() as u64,
+                    },
+                ),
+                (
+                    descriptor::SHADOW_MAPS_BINDING,
+                    BindingType::CombinedImageSampler,
+                    Resource::Image {
+                        view: frame.shadow_maps.array_view(),
+                        sampler: self.shadow_pass.sampler(),
+                        layout: vk::ImageLayout::ShaderReadOnlyOptimal,
+                    },
+                ),
+            ],
+        );
+
+        // Each material's textures are written once per program sampling
+        // them.
+        let mut material_sets = HashMap::new();
+        let mut draws = vec![];
+        let mut opaque_draws = 0;
+        let mut casters = vec![];
+        for &(id, material_id, ref instance) in &self.draw_list {
+            let material = &materials[&material_id];
+            let handle = match self.models.get(&id) {
+                Some(&handle) => handle,
+                None => continue,
+            };
+            let mesh = match meshes.mesh(handle) {
+                Some(mesh) => *mesh,
+                None => continue,
+            };
+
+            let vertex_format = meshes.vertex_format(&mesh);
+            let lit = vertex_format
+                .attributes
+                .iter()
+                .any(|attribute| attribute.location == Semantic::Normal.location());
+            let key = PipelineKey {
+                shaders: if lit {
+                    material.lit_shaders.clone()
+                } else {
+                    material.shaders.clone()
+                },
+                vertex_format: vertex_format.clone(),
+                state: PipelineState::new(material.render_state).with_samples(samples),
+                render_pass,
+            };
+            let (pipeline, layout) = self.pipelines
+                .get(device, &mut self.descriptor_layouts, &key)?;
+            let material_set = match material_sets.get(&(material_id, layout)) {
+                Some(&set) => set,
+                None => {
+                    let program = self.pipelines
+                        .program(device, &mut self.descriptor_layouts, &key.shaders)?;
+                    let set = write_material_set(
+                        device,
+                        &mut frame.descriptor_pools,
+                        program,
+                        material,
+                        self.texture_sampler,
+                    ).map_err(|err| format!("Material {}: {}", material_id, err))?;
+                    material_sets.insert((material_id, layout), set);
+                    set
+                }
+            };
+
+            let instance_data = InstanceData::new(instance);
+            if instance.cast_shadows && !shadows.maps.is_empty() {
+                casters.push((handle, mesh, vertex_format.clone(), instance_data));
+            }
+
+            if material.render_state.blend != Blend::Alpha {
+                opaque_draws += 1;
+            }
+            draws.push((
+                (pipeline, material_id, handle),
+                layout,
+                material_set,
+                mesh,
+                instance_data,
+            ));
+        }
+
+        // Opaque draws are grouped by pipeline, material and mesh, blended
+        // ones keep their back to front order since it decides what they are
+        // blended over.
+        // Runs of the same pipeline, material and mesh become one instanced
+        // draw.
+        draws[..opaque_draws].sort_by_key(|&(key, _, _, _, _)| key);
+        let mut batches: Vec<BatchInstances> = vec![];
+        let mut batch_material = None;
+        for ((pipeline, material, handle), layout, material_set, mesh, instance) in draws {
+            if let Some(batch) = batches.last_mut() {
+                if batch.pipeline == pipeline && batch.handle == handle
+                    && batch_material == Some(material)
+                {
+                    batch.instances.push(instance);
+                    continue;
+                }
+            }
+            batch_material = Some(material);
+            batches.push(BatchInstances {
+                pipeline,
+                layout,
+                material_set,
+                handle,
+                mesh,
+                parameters: materials[&material].parameters,
+                instances: vec![instance],
+            });
+        }
+
+        // Casters are drawn by every shadow pass, double sided so closed
+        // meshes cast shadows whichever way they are wound.
+        casters.sort_by_key(|&(handle, _, _, _)| handle);
+        let mut shadow_batches: Vec<BatchInstances> = vec![];
+        let mut shadow_layout = vk::PipelineLayout::null();
+        for (handle, mesh, vertex_format, instance) in casters {
+            if let Some(batch) = shadow_batches.last_mut() {
+                if batch.handle == handle {
+                    batch.instances.push(instance);
+                    continue;
+                }
+            }
+            let key = PipelineKey {
+                shaders: self.shadow_shaders.clone(),
+                vertex_format,
+                state: PipelineState {
+                    depth_only: true,
+                    ..PipelineState::new(RenderState {
+                        cull: Cull::None,
+                        ..RenderState::default()
+                    })
+                },
+                render_pass: self.shadow_pass.render_pass(),
+            };
+            let (pipeline, layout) = self.pipelines
+                .get(device, &mut self.descriptor_layouts, &key)?;
+            shadow_layout = layout;
+            shadow_batches.push(BatchInstances {
+                pipeline,
+                layout,
+                material_set: None,
+                handle,
+                mesh,
+                parameters: [[0.0; 4]; MAX_PARAMETERS],
+                instances: vec![instance],
+            });
+        }
+
+        // Every program's object set has the same single binding, see
+        // `descriptor::object_bindings`, so its layout is the default
+        // shaders' one.
+        let object_layout = self.set_layouts[descriptor::OBJECT_SET as usize];
+        let mut instances = vec![];
+        let batches =
+            write_batches(device, allocator, frame, object_layout, batches, &mut instances)?;
+        let shadow_batches = write_batches(
+            device,
+            allocator,
+            frame,
+            object_layout,
+            shadow_batches,
+            &mut instances,
+        )?;
+
+        let size = (instances.len() * mem::size_of::<InstanceData>()) as u64;
+        if size > allocator.buffer(frame.instance_buffer).size {
+            let count = (instances.len() as u64).next_power_of_two();
+            let instance_buffer = new_instance_buffer(device, allocator, count)?;
+            allocator.free_buffer(device, frame.instance_buffer);
+            frame.instance_buffer = instance_buffer;
+        }
+        let mapped = allocator
+            .buffer(frame.instance_buffer)
+            .mapped()
+            .ok_or("Instance buffer isn't host visible")?;
+        unsafe {
+            ptr::copy_nonoverlapping(
+                instances.as_ptr(),
+                mapped as *mut InstanceData,
+                instances.len(),
+            );
+        }
+
+        Ok(PreparedFrame {
+            frame_set,
+            batches,
+            shadow_views: shadows.maps.iter().map(|map| map.view_projection).collect(),
+            shadow_batches,
+            shadow_layout,
+        })
+    }
+
+    /// Fills the current frame's camera uniform buffer.
+    fn write_camera_uniforms(&self, extent: vk::Extent2D) -> Result<(), Box<Error>> {
+        let (view, projection) = match self.camera {
+            Some(ref camera) => {
+                let aspect = extent.width as f32 / extent.height as f32;
+                (camera.view, camera.projection_matrix(aspect))
+            }
+            None => (Matrix4::identity(), Matrix4::identity()),
+        };
+        let uniforms = CameraUniforms {
+            view: view.into(),
+            projection: projection.into(),
+        };
+
+        let uniform_buffer = self.allocator
+            .buffer(self.frames[self.current_frame].uniform_buffer);
+        let mapped = uniform_buffer
+            .mapped()
+            .ok_or("Uniform buffer isn't host visible")?;
+        unsafe { ptr::write(mapped as *mut CameraUniforms, uniforms) };
+
+        Ok(())
+    }
+
+    /// Fills the current frame's lighting uniform buffer.
+    fn write_lighting_uniforms(&self, shadows: &FrameShadows) -> Result<(), Box<Error>> {
+        let camera_position = match self.camera {
+            Some(ref camera) => camera.position(),
+            None => Vector3::new(0.0, 0.0, 0.0),
+        };
+        let mut uniforms = LightingUniforms {
+            camera_position: camera_position.extend(1.0).into(),
+            light_count: self.lights.len() as u32,
+            _padding: [0; 3],
+            lights: [LightUniforms::default(); MAX_LIGHTS],
+            shadow_maps: [ShadowMapUniforms::default(); MAX_SHADOW_MAPS],
+        };
+        let lights = self.lights.iter().zip(&shadows.lights);
+        for (uniform, (light, &maps)) in uniforms.lights.iter_mut().zip(lights) {
+            *uniform = LightUniforms::new(light, maps);
+        }
+        for (uniform, map) in uniforms.shadow_maps.iter_mut().zip(&shadows.maps) {
+            *uniform = ShadowMapUniforms {
+                view_projection: map.view_projection.into(),
+                split: [map.split, 0.0, 0.0, 0.0],
+            };
+        }
+
+        let lighting_buffer = self.allocator
+            .buffer(self.frames[self.current_frame].lighting_buffer);
+        let mapped = lighting_buffer
+            .mapped()
+            .ok_or("Lighting buffer isn't host visible")?;
+        unsafe { ptr::write(mapped as *mut LightingUniforms, uniforms) };
+
+        Ok(())
+    }
+
+    fn record_frame(
+        &self,
+        device: &VkDevice,
+        command_buffer: vk::CommandBuffer,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
+        frame: &PreparedFrame,
+    ) {
+        // Every batch's instances are in the one buffer, drawn from their
+        // first instance.
+        let resources = &self.frames[self.current_frame];
+        let instance_buffer = self.allocator.buffer(resources.instance_buffer).buf;
+        let shadow_maps = &resources.shadow_maps;
+        unsafe {
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                graphics_pipeline::INSTANCE_BINDING,
+                &[instance_buffer],
+                &[0],
+            );
+        }
+
+        // Shadow maps are drawn first, the main pass samples them.
+        for (layer, view_projection) in frame.shadow_views.iter().enumerate() {
+            let clear_values = [
+                vk::ClearValue::new_depth_stencil(vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                }),
+            ];
+            self.begin_render_pass(
+                device,
+                command_buffer,
+                self.shadow_pass.render_pass(),
+                shadow_maps.framebuffer(layer),
+                self.shadow_pass.extent(),
+                &clear_values,
+            );
+            let view_projection: [[f32; 4]; 4] = (*view_projection).into();
+            unsafe {
+                // ash has no wrapper for this one.
+                device.fp_v1_0().cmd_push_constants(
+                    command_buffer,
+                    frame.shadow_layout,
+                    vk::SHADER_STAGE_VERTEX_BIT,
+                    0,
+                    mem::size_of_val(&view_projection) as u32,
+                    view_projection.as_ptr() as *const _,
+                );
+            }
+            self.record_batches(device, command_buffer, None, &frame.shadow_batches);
+            unsafe { device.cmd_end_render_pass(command_buffer) };
+        }
+
+        let clear_values = [
+            vk::ClearValue::new_color(vk::ClearColorValue::new_float32([0.0, 0.0, 0.0, 0.0])),
+            vk::ClearValue::new_depth_stencil(vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            }),
+        ];
+        self.begin_render_pass(
+            device,
+            command_buffer,
+            render_pass,
+            framebuffer,
+            extent,
+            &clear_values,
+        );
+        self.record_batches(device, command_buffer, Some(frame.frame_set), &frame.batches);
+        unsafe { device.cmd_end_render_pass(command_buffer) };
+    }
+
+    /// Begins `render_pass` over the whole of `extent`, with the viewport
+    /// and scissor covering it.
+    fn begin_render_pass(
+        &self,
+        device: &VkDevice,
+        command_buffer: vk::CommandBuffer,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
+        clear_values: &[vk::ClearValue],
+    ) {
+        let render_pass_info = vk::RenderPassBeginInfo {
+            s_type: vk::StructureType::RenderPassBeginInfo,
+            render_pass,
+            framebuffer,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+            p_next: ptr::null(),
+        };
+
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_info,
+                vk::SubpassContents::Inline,
+            );
+            device.cmd_set_viewport(command_buffer, &[viewport]);
+            device.cmd_set_scissor(command_buffer, &[scissor]);
+        }
+    }
+
+    /// Records a draw per batch, binding pipelines, material sets and
+    /// meshes only when they change. `frame_set` is bound again whenever the
+    /// pipeline layout changes, since layouts with different push constants
+    /// or material sets aren't compatible for it.
+    fn record_batches(
+        &self,
+        device: &VkDevice,
+        command_buffer: vk::CommandBuffer,
+        frame_set: Option<vk::DescriptorSet>,
+        batches: &[Batch],
+    ) {
+        let vertex_buffer = self.meshes.vertex_buffer(&self.allocator);
+        let index_buffer = self.meshes.index_buffer(&self.allocator);
+
+        let mut bound_pipeline = vk::Pipeline::null();
+        let mut bound_layout = vk::PipelineLayout::null();
+        let mut bound_material_set = None;
+        let mut bound_mesh = None;
+        unsafe {
+            for batch in batches {
+                let mesh = &batch.mesh;
+
+                if batch.pipeline != bound_pipeline {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::Graphics,
+                        batch.pipeline,
+                    );
+                    bound_pipeline = batch.pipeline;
+                }
+
+                if batch.layout != bound_layout {
+                    if let Some(frame_set) = frame_set {
+                        device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::Graphics,
+                            batch.layout,
+                            descriptor::FRAME_SET,
+                            &[frame_set],
+                            &[],
+                        );
+                    }
+                    bound_layout = batch.layout;
+                    bound_material_set = None;
+                }
+
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::Graphics,
+                    batch.layout,
+                    descriptor::OBJECT_SET,
+                    &[batch.object_set],
+                    &[batch.object_offset],
+                );
+
+                if let Some(material_set) = batch.material_set {
+                    if bound_material_set != Some(material_set) {
+                        device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::Graphics,
+                            batch.layout,
+                            descriptor::MATERIAL_SET,
+                            &[material_set],
+                            &[],
+                        );
+                        bound_material_set = Some(material_set);
+                    }
+                }
+
+                if bound_mesh != Some(batch.handle) {
+                    device.cmd_bind_vertex_buffers(
+                        command_buffer,
+                        0,
+                        &[vertex_buffer],
+                        &[mesh.vertex_offset],
+                    );
+                    if mesh.index_count > 0 {
+                        device.cmd_bind_index_buffer(
+                            command_buffer,
+                            index_buffer,
+                            mesh.index_offset,
+                            mesh.index_type,
+                        );
+                    }
+                    bound_mesh = Some(batch.handle);
+                }
+                if mesh.index_count > 0 {
+                    device.cmd_draw_indexed(
+                        command_buffer,
+                        mesh.index_count,
+                        batch.instance_count,
+                        0,
+                        0,
+                        batch.first_instance,
+                    );
+                } else {
+                    device.cmd_draw(
+                        command_buffer,
+                        mesh.vertex_count,
+                        batch.instance_count,
+                        0,
+                        batch.first_instance,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Renderer for VulkanRenderer {
+    fn begin_frame(&mut self) -> Result<(), Box<Error>> {
+        if let Some(settings) = self.pending_settings.take() {
+            self.apply_settings(settings)?;
+        }
+
+        let current_frame = self.current_frame;
+        self.finish_frame(current_frame)?;
+
+        let frame = match self.active_target {
+            Some(index) => FrameTarget::Offscreen(index),
+            None => self.acquire_swapchain_image()?,
+        };
+
+        self.frame = Some(frame);
+        self.draw_list.clear();
+        self.camera = None;
+        self.lights.clear();
+
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> Result<(), Box<Error>> {
+        let frame = self.frame.take().ok_or("end_frame called without begin_frame")?;
+        if let FrameTarget::Skipped = frame {
+            return Ok(());
+        }
+
+        let index = self.current_frame;
+        if let Err(err) = self.submit_frame(frame) {
+            self.abandon_frame(frame);
+            return Err(err);
+        }
+        self.current_frame = (index + 1) % self.frames.len();
+
+        match frame {
+            FrameTarget::Swapchain(image_index) => self.present_frame(index, image_index),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_camera(&mut self, camera: &Camera) -> Result<(), Box<Error>> {
+        self.camera = Some(*camera);
+        Ok(())
+    }
+
+    fn set_lights(&mut self, lights: &[renderer::Light]) -> Result<(), Box<Error>> {
+        self.lights = lights.iter().take(MAX_LIGHTS).cloned().collect();
+        Ok(())
+    }
+
+    fn update_model(
+        &mut self,
+        id: u32,
+        vertices: &VertexData,
+        indices: Indices,
+    ) -> Result<(), Box<Error>> {
+        match self.models.get(&id).cloned() {
+            Some(handle) => self.update_mesh(handle, vertices, indices),
+            None => {
+                let handle = self.upload_mesh(vertices, indices)?;
+                self.models.insert(id, handle);
+                Ok(())
+            }
+        }
+    }
+
+    fn update_material(&mut self, id: u32, material: &Material) -> Result<(), Box<Error>> {
+        if material.parameters.len() > MAX_PARAMETERS {
+            return Err(format!(
+                "Material {} has {} parameters, at most {} are supported",
+                id,
+                material.parameters.len(),
+                MAX_PARAMETERS
+            ).into());
+        }
+
+        let mut parameters = [[0.0; 4]; MAX_PARAMETERS];
+        for (vec4, &(_, parameter)) in parameters.iter_mut().zip(&material.parameters) {
+            *vec4 = parameter.to_vec4();
+        }
+        let mut textures = vec![];
+        for texture in &material.textures {
+            let uploaded = Texture::new(
+                &self.device,
+                self.physical_device,
+                &mut self.allocator,
+                self.command_pool,
+                self.graphics_queue,
+                texture,
+            );
+            match uploaded {
+                Ok(uploaded) => textures.push((texture.name.to_string(), uploaded)),
+                Err(err) => {
+                    for (_, uploaded) in textures {
+                        unsafe { uploaded.destroy(&self.device) };
+                    }
+                    return Err(format!("Material {}: {}", id, err).into());
+                }
+            }
+        }
+
+        let shaders = ShaderSet::for_material(material);
+        let replaced = self.materials.insert(
+            id,
+            GpuMaterial {
+                lit_shaders: shaders.clone().with_define("LIT", "1"),
+                shaders,
+                render_state: material.render_state,
+                parameters,
+                textures,
+            },
+        );
+        if let Some(replaced) = replaced {
+            // Frames in flight may still sample the old textures.
+            self.finish_all_frames()?;
+            unsafe { replaced.destroy(&self.device) };
+        }
+        Ok(())
+    }
+
+    fn draw_model(
+        &mut self,
+        id: u32,
+        material: u32,
+        instance: &renderer::Instance,
+    ) -> Result<(), Box<Error>> {
+        if !self.models.contains_key(&id) {
+            return Err(format!("Model {} isn't loaded", id).into());
+        }
+        if !self.materials.contains_key(&material) {
+            return Err(format!("Material {} isn't loaded", material).into());
+        }
+        self.draw_list.push((id, material, *instance));
+        Ok(())
+    }
+
+    fn update_resolution(&mut self, width: u32, height: u32) -> Result<(), Box<Error>> {
+        // Headless renderers draw at their render target's size.
+        let presenter = match self.presenter {
+            Some(ref mut presenter) => presenter,
+            None => return Ok(()),
+        };
+
+        // Only used by surfaces that let the swapchain pick its size, others
+        // report their new extent themselves.
+        presenter.surface.width = width;
+        presenter.surface.height = height;
+
+        let extent = presenter.swapchain.extent();
+        if extent.width != width || extent.height != height {
+            presenter.outdated = true;
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds the render pass and, before the next frame, the swapchain
+    /// with everything sized after it. Pipelines made for the old render
+    /// pass are dropped and made again as frames need them.
+    fn change_settings(&mut self, settings: &RenderSettings) -> Result<(), Box<Error>> {
+        let settings = self.supported_settings(settings)?;
+        // The frame being recorded has to end in the render pass and
+        // targets it began with.
+        if self.frame.is_some() {
+            self.pending_settings = Some(settings);
+            return Ok(());
+        }
+        self.apply_settings(settings)
+    }
+}
+
+impl Drop for VulkanRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+
+            for target in &self.render_targets {
+                target.destroy(&self.device);
+            }
+
+            for frame in &mut self.frames {
+                frame.destroy(&self.device, &mut self.allocator);
+            }
+            self.meshes.destroy(&self.device, &mut self.allocator);
+            self.uploader.destroy(&self.device, &mut self.allocator);
+            self.allocator.destroy(&self.device);
+            self.device.destroy_command_pool(self.command_pool, None);
+            for material in self.materials.values() {
+                material.destroy(&self.device);
+            }
+            self.device.destroy_sampler(self.texture_sampler, None);
+            self.shadow_pass.destroy(&self.device);
+            // Losing the cache only makes the next start slower.
+            let _ = self.pipelines.save_cache(&self.device);
+            self.pipelines.destroy(&self.device);
+            self.descriptor_layouts.destroy(&self.device);
+            self.device.destroy_render_pass(self.render_pass, None);
+            if let Some(ref presenter) = self.presenter {
+                presenter.destroy_swapchain(&self.device);
+                presenter.surface.destroy();
+            }
+            if let (Some(loader), Some(callback)) =
+                (self.debug_report_loader.as_ref(), self.debug_callback)
+            {
+                loader.destroy_debug_report_callback_ext(callback, None);
+            }
+        }
+    }
+}
+
+/// The render pass drawing frames for a swapchain of `color_format`.
+fn new_render_pass(
+    device: &VkDevice,
+    color_format: vk::Format,
+    settings: &RenderSettings,
+) -> Result<vk::RenderPass, Box<Error>> {
+    // Scaled frames are blitted onto the swapchain image afterwards.
+    let final_layout = if settings.resolution_scale == 1.0 {
+        vk::ImageLayout::PresentSrcKhr
+    } else {
+        vk::ImageLayout::TransferSrcOptimal
+    };
+    render_pass::new(
+        device,
+        color_format,
+        depth_format(settings.depth_format),
+        sample_count(settings.samples),
+        final_layout,
+    )
+}
+
+fn new_scene_target(
+    device: &VkDevice,
+    physical_device: vk::PhysicalDevice,
+    render_pass: vk::RenderPass,
+    swapchain: &Swapchain,
+    present_image_views: &[vk::ImageView],
+    settings: &RenderSettings,
+) -> Result<SceneTarget, Box<Error>> {
+    SceneTarget::new(
+        device,
+        physical_device,
+        render_pass,
+        swapchain.extent(),
+        swapchain.format().format,
+        present_image_views,
+        settings,
+    )
+}
+
+/// `samples` has to be a power of two up to 64.
+fn sample_count(samples: u32) -> vk::SampleCountFlags {
+    match samples {
+        64 => vk::SAMPLE_COUNT_64_BIT,
+        32 => vk::SAMPLE_COUNT_32_BIT,
+        16 => vk::SAMPLE_COUNT_16_BIT,
+        8 => vk::SAMPLE_COUNT_8_BIT,
+        4 => vk::SAMPLE_COUNT_4_BIT,
+        2 => vk::SAMPLE_COUNT_2_BIT,
+        _ => vk::SAMPLE_COUNT_1_BIT,
+    }
+}
+
+fn present_mode(mode: PresentMode) -> vk::PresentModeKHR {
+    match mode {
+        PresentMode::Vsync => vk::PresentModeKHR::Fifo,
+        PresentMode::Mailbox => vk::PresentModeKHR::Mailbox,
+        PresentMode::Immediate => vk::PresentModeKHR::Immediate,
+    }
+}
+
+fn depth_format(format: DepthFormat) -> vk::Format {
+    match format {
+        DepthFormat::D16 => vk::Format::D16Unorm,
+        DepthFormat::D24 => vk::Format::X8D24UnormPack32,
+        DepthFormat::D32 => vk::Format::D32Sfloat,
+    }
+}
+
+/// Allocates a set binding `material`'s textures to the samplers `program`
+/// declares in the material set, `None` if it declares none.
+fn write_material_set(
+    device: &VkDevice,
+    pools: &mut FramePools,
+    program: &ShaderProgram,
+    material: &GpuMaterial,
+    sampler: vk::Sampler,
+) -> Result<Option<vk::DescriptorSet>, Box<Error>> {
+    if program.textures.is_empty() {
+        return Ok(None);
+    }
+
+    let mut writes = vec![];
+    for &(binding, ref name) in &program.textures {
+        let texture = material
+            .textures
+            .iter()
+            .find(|(texture_name, _)| texture_name == name)
+            .map(|(_, texture)| texture)
+            .ok_or_else(|| format!("its shaders sample `{}`, which it has no texture for", name))?;
+        writes.push((
+            binding,
+            BindingType::CombinedImageSampler,
+            Resource::Image {
+                view: texture.view(),
+                sampler,
+                layout: vk::ImageLayout::ShaderReadOnlyOptimal,
+            },
+        ));
+    }
+
+    let set = pools.allocate(device, program.set_layouts[descriptor::MATERIAL_SET as usize])?;
+    descriptor::write_set(device, set, &writes);
+
+    Ok(Some(set))
+}
+
+/// Writes each batch's parameters to the frame's object uniforms and
+/// appends its instances to `instances`.
+fn write_batches(
+    device: &VkDevice,
+    allocator: &mut buffer::Allocator,
+    frame: &mut FrameResources,
+    object_layout: vk::DescriptorSetLayout,
+    batches: Vec<BatchInstances>,
+    instances: &mut Vec<InstanceData>,
+) -> Result<Vec<Batch>, Box<Error>> {
+    let mut written = vec![];
+    for batch in batches {
+        let (object_set, object_offset) = frame.object_uniforms.push(
+            device,
+            allocator,
+            &mut frame.descriptor_pools,
+            object_layout,
+            &batch.parameters,
+        )?;
+        written.push(Batch {
+            pipeline: batch.pipeline,
+            layout: batch.layout,
+            material_set: batch.material_set,
+            handle: batch.handle,
+            mesh: batch.mesh,
+            object_set,
+            object_offset,
+            first_instance: instances.len() as u32,
+            instance_count: batch.instances.len() as u32,
+        });
+        instances.extend(batch.instances);
+    }
+
+    Ok(written)
+}
+
+fn new_instance_buffer(
+    device: &VkDevice,
+    allocator: &mut buffer::Allocator,
+    count: u64,
+) -> Result<usize, Box<Error>> {
+    allocator.create_buffer(
+        device,
+        count * mem::size_of::<InstanceData>() as u64,
+        vk::BUFFER_USAGE_VERTEX_BUFFER_BIT,
+        vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT | vk::MEMORY_PROPERTY_HOST_COHERENT_BIT,
+    )
+}
+
+pub fn find_memorytype_index(
+    memory_req: &vk::MemoryRequirements,
+    memory_prop: &vk::PhysicalDeviceMemoryProperties,
+    flags: vk::MemoryPropertyFlags,
+) -> Result<u32, String> {
+    // Try to find an exactly matching memory flag
+    let best_suitable_index =
+        find_memorytype_index_f(memory_req, memory_prop, flags, |property_flags, flags| {
+            property_flags == flags
+        });
+    if best_suitable_index.is_ok() {
+        return best_suitable_index;
+    }
+    // Otherwise find a memory flag that works
+    find_memorytype_index_f(memory_req, memory_prop, flags, |property_flags, flags| {
+        property_flags & flags == flags
+    })
+}
+
+fn find_memorytype_index_f<F: Fn(vk::MemoryPropertyFlags, vk::MemoryPropertyFlags) -> bool>(
+    memory_req: &vk::MemoryRequirements,
+    memory_prop: &vk::PhysicalDeviceMemoryProperties,
+    flags: vk::MemoryPropertyFlags,
+    f: F,
+) -> Result<u32, String> {
+    let mut memory_type_bits = memory_req.memory_type_bits;
+    for (index, memory_type) in memory_prop.memory_types.iter().enumerate() {
+        if memory_type_bits & 1 == 1 && f(memory_type.property_flags, flags) {
+            return Ok(index as u32);
+        }
+        memory_type_bits >>= 1;
+    }
+    Err("Unable to find suitable memory index for depth image.".to_owned())
+}
+
+/// Prints the validation messages, `None` if the instance can't report
+/// them.
+pub fn set_debug_callback(
+) -> Result<Option<(ext::DebugReport, vk::DebugReportCallbackEXT)>, Box<Error>> {
+    if !instance::has_debug_report(&VK_ENTRY)? {
+        return Ok(None);
+    }
+
+    let debug_info = vk::DebugReportCallbackCreateInfoEXT {
+        s_type: vk::StructureType::DebugReportCallbackCreateInfoExt,
+        p_next: ptr::null(),
+        flags: vk::DEBUG_REPORT_ERROR_BIT_EXT | vk::DEBUG_REPORT_WARNING_BIT_EXT
+            | vk::DEBUG_REPORT_PERFORMANCE_WARNING_BIT_EXT,
+        pfn_callback: vulkan_debug_callback,
+        p_user_data: ptr::null_mut(),
+    };
+
+    let debug_report_loader = ext::DebugReport::new(&*VK_ENTRY, &*VK_INSTANCE)
+        .map_err(|_| "Couldn't create debug repoprt loader")?;
+
+    let callback =
+        unsafe { debug_report_loader.create_debug_report_callback_ext(&debug_info, None)? };
+
+    Ok(Some((debug_report_loader, callback)))
+}
+
+unsafe extern "system" fn vulkan_debug_callback(
+    _: vk::DebugReportFlagsEXT,
+    _: vk::DebugReportObjectTypeEXT,
+    _: vk::uint64_t,
+    _: vk::size_t,
+    _: vk::int32_t,
+    _: *const vk::c_char,
+    p_message: *const vk::c_char,
+    _: *mut vk::c_void,
+) -> u32 {
+    println!("{:?}", CStr::from_ptr(p_message));
+    1
+}
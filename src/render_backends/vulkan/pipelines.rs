@@ -0,0 +1,198 @@
+use ash::vk;
+use ash::version::DeviceV1_0;
+
+use std::ptr;
+use std::mem;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use super::VkDevice;
+use super::descriptor::LayoutCache;
+use super::graphics_pipeline::{self, PipelineState, ShaderProgram, ShaderSet, VertexFormat};
+
+/// Size of the header the driver puts in front of pipeline cache data,
+/// `VkPipelineCacheHeaderVersionOne`.
+const CACHE_HEADER_SIZE: usize = 32;
+
+/// Everything a pipeline is created from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub shaders: ShaderSet,
+    pub vertex_format: VertexFormat,
+    pub state: PipelineState,
+    pub render_pass: vk::RenderPass,
+}
+
+/// Creates pipelines the first time a key is asked for and keeps them until
+/// destroyed. Compiled pipelines go through a `VkPipelineCache` that is
+/// loaded from and saved to disk, so later runs skip most of the work.
+pub struct PipelineManager {
+    cache: vk::PipelineCache,
+    cache_path: PathBuf,
+    /// Wireframes are drawn filled without `fillModeNonSolid`.
+    wireframe_supported: bool,
+    programs: HashMap<ShaderSet, ShaderProgram>,
+    pipelines: HashMap<PipelineKey, vk::Pipeline>,
+}
+
+impl PipelineManager {
+    /// Cache data written by another device or driver version is ignored.
+    pub fn new(
+        device: &VkDevice,
+        properties: &vk::PhysicalDeviceProperties,
+        wireframe_supported: bool,
+        cache_path: &Path,
+    ) -> Result<PipelineManager, Box<Error>> {
+        let data = read_cache(cache_path)
+            .filter(|data| cache_matches(data, properties))
+            .unwrap_or_default();
+
+        let cache_info = vk::PipelineCacheCreateInfo {
+            s_type: vk::StructureType::PipelineCacheCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            initial_data_size: data.len(),
+            p_initial_data: data.as_ptr() as *const _,
+        };
+        let cache = unsafe { device.create_pipeline_cache(&cache_info, None)? };
+
+        Ok(PipelineManager {
+            cache,
+            cache_path: cache_path.to_path_buf(),
+            wireframe_supported,
+            programs: HashMap::new(),
+            pipelines: HashMap::new(),
+        })
+    }
+
+    /// Compiles and reflects `shaders` unless that has been done already.
+    pub fn program(
+        &mut self,
+        device: &VkDevice,
+        layouts: &mut LayoutCache,
+        shaders: &ShaderSet,
+    ) -> Result<&ShaderProgram, Box<Error>> {
+        if !self.programs.contains_key(shaders) {
+            let program = ShaderProgram::new(device, layouts, shaders)?;
+            self.programs.insert(shaders.clone(), program);
+        }
+        Ok(&self.programs[shaders])
+    }
+
+    /// The pipeline for `key` and the layout to bind its descriptors through.
+    pub fn get(
+        &mut self,
+        device: &VkDevice,
+        layouts: &mut LayoutCache,
+        key: &PipelineKey,
+    ) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<Error>> {
+        let mut key = key.clone();
+        if !self.wireframe_supported {
+            key.state.render.wireframe = false;
+        }
+
+        if let Some(&pipeline) = self.pipelines.get(&key) {
+            return Ok((pipeline, self.programs[&key.shaders].layout));
+        }
+
+        let cache = self.cache;
+        let (pipeline, layout) = {
+            let program = self.program(device, layouts, &key.shaders)?;
+            let pipeline = graphics_pipeline::new(
+                device,
+                cache,
+                key.render_pass,
+                program,
+                &key.vertex_format,
+                &key.state,
+            )?;
+            (pipeline, program.layout)
+        };
+        self.pipelines.insert(key, pipeline);
+
+        Ok((pipeline, layout))
+    }
+
+    /// Destroys the pipelines made for `render_pass`, which is about to be
+    /// destroyed. Render passes created later may get the same handle and
+    /// must not be handed pipelines made for the old one.
+    pub unsafe fn forget_render_pass(&mut self, device: &VkDevice, render_pass: vk::RenderPass) {
+        let keys: Vec<_> = self.pipelines
+            .keys()
+            .filter(|key| key.render_pass == render_pass)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(pipeline) = self.pipelines.remove(&key) {
+                device.destroy_pipeline(pipeline, None);
+            }
+        }
+    }
+
+    /// Writes the cache to disk for the next run.
+    pub fn save_cache(&self, device: &VkDevice) -> Result<(), Box<Error>> {
+        // ash has no wrapper for this one.
+        let data = unsafe {
+            let fp = device.fp_v1_0();
+            let mut size = 0;
+            match fp.get_pipeline_cache_data(device.handle(), self.cache, &mut size, ptr::null_mut()) {
+                vk::Result::Success => (),
+                err => return Err(Box::new(err)),
+            }
+            let mut data = vec![0u8; size];
+            let p_data = data.as_mut_ptr() as *mut _;
+            match fp.get_pipeline_cache_data(device.handle(), self.cache, &mut size, p_data) {
+                vk::Result::Success => (),
+                err => return Err(Box::new(err)),
+            }
+            data.truncate(size);
+            data
+        };
+
+        // Written next to the old cache and renamed over it, so an
+        // interrupted write never leaves a truncated cache behind.
+        let temp_path = self.cache_path.with_extension("tmp");
+        File::create(&temp_path)?.write_all(&data)?;
+        fs::rename(&temp_path, &self.cache_path)?;
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&mut self, device: &VkDevice) {
+        for (_, pipeline) in self.pipelines.drain() {
+            device.destroy_pipeline(pipeline, None);
+        }
+        for (_, program) in self.programs.drain() {
+            program.destroy(device);
+        }
+        device.destroy_pipeline_cache(self.cache, None);
+    }
+}
+
+fn read_cache(path: &Path) -> Option<Vec<u8>> {
+    let mut data = vec![];
+    File::open(path).ok()?.read_to_end(&mut data).ok()?;
+    Some(data)
+}
+
+/// Whether `data` was written by the same device and driver. Drivers must
+/// reject foreign data themselves, but not all of them do it gracefully.
+fn cache_matches(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+    if data.len() < CACHE_HEADER_SIZE {
+        return false;
+    }
+
+    let word = |i: usize| {
+        data[i * 4] as u32 | (data[i * 4 + 1] as u32) << 8 | (data[i * 4 + 2] as u32) << 16
+            | (data[i * 4 + 3] as u32) << 24
+    };
+    let header_version_one = 1;
+
+    word(0) as usize >= CACHE_HEADER_SIZE && word(1) == header_version_one
+        && word(2) == properties.vendor_id && word(3) == properties.device_id
+        && data[16..16 + mem::size_of_val(&properties.pipeline_cache_uuid)]
+            == properties.pipeline_cache_uuid[..]
+}
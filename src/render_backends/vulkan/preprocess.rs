@@ -0,0 +1,410 @@
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::error::Error;
+use std::collections::{BTreeMap, HashSet};
+
+/// Directory the game's shaders `#include` from.
+pub const SHADER_ROOT: &str = "data/shaders";
+
+/// GLSL with its includes inlined and defines injected, ready for
+/// `glsl_to_spirv`. Remembers where each line came from so compiler messages
+/// can point at the original files.
+pub struct Source {
+    pub code: String,
+    files: Vec<PathBuf>,
+    /// File index and line number of every line of `code`, `None` for the
+    /// injected defines.
+    lines: Vec<Option<(usize, u32)>>,
+}
+
+struct Preprocessor<'a> {
+    /// Directory includes are resolved in and may not leave.
+    root: &'a Path,
+    /// `root` with symlinks and `..` resolved, to check included files
+    /// against.
+    canonical_root: PathBuf,
+    defines: &'a BTreeMap<String, String>,
+    source: Source,
+    /// Files currently being expanded, to catch include cycles.
+    stack: Vec<PathBuf>,
+    /// Files with `#pragma once` that have been expanded.
+    once: HashSet<PathBuf>,
+}
+
+/// Expands the file at `path`. `#include "file"` is looked up next to the
+/// including file and then in `root`, `#include <file>` only in `root`.
+/// Included files have to be inside `root`. Each define becomes a `#define`
+/// right after `#version`.
+///
+/// Includes are expanded whether or not they're in an active `#if` block,
+/// the block still applies to the included code.
+pub fn preprocess(
+    path: &Path,
+    root: &Path,
+    defines: &BTreeMap<String, String>,
+) -> Result<Source, Box<Error>> {
+    let canonical_root =
+        fs::canonicalize(root).map_err(|err| format!("{}: {}", root.display(), err))?;
+
+    let mut preprocessor = Preprocessor {
+        root,
+        canonical_root,
+        defines,
+        source: Source {
+            code: String::new(),
+            files: vec![],
+            lines: vec![],
+        },
+        stack: vec![],
+        once: HashSet::new(),
+    };
+    preprocessor.expand(path, true)?;
+
+    Ok(preprocessor.source)
+}
+
+impl Source {
+    /// File and line a line of `code`, counted from 1, came from.
+    pub fn location(&self, line: u32) -> Option<(&Path, u32)> {
+        let index = (line as usize).checked_sub(1)?;
+        self.lines
+            .get(index)
+            .and_then(|&location| location)
+            .map(|(file, line)| (self.files[file].as_path(), line))
+    }
+
+    /// Rewrites the `<file>:<line>:` locations in glslang's messages to
+    /// point at the original files.
+    pub fn map_messages(&self, messages: &str) -> String {
+        messages
+            .lines()
+            .filter_map(|message| {
+                let message = message.trim();
+                // The temporary file glslang compiled is named on a line of
+                // its own.
+                if message.is_empty() || message.ends_with(".vert") || message.ends_with(".frag") {
+                    return None;
+                }
+                Some(self.map_message(message))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn map_message(&self, message: &str) -> String {
+        // Messages look like "ERROR: <file>:<line>: <text>", where the file
+        // is a temporary path or a source string number.
+        let bytes = message.as_bytes();
+        let mut start = 0;
+        while let Some(colon) = message[start..].find(':').map(|i| start + i) {
+            let digits = bytes[colon + 1..]
+                .iter()
+                .take_while(|byte| byte.is_ascii_digit())
+                .count();
+            let end = colon + 1 + digits;
+            if digits > 0 && bytes.get(end) == Some(&b':') {
+                let line: u32 = message[colon + 1..end].parse().unwrap_or(0);
+                if let Some((file, line)) = self.location(line) {
+                    let prefix_end = message[..colon].rfind(' ').map(|i| i + 1).unwrap_or(0);
+                    return format!(
+                        "{}{}:{}{}",
+                        &message[..prefix_end],
+                        file.display(),
+                        line,
+                        &message[end..]
+                    );
+                }
+                break;
+            }
+            start = colon + 1;
+        }
+
+        message.to_string()
+    }
+}
+
+impl<'a> Preprocessor<'a> {
+    fn expand(&mut self, path: &Path, main_file: bool) -> Result<(), Box<Error>> {
+        let canonical = fs::canonicalize(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if self.once.contains(&canonical) {
+            return Ok(());
+        }
+        if self.stack.contains(&canonical) {
+            return Err(format!("{} includes itself", path.display()).into());
+        }
+
+        let code = {
+            let mut file = File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            let mut code = String::new();
+            file.read_to_string(&mut code)?;
+            code
+        };
+
+        let file = self.source.files.len();
+        self.source.files.push(path.to_path_buf());
+        self.stack.push(canonical.clone());
+
+        let mut defines_injected = false;
+        for (i, line) in code.lines().enumerate() {
+            let line_number = i as u32 + 1;
+            let directive = line.trim_start();
+
+            if let Some(argument) = directive.strip_prefix("#include") {
+                let included = self.resolve(path, line_number, argument)?;
+                self.expand(&included, false)?;
+                continue;
+            }
+            if directive.strip_prefix("#pragma").map(str::trim) == Some("once") {
+                self.once.insert(canonical.clone());
+                continue;
+            }
+
+            self.push_line(line, Some((file, line_number)));
+
+            // `#version` has to come first, so the defines follow it.
+            if main_file && !defines_injected && directive.starts_with("#version") {
+                self.inject_defines();
+                defines_injected = true;
+            }
+        }
+
+        if main_file && !defines_injected {
+            return Err(format!("{}: missing #version", path.display()).into());
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn inject_defines(&mut self) {
+        let defines: Vec<_> = self.defines
+            .iter()
+            .map(|(name, value)| format!("#define {} {}", name, value))
+            .collect();
+        for define in defines {
+            self.push_line(&define, None);
+        }
+    }
+
+    fn push_line(&mut self, line: &str, location: Option<(usize, u32)>) {
+        self.source.code.push_str(line);
+        self.source.code.push('\n');
+        self.source.lines.push(location);
+    }
+
+    /// Finds the file named by the rest of an `#include` line.
+    fn resolve(&self, including: &Path, line: u32, argument: &str) -> Result<PathBuf, Box<Error>> {
+        let error = |message: &str| -> Box<Error> {
+            format!("{}:{}: {}", including.display(), line, message).into()
+        };
+
+        let argument = argument.trim();
+        let (name, relative) = if argument.starts_with('"') && argument.ends_with('"') && argument.len() >= 2 {
+            (&argument[1..argument.len() - 1], true)
+        } else if argument.starts_with('<') && argument.ends_with('>') {
+            (&argument[1..argument.len() - 1], false)
+        } else {
+            return Err(error("expected #include \"file\" or #include <file>"));
+        };
+
+        let mut candidates = vec![];
+        if relative {
+            if let Some(dir) = including.parent() {
+                candidates.push(dir.join(name));
+            }
+        }
+        candidates.push(self.root.join(name));
+
+        let found = candidates
+            .into_iter()
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| error(&format!("can't find included file {}", name)))?;
+
+        let canonical = fs::canonicalize(&found)?;
+        if !canonical.starts_with(&self.canonical_root) {
+            return Err(error(&format!("{} is outside {}", name, self.root.display())));
+        }
+
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    /// Writes `files` under a fresh directory in the temp directory, named
+    /// after the test, returning the directory.
+    fn fixture(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("xtreme_game_preprocess_{}", test));
+        let _ = fs::remove_dir_all(&dir);
+        for &(name, code) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, code).unwrap();
+        }
+        dir
+    }
+
+    fn no_defines() -> BTreeMap<String, String> {
+        BTreeMap::new()
+    }
+
+    #[test]
+    fn quoted_includes_look_next_to_the_including_file_first() {
+        let root = fixture(
+            "quoted",
+            &[
+                ("common.glsl", "// root common"),
+                ("only_root.glsl", "// only in root"),
+                ("sub/common.glsl", "// sub common"),
+                (
+                    "sub/main.vert",
+                    "#version 450\n\
+                     #include \"common.glsl\"\n\
+                     #include <common.glsl>\n\
+                     #include \"only_root.glsl\"",
+                ),
+            ],
+        );
+
+        let source = preprocess(&root.join("sub/main.vert"), &root, &no_defines()).unwrap();
+        assert_eq!(
+            source.code,
+            "#version 450\n// sub common\n// root common\n// only in root\n"
+        );
+    }
+
+    #[test]
+    fn includes_stay_inside_the_root() {
+        let dir = fixture(
+            "escape",
+            &[
+                ("outside.glsl", "// outside"),
+                ("shaders/main.vert", "#version 450\n#include \"../outside.glsl\""),
+            ],
+        );
+        let root = dir.join("shaders");
+
+        let err = preprocess(&root.join("main.vert"), &root, &no_defines())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("../outside.glsl is outside"), "{}", err);
+
+        // Missing and malformed includes are errors too.
+        fs::write(root.join("main.vert"), "#version 450\n#include <missing.glsl>").unwrap();
+        assert!(preprocess(&root.join("main.vert"), &root, &no_defines()).is_err());
+        fs::write(root.join("main.vert"), "#version 450\n#include common.glsl").unwrap();
+        assert!(preprocess(&root.join("main.vert"), &root, &no_defines()).is_err());
+    }
+
+    #[test]
+    fn pragma_once_files_are_included_once() {
+        let root = fixture(
+            "once",
+            &[
+                ("once.glsl", "#pragma once\n// once"),
+                ("twice.glsl", "// twice"),
+                (
+                    "main.vert",
+                    "#version 450\n\
+                     #include \"once.glsl\"\n\
+                     #include \"twice.glsl\"\n\
+                     #include \"once.glsl\"\n\
+                     #include \"twice.glsl\"",
+                ),
+            ],
+        );
+
+        let source = preprocess(&root.join("main.vert"), &root, &no_defines()).unwrap();
+        assert_eq!(source.code, "#version 450\n// once\n// twice\n// twice\n");
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let root = fixture(
+            "cycle",
+            &[
+                ("a.glsl", "#include \"b.glsl\""),
+                ("b.glsl", "#include \"a.glsl\""),
+                ("main.vert", "#version 450\n#include \"a.glsl\""),
+            ],
+        );
+
+        let err = preprocess(&root.join("main.vert"), &root, &no_defines())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("a.glsl includes itself"), "{}", err);
+    }
+
+    #[test]
+    fn defines_follow_the_version() {
+        let root = fixture(
+            "defines",
+            &[
+                ("main.vert", "// leading comment\n#version 450\nvoid main() {}"),
+                ("unversioned.vert", "void main() {}"),
+            ],
+        );
+        let mut defines = BTreeMap::new();
+        defines.insert("SHADOWS".to_string(), "1".to_string());
+        defines.insert("CASCADES".to_string(), "3".to_string());
+
+        let main = root.join("main.vert");
+        let source = preprocess(&main, &root, &defines).unwrap();
+        assert_eq!(
+            source.code,
+            "// leading comment\n#version 450\n#define CASCADES 3\n#define SHADOWS 1\n\
+             void main() {}\n"
+        );
+        assert_eq!(source.location(2), Some((main.as_path(), 2)));
+        assert_eq!(source.location(3), None);
+        assert_eq!(source.location(4), None);
+        assert_eq!(source.location(5), Some((main.as_path(), 3)));
+        assert_eq!(source.location(0), None);
+        assert_eq!(source.location(6), None);
+
+        let unversioned = preprocess(&root.join("unversioned.vert"), &root, &defines);
+        assert!(unversioned.is_err());
+    }
+
+    #[test]
+    fn messages_point_at_the_original_files() {
+        let root = fixture(
+            "messages",
+            &[
+                ("lighting.glsl", "float light;\nfloat broken"),
+                ("main.frag", "#version 450\n#include \"lighting.glsl\"\nvoid main() {}"),
+            ],
+        );
+        let mut defines = BTreeMap::new();
+        defines.insert("SHADOWS".to_string(), "1".to_string());
+
+        let main = root.join("main.frag");
+        let source = preprocess(&main, &root, &defines).unwrap();
+        let lighting = root.join("lighting.glsl");
+        // Line 2 is the injected define, which has nowhere else to point.
+        let messages = "/tmp/glslang1234.frag\n\
+                        ERROR: /tmp/glslang1234.frag:4: 'broken' : syntax error\n\
+                        ERROR: 0:5: 'main' : bad\n\
+                        ERROR: 0:2: 'SHADOWS' : redefined\n\
+                        \n\
+                        ERROR: 1 compilation errors.  No code generated.";
+        assert_eq!(
+            source.map_messages(messages),
+            format!(
+                "ERROR: {}:2: 'broken' : syntax error\n\
+                 ERROR: {}:3: 'main' : bad\n\
+                 ERROR: 0:2: 'SHADOWS' : redefined\n\
+                 ERROR: 1 compilation errors.  No code generated.",
+                lighting.display(),
+                main.display()
+            )
+        );
+    }
+}
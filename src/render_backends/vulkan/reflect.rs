@@ -0,0 +1,787 @@
+use ash::vk;
+
+use std::error::Error;
+use std::collections::HashMap;
+
+use super::descriptor::BindingType;
+
+const MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_OUTPUT: u32 = 3;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+/// Component type of an interface variable or vertex attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumericType {
+    Float,
+    Int,
+    Uint,
+}
+
+/// A `location` decorated shader input or output. Matrices take one
+/// variable per column.
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub location: u32,
+    pub numeric_type: NumericType,
+    pub components: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Descriptor {
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    pub binding_type: BindingType,
+    pub count: u32,
+}
+
+/// Everything a shader stage expects from the pipeline, read from its
+/// SPIR-V.
+#[derive(Debug, Clone)]
+pub struct ShaderInterface {
+    pub stage: vk::ShaderStageFlags,
+    pub inputs: Vec<Variable>,
+    pub outputs: Vec<Variable>,
+    pub descriptors: Vec<Descriptor>,
+    /// Offset and size of the push constant block, if the stage has one.
+    pub push_constants: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Scalar(NumericType, u32),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    Image,
+    Sampler,
+    SampledImage,
+    Array(u32, u32),
+    RuntimeArray,
+    Struct(Vec<u32>),
+    /// Pointee type.
+    Pointer(u32),
+}
+
+#[derive(Default, Clone, Copy)]
+struct Decorations {
+    block: bool,
+    buffer_block: bool,
+    built_in: bool,
+    array_stride: Option<u32>,
+    location: Option<u32>,
+    binding: Option<u32>,
+    set: Option<u32>,
+}
+
+#[derive(Default)]
+struct MemberDecorations {
+    offset: Option<u32>,
+    matrix_stride: Option<u32>,
+}
+
+/// Ids of a module, collected in one pass over its instructions.
+#[derive(Default)]
+struct Module {
+    stage: Option<vk::ShaderStageFlags>,
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// Id, pointer type and storage class of every global variable.
+    variables: Vec<(u32, u32, u32)>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), MemberDecorations>,
+}
+
+/// Reads the interface of a single entry point SPIR-V module.
+pub fn reflect(code: &[u32]) -> Result<ShaderInterface, Box<Error>> {
+    let module = parse(code)?;
+    let stage = module.stage.ok_or("SPIR-V module has no entry point")?;
+
+    let mut interface = ShaderInterface {
+        stage,
+        inputs: vec![],
+        outputs: vec![],
+        descriptors: vec![],
+        push_constants: None,
+    };
+
+    for &(id, pointer_type, storage_class) in &module.variables {
+        let ty = match module.types.get(&pointer_type) {
+            Some(&Type::Pointer(ty)) => ty,
+            _ => return Err(format!("Variable {} isn't a pointer", module.name(id)).into()),
+        };
+
+        match storage_class {
+            STORAGE_INPUT | STORAGE_OUTPUT => {
+                let decorations = module.decorations(id);
+                let location = match decorations.location {
+                    Some(location) if !decorations.built_in => location,
+                    // Built-ins, like `gl_Position` in the `gl_PerVertex`
+                    // block, aren't matched by location.
+                    _ => continue,
+                };
+                let variables = module.interface_variables(id, location, ty)?;
+                if storage_class == STORAGE_INPUT {
+                    interface.inputs.extend(variables);
+                } else {
+                    interface.outputs.extend(variables);
+                }
+            }
+            STORAGE_UNIFORM | STORAGE_UNIFORM_CONSTANT | STORAGE_STORAGE_BUFFER => {
+                interface
+                    .descriptors
+                    .push(module.descriptor(id, ty, storage_class)?);
+            }
+            STORAGE_PUSH_CONSTANT => {
+                interface.push_constants = Some(module.struct_range(ty)?);
+            }
+            _ => (),
+        }
+    }
+
+    interface.inputs.sort_by_key(|input| input.location);
+    interface.outputs.sort_by_key(|output| output.location);
+    interface
+        .descriptors
+        .sort_by_key(|descriptor| (descriptor.set, descriptor.binding));
+
+    Ok(interface)
+}
+
+/// Converts the bytes of a compiled module to words.
+pub fn words(bytes: &[u8]) -> Result<Vec<u32>, Box<Error>> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err("SPIR-V size isn't a multiple of 4 bytes".into());
+    }
+
+    Ok(bytes
+        .chunks(4)
+        .map(|b| b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+        .collect())
+}
+
+/// Component type and count a vertex attribute format is read as by shaders,
+/// for the formats vertex data is stored in.
+pub fn format_components(format: vk::Format) -> Option<(NumericType, u32)> {
+    use ash::vk::Format::*;
+
+    let components = match format {
+        R32Sfloat => (NumericType::Float, 1),
+        R32g32Sfloat | R16g16Sfloat | R16g16Unorm | R16g16Snorm | R8g8Unorm => (NumericType::Float, 2),
+        R32g32b32Sfloat => (NumericType::Float, 3),
+        R32g32b32a32Sfloat | R16g16b16a16Sfloat | R16g16b16a16Unorm | R16g16b16a16Snorm
+        | R8g8b8a8Unorm | R8g8b8a8Snorm | A2b10g10r10UnormPack32 | A2b10g10r10SnormPack32 => {
+            (NumericType::Float, 4)
+        }
+        R32Uint => (NumericType::Uint, 1),
+        R32g32b32a32Uint | R16g16b16a16Uint | R8g8b8a8Uint => (NumericType::Uint, 4),
+        R32Sint => (NumericType::Int, 1),
+        R32g32b32a32Sint => (NumericType::Int, 4),
+        _ => return None,
+    };
+
+    Some(components)
+}
+
+fn parse(code: &[u32]) -> Result<Module, Box<Error>> {
+    if code.len() < HEADER_WORDS || code[0] != MAGIC {
+        return Err("Not a SPIR-V module".into());
+    }
+
+    let mut module = Module::default();
+    let mut i = HEADER_WORDS;
+    while i < code.len() {
+        let word_count = (code[i] >> 16) as usize;
+        let opcode = code[i] & 0xffff;
+        if word_count == 0 || i + word_count > code.len() {
+            return Err("Truncated SPIR-V instruction".into());
+        }
+        let operands = &code[i + 1..i + word_count];
+        i += word_count;
+
+        match opcode {
+            OP_NAME if operands.len() >= 2 => {
+                module.names.insert(operands[0], literal_string(&operands[1..]));
+            }
+            OP_ENTRY_POINT if operands.len() >= 2 => {
+                if module.stage.is_some() {
+                    return Err("SPIR-V module has more than one entry point".into());
+                }
+                module.stage = Some(match operands[0] {
+                    0 => vk::SHADER_STAGE_VERTEX_BIT,
+                    1 => vk::SHADER_STAGE_TESSELLATION_CONTROL_BIT,
+                    2 => vk::SHADER_STAGE_TESSELLATION_EVALUATION_BIT,
+                    3 => vk::SHADER_STAGE_GEOMETRY_BIT,
+                    4 => vk::SHADER_STAGE_FRAGMENT_BIT,
+                    5 => vk::SHADER_STAGE_COMPUTE_BIT,
+                    model => return Err(format!("Unsupported execution model {}", model).into()),
+                });
+            }
+            OP_TYPE_INT if operands.len() >= 3 => {
+                let numeric_type = if operands[2] == 0 {
+                    NumericType::Uint
+                } else {
+                    NumericType::Int
+                };
+                module
+                    .types
+                    .insert(operands[0], Type::Scalar(numeric_type, operands[1]));
+            }
+            OP_TYPE_FLOAT if operands.len() >= 2 => {
+                module
+                    .types
+                    .insert(operands[0], Type::Scalar(NumericType::Float, operands[1]));
+            }
+            OP_TYPE_VECTOR | OP_TYPE_MATRIX | OP_TYPE_ARRAY | OP_TYPE_POINTER if operands.len() >= 3 => {
+                let ty = match opcode {
+                    OP_TYPE_VECTOR => Type::Vector(operands[1], operands[2]),
+                    OP_TYPE_MATRIX => Type::Matrix(operands[1], operands[2]),
+                    OP_TYPE_ARRAY => Type::Array(operands[1], operands[2]),
+                    _ => Type::Pointer(operands[2]),
+                };
+                module.types.insert(operands[0], ty);
+            }
+            OP_TYPE_IMAGE | OP_TYPE_SAMPLER | OP_TYPE_SAMPLED_IMAGE | OP_TYPE_RUNTIME_ARRAY
+                if !operands.is_empty() =>
+            {
+                let ty = match opcode {
+                    OP_TYPE_IMAGE => Type::Image,
+                    OP_TYPE_SAMPLER => Type::Sampler,
+                    OP_TYPE_SAMPLED_IMAGE => Type::SampledImage,
+                    _ => Type::RuntimeArray,
+                };
+                module.types.insert(operands[0], ty);
+            }
+            OP_TYPE_STRUCT if !operands.is_empty() => {
+                module
+                    .types
+                    .insert(operands[0], Type::Struct(operands[1..].to_vec()));
+            }
+            OP_CONSTANT if operands.len() >= 3 => {
+                module.constants.insert(operands[1], operands[2]);
+            }
+            OP_VARIABLE if operands.len() >= 3 => {
+                module.variables.push((operands[1], operands[0], operands[2]));
+            }
+            OP_DECORATE if operands.len() >= 2 => {
+                let value = operands.get(2).cloned();
+                let decorations = module
+                    .decorations
+                    .entry(operands[0])
+                    .or_insert_with(Decorations::default);
+                match operands[1] {
+                    DECORATION_BLOCK => decorations.block = true,
+                    DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                    DECORATION_BUILT_IN => decorations.built_in = true,
+                    DECORATION_ARRAY_STRIDE => decorations.array_stride = value,
+                    DECORATION_LOCATION => decorations.location = value,
+                    DECORATION_BINDING => decorations.binding = value,
+                    DECORATION_DESCRIPTOR_SET => decorations.set = value,
+                    _ => (),
+                }
+            }
+            OP_MEMBER_DECORATE if operands.len() >= 3 => {
+                let value = operands.get(3).cloned();
+                let decorations = module
+                    .member_decorations
+                    .entry((operands[0], operands[1]))
+                    .or_insert_with(MemberDecorations::default);
+                match operands[2] {
+                    DECORATION_OFFSET => decorations.offset = value,
+                    DECORATION_MATRIX_STRIDE => decorations.matrix_stride = value,
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(module)
+}
+
+/// Reads a nul terminated UTF-8 string packed into words.
+fn literal_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|&word| (0..4).map(move |i| (word >> (i * 8)) as u8))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl Module {
+    fn name(&self, id: u32) -> String {
+        match self.names.get(&id) {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => format!("%{}", id),
+        }
+    }
+
+    fn decorations(&self, id: u32) -> Decorations {
+        self.decorations.get(&id).cloned().unwrap_or_default()
+    }
+
+    fn ty(&self, id: u32) -> Result<&Type, Box<Error>> {
+        self.types
+            .get(&id)
+            .ok_or_else(|| format!("Unknown SPIR-V type %{}", id).into())
+    }
+
+    fn constant(&self, id: u32) -> Result<u32, Box<Error>> {
+        self.constants
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| format!("Array length %{} isn't a constant", id).into())
+    }
+
+    /// Numeric type and component count of a scalar or vector type.
+    fn components(&self, ty: u32) -> Option<(NumericType, u32)> {
+        match *self.ty(ty).ok()? {
+            Type::Scalar(numeric_type, _) => Some((numeric_type, 1)),
+            Type::Vector(component, count) => match *self.ty(component).ok()? {
+                Type::Scalar(numeric_type, _) => Some((numeric_type, count)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn interface_variables(&self, id: u32, location: u32, ty: u32) -> Result<Vec<Variable>, Box<Error>> {
+        let name = self.name(id);
+        let (column, columns) = match *self.ty(ty)? {
+            Type::Matrix(column, columns) => (column, columns),
+            Type::Array(element, length) => match self.components(element) {
+                Some(_) => (element, self.constant(length)?),
+                None => return Err(format!("Unsupported type of interface array `{}`", name).into()),
+            },
+            _ => (ty, 1),
+        };
+
+        let (numeric_type, components) = self.components(column)
+            .ok_or_else(|| format!("Unsupported type of interface variable `{}`", name))?;
+
+        Ok((0..columns)
+            .map(|i| Variable {
+                name: name.clone(),
+                location: location + i,
+                numeric_type,
+                components,
+            })
+            .collect())
+    }
+
+    fn descriptor(&self, id: u32, ty: u32, storage_class: u32) -> Result<Descriptor, Box<Error>> {
+        let name = self.name(id);
+        let decorations = self.decorations(id);
+        let (set, binding) = match (decorations.set, decorations.binding) {
+            (Some(set), Some(binding)) => (set, binding),
+            _ => return Err(format!("`{}` has no set or binding decoration", name).into()),
+        };
+
+        let (ty, count) = match *self.ty(ty)? {
+            Type::Array(element, length) => (element, self.constant(length)?),
+            Type::RuntimeArray => {
+                return Err(format!("`{}` is a runtime sized descriptor array", name).into())
+            }
+            _ => (ty, 1),
+        };
+
+        let type_decorations = self.decorations(ty);
+        let binding_type = match (storage_class, self.ty(ty)?) {
+            (STORAGE_UNIFORM, &Type::Struct(_)) if type_decorations.block => BindingType::UniformBuffer,
+            (STORAGE_UNIFORM, &Type::Struct(_)) if type_decorations.buffer_block => {
+                BindingType::StorageBuffer
+            }
+            (STORAGE_STORAGE_BUFFER, &Type::Struct(_)) => BindingType::StorageBuffer,
+            (STORAGE_UNIFORM_CONSTANT, &Type::SampledImage) => BindingType::CombinedImageSampler,
+            (_, &Type::Image) | (_, &Type::Sampler) => {
+                return Err(format!(
+                    "`{}` is a separate image or sampler, only combined image samplers are supported",
+                    name
+                ).into())
+            }
+            _ => return Err(format!("Unsupported descriptor type of `{}`", name).into()),
+        };
+
+        Ok(Descriptor {
+            name,
+            set,
+            binding,
+            binding_type,
+            count,
+        })
+    }
+
+    /// Offset and size of the members of a struct.
+    fn struct_range(&self, ty: u32) -> Result<(u32, u32), Box<Error>> {
+        let members = match *self.ty(ty)? {
+            Type::Struct(ref members) => members,
+            _ => return Err("Push constant block isn't a struct".into()),
+        };
+
+        let mut start = u32::MAX;
+        let mut end = 0;
+        for (i, &member) in members.iter().enumerate() {
+            let decorations = self.member_decorations.get(&(ty, i as u32));
+            let offset = decorations.and_then(|decorations| decorations.offset).unwrap_or(0);
+            let matrix_stride = decorations.and_then(|decorations| decorations.matrix_stride);
+            start = start.min(offset);
+            end = end.max(offset + self.size_of(member, matrix_stride)?);
+        }
+
+        if start > end {
+            start = end;
+        }
+        Ok((start, end - start))
+    }
+
+    fn size_of(&self, ty: u32, matrix_stride: Option<u32>) -> Result<u32, Box<Error>> {
+        let size = match *self.ty(ty)? {
+            Type::Scalar(_, width) => width / 8,
+            Type::Vector(component, count) => count * self.size_of(component, None)?,
+            Type::Matrix(column, columns) => {
+                let stride = match matrix_stride {
+                    Some(stride) => stride,
+                    None => self.size_of(column, None)?,
+                };
+                columns * stride
+            }
+            Type::Array(element, length) => {
+                let stride = match self.decorations(ty).array_stride {
+                    Some(stride) => stride,
+                    None => self.size_of(element, matrix_stride)?,
+                };
+                self.constant(length)? * stride
+            }
+            Type::Struct(_) => {
+                let (offset, size) = self.struct_range(ty)?;
+                offset + size
+            }
+            _ => return Err(format!("SPIR-V type %{} has no size", ty).into()),
+        };
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OP_TYPE_VOID: u32 = 19;
+    const EXECUTION_MODEL_VERTEX: u32 = 0;
+    const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+
+    /// Assembles a module from instructions, without checking them.
+    struct Assembler {
+        code: Vec<u32>,
+    }
+
+    impl Assembler {
+        fn new() -> Assembler {
+            Assembler {
+                code: vec![MAGIC, 0x0001_0000, 0, 100, 0],
+            }
+        }
+
+        fn op(&mut self, opcode: u32, operands: &[u32]) -> &mut Assembler {
+            self.code.push((operands.len() as u32 + 1) << 16 | opcode);
+            self.code.extend_from_slice(operands);
+            self
+        }
+
+        fn name(&mut self, id: u32, name: &str) -> &mut Assembler {
+            let mut operands = vec![id];
+            operands.extend(string(name));
+            self.op(OP_NAME, &operands)
+        }
+
+        fn entry_point(&mut self, model: u32) -> &mut Assembler {
+            let mut operands = vec![model, 99];
+            operands.extend(string("main"));
+            self.op(OP_ENTRY_POINT, &operands)
+        }
+
+        fn decorate(&mut self, id: u32, decoration: u32, value: &[u32]) -> &mut Assembler {
+            let mut operands = vec![id, decoration];
+            operands.extend_from_slice(value);
+            self.op(OP_DECORATE, &operands)
+        }
+
+        fn variable(&mut self, id: u32, pointer: u32, storage_class: u32) -> &mut Assembler {
+            self.op(OP_VARIABLE, &[pointer, id, storage_class])
+        }
+
+        fn reflect(&self) -> Result<ShaderInterface, Box<Error>> {
+            reflect(&self.code)
+        }
+    }
+
+    /// A nul terminated literal string, padded to whole words.
+    fn string(s: &str) -> Vec<u32> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.push(0);
+        while !bytes.len().is_multiple_of(4) {
+            bytes.push(0);
+        }
+        words(&bytes).unwrap()
+    }
+
+    /// Scalar, vector and matrix types most tests use: %1 float, %2 vec4,
+    /// %3 mat4, %4 int, %5 uint, %6 uvec2.
+    fn with_types(assembler: &mut Assembler) -> &mut Assembler {
+        assembler
+            .op(OP_TYPE_VOID, &[90])
+            .op(OP_TYPE_FLOAT, &[1, 32])
+            .op(OP_TYPE_VECTOR, &[2, 1, 4])
+            .op(OP_TYPE_MATRIX, &[3, 2, 4])
+            .op(OP_TYPE_INT, &[4, 32, 1])
+            .op(OP_TYPE_INT, &[5, 32, 0])
+            .op(OP_TYPE_VECTOR, &[6, 5, 2])
+    }
+
+    #[test]
+    fn reads_inputs_and_outputs() {
+        let mut assembler = Assembler::new();
+        assembler.entry_point(EXECUTION_MODEL_VERTEX);
+        with_types(&mut assembler)
+            .op(OP_TYPE_POINTER, &[10, STORAGE_INPUT, 2])
+            .op(OP_TYPE_POINTER, &[11, STORAGE_INPUT, 3])
+            .op(OP_TYPE_POINTER, &[12, STORAGE_INPUT, 4])
+            .op(OP_TYPE_POINTER, &[13, STORAGE_OUTPUT, 2])
+            .op(OP_TYPE_POINTER, &[14, STORAGE_OUTPUT, 6])
+            .name(20, "pos")
+            .name(21, "model")
+            .name(22, "id")
+            .decorate(20, DECORATION_LOCATION, &[0])
+            .decorate(21, DECORATION_LOCATION, &[8])
+            .decorate(22, DECORATION_LOCATION, &[5])
+            .decorate(23, DECORATION_BUILT_IN, &[0])
+            .decorate(24, DECORATION_LOCATION, &[1])
+            .variable(20, 10, STORAGE_INPUT)
+            .variable(21, 11, STORAGE_INPUT)
+            .variable(22, 12, STORAGE_INPUT)
+            .variable(23, 13, STORAGE_OUTPUT)
+            .variable(24, 14, STORAGE_OUTPUT);
+
+        let interface = assembler.reflect().unwrap();
+        assert_eq!(interface.stage, vk::SHADER_STAGE_VERTEX_BIT);
+
+        let inputs: Vec<_> = interface
+            .inputs
+            .iter()
+            .map(|input| (input.name.as_str(), input.location, input.numeric_type, input.components))
+            .collect();
+        assert_eq!(
+            inputs,
+            vec![
+                ("pos", 0, NumericType::Float, 4),
+                ("id", 5, NumericType::Int, 1),
+                ("model", 8, NumericType::Float, 4),
+                ("model", 9, NumericType::Float, 4),
+                ("model", 10, NumericType::Float, 4),
+                ("model", 11, NumericType::Float, 4),
+            ]
+        );
+
+        // The built-in position isn't matched by location.
+        assert_eq!(interface.outputs.len(), 1);
+        let output = &interface.outputs[0];
+        assert_eq!(output.name, "%24");
+        assert_eq!(output.location, 1);
+        assert_eq!((output.numeric_type, output.components), (NumericType::Uint, 2));
+        assert!(interface.descriptors.is_empty());
+        assert_eq!(interface.push_constants, None);
+    }
+
+    #[test]
+    fn reads_descriptors() {
+        let mut assembler = Assembler::new();
+        assembler.entry_point(EXECUTION_MODEL_FRAGMENT);
+        with_types(&mut assembler)
+            // Uniform block.
+            .op(OP_TYPE_STRUCT, &[30, 3, 3])
+            .decorate(30, DECORATION_BLOCK, &[])
+            .op(OP_TYPE_POINTER, &[31, STORAGE_UNIFORM, 30])
+            .name(32, "camera")
+            .decorate(32, DECORATION_DESCRIPTOR_SET, &[0])
+            .decorate(32, DECORATION_BINDING, &[1])
+            .variable(32, 31, STORAGE_UNIFORM)
+            // Array of four combined image samplers.
+            .op(OP_TYPE_IMAGE, &[40, 1, 1, 0, 0, 0, 1, 0])
+            .op(OP_TYPE_SAMPLED_IMAGE, &[41, 40])
+            .op(OP_CONSTANT, &[5, 42, 4])
+            .op(OP_TYPE_ARRAY, &[43, 41, 42])
+            .op(OP_TYPE_POINTER, &[44, STORAGE_UNIFORM_CONSTANT, 43])
+            .name(45, "textures")
+            .decorate(45, DECORATION_DESCRIPTOR_SET, &[1])
+            .decorate(45, DECORATION_BINDING, &[0])
+            .variable(45, 44, STORAGE_UNIFORM_CONSTANT)
+            // Storage buffers, the old and the new way.
+            .op(OP_TYPE_STRUCT, &[50, 2])
+            .decorate(50, DECORATION_BUFFER_BLOCK, &[])
+            .op(OP_TYPE_POINTER, &[51, STORAGE_UNIFORM, 50])
+            .name(52, "particles")
+            .decorate(52, DECORATION_DESCRIPTOR_SET, &[0])
+            .decorate(52, DECORATION_BINDING, &[3])
+            .variable(52, 51, STORAGE_UNIFORM)
+            .op(OP_TYPE_STRUCT, &[53, 2])
+            .decorate(53, DECORATION_BLOCK, &[])
+            .op(OP_TYPE_POINTER, &[54, STORAGE_STORAGE_BUFFER, 53])
+            .name(55, "lights")
+            .decorate(55, DECORATION_DESCRIPTOR_SET, &[0])
+            .decorate(55, DECORATION_BINDING, &[0])
+            .variable(55, 54, STORAGE_STORAGE_BUFFER);
+
+        let interface = assembler.reflect().unwrap();
+        assert_eq!(interface.stage, vk::SHADER_STAGE_FRAGMENT_BIT);
+
+        // Sorted by set and binding.
+        let descriptors: Vec<_> = interface
+            .descriptors
+            .iter()
+            .map(|d| (d.name.as_str(), d.set, d.binding, d.binding_type, d.count))
+            .collect();
+        assert_eq!(
+            descriptors,
+            vec![
+                ("lights", 0, 0, BindingType::StorageBuffer, 1),
+                ("camera", 0, 1, BindingType::UniformBuffer, 1),
+                ("particles", 0, 3, BindingType::StorageBuffer, 1),
+                ("textures", 1, 0, BindingType::CombinedImageSampler, 4),
+            ]
+        );
+        assert!(interface.inputs.is_empty());
+    }
+
+    #[test]
+    fn reads_the_push_constant_range() {
+        let mut assembler = Assembler::new();
+        assembler.entry_point(EXECUTION_MODEL_VERTEX);
+        with_types(&mut assembler)
+            // Members declared out of offset order, the matrix padded to a
+            // 32 byte column stride.
+            .op(OP_TYPE_STRUCT, &[60, 3, 2, 5])
+            .op(OP_MEMBER_DECORATE, &[60, 0, DECORATION_OFFSET, 32])
+            .op(OP_MEMBER_DECORATE, &[60, 0, DECORATION_MATRIX_STRIDE, 32])
+            .op(OP_MEMBER_DECORATE, &[60, 1, DECORATION_OFFSET, 16])
+            .op(OP_MEMBER_DECORATE, &[60, 2, DECORATION_OFFSET, 160])
+            .decorate(60, DECORATION_BLOCK, &[])
+            .op(OP_TYPE_POINTER, &[61, STORAGE_PUSH_CONSTANT, 60])
+            .variable(62, 61, STORAGE_PUSH_CONSTANT);
+
+        let interface = assembler.reflect().unwrap();
+        // From the vec4 at 16 to the end of the uint at 160.
+        assert_eq!(interface.push_constants, Some((16, 148)));
+        assert!(interface.descriptors.is_empty());
+    }
+
+    #[test]
+    fn sizes_arrays_by_their_stride() {
+        let mut assembler = Assembler::new();
+        assembler.entry_point(EXECUTION_MODEL_VERTEX);
+        with_types(&mut assembler)
+            .op(OP_CONSTANT, &[5, 70, 3])
+            .op(OP_TYPE_ARRAY, &[71, 1, 70])
+            .decorate(71, DECORATION_ARRAY_STRIDE, &[16])
+            .op(OP_TYPE_STRUCT, &[72, 71])
+            .op(OP_MEMBER_DECORATE, &[72, 0, DECORATION_OFFSET, 0])
+            .op(OP_TYPE_POINTER, &[73, STORAGE_PUSH_CONSTANT, 72])
+            .variable(74, 73, STORAGE_PUSH_CONSTANT);
+
+        assert_eq!(assembler.reflect().unwrap().push_constants, Some((0, 48)));
+    }
+
+    #[test]
+    fn rejects_unsupported_descriptors() {
+        let mut assembler = Assembler::new();
+        assembler.entry_point(EXECUTION_MODEL_FRAGMENT);
+        with_types(&mut assembler)
+            .op(OP_TYPE_SAMPLER, &[40])
+            .op(OP_TYPE_POINTER, &[41, STORAGE_UNIFORM_CONSTANT, 40])
+            .name(42, "linear")
+            .decorate(42, DECORATION_DESCRIPTOR_SET, &[0])
+            .decorate(42, DECORATION_BINDING, &[0])
+            .variable(42, 41, STORAGE_UNIFORM_CONSTANT);
+        let err = assembler.reflect().unwrap_err().to_string();
+        assert!(err.contains("`linear` is a separate image or sampler"), "{}", err);
+
+        let mut assembler = Assembler::new();
+        assembler.entry_point(EXECUTION_MODEL_FRAGMENT);
+        with_types(&mut assembler)
+            .op(OP_TYPE_STRUCT, &[30, 2])
+            .decorate(30, DECORATION_BLOCK, &[])
+            .op(OP_TYPE_POINTER, &[31, STORAGE_UNIFORM, 30])
+            .name(32, "unbound")
+            .decorate(32, DECORATION_DESCRIPTOR_SET, &[0])
+            .variable(32, 31, STORAGE_UNIFORM);
+        let err = assembler.reflect().unwrap_err().to_string();
+        assert!(err.contains("`unbound` has no set or binding"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_malformed_modules() {
+        assert!(reflect(&[]).is_err());
+        assert!(reflect(&[0x0302_2307, 0x0001_0000, 0, 1, 0]).is_err());
+
+        let mut assembler = Assembler::new();
+        with_types(&mut assembler);
+        let err = assembler.reflect().unwrap_err().to_string();
+        assert!(err.contains("no entry point"), "{}", err);
+
+        assembler
+            .entry_point(EXECUTION_MODEL_VERTEX)
+            .entry_point(EXECUTION_MODEL_FRAGMENT);
+        let err = assembler.reflect().unwrap_err().to_string();
+        assert!(err.contains("more than one entry point"), "{}", err);
+
+        // The last instruction claims more words than are left.
+        let mut assembler = Assembler::new();
+        assembler.entry_point(EXECUTION_MODEL_VERTEX);
+        assembler.code.push(4 << 16 | OP_TYPE_FLOAT);
+        assembler.code.push(1);
+        let err = assembler.reflect().unwrap_err().to_string();
+        assert!(err.contains("Truncated"), "{}", err);
+    }
+
+    #[test]
+    fn converts_bytes_to_little_endian_words() {
+        assert_eq!(words(&[0x03, 0x02, 0x23, 0x07, 1, 0, 0, 0]).unwrap(), vec![MAGIC, 1]);
+        assert!(words(&[0, 0, 0]).is_err());
+    }
+}
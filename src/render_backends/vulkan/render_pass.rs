@@ -0,0 +1,213 @@
+use ash::vk;
+use ash::version::DeviceV1_0;
+
+use std::ptr;
+use std::error::Error;
+
+use super::VkDevice;
+
+/// `final_layout` is the layout the color attachment is left in: `PresentSrcKhr`
+/// for the swapchain, `ShaderReadOnlyOptimal` for targets sampled later.
+///
+/// With more than one sample the color and depth attachments are
+/// multisampled and only live during the pass, the color is resolved into a
+/// third, single sampled attachment which is the one left in `final_layout`.
+pub fn new(
+    device: &VkDevice,
+    color_format: vk::Format,
+    depth_format: vk::Format,
+    samples: vk::SampleCountFlags,
+    final_layout: vk::ImageLayout,
+) -> Result<vk::RenderPass, Box<Error>> {
+    let multisampled = samples != vk::SAMPLE_COUNT_1_BIT;
+    let mut attachments = vec![
+        vk::AttachmentDescription {
+            format: color_format,
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            samples,
+            load_op: vk::AttachmentLoadOp::Clear,
+            store_op: if multisampled {
+                vk::AttachmentStoreOp::DontCare
+            } else {
+                vk::AttachmentStoreOp::Store
+            },
+            stencil_load_op: vk::AttachmentLoadOp::DontCare,
+            stencil_store_op: vk::AttachmentStoreOp::DontCare,
+            initial_layout: vk::ImageLayout::Undefined,
+            final_layout: if multisampled {
+                vk::ImageLayout::ColorAttachmentOptimal
+            } else {
+                final_layout
+            },
+        },
+        vk::AttachmentDescription {
+            format: depth_format,
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            samples,
+            load_op: vk::AttachmentLoadOp::Clear,
+            store_op: vk::AttachmentStoreOp::DontCare,
+            stencil_load_op: vk::AttachmentLoadOp::DontCare,
+            stencil_store_op: vk::AttachmentStoreOp::DontCare,
+            initial_layout: vk::ImageLayout::Undefined,
+            final_layout: vk::ImageLayout::DepthStencilAttachmentOptimal,
+        },
+    ];
+    if multisampled {
+        attachments.push(vk::AttachmentDescription {
+            format: color_format,
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            samples: vk::SAMPLE_COUNT_1_BIT,
+            load_op: vk::AttachmentLoadOp::DontCare,
+            store_op: vk::AttachmentStoreOp::Store,
+            stencil_load_op: vk::AttachmentLoadOp::DontCare,
+            stencil_store_op: vk::AttachmentStoreOp::DontCare,
+            initial_layout: vk::ImageLayout::Undefined,
+            final_layout,
+        });
+    }
+
+    let dependencies = [
+        // Also waits for the previous frame's blit out of a scaled frame's
+        // color before drawing over it.
+        vk::SubpassDependency {
+            dependency_flags: Default::default(),
+            src_subpass: vk::VK_SUBPASS_EXTERNAL,
+            dst_subpass: Default::default(),
+            src_stage_mask: vk::PIPELINE_STAGE_COLOR_ATTACHMENT_OUTPUT_BIT
+                | vk::PIPELINE_STAGE_TRANSFER_BIT,
+            src_access_mask: Default::default(),
+            dst_access_mask: vk::ACCESS_COLOR_ATTACHMENT_READ_BIT
+                | vk::ACCESS_COLOR_ATTACHMENT_WRITE_BIT,
+            dst_stage_mask: vk::PIPELINE_STAGE_COLOR_ATTACHMENT_OUTPUT_BIT,
+        },
+        // Makes the color writes visible to later passes sampling the
+        // attachment and to readback copies.
+        vk::SubpassDependency {
+            dependency_flags: Default::default(),
+            src_subpass: Default::default(),
+            dst_subpass: vk::VK_SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PIPELINE_STAGE_COLOR_ATTACHMENT_OUTPUT_BIT,
+            src_access_mask: vk::ACCESS_COLOR_ATTACHMENT_WRITE_BIT,
+            dst_access_mask: vk::ACCESS_SHADER_READ_BIT | vk::ACCESS_TRANSFER_READ_BIT,
+            dst_stage_mask: vk::PIPELINE_STAGE_FRAGMENT_SHADER_BIT
+                | vk::PIPELINE_STAGE_TRANSFER_BIT,
+        },
+    ];
+
+    let subpass = {
+        let color_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::ColorAttachmentOptimal,
+        };
+        let depth_attachment_ref = vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::DepthStencilAttachmentOptimal,
+        };
+        let resolve_attachment_ref = vk::AttachmentReference {
+            attachment: 2,
+            layout: vk::ImageLayout::ColorAttachmentOptimal,
+        };
+
+        vk::SubpassDescription {
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref,
+            p_depth_stencil_attachment: &depth_attachment_ref,
+            flags: Default::default(),
+            pipeline_bind_point: vk::PipelineBindPoint::Graphics,
+            input_attachment_count: 0,
+            p_input_attachments: ptr::null(),
+            p_resolve_attachments: if multisampled {
+                &resolve_attachment_ref
+            } else {
+                ptr::null()
+            },
+            preserve_attachment_count: 0,
+            p_preserve_attachments: ptr::null(),
+        }
+    };
+
+    let renderpass_create_info = vk::RenderPassCreateInfo {
+        s_type: vk::StructureType::RenderPassCreateInfo,
+        flags: Default::default(),
+        p_next: ptr::null(),
+        attachment_count: attachments.len() as u32,
+        p_attachments: attachments.as_ptr(),
+        subpass_count: 1,
+        p_subpasses: &subpass,
+        dependency_count: dependencies.len() as u32,
+        p_dependencies: dependencies.as_ptr(),
+    };
+
+    let render_pass = unsafe { device.create_render_pass(&renderpass_create_info, None)? };
+
+    Ok(render_pass)
+}
+
+/// Pass with a single depth attachment and no color, for shadow maps. The
+/// depth is left in `ShaderReadOnlyOptimal` to be sampled by later passes.
+pub fn new_depth_only(
+    device: &VkDevice,
+    depth_format: vk::Format,
+) -> Result<vk::RenderPass, Box<Error>> {
+    let attachment = vk::AttachmentDescription {
+        format: depth_format,
+        flags: vk::AttachmentDescriptionFlags::empty(),
+        samples: vk::SAMPLE_COUNT_1_BIT,
+        load_op: vk::AttachmentLoadOp::Clear,
+        store_op: vk::AttachmentStoreOp::Store,
+        stencil_load_op: vk::AttachmentLoadOp::DontCare,
+        stencil_store_op: vk::AttachmentStoreOp::DontCare,
+        initial_layout: vk::ImageLayout::Undefined,
+        final_layout: vk::ImageLayout::ShaderReadOnlyOptimal,
+    };
+
+    // Nothing orders the clear after earlier sampling: every frame in flight
+    // clears its own maps, which the previous frame using them is done with
+    // once its fence has been waited on. Such a dependency would also hold
+    // the pass back until the frame before it had finished shading.
+    let dependencies = [
+        // Makes the depth writes visible to later passes sampling it.
+        vk::SubpassDependency {
+            dependency_flags: Default::default(),
+            src_subpass: Default::default(),
+            dst_subpass: vk::VK_SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PIPELINE_STAGE_LATE_FRAGMENT_TESTS_BIT,
+            src_access_mask: vk::ACCESS_DEPTH_STENCIL_ATTACHMENT_WRITE_BIT,
+            dst_access_mask: vk::ACCESS_SHADER_READ_BIT,
+            dst_stage_mask: vk::PIPELINE_STAGE_FRAGMENT_SHADER_BIT,
+        },
+    ];
+
+    let depth_attachment_ref = vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::DepthStencilAttachmentOptimal,
+    };
+    let subpass = vk::SubpassDescription {
+        color_attachment_count: 0,
+        p_color_attachments: ptr::null(),
+        p_depth_stencil_attachment: &depth_attachment_ref,
+        flags: Default::default(),
+        pipeline_bind_point: vk::PipelineBindPoint::Graphics,
+        input_attachment_count: 0,
+        p_input_attachments: ptr::null(),
+        p_resolve_attachments: ptr::null(),
+        preserve_attachment_count: 0,
+        p_preserve_attachments: ptr::null(),
+    };
+
+    let renderpass_create_info = vk::RenderPassCreateInfo {
+        s_type: vk::StructureType::RenderPassCreateInfo,
+        flags: Default::default(),
+        p_next: ptr::null(),
+        attachment_count: 1,
+        p_attachments: &attachment,
+        subpass_count: 1,
+        p_subpasses: &subpass,
+        dependency_count: dependencies.len() as u32,
+        p_dependencies: dependencies.as_ptr(),
+    };
+
+    let render_pass = unsafe { device.create_render_pass(&renderpass_create_info, None)? };
+
+    Ok(render_pass)
+}
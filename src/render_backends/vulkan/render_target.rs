@@ -0,0 +1,361 @@
+use ash::vk;
+use ash::version::{DeviceV1_0, InstanceV1_0};
+use image::RgbaImage;
+
+use std::ptr;
+use std::slice;
+use std::error::Error;
+
+use super::VK_INSTANCE;
+use super::VkDevice;
+use super::{framebuffers, image_views, render_pass};
+use super::find_memorytype_index;
+
+/// Every device can render depth in this format.
+const TARGET_DEPTH_FORMAT: vk::Format = vk::Format::D16Unorm;
+
+/// Color and depth images that can be rendered into instead of the swapchain.
+/// After a frame the color image is left in `ShaderReadOnlyOptimal`, ready to
+/// be sampled by later passes through `color_view` and `sampler`. Targets
+/// are drawn with one sample and ignore the `RenderSettings`.
+pub struct RenderTarget {
+    extent: vk::Extent2D,
+    format: vk::Format,
+
+    color_image: vk::Image,
+    color_memory: vk::DeviceMemory,
+    color_view: vk::ImageView,
+
+    depth_image: vk::Image,
+    depth_memory: vk::DeviceMemory,
+    depth_view: vk::ImageView,
+
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    sampler: vk::Sampler,
+}
+
+impl RenderTarget {
+    /// `format` has to match the swapchain's for the target to work with the
+    /// same pipelines.
+    pub fn new(
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<RenderTarget, Box<Error>> {
+        let (color_image, color_memory) = image_views::new_image(
+            device,
+            &extent,
+            format,
+            vk::IMAGE_USAGE_COLOR_ATTACHMENT_BIT | vk::IMAGE_USAGE_SAMPLED_BIT
+                | vk::IMAGE_USAGE_TRANSFER_SRC_BIT,
+            physical_device,
+        )?;
+        let color_view =
+            image_views::new_view(device, color_image, format, vk::IMAGE_ASPECT_COLOR_BIT)?;
+
+        let (depth_image, depth_memory) = image_views::new_depth_image(
+            device,
+            &extent,
+            TARGET_DEPTH_FORMAT,
+            vk::SAMPLE_COUNT_1_BIT,
+            physical_device,
+        )?;
+        let depth_view = image_views::new_depth_view(device, depth_image, TARGET_DEPTH_FORMAT)?;
+
+        let render_pass = render_pass::new(
+            device,
+            format,
+            TARGET_DEPTH_FORMAT,
+            vk::SAMPLE_COUNT_1_BIT,
+            vk::ImageLayout::ShaderReadOnlyOptimal,
+        )?;
+
+        let framebuffer =
+            framebuffers::new(device, render_pass, &extent, &[color_view], depth_view, None)?[0];
+
+        let sampler_info = vk::SamplerCreateInfo {
+            s_type: vk::StructureType::SamplerCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            mag_filter: vk::Filter::Linear,
+            min_filter: vk::Filter::Linear,
+            mipmap_mode: vk::SamplerMipmapMode::Linear,
+            address_mode_u: vk::SamplerAddressMode::ClampToEdge,
+            address_mode_v: vk::SamplerAddressMode::ClampToEdge,
+            address_mode_w: vk::SamplerAddressMode::ClampToEdge,
+            mip_lod_bias: 0.0,
+            anisotropy_enable: 0,
+            max_anisotropy: 1.0,
+            compare_enable: 0,
+            compare_op: vk::CompareOp::Always,
+            min_lod: 0.0,
+            max_lod: 0.0,
+            border_color: vk::BorderColor::FloatOpaqueBlack,
+            unnormalized_coordinates: 0,
+        };
+        let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+
+        Ok(RenderTarget {
+            extent,
+            format,
+
+            color_image,
+            color_memory,
+            color_view,
+
+            depth_image,
+            depth_memory,
+            depth_view,
+
+            render_pass,
+            framebuffer,
+            sampler,
+        })
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn color_image(&self) -> vk::Image {
+        self.color_image
+    }
+
+    pub fn color_view(&self) -> vk::ImageView {
+        self.color_view
+    }
+
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    pub fn framebuffer(&self) -> vk::Framebuffer {
+        self.framebuffer
+    }
+
+    /// # Safety
+    ///
+    /// The GPU must be done with the target, and it can't be used again.
+    pub unsafe fn destroy(&self, device: &VkDevice) {
+        device.destroy_sampler(self.sampler, None);
+        device.destroy_framebuffer(self.framebuffer, None);
+        device.destroy_render_pass(self.render_pass, None);
+        device.destroy_image_view(self.depth_view, None);
+        device.destroy_image(self.depth_image, None);
+        device.free_memory(self.depth_memory, None);
+        device.destroy_image_view(self.color_view, None);
+        device.destroy_image(self.color_image, None);
+        device.free_memory(self.color_memory, None);
+    }
+}
+
+/// Host visible buffer a color image is copied into, so it can be read on
+/// the CPU once the copy has finished.
+pub struct Readback {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    size: u64,
+    extent: vk::Extent2D,
+    format: vk::Format,
+}
+
+impl Readback {
+    pub fn new(
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Readback, Box<Error>> {
+        match format {
+            vk::Format::R8g8b8a8Unorm
+            | vk::Format::R8g8b8a8Srgb
+            | vk::Format::B8g8r8a8Unorm
+            | vk::Format::B8g8r8a8Srgb => (),
+            _ => return Err(format!("Can't read back images in {:?}", format).into()),
+        }
+
+        let size = extent.width as u64 * extent.height as u64 * 4;
+
+        let buffer_info = vk::BufferCreateInfo {
+            s_type: vk::StructureType::BufferCreateInfo,
+            p_next: ptr::null(),
+            flags: vk::BufferCreateFlags::empty(),
+            size,
+            usage: vk::BUFFER_USAGE_TRANSFER_DST_BIT,
+            sharing_mode: vk::SharingMode::Exclusive,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+        };
+        let buffer = unsafe { device.create_buffer(&buffer_info, None)? };
+
+        let memory_requirements = device.get_buffer_memory_requirements(buffer);
+        let memory_properties = VK_INSTANCE.get_physical_device_memory_properties(physical_device);
+        let memory_type = find_memorytype_index(
+            &memory_requirements,
+            &memory_properties,
+            vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT | vk::MEMORY_PROPERTY_HOST_COHERENT_BIT,
+        )?;
+        let allocate_info = vk::MemoryAllocateInfo {
+            s_type: vk::StructureType::MemoryAllocateInfo,
+            p_next: ptr::null(),
+            allocation_size: memory_requirements.size,
+            memory_type_index: memory_type,
+        };
+
+        let memory = unsafe {
+            let memory = device.allocate_memory(&allocate_info, None)?;
+            device.bind_buffer_memory(buffer, memory, 0)?;
+            memory
+        };
+
+        Ok(Readback {
+            buffer,
+            memory,
+            size,
+            extent,
+            format,
+        })
+    }
+
+    /// Records copying `image`, which is in `layout` after the render pass,
+    /// into the buffer. The image is put back into `layout` afterwards.
+    pub fn record_copy(
+        &self,
+        device: &VkDevice,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        layout: vk::ImageLayout,
+    ) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::IMAGE_ASPECT_COLOR_BIT,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        let to_transfer = vk::ImageMemoryBarrier {
+            s_type: vk::StructureType::ImageMemoryBarrier,
+            p_next: ptr::null(),
+            src_access_mask: vk::ACCESS_COLOR_ATTACHMENT_WRITE_BIT,
+            dst_access_mask: vk::ACCESS_TRANSFER_READ_BIT,
+            old_layout: layout,
+            new_layout: vk::ImageLayout::TransferSrcOptimal,
+            src_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: subresource_range.clone(),
+        };
+
+        let back_from_transfer = vk::ImageMemoryBarrier {
+            s_type: vk::StructureType::ImageMemoryBarrier,
+            p_next: ptr::null(),
+            src_access_mask: vk::ACCESS_TRANSFER_READ_BIT,
+            dst_access_mask: Default::default(),
+            old_layout: vk::ImageLayout::TransferSrcOptimal,
+            new_layout: layout,
+            src_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range,
+        };
+
+        let to_host = vk::BufferMemoryBarrier {
+            s_type: vk::StructureType::BufferMemoryBarrier,
+            p_next: ptr::null(),
+            src_access_mask: vk::ACCESS_TRANSFER_WRITE_BIT,
+            dst_access_mask: vk::ACCESS_HOST_READ_BIT,
+            src_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            buffer: self.buffer,
+            offset: 0,
+            size: self.size,
+        };
+
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::IMAGE_ASPECT_COLOR_BIT,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            },
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PIPELINE_STAGE_COLOR_ATTACHMENT_OUTPUT_BIT,
+                vk::PIPELINE_STAGE_TRANSFER_BIT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TransferSrcOptimal,
+                self.buffer,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PIPELINE_STAGE_TRANSFER_BIT,
+                vk::PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT | vk::PIPELINE_STAGE_HOST_BIT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[to_host],
+                &[back_from_transfer],
+            );
+        }
+    }
+
+    /// Reads the copied pixels. Only valid once the submission recorded by
+    /// `record_copy` has completed.
+    pub fn read(&self, device: &VkDevice) -> Result<RgbaImage, Box<Error>> {
+        let mut pixels = unsafe {
+            let data = device.map_memory(self.memory, 0, self.size, vk::MemoryMapFlags::empty())?;
+            let pixels = slice::from_raw_parts(data as *const u8, self.size as usize).to_vec();
+            device.unmap_memory(self.memory);
+            pixels
+        };
+
+        match self.format {
+            vk::Format::B8g8r8a8Unorm | vk::Format::B8g8r8a8Srgb => {
+                for pixel in pixels.chunks_mut(4) {
+                    pixel.swap(0, 2);
+                }
+            }
+            _ => (),
+        }
+
+        let image = RgbaImage::from_raw(self.extent.width, self.extent.height, pixels)
+            .ok_or("Readback buffer doesn't match the image size")?;
+
+        Ok(image)
+    }
+
+    pub unsafe fn destroy(&self, device: &VkDevice) {
+        device.destroy_buffer(self.buffer, None);
+        device.free_memory(self.memory, None);
+    }
+}
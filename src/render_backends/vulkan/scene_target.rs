@@ -0,0 +1,322 @@
+use ash::vk;
+use ash::version::DeviceV1_0;
+
+use std::ptr;
+use std::error::Error;
+
+use renderer::RenderSettings;
+use super::VkDevice;
+use super::{depth_format, framebuffers, image_views, sample_count};
+
+/// An image with its memory and a view of it.
+struct Attachment {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+}
+
+impl Attachment {
+    fn new(
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        samples: vk::SampleCountFlags,
+        aspect: vk::ImageAspectFlags,
+    ) -> Result<Attachment, Box<Error>> {
+        let (image, memory) = image_views::new_multisampled_image(
+            device,
+            &extent,
+            format,
+            usage,
+            samples,
+            physical_device,
+        )?;
+        let view = match image_views::new_view(device, image, format, aspect) {
+            Ok(view) => view,
+            Err(err) => {
+                unsafe {
+                    device.destroy_image(image, None);
+                    device.free_memory(memory, None);
+                }
+                return Err(err);
+            }
+        };
+
+        Ok(Attachment {
+            image,
+            memory,
+            view,
+        })
+    }
+
+    unsafe fn destroy(&self, device: &VkDevice) {
+        device.destroy_image_view(self.view, None);
+        device.destroy_image(self.image, None);
+        device.free_memory(self.memory, None);
+    }
+}
+
+/// Images the main pass needs besides the swapchain's to draw a frame for
+/// it: the depth buffer, the multisampled color resolved at the end of the
+/// pass and, at a resolution scale other than 1, the color image the frame
+/// is drawn into at its own size before being blitted onto the swapchain
+/// image. Frames in flight share them, the render pass's dependencies keep
+/// them from overlapping.
+pub struct SceneTarget {
+    /// Size frames are drawn at.
+    extent: vk::Extent2D,
+    depth: Attachment,
+    multisampled: Option<Attachment>,
+    scaled: Option<Attachment>,
+    /// One per swapchain image, or a single one drawing into `scaled`.
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+impl SceneTarget {
+    /// `render_pass` has to have been created with the `settings`' samples
+    /// and depth format and, when their resolution scale isn't 1,
+    /// `TransferSrcOptimal` as its final layout.
+    pub fn new(
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+        swapchain_extent: vk::Extent2D,
+        color_format: vk::Format,
+        present_image_views: &[vk::ImageView],
+        settings: &RenderSettings,
+    ) -> Result<SceneTarget, Box<Error>> {
+        let samples = sample_count(settings.samples);
+        let resolution_scale = settings.resolution_scale;
+        let scale = |size: u32| ((size as f32 * resolution_scale).round() as u32).max(1);
+        let extent = if resolution_scale == 1.0 {
+            swapchain_extent
+        } else {
+            vk::Extent2D {
+                width: scale(swapchain_extent.width),
+                height: scale(swapchain_extent.height),
+            }
+        };
+
+        let depth = Attachment::new(
+            device,
+            physical_device,
+            extent,
+            depth_format(settings.depth_format),
+            vk::IMAGE_USAGE_DEPTH_STENCIL_ATTACHMENT_BIT,
+            samples,
+            vk::IMAGE_ASPECT_DEPTH_BIT,
+        )?;
+        let mut target = SceneTarget {
+            extent,
+            depth,
+            multisampled: None,
+            scaled: None,
+            framebuffers: vec![],
+        };
+
+        // Whatever fails past the depth buffer destroys what was made before
+        // it, `destroy` skips the parts that are still missing.
+        let created = target.create_color(
+            device,
+            physical_device,
+            render_pass,
+            color_format,
+            present_image_views,
+            settings,
+        );
+        if let Err(err) = created {
+            unsafe { target.destroy(device) };
+            return Err(err);
+        }
+
+        Ok(target)
+    }
+
+    /// Creates the color attachments and the framebuffers of a target that
+    /// only has its depth buffer yet.
+    fn create_color(
+        &mut self,
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+        color_format: vk::Format,
+        present_image_views: &[vk::ImageView],
+        settings: &RenderSettings,
+    ) -> Result<(), Box<Error>> {
+        let samples = sample_count(settings.samples);
+        // Only ever read by the resolve, so it can stay in tile memory.
+        if samples != vk::SAMPLE_COUNT_1_BIT {
+            self.multisampled = Some(Attachment::new(
+                device,
+                physical_device,
+                self.extent,
+                color_format,
+                vk::IMAGE_USAGE_COLOR_ATTACHMENT_BIT | vk::IMAGE_USAGE_TRANSIENT_ATTACHMENT_BIT,
+                samples,
+                vk::IMAGE_ASPECT_COLOR_BIT,
+            )?);
+        }
+        if settings.resolution_scale != 1.0 {
+            self.scaled = Some(Attachment::new(
+                device,
+                physical_device,
+                self.extent,
+                color_format,
+                vk::IMAGE_USAGE_COLOR_ATTACHMENT_BIT | vk::IMAGE_USAGE_TRANSFER_SRC_BIT,
+                vk::SAMPLE_COUNT_1_BIT,
+                vk::IMAGE_ASPECT_COLOR_BIT,
+            )?);
+        }
+
+        let color_views = match self.scaled {
+            Some(ref scaled) => vec![scaled.view],
+            None => present_image_views.to_vec(),
+        };
+        self.framebuffers = framebuffers::new(
+            device,
+            render_pass,
+            &self.extent,
+            &color_views,
+            self.depth.view,
+            self.multisampled.as_ref().map(|multisampled| multisampled.view),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// The framebuffer drawing the frame for the swapchain image at
+    /// `image_index`.
+    pub fn framebuffer(&self, image_index: usize) -> vk::Framebuffer {
+        match self.scaled {
+            Some(_) => self.framebuffers[0],
+            None => self.framebuffers[image_index],
+        }
+    }
+
+    /// The image frames are drawn into before being blitted onto the
+    /// swapchain, if they are drawn at another size.
+    pub fn scaled_image(&self) -> Option<vk::Image> {
+        self.scaled.as_ref().map(|scaled| scaled.image)
+    }
+
+    /// Scales the frame drawn into the scaled image onto `present_image`,
+    /// leaving it ready to be presented. The frame's render pass has left
+    /// the scaled image in `TransferSrcOptimal`. Does nothing for frames
+    /// drawn at the swapchain's size.
+    pub fn record_blit(
+        &self,
+        device: &VkDevice,
+        command_buffer: vk::CommandBuffer,
+        present_image: vk::Image,
+        present_extent: vk::Extent2D,
+    ) {
+        let scaled = match self.scaled {
+            Some(ref scaled) => scaled,
+            None => return,
+        };
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::IMAGE_ASPECT_COLOR_BIT,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::IMAGE_ASPECT_COLOR_BIT,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let corner = |extent: vk::Extent2D| vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: 1,
+        };
+        let origin = vk::Offset3D { x: 0, y: 0, z: 0 };
+
+        // The previous contents were presented already, the acquire
+        // semaphore is waited on at the transfer stage.
+        let to_transfer = vk::ImageMemoryBarrier {
+            s_type: vk::StructureType::ImageMemoryBarrier,
+            p_next: ptr::null(),
+            src_access_mask: Default::default(),
+            dst_access_mask: vk::ACCESS_TRANSFER_WRITE_BIT,
+            old_layout: vk::ImageLayout::Undefined,
+            new_layout: vk::ImageLayout::TransferDstOptimal,
+            src_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            image: present_image,
+            subresource_range: subresource_range.clone(),
+        };
+        let to_present = vk::ImageMemoryBarrier {
+            s_type: vk::StructureType::ImageMemoryBarrier,
+            p_next: ptr::null(),
+            src_access_mask: vk::ACCESS_TRANSFER_WRITE_BIT,
+            dst_access_mask: Default::default(),
+            old_layout: vk::ImageLayout::TransferDstOptimal,
+            new_layout: vk::ImageLayout::PresentSrcKhr,
+            src_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            image: present_image,
+            subresource_range,
+        };
+        let region = vk::ImageBlit {
+            src_subresource: subresource,
+            src_offsets: [origin, corner(self.extent)],
+            dst_subresource: subresource,
+            dst_offsets: [origin, corner(present_extent)],
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PIPELINE_STAGE_TRANSFER_BIT,
+                vk::PIPELINE_STAGE_TRANSFER_BIT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+            // ash has no wrapper for this one.
+            device.fp_v1_0().cmd_blit_image(
+                command_buffer,
+                scaled.image,
+                vk::ImageLayout::TransferSrcOptimal,
+                present_image,
+                vk::ImageLayout::TransferDstOptimal,
+                1,
+                &region,
+                vk::Filter::Linear,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PIPELINE_STAGE_TRANSFER_BIT,
+                vk::PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_present],
+            );
+        }
+    }
+
+    pub unsafe fn destroy(&self, device: &VkDevice) {
+        for &framebuffer in &self.framebuffers {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+        if let Some(ref scaled) = self.scaled {
+            scaled.destroy(device);
+        }
+        if let Some(ref multisampled) = self.multisampled {
+            multisampled.destroy(device);
+        }
+        self.depth.destroy(device);
+    }
+}
@@ -0,0 +1,230 @@
+use ash::vk;
+use ash::version::DeviceV1_0;
+
+use std::ptr;
+use std::error::Error;
+
+use renderer::{MAX_SHADOW_MAPS, SHADOW_MAP_SIZE};
+use super::VkDevice;
+use super::{command, image_views, render_pass};
+
+const SHADOW_MAP_FORMAT: vk::Format = vk::Format::D16Unorm;
+
+/// What every frame's `ShadowMaps` are drawn and sampled with.
+pub struct ShadowPass {
+    render_pass: vk::RenderPass,
+    sampler: vk::Sampler,
+}
+
+impl ShadowPass {
+    pub fn new(device: &VkDevice) -> Result<ShadowPass, Box<Error>> {
+        let render_pass = render_pass::new_depth_only(device, SHADOW_MAP_FORMAT)?;
+
+        // Points outside every map read as lit through the white border,
+        // filtering is done by the shaders.
+        let sampler_info = vk::SamplerCreateInfo {
+            s_type: vk::StructureType::SamplerCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            mag_filter: vk::Filter::Nearest,
+            min_filter: vk::Filter::Nearest,
+            mipmap_mode: vk::SamplerMipmapMode::Nearest,
+            address_mode_u: vk::SamplerAddressMode::ClampToBorder,
+            address_mode_v: vk::SamplerAddressMode::ClampToBorder,
+            address_mode_w: vk::SamplerAddressMode::ClampToEdge,
+            mip_lod_bias: 0.0,
+            anisotropy_enable: 0,
+            max_anisotropy: 1.0,
+            compare_enable: 1,
+            compare_op: vk::CompareOp::LessOrEqual,
+            min_lod: 0.0,
+            max_lod: 0.0,
+            border_color: vk::BorderColor::FloatOpaqueWhite,
+            unnormalized_coordinates: 0,
+        };
+        let sampler = match unsafe { device.create_sampler(&sampler_info, None) } {
+            Ok(sampler) => sampler,
+            Err(err) => {
+                unsafe { device.destroy_render_pass(render_pass, None) };
+                return Err(Box::new(err));
+            }
+        };
+
+        Ok(ShadowPass {
+            render_pass,
+            sampler,
+        })
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        vk::Extent2D {
+            width: SHADOW_MAP_SIZE,
+            height: SHADOW_MAP_SIZE,
+        }
+    }
+
+    pub fn render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    pub fn sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    pub unsafe fn destroy(&self, device: &VkDevice) {
+        device.destroy_sampler(self.sampler, None);
+        device.destroy_render_pass(self.render_pass, None);
+    }
+}
+
+/// Depth image with a layer per shadow map, each drawn through its own
+/// framebuffer and all sampled together as an array with depth comparison.
+/// Every frame in flight has its own, so a frame's shadow passes don't wait
+/// for the previous frame to be done sampling its maps.
+pub struct ShadowMaps {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    /// Every layer, as sampled by the lighting shaders.
+    array_view: vk::ImageView,
+    layer_views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+impl ShadowMaps {
+    /// Layers are put into `ShaderReadOnlyOptimal` right away, so frames
+    /// drawing fewer maps than there are layers can still bind them all.
+    pub fn new(
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        pass: &ShadowPass,
+    ) -> Result<ShadowMaps, Box<Error>> {
+        let extent = pass.extent();
+        let layers = MAX_SHADOW_MAPS as u32;
+        let (image, memory) = image_views::new_layered_image(
+            device,
+            &extent,
+            SHADOW_MAP_FORMAT,
+            vk::IMAGE_USAGE_DEPTH_STENCIL_ATTACHMENT_BIT | vk::IMAGE_USAGE_SAMPLED_BIT,
+            layers,
+            physical_device,
+        )?;
+        let array_view = image_views::new_layer_view(
+            device,
+            image,
+            SHADOW_MAP_FORMAT,
+            vk::IMAGE_ASPECT_DEPTH_BIT,
+            vk::ImageViewType::Type2dArray,
+            0,
+            layers,
+        )?;
+        let layer_views = (0..layers)
+            .map(|layer| {
+                image_views::new_layer_view(
+                    device,
+                    image,
+                    SHADOW_MAP_FORMAT,
+                    vk::IMAGE_ASPECT_DEPTH_BIT,
+                    vk::ImageViewType::Type2d,
+                    layer,
+                    1,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let framebuffers = layer_views
+            .iter()
+            .map(|view| {
+                let create_info = vk::FramebufferCreateInfo {
+                    s_type: vk::StructureType::FramebufferCreateInfo,
+                    p_next: ptr::null(),
+                    flags: Default::default(),
+                    render_pass: pass.render_pass(),
+                    attachment_count: 1,
+                    p_attachments: view,
+                    width: extent.width,
+                    height: extent.height,
+                    layers: 1,
+                };
+                unsafe { device.create_framebuffer(&create_info, None) }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let shadow_maps = ShadowMaps {
+            image,
+            memory,
+            array_view,
+            layer_views,
+            framebuffers,
+        };
+
+        let command_buffers = command::alloc_buffers(device, command_pool, 1)?;
+        let transitioned = command::submit(
+            device,
+            command_buffers[0],
+            queue,
+            &[],
+            &[],
+            &[],
+            |device, command_buffer| shadow_maps.record_initial_layout(device, command_buffer),
+        );
+        unsafe { device.free_command_buffers(command_pool, &command_buffers) };
+        transitioned?;
+
+        Ok(shadow_maps)
+    }
+
+    fn record_initial_layout(&self, device: &VkDevice, command_buffer: vk::CommandBuffer) {
+        let barrier = vk::ImageMemoryBarrier {
+            s_type: vk::StructureType::ImageMemoryBarrier,
+            p_next: ptr::null(),
+            src_access_mask: Default::default(),
+            dst_access_mask: vk::ACCESS_SHADER_READ_BIT,
+            old_layout: vk::ImageLayout::Undefined,
+            new_layout: vk::ImageLayout::ShaderReadOnlyOptimal,
+            src_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+            image: self.image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::IMAGE_ASPECT_DEPTH_BIT,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: MAX_SHADOW_MAPS as u32,
+            },
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PIPELINE_STAGE_TOP_OF_PIPE_BIT,
+                vk::PIPELINE_STAGE_FRAGMENT_SHADER_BIT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+        }
+    }
+
+    pub fn framebuffer(&self, layer: usize) -> vk::Framebuffer {
+        self.framebuffers[layer]
+    }
+
+    pub fn array_view(&self) -> vk::ImageView {
+        self.array_view
+    }
+
+    pub unsafe fn destroy(&self, device: &VkDevice) {
+        for &framebuffer in &self.framebuffers {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+        for &view in &self.layer_views {
+            device.destroy_image_view(view, None);
+        }
+        device.destroy_image_view(self.array_view, None);
+        device.destroy_image(self.image, None);
+        device.free_memory(self.memory, None);
+    }
+}
@@ -15,25 +15,20 @@ pub struct Surface {
     pub height: u32,
     loader: ext::Surface,
     handle: vk::SurfaceKHR,
-    format: vk::SurfaceFormatKHR,
 }
 
 impl Surface {
     pub fn new(window: &winit::Window) -> Result<Surface, Box<Error>> {
         let handle = Surface::create_surface_khr(window)?;
-        let loader = ext::Surface::new(VK_ENTRY, VK_INSTANCE).map_err(|errors| errors.join("\n"))?;
+        let loader =
+            ext::Surface::new(&*VK_ENTRY, &*VK_INSTANCE).map_err(|errors| errors.join("\n"))?;
         let (width, height) = window.get_inner_size().unwrap_or((0, 0));
-        let format = vk::SurfaceFormatKHR {
-            format: vk::Format::B8g8r8Unorm,
-            color_space: vk::ColorSpaceKHR::SrgbNonlinear,
-        };
 
         Ok(Surface {
             width,
             height,
             loader,
             handle,
-            format,
         })
     }
 
@@ -54,7 +49,7 @@ impl Surface {
         };
 
         let xlib_surface_loader =
-            XlibSurface::new(VK_ENTRY, VK_INSTANCE).map_err(|errors| errors.join("\n"))?;
+            XlibSurface::new(&*VK_ENTRY, &*VK_INSTANCE).map_err(|errors| errors.join("\n"))?;
 
         let surface =
             unsafe { xlib_surface_loader.create_xlib_surface_khr(&x11_create_info, None)? };
@@ -78,7 +73,7 @@ impl Surface {
         };
 
         let win32_surface_loader =
-            Win32Surface::new(VK_ENTRY, VK_INSTANCE).map_err(|errors| errors.join("\n"))?;
+            Win32Surface::new(&*VK_ENTRY, &*VK_INSTANCE).map_err(|errors| errors.join("\n"))?;
 
         let surface =
             unsafe { win32_surface_loader.create_win32_surface_khr(&win32_create_info, None)? };
@@ -86,8 +81,8 @@ impl Surface {
         Ok(surface)
     }
 
-    pub fn loader(&self) -> ext::Surface {
-        self.loader
+    pub fn loader(&self) -> &ext::Surface {
+        &self.loader
     }
 
     pub fn handle(&self) -> vk::SurfaceKHR {
@@ -97,18 +92,21 @@ impl Surface {
     pub fn present_modes(
         &self,
         physical_device: vk::PhysicalDevice,
-    ) -> Result<vk::PresentModeKHR, Box<Error>> {
-        self.loader
-            .get_physical_device_surface_present_modes_khr(physical_device, self.handle)?
-            .iter()
+    ) -> Result<Vec<vk::PresentModeKHR>, Box<Error>> {
+        let present_modes = self.loader
+            .get_physical_device_surface_present_modes_khr(physical_device, self.handle)?;
+
+        Ok(present_modes)
     }
 
     pub fn capabilities(
         &self,
         physical_device: vk::PhysicalDevice,
     ) -> Result<vk::SurfaceCapabilitiesKHR, Box<Error>> {
-        self.loader
-            .get_physical_device_surface_capabilities_khr(physical_device, self.handle)
+        let capabilities = self.loader
+            .get_physical_device_surface_capabilities_khr(physical_device, self.handle)?;
+
+        Ok(capabilities)
     }
 
     pub fn format(
@@ -134,14 +132,24 @@ impl Surface {
     pub fn extent(&self, physical_device: vk::PhysicalDevice) -> Result<vk::Extent2D, Box<Error>> {
         let capabilities = self.capabilities(physical_device)?;
 
+        // A current extent of u32::MAX means the swapchain decides the size,
+        // within the limits the surface allows.
         let extent = match capabilities.current_extent.width {
             u32::MAX => vk::Extent2D {
-                width: self.width,
-                height: self.height,
+                width: self.width
+                    .max(capabilities.min_image_extent.width)
+                    .min(capabilities.max_image_extent.width),
+                height: self.height
+                    .max(capabilities.min_image_extent.height)
+                    .min(capabilities.max_image_extent.height),
             },
             _ => capabilities.current_extent,
         };
 
         Ok(extent)
     }
+
+    pub unsafe fn destroy(&self) {
+        self.loader.destroy_surface_khr(self.handle, None);
+    }
 }
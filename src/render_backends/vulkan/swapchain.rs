@@ -0,0 +1,220 @@
+use ash::vk;
+use ash::extensions as ext;
+use ash::version::InstanceV1_0;
+
+use std::ptr;
+use std::fmt;
+use std::error::Error;
+
+use super::VK_INSTANCE;
+use super::VkDevice;
+use super::surface::Surface;
+
+/// Returned when the surface has to be recreated from the window, the
+/// swapchain alone can't be rebuilt on it anymore.
+#[derive(Debug)]
+pub struct SurfaceLost;
+
+impl fmt::Display for SurfaceLost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The window surface was lost")
+    }
+}
+
+impl Error for SurfaceLost {
+    fn description(&self) -> &str {
+        "surface lost"
+    }
+}
+
+pub struct Swapchain {
+    handle: vk::SwapchainKHR,
+    loader: ext::Swapchain,
+    // ash's acquire and present wrappers turn `SUBOPTIMAL_KHR` into an error
+    // and drop the acquired index, so those two are called through the raw
+    // function table.
+    swapchain_fn: vk::SwapchainFn,
+    device: vk::Device,
+    format: vk::SurfaceFormatKHR,
+    extent: vk::Extent2D,
+    image_usage: vk::ImageUsageFlags,
+}
+
+impl Swapchain {
+    /// `old_swapchain` is the swapchain being replaced, or null. Passing it
+    /// lets the driver hand over its resources and keep presenting images
+    /// that were already queued. It still has to be destroyed by the caller.
+    /// Surfaces without `present_mode` get `Fifo`, which all of them have.
+    pub fn new(
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        surface: &Surface,
+        format: vk::SurfaceFormatKHR,
+        present_mode: vk::PresentModeKHR,
+        old_swapchain: vk::SwapchainKHR,
+    ) -> Result<Swapchain, Box<Error>> {
+        let loader =
+            ext::Swapchain::new(&*VK_INSTANCE, device).map_err(|_| "Unable to load swapchain")?;
+        let swapchain_fn = vk::SwapchainFn::load(|name| {
+            VK_INSTANCE.get_device_proc_addr(device.handle(), name.as_ptr()) as *const _
+        }).map_err(|_| "Unable to load swapchain")?;
+
+        let capabilities = surface.capabilities(physical_device)?;
+
+        let desired_image_count = {
+            let min_count = capabilities.min_image_count;
+            let max_count = capabilities.max_image_count;
+
+            if max_count > 0 && min_count + 1 > max_count {
+                max_count
+            } else {
+                min_count + 1
+            }
+        };
+
+        let pre_transform = if capabilities
+            .supported_transforms
+            .subset(vk::SURFACE_TRANSFORM_IDENTITY_BIT_KHR)
+        {
+            vk::SURFACE_TRANSFORM_IDENTITY_BIT_KHR
+        } else {
+            capabilities.current_transform
+        };
+
+        let present_mode = surface
+            .present_modes(physical_device)?
+            .into_iter()
+            .find(|&mode| mode == present_mode)
+            .unwrap_or(vk::PresentModeKHR::Fifo);
+
+        let extent = surface.extent(physical_device)?;
+
+        // Copying out of swapchain images is what screenshots rely on,
+        // copying into them is how frames drawn at another resolution get
+        // there.
+        let image_usage = [vk::IMAGE_USAGE_TRANSFER_SRC_BIT, vk::IMAGE_USAGE_TRANSFER_DST_BIT]
+            .iter()
+            .filter(|&&usage| capabilities.supported_usage_flags.subset(usage))
+            .fold(vk::IMAGE_USAGE_COLOR_ATTACHMENT_BIT, |all, &usage| all | usage);
+
+        let handle = {
+            let swapchain_create_info = vk::SwapchainCreateInfoKHR {
+                s_type: vk::StructureType::SwapchainCreateInfoKhr,
+                p_next: ptr::null(),
+                flags: Default::default(),
+                surface: surface.handle(),
+                min_image_count: desired_image_count,
+                image_color_space: format.color_space,
+                image_format: format.format,
+                image_extent: extent,
+                image_usage,
+                image_sharing_mode: vk::SharingMode::Exclusive,
+                pre_transform,
+                composite_alpha: vk::COMPOSITE_ALPHA_OPAQUE_BIT_KHR,
+                present_mode,
+                clipped: 1,
+                old_swapchain,
+                image_array_layers: 1,
+                p_queue_family_indices: ptr::null(),
+                queue_family_index_count: 0,
+            };
+
+            unsafe { loader.create_swapchain_khr(&swapchain_create_info, None)? }
+        };
+
+        Ok(Swapchain {
+            handle,
+            loader,
+            swapchain_fn,
+            device: device.handle(),
+            format,
+            extent,
+            image_usage,
+        })
+    }
+
+    pub fn handle(&self) -> vk::SwapchainKHR {
+        self.handle
+    }
+
+    pub fn format(&self) -> vk::SurfaceFormatKHR {
+        self.format.clone()
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn supports_readback(&self) -> bool {
+        self.image_usage.subset(vk::IMAGE_USAGE_TRANSFER_SRC_BIT)
+    }
+
+    pub fn supports_blit(&self) -> bool {
+        self.image_usage.subset(vk::IMAGE_USAGE_TRANSFER_DST_BIT)
+    }
+
+    pub fn images(&self) -> Result<Vec<vk::Image>, Box<Error>> {
+        let images = self.loader.get_swapchain_images_khr(self.handle)?;
+        Ok(images)
+    }
+
+    /// Returns `None` when the swapchain is out of date and has to be rebuilt
+    /// before anything can be drawn. A suboptimal swapchain still returns an
+    /// image; `present` reports it afterwards.
+    pub fn acquire_next_image(
+        &self,
+        signal_semaphore: vk::Semaphore,
+    ) -> Result<Option<u32>, Box<Error>> {
+        let mut index = 0;
+        let result = unsafe {
+            self.swapchain_fn.acquire_next_image_khr(
+                self.device,
+                self.handle,
+                u64::MAX,
+                signal_semaphore,
+                vk::Fence::null(),
+                &mut index,
+            )
+        };
+
+        match result {
+            vk::Result::Success | vk::Result::SuboptimalKhr => Ok(Some(index)),
+            vk::Result::ErrorOutOfDateKhr => Ok(None),
+            vk::Result::ErrorSurfaceLostKhr => Err(Box::new(SurfaceLost)),
+            err => Err(Box::new(err)),
+        }
+    }
+
+    /// Returns `true` when the swapchain no longer matches the surface and
+    /// should be rebuilt.
+    pub fn present(
+        &self,
+        queue: vk::Queue,
+        image_index: u32,
+        wait_semaphores: &[vk::Semaphore],
+    ) -> Result<bool, Box<Error>> {
+        let present_info = vk::PresentInfoKHR {
+            s_type: vk::StructureType::PresentInfoKhr,
+            p_next: ptr::null(),
+            wait_semaphore_count: wait_semaphores.len() as u32,
+            p_wait_semaphores: wait_semaphores.as_ptr(),
+            swapchain_count: 1,
+            p_swapchains: &self.handle,
+            p_image_indices: &image_index,
+            p_results: ptr::null_mut(),
+        };
+
+        let result = unsafe { self.swapchain_fn.queue_present_khr(queue, &present_info) };
+
+        match result {
+            vk::Result::Success => Ok(false),
+            vk::Result::SuboptimalKhr | vk::Result::ErrorOutOfDateKhr => Ok(true),
+            vk::Result::ErrorSurfaceLostKhr => Err(Box::new(SurfaceLost)),
+            err => Err(Box::new(err)),
+        }
+    }
+
+    pub unsafe fn destroy(&self) {
+        self.loader.destroy_swapchain_khr(self.handle, None);
+    }
+}
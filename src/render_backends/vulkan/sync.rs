@@ -1,3 +1,9 @@
+use ash::vk;
+use ash::version::DeviceV1_0;
+
+use std::ptr;
+use std::error::Error;
+
 use super::VkDevice;
 
 pub fn create_semaphore(device: &VkDevice) -> Result<vk::Semaphore, Box<Error>> {
@@ -12,14 +18,20 @@ pub fn create_semaphore(device: &VkDevice) -> Result<vk::Semaphore, Box<Error>>
     Ok(semaphore)
 }
 
-pub fn create_fence(device: &VkDevice) -> Result<vk::Fence, Box<Error>> {
+/// Frame fences start out `signaled` so the first wait on them returns
+/// right away.
+pub fn create_fence(device: &VkDevice, signaled: bool) -> Result<vk::Fence, Box<Error>> {
     let fence_info = vk::FenceCreateInfo {
         s_type: vk::StructureType::FenceCreateInfo,
         p_next: ptr::null(),
-        flags: Default::default(),
+        flags: if signaled {
+            vk::FENCE_CREATE_SIGNALED_BIT
+        } else {
+            Default::default()
+        },
     };
 
     let fence = unsafe { device.create_fence(&fence_info, None)? };
 
-    Ok(fences)
+    Ok(fence)
 }
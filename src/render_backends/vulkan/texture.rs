@@ -0,0 +1,342 @@
+use ash::vk;
+use ash::version::DeviceV1_0;
+
+use std::ptr;
+use std::error::Error;
+
+use renderer::{ColorSpace, MaterialTexture, MipLevel};
+use super::VkDevice;
+use super::buffer::Allocator;
+use super::{command, image_views};
+
+/// A material texture with its whole mip chain, sampled by the shaders
+/// through the material set.
+pub struct Texture {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+}
+
+impl Texture {
+    /// Copies the levels into a device local image through a staging
+    /// buffer and waits for the copy, leaving every level in
+    /// `ShaderReadOnlyOptimal`.
+    pub fn new(
+        device: &VkDevice,
+        physical_device: vk::PhysicalDevice,
+        allocator: &mut Allocator,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        texture: &MaterialTexture,
+    ) -> Result<Texture, Box<Error>> {
+        check_mip_levels(texture.mip_levels)
+            .map_err(|err| format!("Texture `{}`: {}", texture.name, err))?;
+
+        let format = match texture.color_space {
+            ColorSpace::Srgb => vk::Format::R8g8b8a8Srgb,
+            ColorSpace::Linear => vk::Format::R8g8b8a8Unorm,
+        };
+        let levels = texture.mip_levels;
+        let extent = vk::Extent2D {
+            width: levels[0].width,
+            height: levels[0].height,
+        };
+        let (image, memory) = image_views::new_mipmapped_image(
+            device,
+            &extent,
+            format,
+            vk::IMAGE_USAGE_TRANSFER_DST_BIT | vk::IMAGE_USAGE_SAMPLED_BIT,
+            levels.len() as u32,
+            physical_device,
+        )?;
+        let view = image_views::new_mipmapped_view(device, image, format, levels.len() as u32);
+        let view = match view {
+            Ok(view) => view,
+            Err(err) => {
+                unsafe {
+                    device.destroy_image(image, None);
+                    device.free_memory(memory, None);
+                }
+                return Err(err);
+            }
+        };
+        let texture = Texture {
+            image,
+            memory,
+            view,
+        };
+
+        if let Err(err) = texture.upload(device, allocator, command_pool, queue, levels) {
+            unsafe { texture.destroy(device) };
+            return Err(err);
+        }
+
+        Ok(texture)
+    }
+
+    fn upload(
+        &self,
+        device: &VkDevice,
+        allocator: &mut Allocator,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        levels: &[MipLevel],
+    ) -> Result<(), Box<Error>> {
+        let size: usize = levels.iter().map(|level| level.pixels.len()).sum();
+        let staging = allocator.create_buffer(
+            device,
+            size as u64,
+            vk::BUFFER_USAGE_TRANSFER_SRC_BIT,
+            vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT | vk::MEMORY_PROPERTY_HOST_COHERENT_BIT,
+        )?;
+
+        let uploaded = allocator
+            .buffer(staging)
+            .mapped()
+            .ok_or_else(|| "Staging buffer isn't host visible".into())
+            .and_then(|mapped| {
+                let mut offset = 0;
+                let mut regions = vec![];
+                for (i, level) in levels.iter().enumerate() {
+                    unsafe {
+                        ptr::copy_nonoverlapping(
+                            level.pixels.as_ptr(),
+                            mapped.add(offset),
+                            level.pixels.len(),
+                        )
+                    };
+                    regions.push(vk::BufferImageCopy {
+                        buffer_offset: offset as u64,
+                        buffer_row_length: 0,
+                        buffer_image_height: 0,
+                        image_subresource: vk::ImageSubresourceLayers {
+                            aspect_mask: vk::IMAGE_ASPECT_COLOR_BIT,
+                            mip_level: i as u32,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        },
+                        image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                        image_extent: vk::Extent3D {
+                            width: level.width,
+                            height: level.height,
+                            depth: 1,
+                        },
+                    });
+                    offset += level.pixels.len();
+                }
+
+                let buffer = allocator.buffer(staging).buf;
+                let command_buffers = command::alloc_buffers(device, command_pool, 1)?;
+                let copied = command::submit(
+                    device,
+                    command_buffers[0],
+                    queue,
+                    &[],
+                    &[],
+                    &[],
+                    |device, command_buffer| {
+                        self.record_copy(
+                            device,
+                            command_buffer,
+                            buffer,
+                            levels.len() as u32,
+                            &regions,
+                        )
+                    },
+                );
+                unsafe { device.free_command_buffers(command_pool, &command_buffers) };
+                copied
+            });
+
+        // `submit` has waited for the copy, whether or not it went through.
+        allocator.free_buffer(device, staging);
+
+        uploaded
+    }
+
+    fn record_copy(
+        &self,
+        device: &VkDevice,
+        command_buffer: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        mip_levels: u32,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        let barrier = |src_access_mask, dst_access_mask, old_layout, new_layout| {
+            vk::ImageMemoryBarrier {
+                s_type: vk::StructureType::ImageMemoryBarrier,
+                p_next: ptr::null(),
+                src_access_mask,
+                dst_access_mask,
+                old_layout,
+                new_layout,
+                src_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
+                image: self.image,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::IMAGE_ASPECT_COLOR_BIT,
+                    base_mip_level: 0,
+                    level_count: mip_levels,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+            }
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PIPELINE_STAGE_TOP_OF_PIPE_BIT,
+                vk::PIPELINE_STAGE_TRANSFER_BIT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        Default::default(),
+                        vk::ACCESS_TRANSFER_WRITE_BIT,
+                        vk::ImageLayout::Undefined,
+                        vk::ImageLayout::TransferDstOptimal,
+                    ),
+                ],
+            );
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                buffer,
+                self.image,
+                vk::ImageLayout::TransferDstOptimal,
+                regions,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PIPELINE_STAGE_TRANSFER_BIT,
+                vk::PIPELINE_STAGE_FRAGMENT_SHADER_BIT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        vk::ACCESS_TRANSFER_WRITE_BIT,
+                        vk::ACCESS_SHADER_READ_BIT,
+                        vk::ImageLayout::TransferDstOptimal,
+                        vk::ImageLayout::ShaderReadOnlyOptimal,
+                    ),
+                ],
+            );
+        }
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    /// The GPU must be done with the texture.
+    pub unsafe fn destroy(&self, device: &VkDevice) {
+        device.destroy_image_view(self.view, None);
+        device.destroy_image(self.image, None);
+        device.free_memory(self.memory, None);
+    }
+}
+
+/// The sampler every material texture is read through: trilinear and
+/// repeating.
+pub fn new_sampler(device: &VkDevice) -> Result<vk::Sampler, Box<Error>> {
+    let sampler_info = vk::SamplerCreateInfo {
+        s_type: vk::StructureType::SamplerCreateInfo,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        mag_filter: vk::Filter::Linear,
+        min_filter: vk::Filter::Linear,
+        mipmap_mode: vk::SamplerMipmapMode::Linear,
+        address_mode_u: vk::SamplerAddressMode::Repeat,
+        address_mode_v: vk::SamplerAddressMode::Repeat,
+        address_mode_w: vk::SamplerAddressMode::Repeat,
+        mip_lod_bias: 0.0,
+        anisotropy_enable: 0,
+        max_anisotropy: 1.0,
+        compare_enable: 0,
+        compare_op: vk::CompareOp::Always,
+        min_lod: 0.0,
+        // No clamping, every texture's chain is sampled down to its last
+        // level.
+        max_lod: 1000.0,
+        border_color: vk::BorderColor::FloatOpaqueBlack,
+        unnormalized_coordinates: 0,
+    };
+    let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+
+    Ok(sampler)
+}
+
+/// Checks that `levels` is a mip chain of RGBA8 images, each level half the
+/// size of the one before it rounded down, but at least 1.
+fn check_mip_levels(levels: &[MipLevel]) -> Result<(), String> {
+    let first = levels.first().ok_or("no mip levels")?;
+    if first.width == 0 || first.height == 0 {
+        return Err(format!("{}x{} is empty", first.width, first.height));
+    }
+
+    let halved = |size: u32, i: usize| size.checked_shr(i as u32).unwrap_or(0);
+    for (i, level) in levels.iter().enumerate() {
+        if halved(first.width.max(first.height), i) == 0 {
+            return Err(format!(
+                "{} mip levels are more than a {}x{} image has",
+                levels.len(),
+                first.width,
+                first.height
+            ));
+        }
+        let width = halved(first.width, i).max(1);
+        let height = halved(first.height, i).max(1);
+        if level.width != width || level.height != height {
+            return Err(format!(
+                "mip level {} is {}x{} instead of {}x{}",
+                i, level.width, level.height, width, height
+            ));
+        }
+        let size = width as usize * height as usize * 4;
+        if level.pixels.len() != size {
+            return Err(format!(
+                "mip level {} has {} bytes of pixels instead of {}",
+                i,
+                level.pixels.len(),
+                size
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(width: u32, height: u32) -> MipLevel {
+        MipLevel {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    #[test]
+    fn accepts_full_and_partial_mip_chains() {
+        assert!(check_mip_levels(&[level(4, 2), level(2, 1), level(1, 1)]).is_ok());
+        assert!(check_mip_levels(&[level(4, 2), level(2, 1)]).is_ok());
+        assert!(check_mip_levels(&[level(3, 5)]).is_ok());
+    }
+
+    #[test]
+    fn rejects_broken_mip_chains() {
+        assert!(check_mip_levels(&[]).is_err());
+        assert!(check_mip_levels(&[level(0, 4)]).is_err());
+        // Levels have to halve, and stop once both sides are 1.
+        assert!(check_mip_levels(&[level(4, 4), level(4, 4)]).is_err());
+        assert!(check_mip_levels(&[level(2, 2), level(1, 1), level(1, 1)]).is_err());
+
+        let mut short = level(2, 2);
+        short.pixels.pop();
+        assert!(check_mip_levels(&[short]).is_err());
+    }
+}
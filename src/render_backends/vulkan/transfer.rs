@@ -0,0 +1,436 @@
+use ash::vk;
+use ash::version::DeviceV1_0;
+
+use std::ptr;
+use std::collections::VecDeque;
+use std::error::Error;
+
+use super::VkDevice;
+use super::{command, sync};
+use super::buffer::Allocator;
+
+/// Size of the host visible ring uploads are staged in.
+const RING_SIZE: u64 = 16 * 1024 * 1024;
+/// Larger uploads are split so they never need more than this much of the
+/// ring at once.
+const MAX_CHUNK_SIZE: u64 = RING_SIZE / 4;
+const RING_ALIGNMENT: u64 = 16;
+
+/// Identifies a batch of uploads, later batches have larger ids. The default
+/// id counts as complete.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UploadId(u64);
+
+struct PendingCopy {
+    src: vk::Buffer,
+    dst: vk::Buffer,
+    region: vk::BufferCopy,
+}
+
+/// Offsets into the staging ring. The used part is `[tail, head)`, wrapping
+/// around the end when `head < tail`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Ring {
+    size: u64,
+    /// Next free byte.
+    head: u64,
+    /// First byte still used by a batch in flight or the pending batch.
+    tail: u64,
+}
+
+/// A submitted batch of copies.
+struct Batch {
+    id: u64,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    /// Where the ring's free space starts once the batch is done.
+    ring_end: u64,
+}
+
+/// Copies data to device local buffers through a staging ring buffer. Copies
+/// are collected into batches which run on the transfer queue without
+/// blocking, the graphics queue waits for them through the semaphores from
+/// `take_wait_semaphores` and the CPU can poll them with `is_complete`.
+pub struct Uploader {
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+
+    /// Looked up for every upload, defragmenting may move it.
+    ring_buffer: usize,
+    ring: Ring,
+
+    pending: Vec<PendingCopy>,
+    in_flight: VecDeque<Batch>,
+    /// Id the pending batch will get.
+    next_id: u64,
+    /// Every batch up to and including this id has completed.
+    completed_id: u64,
+
+    /// Signaled by submitted batches the graphics queue hasn't waited on.
+    unwaited_semaphores: Vec<vk::Semaphore>,
+    free_semaphores: Vec<vk::Semaphore>,
+}
+
+impl Uploader {
+    /// `queue` should be on a transfer only family if the device has one, so
+    /// uploads run alongside rendering.
+    pub fn new(
+        device: &VkDevice,
+        allocator: &mut Allocator,
+        queue: vk::Queue,
+        queue_family_index: u32,
+    ) -> Result<Uploader, Box<Error>> {
+        let command_pool = command::create_pool(device, queue_family_index)?;
+
+        let ring_buffer = allocator.create_buffer(
+            device,
+            RING_SIZE,
+            vk::BUFFER_USAGE_TRANSFER_SRC_BIT,
+            vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT | vk::MEMORY_PROPERTY_HOST_COHERENT_BIT,
+        )?;
+
+        Ok(Uploader {
+            queue,
+            command_pool,
+
+            ring_buffer,
+            ring: Ring::new(RING_SIZE),
+
+            pending: vec![],
+            in_flight: VecDeque::new(),
+            next_id: 1,
+            completed_id: 0,
+
+            unwaited_semaphores: vec![],
+            free_semaphores: vec![],
+        })
+    }
+
+    /// Queues copying `data` to `dst` at `dst_offset`. The data is staged
+    /// right away, the copy starts with the next `flush`.
+    pub fn upload(
+        &mut self,
+        device: &VkDevice,
+        allocator: &Allocator,
+        data: &[u8],
+        dst: vk::Buffer,
+        dst_offset: u64,
+    ) -> Result<UploadId, Box<Error>> {
+        let (ring, ring_ptr) = {
+            let buffer = allocator.buffer(self.ring_buffer);
+            (buffer.buf, buffer.mapped().ok_or("Staging ring isn't host visible")?)
+        };
+
+        for (i, chunk) in data.chunks(MAX_CHUNK_SIZE as usize).enumerate() {
+            let size = chunk.len() as u64;
+            let src_offset = self.reserve(device, size)?;
+            unsafe {
+                ptr::copy_nonoverlapping(chunk.as_ptr(), ring_ptr.offset(src_offset as isize), chunk.len())
+            };
+
+            self.pending.push(PendingCopy {
+                src: ring,
+                dst,
+                region: vk::BufferCopy {
+                    src_offset,
+                    dst_offset: dst_offset + i as u64 * MAX_CHUNK_SIZE,
+                    size,
+                },
+            });
+        }
+
+        Ok(UploadId(self.next_id))
+    }
+
+    /// Queues a copy between two device buffers, e.g. when one replaces the
+    /// other.
+    pub fn copy(&mut self, src: vk::Buffer, dst: vk::Buffer, region: vk::BufferCopy) -> UploadId {
+        self.pending.push(PendingCopy { src, dst, region });
+        UploadId(self.next_id)
+    }
+
+    /// Submits the queued copies as one batch.
+    pub fn flush(&mut self, device: &VkDevice) -> Result<(), Box<Error>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let command_buffer = command::alloc_buffers(device, self.command_pool, 1)?[0];
+        let fence = sync::create_fence(device, false)?;
+        let semaphore = match self.free_semaphores.pop() {
+            Some(semaphore) => semaphore,
+            None => sync::create_semaphore(device)?,
+        };
+
+        let copies = &self.pending;
+        let submitted = command::record(device, command_buffer, |device, command_buffer| {
+            for copy in copies {
+                let regions = std::slice::from_ref(&copy.region);
+                unsafe { device.cmd_copy_buffer(command_buffer, copy.src, copy.dst, regions) };
+            }
+        }).and_then(|_| {
+            command::submit_with_fence(
+                device,
+                command_buffer,
+                self.queue,
+                &[],
+                &[],
+                &[semaphore],
+                fence,
+            )
+        });
+        if let Err(err) = submitted {
+            unsafe {
+                device.free_command_buffers(self.command_pool, &[command_buffer]);
+                device.destroy_fence(fence, None);
+            }
+            self.free_semaphores.push(semaphore);
+            return Err(err);
+        }
+
+        self.pending.clear();
+        self.in_flight.push_back(Batch {
+            id: self.next_id,
+            command_buffer,
+            fence,
+            ring_end: self.ring.head,
+        });
+        self.unwaited_semaphores.push(semaphore);
+        self.next_id += 1;
+
+        Ok(())
+    }
+
+    /// Polls whether the batch `id` belongs to has finished. Flushes it if it
+    /// is still pending.
+    pub fn is_complete(&mut self, device: &VkDevice, id: UploadId) -> Result<bool, Box<Error>> {
+        if id.0 == self.next_id {
+            self.flush(device)?;
+        }
+        self.retire(device)?;
+        Ok(id.0 <= self.completed_id)
+    }
+
+    /// Blocks until the batch `id` belongs to has finished.
+    pub fn wait(&mut self, device: &VkDevice, id: UploadId) -> Result<(), Box<Error>> {
+        if id.0 == self.next_id {
+            self.flush(device)?;
+        }
+        while id.0 > self.completed_id {
+            self.wait_oldest(device)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes and blocks until every upload has finished.
+    pub fn wait_idle(&mut self, device: &VkDevice) -> Result<(), Box<Error>> {
+        self.flush(device)?;
+        while !self.in_flight.is_empty() {
+            self.wait_oldest(device)?;
+        }
+        Ok(())
+    }
+
+    /// Semaphores the next graphics submission has to wait on before reading
+    /// uploaded data. Hand them back with `recycle_semaphores` once that
+    /// submission has completed.
+    pub fn take_wait_semaphores(&mut self) -> Vec<vk::Semaphore> {
+        self.unwaited_semaphores.drain(..).collect()
+    }
+
+    pub fn recycle_semaphores(&mut self, semaphores: &mut Vec<vk::Semaphore>) {
+        self.free_semaphores.append(semaphores);
+    }
+
+    /// Nothing may be in use by the GPU, including the semaphores handed out.
+    pub unsafe fn destroy(&mut self, device: &VkDevice, allocator: &mut Allocator) {
+        for batch in self.in_flight.drain(..) {
+            device.destroy_fence(batch.fence, None);
+        }
+        for semaphore in self.unwaited_semaphores.drain(..).chain(self.free_semaphores.drain(..)) {
+            device.destroy_semaphore(semaphore, None);
+        }
+        device.destroy_command_pool(self.command_pool, None);
+        allocator.free_buffer(device, self.ring_buffer);
+    }
+
+    /// Returns the ring offset of `size` free bytes, waiting for batches in
+    /// flight to finish if the ring is full.
+    fn reserve(&mut self, device: &VkDevice, size: u64) -> Result<u64, Box<Error>> {
+        if size > RING_SIZE {
+            return Err("Upload is larger than the staging ring".into());
+        }
+
+        loop {
+            self.retire(device)?;
+
+            let idle = self.in_flight.is_empty() && self.pending.is_empty();
+            if let Some(offset) = self.ring.reserve(size, idle) {
+                return Ok(offset);
+            }
+
+            if !self.pending.is_empty() {
+                self.flush(device)?;
+            }
+            self.wait_oldest(device)?;
+        }
+    }
+
+    fn wait_oldest(&mut self, device: &VkDevice) -> Result<(), Box<Error>> {
+        if let Some(fence) = self.in_flight.front().map(|batch| batch.fence) {
+            unsafe { device.wait_for_fences(&[fence], true, u64::MAX)? };
+        }
+        self.retire(device)
+    }
+
+    /// Releases batches that have finished, in submission order.
+    fn retire(&mut self, device: &VkDevice) -> Result<(), Box<Error>> {
+        while let Some(fence) = self.in_flight.front().map(|batch| batch.fence) {
+            match unsafe { device.get_fence_status(fence) } {
+                Ok(()) => (),
+                Err(vk::Result::NotReady) => break,
+                Err(err) => return Err(Box::new(err)),
+            }
+
+            let batch = self.in_flight.pop_front().unwrap();
+            unsafe {
+                device.free_command_buffers(self.command_pool, &[batch.command_buffer]);
+                device.destroy_fence(batch.fence, None);
+            }
+            self.ring.release(batch.ring_end);
+            self.completed_id = batch.id;
+        }
+
+        Ok(())
+    }
+}
+
+impl Ring {
+    fn new(size: u64) -> Ring {
+        Ring {
+            size,
+            head: 0,
+            tail: 0,
+        }
+    }
+
+    /// Takes `size` bytes at an offset aligned to `RING_ALIGNMENT`, or
+    /// returns `None` until more of the ring has been released. `idle` tells
+    /// an empty ring from a full one when `head == tail`, and starts over at
+    /// the beginning.
+    fn reserve(&mut self, size: u64, idle: bool) -> Option<u64> {
+        if idle {
+            self.head = 0;
+            self.tail = 0;
+        }
+
+        let start = align_up(self.head, RING_ALIGNMENT);
+        let wrapped = self.head < self.tail || (self.head == self.tail && !idle);
+        // Never fills the ring up to `tail`, `head == tail` would look empty.
+        let offset = if !wrapped {
+            if start + size <= self.size {
+                Some(start)
+            } else if size < self.tail {
+                Some(0)
+            } else {
+                None
+            }
+        } else if start + size < self.tail {
+            Some(start)
+        } else {
+            None
+        };
+
+        if let Some(offset) = offset {
+            self.head = offset + size;
+        }
+        offset
+    }
+
+    /// Frees everything before `ring_end`, the `head` a finished batch was
+    /// submitted at.
+    fn release(&mut self, ring_end: u64) {
+        self.tail = ring_end;
+    }
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserves_aligned_ranges() {
+        let mut ring = Ring::new(256);
+        assert_eq!(ring.reserve(10, true), Some(0));
+        assert_eq!(ring.reserve(20, false), Some(16));
+        assert_eq!(ring.reserve(1, false), Some(48));
+        assert_eq!(ring.head, 49);
+    }
+
+    #[test]
+    fn starts_over_when_idle() {
+        let mut ring = Ring::new(256);
+        ring.reserve(200, true).unwrap();
+        ring.release(200);
+
+        assert_eq!(ring.reserve(100, true), Some(0));
+        assert_eq!(ring.tail, 0);
+    }
+
+    #[test]
+    fn wraps_around_once_the_start_is_released() {
+        let mut ring = Ring::new(256);
+        ring.reserve(96, true).unwrap();
+        let batch_end = ring.head;
+        ring.reserve(128, false).unwrap();
+
+        // Only 32 bytes are left at the end.
+        assert_eq!(ring.reserve(64, false), None);
+
+        ring.release(batch_end);
+        assert_eq!(ring.reserve(64, false), Some(0));
+        assert_eq!(ring.head, 64);
+        // Still wrapped, so the next range has to end before the tail.
+        assert_eq!(ring.reserve(16, false), Some(64));
+        assert_eq!(ring.reserve(16, false), None);
+    }
+
+    #[test]
+    fn never_fills_up_to_the_tail() {
+        let mut ring = Ring::new(256);
+        ring.reserve(64, true).unwrap();
+        ring.reserve(192, false).unwrap();
+        ring.release(64);
+
+        // Taking all 64 bytes would leave `head == tail`.
+        assert_eq!(ring.reserve(64, false), None);
+        assert_eq!(ring.reserve(48, false), Some(0));
+    }
+
+    #[test]
+    fn full_ring_waits_for_release() {
+        let mut ring = Ring::new(256);
+        ring.reserve(256, true).unwrap();
+        assert_eq!((ring.tail, ring.head), (0, 256));
+        assert_eq!(ring.reserve(1, false), None);
+
+        // The batch holding everything finished, with another one pending.
+        ring.release(256);
+        assert_eq!(ring.reserve(1, false), None);
+        assert_eq!(ring.reserve(1, true), Some(0));
+    }
+
+    #[test]
+    fn rejects_ranges_larger_than_the_ring() {
+        let mut ring = Ring::new(256);
+        assert_eq!(ring.reserve(257, true), None);
+        assert_eq!(ring.head, 0);
+
+        // Chunks always fit an idle ring.
+        assert_eq!(ring.reserve(MAX_CHUNK_SIZE.min(256), true), Some(0));
+    }
+}
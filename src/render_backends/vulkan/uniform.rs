@@ -0,0 +1,135 @@
+use ash::vk;
+
+use std::ptr;
+use std::mem;
+use std::error::Error;
+
+use super::VkDevice;
+use super::buffer::Allocator;
+use super::descriptor::{BindingType, FramePools, Resource};
+use super::descriptor;
+
+/// Size of the buffers per-object uniforms are packed into.
+const CHUNK_SIZE: u64 = 256 * 1024;
+
+struct Chunk {
+    buffer: usize,
+    /// Allocated from the frame's pools on first use after a reset.
+    set: Option<vk::DescriptorSet>,
+}
+
+/// Per-object uniforms of one frame in flight. Every draw's values are
+/// appended to host visible chunks, each bound through one set with a dynamic
+/// uniform buffer at `binding`; draws pick their data by dynamic offset.
+pub struct DynamicUniforms {
+    binding: u32,
+    /// Size of the binding, the range every offset sees. A draw's values
+    /// have to fit in it.
+    range: u64,
+    /// `minUniformBufferOffsetAlignment`, offsets are multiples of it.
+    alignment: u64,
+    chunks: Vec<Chunk>,
+    current: usize,
+    used: u64,
+}
+
+impl DynamicUniforms {
+    pub fn new(binding: u32, range: u64, alignment: u64) -> DynamicUniforms {
+        DynamicUniforms {
+            binding,
+            range,
+            alignment: alignment.max(1),
+            chunks: vec![],
+            current: 0,
+            used: 0,
+        }
+    }
+
+    /// Copies `value` into the uniforms and returns the set and dynamic
+    /// offset to bind it with.
+    pub fn push<T: Copy>(
+        &mut self,
+        device: &VkDevice,
+        allocator: &mut Allocator,
+        pools: &mut FramePools,
+        layout: vk::DescriptorSetLayout,
+        value: &T,
+    ) -> Result<(vk::DescriptorSet, u32), Box<Error>> {
+        let size = mem::size_of::<T>() as u64;
+        if size > self.range {
+            return Err(format!(
+                "{} bytes of uniforms exceed the binding's {}",
+                size, self.range
+            ).into());
+        }
+
+        let mut offset = align_up(self.used, self.alignment);
+        if self.current < self.chunks.len() && offset + self.range > CHUNK_SIZE {
+            self.current += 1;
+            offset = 0;
+        }
+        if self.current == self.chunks.len() {
+            let buffer = allocator.create_buffer(
+                device,
+                CHUNK_SIZE,
+                vk::BUFFER_USAGE_UNIFORM_BUFFER_BIT,
+                vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT | vk::MEMORY_PROPERTY_HOST_COHERENT_BIT,
+            )?;
+            self.chunks.push(Chunk { buffer, set: None });
+            offset = 0;
+        }
+
+        let chunk = &mut self.chunks[self.current];
+        let buffer = allocator.buffer(chunk.buffer);
+
+        let set = match chunk.set {
+            Some(set) => set,
+            None => {
+                let set = pools.allocate(device, layout)?;
+                descriptor::write_set(
+                    device,
+                    set,
+                    &[
+                        (
+                            self.binding,
+                            BindingType::UniformBufferDynamic,
+                            Resource::Buffer {
+                                buffer: buffer.buf,
+                                offset: 0,
+                                range: self.range,
+                            },
+                        ),
+                    ],
+                );
+                chunk.set = Some(set);
+                set
+            }
+        };
+
+        let mapped = buffer.mapped().ok_or("Uniform buffer isn't host visible")?;
+        unsafe { ptr::write_unaligned(mapped.offset(offset as isize) as *mut T, *value) };
+        self.used = offset + size;
+
+        Ok((set, offset as u32))
+    }
+
+    /// Starts the frame over. The GPU must be done with the previous data and
+    /// the sets must have been freed by resetting the frame's pools.
+    pub fn reset(&mut self) {
+        for chunk in &mut self.chunks {
+            chunk.set = None;
+        }
+        self.current = 0;
+        self.used = 0;
+    }
+
+    pub fn destroy(&mut self, device: &VkDevice, allocator: &mut Allocator) {
+        for chunk in self.chunks.drain(..) {
+            allocator.free_buffer(device, chunk.buffer);
+        }
+    }
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
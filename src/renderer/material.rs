@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use super::RenderState;
+
+/// Most parameters a material can have, matches `MAX_PARAMETERS` in the
+/// shaders.
+pub const MAX_PARAMETERS: usize = 16;
+
+/// A shader parameter of a material, each is a `vec4` in the shaders with
+/// the components it doesn't have set to 0.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Parameter {
+    Float(f32),
+    Vec2(f32, f32),
+    Vec3(f32, f32, f32),
+    Vec4(f32, f32, f32, f32),
+}
+
+impl Parameter {
+    pub fn to_vec4(self) -> [f32; 4] {
+        match self {
+            Parameter::Float(x) => [x, 0.0, 0.0, 0.0],
+            Parameter::Vec2(x, y) => [x, y, 0.0, 0.0],
+            Parameter::Vec3(x, y, z) => [x, y, z, 0.0],
+            Parameter::Vec4(x, y, z, w) => [x, y, z, w],
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+#[derive(Debug, Clone)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed RGBA8 pixels, row by row.
+    pub pixels: Vec<u8>,
+}
+
+/// A texture a material's shaders sample, under the name of their sampler.
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialTexture<'a> {
+    pub name: &'a str,
+    pub color_space: ColorSpace,
+    /// The full mip chain, largest level first.
+    pub mip_levels: &'a [MipLevel],
+}
+
+/// What models are drawn with, see `Renderer::update_material`.
+#[derive(Debug, Clone)]
+pub struct Material<'a> {
+    pub vertex_shader: &'a Path,
+    pub fragment_shader: &'a Path,
+    pub textures: Vec<MaterialTexture<'a>>,
+    /// Sorted by name, which is the order the shaders see them in.
+    pub parameters: Vec<(&'a str, Parameter)>,
+    pub render_state: RenderState,
+}
@@ -1,348 +1,387 @@
-mod command;
-mod device;
-mod framebuffers;
-mod graphics_pipeline;
-mod image_views;
-mod instance;
-mod physical_device;
-mod render_pass;
-mod semaphore;
-mod surface;
-mod swapchain;
-mod buffer;
-mod sync;
-
-use ash::vk;
-use ash::Entry;
-use ash::Instance;
-use ash::Device;
-use ash::version::{DeviceV1_0, V1_0};
-use ash::extensions as ext;
-use winit;
+use cgmath::{self, Deg, InnerSpace, Matrix4, SquareMatrix, Vector3};
 
 use std::error::Error;
-use std::u64;
-use std::ptr;
-use std::ffi::CStr;
-
-use self::surface::Surface;
-use self::swapchain::Swapchain;
-
-lazy_static! {
-    static ref VK_ENTRY: Entry<V1_0> = Entry::new().unwrap();
-    static ref VK_INSTANCE: Instance<V1_0> = instance::new(&VK_ENTRY).unwrap();
+use std::cmp::Ordering;
+
+mod vertex;
+mod shadow;
+mod material;
+
+pub use self::material::{ColorSpace, Material, MaterialTexture, MipLevel, Parameter,
+                         MAX_PARAMETERS};
+pub use self::vertex::{Attribute, AttributeFormat, Semantic, Vertex, VertexData, VertexLayout};
+pub use self::shadow::{FrameShadows, ShadowMap, MAX_SHADOW_DISTANCE, MAX_SHADOW_MAPS, SHADOW_BIAS,
+                       SHADOW_CASCADES, SHADOW_MAP_SIZE, SHADOW_NORMAL_OFFSET};
+
+/// Everything the game library needs from a rendering backend. The game only
+/// ever sees a `&mut Renderer` trait object, so the host can swap backends
+/// without the game library knowing which one it talks to.
+pub trait Renderer {
+    fn begin_frame(&mut self) -> Result<(), Box<Error>>;
+    fn end_frame(&mut self) -> Result<(), Box<Error>>;
+    /// Sets the camera for the current frame. Frames without one are drawn
+    /// with identity view and projection matrices.
+    fn set_camera(&mut self, camera: &Camera) -> Result<(), Box<Error>>;
+    /// Sets the lights for the current frame, only the first `MAX_LIGHTS`
+    /// are used. Frames without lights, and models without normals, are
+    /// drawn unlit in their plain vertex colors.
+    fn set_lights(&mut self, lights: &[Light]) -> Result<(), Box<Error>>;
+    /// Uploads the vertices and indices of a model, replacing any earlier
+    /// data for `id`. Shaders read each attribute at its semantic's location.
+    fn update_model(
+        &mut self,
+        id: u32,
+        vertices: &VertexData,
+        indices: Indices,
+    ) -> Result<(), Box<Error>>;
+    /// Uploads a material's textures and parameters and prepares its
+    /// shaders, replacing any earlier material for `id`. Fails for more than
+    /// `MAX_PARAMETERS` parameters.
+    fn update_material(&mut self, id: u32, material: &Material) -> Result<(), Box<Error>>;
+    /// Queues an instance of a model uploaded with `update_model` to be
+    /// drawn this frame with a material from `update_material`. Backends may
+    /// draw the instances of a model and material together.
+    fn draw_model(&mut self, id: u32, material: u32, instance: &Instance) -> Result<(), Box<Error>>;
+    fn update_resolution(&mut self, width: u32, height: u32) -> Result<(), Box<Error>>;
+    /// Applies `settings` from the next frame on. Settings the backend or
+    /// device can't do are replaced by the nearest ones it can.
+    fn change_settings(&mut self, settings: &RenderSettings) -> Result<(), Box<Error>>;
 }
 
-type VkDevice = Device<V1_0>;
-
-pub struct Renderer {
-    device: VkDevice,
-
-    physical_device: vk::PhysicalDevice,
-    graphics_queue_index: u32,
-    graphics_queue: vk::Queue,
-
-    surface_loader: Surface,
-    surface: vk::SurfaceKHR,
-    surface_format: vk::SurfaceFormatKHR,
-    surface_resolution: vk::Extent2D,
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    /// Vertical field of view in degrees.
+    Perspective { fov_y: f32 },
+    /// Visible height in world units, the width follows the aspect ratio.
+    Orthographic { height: f32 },
+}
 
-    frame_index: u32,
-    swapchain_loader: Swapchain,
-    swapchain: vk::SwapchainKHR,
-    swapchain_frames: Vec<swapchain::Frame>,
+/// Viewpoint a frame is drawn from.
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub view: Matrix4<f32>,
+    pub projection: Projection,
+    pub near: f32,
+    pub far: f32,
+}
 
-    command_pool: vk::CommandPool,
+impl Camera {
+    /// Projection into Vulkan's clip space, where y points down and depth
+    /// goes from 0 to 1. Every backend uses this so they agree on the
+    /// picture.
+    pub fn projection_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        let projection = match self.projection {
+            Projection::Perspective { fov_y } => {
+                cgmath::perspective(Deg(fov_y), aspect, self.near, self.far)
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * aspect;
+                cgmath::ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.near,
+                    self.far,
+                )
+            }
+        };
 
-    depth_image: vk::Image,
-    depth_image_view: vk::ImageView,
-    depth_image_memory: vk::DeviceMemory,
+        gl_to_vulkan() * projection
+    }
 
-    allocator: buffer::Allocator,
-    staging_buffer: usize,
-    vertex_buffer: usize,
+    /// Where the camera is in the world.
+    pub fn position(&self) -> Vector3<f32> {
+        match self.view.invert() {
+            Some(inverse) => inverse.w.truncate(),
+            None => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
 }
 
-impl Renderer {
-    pub fn new(window: &winit::Window) -> Result<Renderer, Box<Error>> {
-        set_debug_callback()?;
-
-        let surface = Surface::new(window)?;
-
-        let phys_device = physical_device::new()?;
+/// Turns cgmath's OpenGL style clip space into Vulkan's.
+fn gl_to_vulkan() -> Matrix4<f32> {
+    #[rustfmt::skip]
+    let gl_to_vulkan = Matrix4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, -1.0, 0.0, 0.0,
+        0.0, 0.0, 0.5, 0.0,
+        0.0, 0.0, 0.5, 1.0,
+    );
+    gl_to_vulkan
+}
 
-        let surface_format = surface.format(phys_device)?;
-        let surface_resolution = surface.resolution(phys_device)?;
+/// Most lights a frame is lit by.
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LightKind {
+    /// Lights everything evenly, whatever way it faces.
+    Ambient,
+    /// Parallel rays along the light's direction, like sunlight.
+    Directional,
+    /// Shines in every direction from the light's position.
+    Point,
+    /// A cone along the light's direction, at full brightness up to
+    /// `inner_angle` degrees off its axis and fading out towards
+    /// `outer_angle`.
+    Spot { inner_angle: f32, outer_angle: f32 },
+}
 
-        let graphics_queue_index = physical_device::graphics_queue_index(&phys_device, &surface)
+impl LightKind {
+    /// Cosines of a spot light's inner and outer angle. The outer one is
+    /// kept below the inner one so the cone's edge never divides by zero.
+    pub fn spot_cosines(self) -> Option<(f32, f32)> {
+        match self {
+            LightKind::Spot {
+                inner_angle,
+                outer_angle,
+            } => {
+                let cos_inner = inner_angle.to_radians().cos();
+                let cos_outer = outer_angle.to_radians().cos().min(cos_inner - 1e-4);
+                Some((cos_inner, cos_outer))
+            }
+            _ => None,
+        }
+    }
+}
 
-        let device = device::new(graphics_queue_index, phys_device)?;
+/// A light as backends see it, in world space. Surfaces are lit with the
+/// Blinn-Phong model.
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: Vector3<f32>,
+    /// Normalized, unused by ambient and point lights.
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance point and spot lights fade out at.
+    pub range: f32,
+    /// Whether models are shadowed from it, see `FrameShadows` for the
+    /// lights that can.
+    pub cast_shadows: bool,
+}
 
-        let graphics_queue = unsafe { device.get_device_queue(graphics_queue_index as u32, 0) };
+/// Where a copy of a model is drawn and how it's colored.
+#[derive(Debug, Clone, Copy)]
+pub struct Instance {
+    pub model: Matrix4<f32>,
+    /// Multiplies the vertex colors.
+    pub tint: [f32; 4],
+    /// Whether it's drawn into shadow maps.
+    pub cast_shadows: bool,
+    /// Whether shadows fall on it.
+    pub receive_shadows: bool,
+}
 
-        let swapchain = Swapchain::new(&device, phys_device, &surface)?;
+impl Instance {
+    pub fn new(model: Matrix4<f32>) -> Instance {
+        Instance {
+            model,
+            tint: [1.0, 1.0, 1.0, 1.0],
+            cast_shadows: true,
+            receive_shadows: true,
+        }
+    }
+}
 
-        let (depth_image, depth_image_memory) =
-            image_views::new_depth_image(&device, &instance, &surface_resolution, physical_device)?;
-        let depth_image_view = image_views::new_depth_view(&device, depth_image)?;
+/// How a model's triangles are rasterized and combined with what's already
+/// drawn. Materials pick one, backends keep a pipeline per state in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RenderState {
+    #[serde(default)]
+    pub blend: Blend,
+    #[serde(default)]
+    pub depth: Depth,
+    #[serde(default)]
+    pub cull: Cull,
+    /// Draws only the triangles' edges.
+    #[serde(default)]
+    pub wireframe: bool,
+}
 
-        let present_image_views =
-            image_views::new(&device, swapchain, &swapchain_loader, &surface_format)?;
+impl Default for RenderState {
+    fn default() -> RenderState {
+        RenderState {
+            blend: Blend::Opaque,
+            depth: Depth::TestAndWrite,
+            cull: Cull::Back,
+            wireframe: false,
+        }
+    }
+}
 
-        let swapchain_len = present_image_views.len();
+impl RenderState {
+    pub fn wireframe() -> RenderState {
+        RenderState {
+            wireframe: true,
+            cull: Cull::None,
+            ..RenderState::default()
+        }
+    }
 
-        let render_pass = render_pass::new(&device, &surface_format)?;
+    /// Blended over what's behind it, without hiding what's drawn later.
+    pub fn alpha_blended() -> RenderState {
+        RenderState {
+            blend: Blend::Alpha,
+            depth: Depth::Test,
+            ..RenderState::default()
+        }
+    }
+}
 
-        let graphics_pipeline =
-            graphics_pipeline::new(&device, render_pass, &surface_resolution, depth_image_view)?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Blend {
+    #[default]
+    Opaque,
+    /// Mixed by the fragment's alpha. Drawn after opaque models, farthest
+    /// first.
+    Alpha,
+}
 
-        let framebuffers = framebuffers::new(
-            &device,
-            render_pass,
-            &surface_resolution,
-            &present_image_views,
-            depth_image_view,
-        )?;
+/// Puts blended draws after the opaque ones, sorted back to front by the
+/// distance of their instance's origin from `eye`, so each is blended over
+/// everything behind it. Opaque draws keep their order.
+pub fn sort_blended_last<T, F>(draws: &mut [T], eye: Vector3<f32>, blend_and_instance: F)
+where
+    F: Fn(&T) -> (Blend, &Instance),
+{
+    draws.sort_by_key(|draw| blend_and_instance(draw).0 == Blend::Alpha);
+    let first_blended = draws
+        .iter()
+        .position(|draw| blend_and_instance(draw).0 == Blend::Alpha)
+        .unwrap_or(draws.len());
+
+    let distance = |draw: &T| (blend_and_instance(draw).1.model.w.truncate() - eye).magnitude2();
+    draws[first_blended..].sort_by(|a, b| {
+        distance(b)
+            .partial_cmp(&distance(a))
+            .unwrap_or(Ordering::Equal)
+    });
+}
 
-        let command_pool = command::new_pool(&device, graphics_queue_index)?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Depth {
+    #[default]
+    TestAndWrite,
+    /// Hidden by nearer models but doesn't hide anything itself.
+    Test,
+    Off,
+}
 
-        let command_buffers = command::new_buffers(&device, command_pool, swapchain_len as u32)?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Cull {
+    #[default]
+    Back,
+    Front,
+    None,
+}
 
-        let swapchain_frames = {
-            let mut frames = vec![];
-            for i in 0..swapchain_len {
-                frames.push(swapchain::Frame::new(
-                    &device,
-                    present_image_views[i],
-                    framebuffers[i],
-                    command_buffers[i],
-                )?);
-            }
-            frames
-        };
+/// How frames are drawn and presented, see `Renderer::change_settings`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RenderSettings {
+    /// Samples per pixel for multisample anti-aliasing, 1 turns it off.
+    /// Rounded down to a sample count the device supports.
+    pub samples: u32,
+    pub present_mode: PresentMode,
+    /// Size frames are drawn at relative to the window, they are scaled to
+    /// the window's size when presented.
+    pub resolution_scale: f32,
+    pub depth_format: DepthFormat,
+}
 
-        command::submit(
-            &device,
-            swapchain_frames[0].command_buffer,
-            graphics_queue,
-            &[vk::PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT],
-            &[],
-            &[],
-            |device, setup_command_buffer| {
-                let layout_transition_barrier = vk::ImageMemoryBarrier {
-                    s_type: vk::StructureType::ImageMemoryBarrier,
-                    p_next: ptr::null(),
-                    src_access_mask: Default::default(),
-                    dst_access_mask: vk::ACCESS_DEPTH_STENCIL_ATTACHMENT_READ_BIT
-                        | vk::ACCESS_DEPTH_STENCIL_ATTACHMENT_WRITE_BIT,
-                    old_layout: vk::ImageLayout::Undefined,
-                    new_layout: vk::ImageLayout::DepthStencilAttachmentOptimal,
-                    src_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
-                    dst_queue_family_index: vk::VK_QUEUE_FAMILY_IGNORED,
-                    image: depth_image,
-                    subresource_range: vk::ImageSubresourceRange {
-                        aspect_mask: vk::IMAGE_ASPECT_DEPTH_BIT,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    },
-                };
-
-                unsafe {
-                    device.cmd_pipeline_barrier(
-                        setup_command_buffer,
-                        vk::PIPELINE_STAGE_TOP_OF_PIPE_BIT,
-                        vk::PIPELINE_STAGE_TOP_OF_PIPE_BIT,
-                        vk::DependencyFlags::empty(),
-                        &[],
-                        &[],
-                        &[layout_transition_barrier],
-                    )
-                };
-            },
-        )?;
-
-        for (i, &command_buffer) in command_buffers.iter().enumerate() {
-            let clear_values = [
-                vk::ClearValue::new_color(vk::ClearColorValue::new_float32([0.0, 0.0, 0.0, 0.0])),
-                vk::ClearValue::new_depth_stencil(vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0,
-                }),
-            ];
-            let render_pass_info = vk::RenderPassBeginInfo {
-                s_type: vk::StructureType::RenderPassBeginInfo,
-                render_pass,
-                framebuffer: framebuffers[i],
-                render_area: vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: surface_resolution.clone(),
-                },
-                clear_value_count: clear_values.len() as u32,
-                p_clear_values: clear_values.as_ptr(),
-                p_next: ptr::null(),
-            };
-
-            let viewports = [
-                vk::Viewport {
-                    x: 0.0,
-                    y: 0.0,
-                    width: surface_resolution.width as f32,
-                    height: surface_resolution.height as f32,
-                    min_depth: 0.0,
-                    max_depth: 1.0,
-                },
-            ];
-            let scissors = [
-                vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: surface_resolution.clone(),
-                },
-            ];
-
-            unsafe {
-                command::submit(
-                    &device,
-                    command_buffer,
-                    graphics_queue,
-                    &[vk::PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT],
-                    &[],
-                    &[],
-                    |device, cmd| {
-                        &device.cmd_bind_pipeline(
-                            cmd,
-                            vk::PipelineBindPoint::Graphics,
-                            graphics_pipeline,
-                        );
-                        &device.cmd_begin_render_pass(
-                            cmd,
-                            &render_pass_info,
-                            vk::SubpassContents::Inline,
-                        );
-                        device.cmd_set_viewport(cmd, &viewports);
-                        device.cmd_set_scissor(cmd, &scissors);
-                    },
-                )?;
-            };
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            samples: 1,
+            present_mode: PresentMode::Mailbox,
+            resolution_scale: 1.0,
+            depth_format: DepthFormat::D16,
         }
-
-        let mut allocator = buffer::Allocator::new(physical_device);
-
-        let staging_buffer = allocator.create_buffer(
-            &device,
-            &instance,
-            4048,
-            vk::BUFFER_USAGE_TRANSFER_SRC_BIT,
-            vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT | vk::MEMORY_PROPERTY_HOST_COHERENT_BIT,
-        )?;
-
-        let vertex_buffer = allocator.create_buffer(
-            &device,
-            &instance,
-            4048,
-            vk::BUFFER_USAGE_TRANSFER_DST_BIT | vk::BUFFER_USAGE_VERTEX_BUFFER_BIT,
-            vk::MEMORY_PROPERTY_DEVICE_LOCAL_BIT,
-        )?;
-
-        Ok(VulkanRenderer {
-            entry,
-            instance,
-            device,
-
-            physical_device,
-            graphics_queue_index,
-            graphics_queue,
-
-            surface_loader,
-            surface,
-            surface_format,
-            surface_resolution,
-
-            swapchain_loader,
-            swapchain,
-            swapchain_frames,
-            frame_index: 0,
-
-            command_pool,
-
-            depth_image,
-            depth_image_view,
-            depth_image_memory,
-
-            allocator,
-            staging_buffer,
-            vertex_buffer,
-        })
     }
 }
 
-pub fn find_memorytype_index(
-    memory_req: &vk::MemoryRequirements,
-    memory_prop: &vk::PhysicalDeviceMemoryProperties,
-    flags: vk::MemoryPropertyFlags,
-) -> Result<u32, String> {
-    // Try to find an exactly matching memory flag
-    let best_suitable_index =
-        find_memorytype_index_f(memory_req, memory_prop, flags, |property_flags, flags| {
-            property_flags == flags
-        });
-    if best_suitable_index.is_ok() {
-        return best_suitable_index;
-    }
-    // Otherwise find a memory flag that works
-    find_memorytype_index_f(memory_req, memory_prop, flags, |property_flags, flags| {
-        property_flags & flags == flags
-    })
+/// Smallest and largest `RenderSettings::resolution_scale`.
+pub const MIN_RESOLUTION_SCALE: f32 = 0.25;
+pub const MAX_RESOLUTION_SCALE: f32 = 2.0;
+
+/// When finished frames are shown. Modes the device lacks fall back to
+/// `Vsync`, which every device has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PresentMode {
+    /// Waits for the display's refresh, queueing frames drawn faster.
+    Vsync,
+    /// Waits for the refresh but replaces the queued frame with newer ones,
+    /// so drawing never blocks.
+    Mailbox,
+    /// Shows frames right away, which can tear.
+    Immediate,
 }
 
-fn find_memorytype_index_f<F: Fn(vk::MemoryPropertyFlags, vk::MemoryPropertyFlags) -> bool>(
-    memory_req: &vk::MemoryRequirements,
-    memory_prop: &vk::PhysicalDeviceMemoryProperties,
-    flags: vk::MemoryPropertyFlags,
-    f: F,
-) -> Result<u32, String> {
-    let mut memory_type_bits = memory_req.memory_type_bits;
-    for (index, ref memory_type) in memory_prop.memory_types.iter().enumerate() {
-        if memory_type_bits & 1 == 1 {
-            if f(memory_type.property_flags, flags) {
-                return Ok(index as u32);
-            }
-        }
-        memory_type_bits = memory_type_bits >> 1;
-    }
-    Err("Unable to find suitable memory index for depth image.".to_owned())
+/// Precision of the depth buffer. Formats the device can't render depth
+/// into fall back to `D16`, which every device can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DepthFormat {
+    /// 16 bit normalized.
+    D16,
+    /// 24 bit normalized.
+    D24,
+    /// 32 bit float.
+    D32,
 }
 
-pub fn set_debug_callback() -> Result<(), Box<Error>> {
-    let debug_info = vk::DebugReportCallbackCreateInfoEXT {
-        s_type: vk::StructureType::DebugReportCallbackCreateInfoExt,
-        p_next: ptr::null(),
-        flags: vk::DEBUG_REPORT_ERROR_BIT_EXT | vk::DEBUG_REPORT_WARNING_BIT_EXT
-            | vk::DEBUG_REPORT_PERFORMANCE_WARNING_BIT_EXT,
-        pfn_callback: vulkan_debug_callback,
-        p_user_data: ptr::null_mut(),
-    };
+/// Index data of a mesh. Backends keep the index type the game picked.
+#[derive(Debug, Clone, Copy)]
+pub enum Indices<'a> {
+    /// The vertices are drawn in order, three per triangle.
+    None,
+    U16(&'a [u16]),
+    U32(&'a [u32]),
+}
 
-    let debug_report_loader = ext::DebugReport::new(VK_ENTRY, VK_INSTANCE)
-        .map_err(|_| "Couldn't create debug repoprt loader")?;
+impl<'a> Indices<'a> {
+    pub fn len(&self) -> usize {
+        match *self {
+            Indices::None => 0,
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
 
-    let callback =
-        unsafe { debug_report_loader.create_debug_report_callback_ext(&debug_info, None)? };
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
 
-    Ok(())
+#[derive(Debug, Clone)]
+pub struct Model3D {
+    pub vertices: Vec<Vertex>,
 }
 
-unsafe extern "system" fn vulkan_debug_callback(
-    _: vk::DebugReportFlagsEXT,
-    _: vk::DebugReportObjectTypeEXT,
-    _: vk::uint64_t,
-    _: vk::size_t,
-    _: vk::int32_t,
-    _: *const vk::c_char,
-    p_message: *const vk::c_char,
-    _: *mut vk::c_void,
-) -> u32 {
-    println!("{:?}", CStr::from_ptr(p_message));
-    1
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blended_draws_go_last_back_to_front() {
+        let at = |z: f32| Instance::new(Matrix4::from_translation(Vector3::new(0.0, 0.0, z)));
+        let mut draws = vec![
+            ("near glass", Blend::Alpha, at(-1.0)),
+            ("first wall", Blend::Opaque, at(-1.0)),
+            ("far glass", Blend::Alpha, at(-10.0)),
+            ("second wall", Blend::Opaque, at(-20.0)),
+            ("middle glass", Blend::Alpha, at(-5.0)),
+        ];
+        sort_blended_last(&mut draws, Vector3::new(0.0, 0.0, 0.0), |draw| {
+            (draw.1, &draw.2)
+        });
+
+        let order: Vec<_> = draws.iter().map(|draw| draw.0).collect();
+        assert_eq!(
+            order,
+            vec!["first wall", "second wall", "far glass", "middle glass", "near glass"]
+        );
+    }
 }
@@ -0,0 +1,340 @@
+use cgmath::{self, Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3,
+             Vector4};
+
+use super::{gl_to_vulkan, Camera, Light, LightKind};
+
+/// Shadow maps a frame can have, over all of its lights.
+pub const MAX_SHADOW_MAPS: usize = 8;
+/// Width and height of every shadow map, in texels.
+pub const SHADOW_MAP_SIZE: u32 = 1024;
+/// Shadow maps a directional light's view is split into, the nearest
+/// cascade covering the smallest part of it in the most detail.
+pub const SHADOW_CASCADES: usize = 3;
+/// Farthest from the camera directional lights cast shadows.
+pub const MAX_SHADOW_DISTANCE: f32 = 50.0;
+/// Depth subtracted from a point's depth in a shadow map before comparing,
+/// so surfaces don't shadow themselves.
+pub const SHADOW_BIAS: f32 = 0.0005;
+/// World space distance points are moved along their normal before being
+/// looked up in a shadow map, for the same reason.
+pub const SHADOW_NORMAL_OFFSET: f32 = 0.02;
+/// How far behind a cascade casters are still drawn into it, for shadows
+/// falling in from outside the camera's view.
+const CASTER_DISTANCE: f32 = 20.0;
+
+/// How much of the cascades' split distances follows a logarithmic rather
+/// than an even distribution. Logarithmic splits keep the texel density
+/// even on screen but leave the far cascades huge.
+const CASCADE_SPLIT_BLEND: f32 = 0.5;
+
+/// The view of one shadow map.
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowMap {
+    /// From world space into the map's clip space, which is Vulkan's like
+    /// the camera's.
+    pub view_projection: Matrix4<f32>,
+    /// Camera depth up to which a cascade is used, infinite for spot lights.
+    pub split: f32,
+}
+
+/// Shadow maps of a frame's lights. Directional lights get
+/// `SHADOW_CASCADES` maps fitted to the camera's view, spot lights one map
+/// covering their cone. Point and ambient lights cast no shadows, neither
+/// do lights past `MAX_SHADOW_MAPS` or ones without a direction, and spot
+/// lights without a range or cone.
+#[derive(Debug, Clone, Default)]
+pub struct FrameShadows {
+    pub maps: Vec<ShadowMap>,
+    /// First map and number of maps of each light, in the order the lights
+    /// were given. Lights without shadows have no maps.
+    pub lights: Vec<(usize, usize)>,
+}
+
+impl FrameShadows {
+    /// Directional lights need a camera to fit their cascades to and cast
+    /// no shadows without one.
+    pub fn new(lights: &[Light], camera: Option<&Camera>, aspect: f32) -> FrameShadows {
+        let mut maps = vec![];
+        let lights = lights
+            .iter()
+            .map(|light| {
+                let light_maps = match light.kind {
+                    _ if !light.cast_shadows => vec![],
+                    LightKind::Directional => match camera {
+                        Some(camera) => cascades(light, camera, aspect),
+                        None => vec![],
+                    },
+                    LightKind::Spot { outer_angle, .. } => {
+                        spot_map(light, outer_angle).into_iter().collect()
+                    }
+                    LightKind::Ambient | LightKind::Point => vec![],
+                };
+                if light_maps.is_empty() || maps.len() + light_maps.len() > MAX_SHADOW_MAPS {
+                    return (0, 0);
+                }
+                let first = maps.len();
+                maps.extend(light_maps);
+                (first, maps.len() - first)
+            })
+            .collect();
+
+        FrameShadows { maps, lights }
+    }
+}
+
+/// Maps each covering a slice of the camera's view, for a directional
+/// light.
+fn cascades(light: &Light, camera: &Camera, aspect: f32) -> Vec<ShadowMap> {
+    if !has_direction(light) {
+        return vec![];
+    }
+    let inverse = match (camera.projection_matrix(aspect) * camera.view).invert() {
+        Some(inverse) => inverse,
+        None => return vec![],
+    };
+    // Corners of the camera's view at its near and far plane.
+    let corner = |x: f32, y: f32, z: f32| {
+        let corner = inverse * Vector4::new(x, y, z, 1.0);
+        corner.truncate() / corner.w
+    };
+    let edges: Vec<_> = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+        .iter()
+        .map(|&(x, y)| (corner(x, y, 0.0), corner(x, y, 1.0)))
+        .collect();
+
+    let near = camera.near;
+    let far = camera.far.min(MAX_SHADOW_DISTANCE);
+    let mut slice_near = near;
+    (1..SHADOW_CASCADES + 1)
+        .map(|cascade| {
+            let fraction = cascade as f32 / SHADOW_CASCADES as f32;
+            let logarithmic = near * (far / near).powf(fraction);
+            let even = near + (far - near) * fraction;
+            let slice_far = logarithmic * CASCADE_SPLIT_BLEND + even * (1.0 - CASCADE_SPLIT_BLEND);
+
+            // View depth changes linearly along the frustum's edges.
+            let corners: Vec<Vector3<f32>> = edges
+                .iter()
+                .flat_map(|&(near_corner, far_corner)| {
+                    let at = |depth: f32| {
+                        let t = (depth - camera.near) / (camera.far - camera.near);
+                        near_corner + (far_corner - near_corner) * t
+                    };
+                    vec![at(slice_near), at(slice_far)]
+                })
+                .collect();
+            slice_near = slice_far;
+
+            ShadowMap {
+                view_projection: cascade_view_projection(light.direction, &corners),
+                split: slice_far,
+            }
+        })
+        .collect()
+}
+
+/// Fits an orthographic view along `direction` around the sphere bounding
+/// `corners`. The sphere keeps the map's size as the camera turns and its
+/// center is snapped to whole texels, so shadow edges don't shimmer as the
+/// camera moves.
+fn cascade_view_projection(direction: Vector3<f32>, corners: &[Vector3<f32>]) -> Matrix4<f32> {
+    let center = corners
+        .iter()
+        .fold(Vector3::new(0.0, 0.0, 0.0), |sum, &corner| sum + corner) / corners.len() as f32;
+    let radius = corners
+        .iter()
+        .map(|&corner| (corner - center).magnitude())
+        .fold(0.0, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let view = Matrix4::look_at(Point3::origin(), Point3::from_vec(direction), up(direction));
+    let center = (view * center.extend(1.0)).truncate();
+    let texel = radius * 2.0 / SHADOW_MAP_SIZE as f32;
+    let x = (center.x / texel).floor() * texel;
+    let y = (center.y / texel).floor() * texel;
+
+    // The view looks down its negative z axis.
+    let projection = cgmath::ortho(
+        x - radius,
+        x + radius,
+        y - radius,
+        y + radius,
+        -center.z - radius - CASTER_DISTANCE,
+        -center.z + radius,
+    );
+
+    gl_to_vulkan() * projection * view
+}
+
+/// A perspective view from a spot light covering its cone, `None` if the
+/// light has no range or cone to cover.
+fn spot_map(light: &Light, outer_angle: f32) -> Option<ShadowMap> {
+    let has_cone = outer_angle > 0.0 && light.range > 0.0 && light.range.is_finite();
+    if !has_cone || !has_direction(light) {
+        return None;
+    }
+
+    let position = Point3::from_vec(light.position);
+    let view = Matrix4::look_at(position, position + light.direction, up(light.direction));
+    let fov = (outer_angle * 2.0).min(170.0);
+    // A near plane far from the light keeps the depth precision up.
+    let projection = cgmath::perspective(Deg(fov), 1.0, light.range * 0.05, light.range);
+
+    Some(ShadowMap {
+        view_projection: gl_to_vulkan() * projection * view,
+        split: f32::INFINITY,
+    })
+}
+
+/// Views along a zero or non-finite direction have no orientation, and
+/// their matrices are all NaN.
+fn has_direction(light: &Light) -> bool {
+    let length = light.direction.magnitude2();
+    length > 0.0 && length.is_finite()
+}
+
+/// An up vector for views along `direction`.
+fn up(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.y.abs() > 0.99 {
+        Vector3::new(0.0, 0.0, 1.0)
+    } else {
+        Vector3::new(0.0, 1.0, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::Transform;
+
+    use renderer::Projection;
+
+    fn light(kind: LightKind, direction: Vector3<f32>) -> Light {
+        Light {
+            kind,
+            position: Vector3::new(0.0, 5.0, 0.0),
+            direction,
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            range: 20.0,
+            cast_shadows: true,
+        }
+    }
+
+    fn spot_light(outer_angle: f32, range: f32) -> Light {
+        Light {
+            range,
+            ..light(
+                LightKind::Spot {
+                    inner_angle: outer_angle / 2.0,
+                    outer_angle,
+                },
+                Vector3::new(0.0, -1.0, 0.0),
+            )
+        }
+    }
+
+    fn camera() -> Camera {
+        Camera {
+            view: Matrix4::look_at(
+                Point3::new(0.0, 2.0, 10.0),
+                Point3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+            ),
+            projection: Projection::Perspective { fov_y: 60.0 },
+            near: 0.1,
+            far: 100.0,
+        }
+    }
+
+    fn in_clip_volume(view_projection: &Matrix4<f32>, point: Vector3<f32>) -> bool {
+        let clip = view_projection.transform_point(Point3::from_vec(point));
+        let inside = |value: f32, min: f32, max: f32| value >= min - 1e-3 && value <= max + 1e-3;
+        inside(clip.x, -1.0, 1.0) && inside(clip.y, -1.0, 1.0) && inside(clip.z, 0.0, 1.0)
+    }
+
+    #[test]
+    fn cascades_split_the_view_up_to_the_shadow_distance() {
+        let sun = light(LightKind::Directional, Vector3::new(0.3, -1.0, 0.2).normalize());
+        let maps = cascades(&sun, &camera(), 16.0 / 9.0);
+
+        assert_eq!(maps.len(), SHADOW_CASCADES);
+        assert!(maps.windows(2).all(|pair| pair[0].split < pair[1].split));
+        assert!((maps[SHADOW_CASCADES - 1].split - MAX_SHADOW_DISTANCE).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cascades_cover_their_slice_of_the_view() {
+        let sun = light(LightKind::Directional, Vector3::new(0.3, -1.0, 0.2).normalize());
+        let camera = camera();
+        let maps = cascades(&sun, &camera, 1.0);
+
+        // Points along the view's center line, each in the cascade its depth
+        // falls in.
+        let eye = camera.position();
+        let forward = (Vector3::new(0.0, 0.0, 0.0) - eye).normalize();
+        let mut near = camera.near;
+        for map in &maps {
+            for step in 0..8 {
+                let depth = near + (map.split - near) * step as f32 / 7.0;
+                assert!(in_clip_volume(&map.view_projection, eye + forward * depth));
+            }
+            near = map.split;
+        }
+    }
+
+    #[test]
+    fn cascades_snap_to_whole_texels() {
+        let direction = Vector3::new(0.3, -1.0, 0.2).normalize();
+        let square = |offset: f32| {
+            vec![
+                Vector3::new(offset, 0.0, 0.0),
+                Vector3::new(offset + 4.0, 0.0, 0.0),
+                Vector3::new(offset, 0.0, 4.0),
+                Vector3::new(offset + 4.0, 0.0, 4.0),
+            ]
+        };
+
+        // However the corners move, the world's origin stays on a texel
+        // corner of the map, so the texels under a still object don't change.
+        for &offset in &[0.0, 0.013, 0.31, 1.7] {
+            let view_projection = cascade_view_projection(direction, &square(offset));
+            let origin = view_projection.transform_point(Point3::origin());
+            for &ndc in &[origin.x, origin.y] {
+                let texels = (ndc + 1.0) / 2.0 * SHADOW_MAP_SIZE as f32;
+                assert!((texels - texels.round()).abs() < 0.01, "{} texels", texels);
+            }
+        }
+    }
+
+    #[test]
+    fn spot_maps_cover_the_cone() {
+        let map = spot_map(&spot_light(30.0, 20.0), 30.0).unwrap();
+
+        assert!(map.split.is_infinite());
+        // Down the axis from the light at (0, 5, 0), within its range.
+        assert!(in_clip_volume(&map.view_projection, Vector3::new(0.0, 0.0, 0.0)));
+        assert!(in_clip_volume(&map.view_projection, Vector3::new(0.0, -10.0, 0.0)));
+        // Past the range and outside the cone.
+        assert!(!in_clip_volume(&map.view_projection, Vector3::new(0.0, -20.0, 0.0)));
+        assert!(!in_clip_volume(&map.view_projection, Vector3::new(10.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn degenerate_lights_cast_no_shadows() {
+        assert!(spot_map(&spot_light(30.0, 0.0), 30.0).is_none());
+        assert!(spot_map(&spot_light(30.0, -5.0), 30.0).is_none());
+        assert!(spot_map(&spot_light(0.0, 20.0), 0.0).is_none());
+        assert!(spot_map(&spot_light(-10.0, 20.0), -10.0).is_none());
+
+        let lights = [
+            spot_light(30.0, 0.0),
+            light(LightKind::Directional, Vector3::new(0.0, 0.0, 0.0)),
+            spot_light(30.0, 20.0),
+        ];
+        let shadows = FrameShadows::new(&lights, Some(&camera()), 1.0);
+        assert_eq!(shadows.lights, vec![(0, 0), (0, 0), (0, 1)]);
+        assert_eq!(shadows.maps.len(), 1);
+    }
+}
@@ -0,0 +1,596 @@
+use std::mem;
+use std::error::Error;
+
+/// What a vertex attribute holds. Shaders read each semantic at a fixed input
+/// location, see `location`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Semantic {
+    Position,
+    Color,
+    Normal,
+    Tangent,
+    Uv0,
+    Uv1,
+    /// Indices of the joints a skinned vertex follows.
+    Joints,
+    /// How much each of the `Joints` moves the vertex.
+    Weights,
+}
+
+impl Semantic {
+    /// Input location of the attribute in vertex shaders.
+    pub fn location(self) -> u32 {
+        match self {
+            Semantic::Position => 0,
+            Semantic::Color => 1,
+            Semantic::Normal => 2,
+            Semantic::Tangent => 3,
+            Semantic::Uv0 => 4,
+            Semantic::Uv1 => 5,
+            Semantic::Joints => 6,
+            Semantic::Weights => 7,
+        }
+    }
+}
+
+/// How an attribute's components are stored. Shaders read the normalized
+/// formats as floats, in [0, 1] for `Unorm` and [-1, 1] for `Snorm`, and
+/// the `Uint` formats as unsigned integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AttributeFormat {
+    Float,
+    Float2,
+    Float3,
+    Float4,
+    Half2,
+    Half4,
+    Unorm8x4,
+    Snorm8x4,
+    Unorm16x2,
+    Unorm16x4,
+    Snorm16x2,
+    Snorm16x4,
+    /// Three 10 bit components and a 2 bit one packed into 32 bits, for
+    /// normals and tangents with the handedness in the last component.
+    Snorm10x3,
+    Uint8x4,
+    Uint16x4,
+}
+
+impl AttributeFormat {
+    /// Size in bytes. Always a multiple of 4, so attributes stay aligned
+    /// when packed back to back.
+    pub fn size(self) -> u32 {
+        match self {
+            AttributeFormat::Float
+            | AttributeFormat::Half2
+            | AttributeFormat::Unorm8x4
+            | AttributeFormat::Snorm8x4
+            | AttributeFormat::Unorm16x2
+            | AttributeFormat::Snorm16x2
+            | AttributeFormat::Snorm10x3
+            | AttributeFormat::Uint8x4 => 4,
+            AttributeFormat::Float2
+            | AttributeFormat::Half4
+            | AttributeFormat::Unorm16x4
+            | AttributeFormat::Snorm16x4
+            | AttributeFormat::Uint16x4 => 8,
+            AttributeFormat::Float3 => 12,
+            AttributeFormat::Float4 => 16,
+        }
+    }
+
+    pub fn components(self) -> usize {
+        match self {
+            AttributeFormat::Float => 1,
+            AttributeFormat::Float2
+            | AttributeFormat::Half2
+            | AttributeFormat::Unorm16x2
+            | AttributeFormat::Snorm16x2 => 2,
+            AttributeFormat::Float3 => 3,
+            _ => 4,
+        }
+    }
+
+    /// Stores the first `components` of `value`, rounded and clamped to
+    /// what the format can hold.
+    fn encode(self, value: [f32; 4], out: &mut [u8]) {
+        let components = self.components();
+        match self {
+            AttributeFormat::Float
+            | AttributeFormat::Float2
+            | AttributeFormat::Float3
+            | AttributeFormat::Float4 => for i in 0..components {
+                write_u32(&mut out[i * 4..], value[i].to_bits());
+            },
+            AttributeFormat::Half2 | AttributeFormat::Half4 => for i in 0..components {
+                write_u16(&mut out[i * 2..], f32_to_f16(value[i]));
+            },
+            AttributeFormat::Unorm8x4 => for i in 0..4 {
+                out[i] = unorm(value[i], 255) as u8;
+            },
+            AttributeFormat::Snorm8x4 => for i in 0..4 {
+                out[i] = snorm(value[i], 127) as u8;
+            },
+            AttributeFormat::Unorm16x2 | AttributeFormat::Unorm16x4 => for i in 0..components {
+                write_u16(&mut out[i * 2..], unorm(value[i], 65535) as u16);
+            },
+            AttributeFormat::Snorm16x2 | AttributeFormat::Snorm16x4 => for i in 0..components {
+                write_u16(&mut out[i * 2..], snorm(value[i], 32767) as u16);
+            },
+            AttributeFormat::Snorm10x3 => {
+                let packed = (snorm(value[0], 511) as u32 & 0x3ff)
+                    | (snorm(value[1], 511) as u32 & 0x3ff) << 10
+                    | (snorm(value[2], 511) as u32 & 0x3ff) << 20
+                    | (snorm(value[3], 1) as u32 & 0x3) << 30;
+                write_u32(out, packed);
+            }
+            AttributeFormat::Uint8x4 => for i in 0..4 {
+                out[i] = value[i].clamp(0.0, 255.0).round() as u8;
+            },
+            AttributeFormat::Uint16x4 => for i in 0..4 {
+                write_u16(&mut out[i * 2..], value[i].clamp(0.0, 65535.0).round() as u16);
+            },
+        }
+    }
+
+    /// Reads the attribute the way shaders see it, missing components are
+    /// filled in with 0 and a last component of 1.
+    fn decode(self, bytes: &[u8]) -> [f32; 4] {
+        let mut value = [0.0, 0.0, 0.0, 1.0];
+        let components = self.components();
+        match self {
+            AttributeFormat::Float
+            | AttributeFormat::Float2
+            | AttributeFormat::Float3
+            | AttributeFormat::Float4 => for i in 0..components {
+                value[i] = f32::from_bits(read_u32(&bytes[i * 4..]));
+            },
+            AttributeFormat::Half2 | AttributeFormat::Half4 => for i in 0..components {
+                value[i] = f16_to_f32(read_u16(&bytes[i * 2..]));
+            },
+            AttributeFormat::Unorm8x4 => for i in 0..4 {
+                value[i] = bytes[i] as f32 / 255.0;
+            },
+            AttributeFormat::Snorm8x4 => for i in 0..4 {
+                value[i] = (bytes[i] as i8 as f32 / 127.0).max(-1.0);
+            },
+            AttributeFormat::Unorm16x2 | AttributeFormat::Unorm16x4 => for i in 0..components {
+                value[i] = read_u16(&bytes[i * 2..]) as f32 / 65535.0;
+            },
+            AttributeFormat::Snorm16x2 | AttributeFormat::Snorm16x4 => for i in 0..components {
+                value[i] = (read_u16(&bytes[i * 2..]) as i16 as f32 / 32767.0).max(-1.0);
+            },
+            AttributeFormat::Snorm10x3 => {
+                let packed = read_u32(bytes);
+                // Shifted up to the top of an i32 and back to sign extend.
+                for (i, channel) in value[..3].iter_mut().enumerate() {
+                    let component = ((packed << (22 - i * 10)) as i32) >> 22;
+                    *channel = (component as f32 / 511.0).max(-1.0);
+                }
+                value[3] = (((packed as i32) >> 30) as f32).max(-1.0);
+            }
+            AttributeFormat::Uint8x4 => for i in 0..4 {
+                value[i] = bytes[i] as f32;
+            },
+            AttributeFormat::Uint16x4 => for i in 0..4 {
+                value[i] = read_u16(&bytes[i * 2..]) as f32;
+            },
+        }
+        value
+    }
+}
+
+/// An attribute's place within a vertex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Attribute {
+    pub semantic: Semantic,
+    pub format: AttributeFormat,
+    /// Byte offset from the start of the vertex.
+    pub offset: u32,
+}
+
+/// The attributes a mesh's vertices have and how they're stored. Attributes
+/// are packed back to back in the order they're declared.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+    attributes: Vec<Attribute>,
+    stride: u32,
+}
+
+impl VertexLayout {
+    /// Fails if a semantic is declared twice.
+    pub fn new(attributes: &[(Semantic, AttributeFormat)]) -> Result<VertexLayout, Box<Error>> {
+        let mut layout = VertexLayout {
+            attributes: vec![],
+            stride: 0,
+        };
+        for &(semantic, format) in attributes {
+            if layout.attribute(semantic).is_some() {
+                return Err(format!("Vertex layout declares {:?} twice", semantic).into());
+            }
+            layout.attributes.push(Attribute {
+                semantic,
+                format,
+                offset: layout.stride,
+            });
+            layout.stride += format.size();
+        }
+        Ok(layout)
+    }
+
+    /// Layout of `Vertex`.
+    pub fn position_color() -> VertexLayout {
+        VertexLayout::new(&[
+            (Semantic::Position, AttributeFormat::Float4),
+            (Semantic::Color, AttributeFormat::Float4),
+        ]).unwrap()
+    }
+
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    pub fn attribute(&self, semantic: Semantic) -> Option<&Attribute> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.semantic == semantic)
+    }
+
+    /// Size of a vertex in bytes.
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+
+    /// Names and offsets of the fields, for the ABI fingerprint.
+    pub fn field_offsets() -> [(&'static str, usize); 2] {
+        [
+            ("attributes", mem::offset_of!(VertexLayout, attributes)),
+            ("stride", mem::offset_of!(VertexLayout, stride)),
+        ]
+    }
+}
+
+/// Vertices stored in a `VertexLayout`, ready to be uploaded as they are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexData {
+    layout: VertexLayout,
+    bytes: Vec<u8>,
+}
+
+impl Default for VertexData {
+    fn default() -> VertexData {
+        VertexData::new(VertexLayout::position_color(), 0)
+    }
+}
+
+impl VertexData {
+    /// `len` vertices with every attribute zeroed.
+    pub fn new(layout: VertexLayout, len: usize) -> VertexData {
+        let bytes = vec![0; len * layout.stride() as usize];
+        VertexData { layout, bytes }
+    }
+
+    pub fn from_vertices(vertices: &[Vertex]) -> VertexData {
+        let mut data = VertexData::new(VertexLayout::position_color(), vertices.len());
+        for (i, vertex) in vertices.iter().enumerate() {
+            data.set(i, Semantic::Position, vertex.pos);
+            data.set(i, Semantic::Color, vertex.color);
+        }
+        data
+    }
+
+    pub fn layout(&self) -> &VertexLayout {
+        &self.layout
+    }
+
+    pub fn len(&self) -> usize {
+        match self.layout.stride() {
+            0 => 0,
+            stride => self.bytes.len() / stride as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The vertices as laid out in memory.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Converts `value` to the attribute's format, dropping the components
+    /// it doesn't have. Semantics the layout doesn't have are ignored, so
+    /// the same code can fill different layouts.
+    pub fn set(&mut self, index: usize, semantic: Semantic, value: [f32; 4]) {
+        if let Some(&attribute) = self.layout.attribute(semantic) {
+            let start = index * self.layout.stride() as usize + attribute.offset as usize;
+            let end = start + attribute.format.size() as usize;
+            attribute.format.encode(value, &mut self.bytes[start..end]);
+        }
+    }
+
+    /// The attribute as shaders read it, `None` if the layout doesn't have
+    /// it.
+    pub fn get(&self, index: usize, semantic: Semantic) -> Option<[f32; 4]> {
+        let attribute = self.layout.attribute(semantic)?;
+        let start = index * self.layout.stride() as usize + attribute.offset as usize;
+        let end = start + attribute.format.size() as usize;
+        Some(attribute.format.decode(&self.bytes[start..end]))
+    }
+
+    /// Names and offsets of the fields, for the ABI fingerprint.
+    pub fn field_offsets() -> [(&'static str, usize); 2] {
+        [
+            ("layout", mem::offset_of!(VertexData, layout)),
+            ("bytes", mem::offset_of!(VertexData, bytes)),
+        ]
+    }
+}
+
+#[derive(Clone, Debug, Copy)]
+pub struct Vertex {
+    pub pos: [f32; 4],
+    pub color: [f32; 4],
+}
+
+impl Vertex {
+    pub fn new(pos: [f32; 4], color: [f32; 4]) -> Vertex {
+        Vertex { pos, color }
+    }
+}
+
+fn unorm(value: f32, max: u32) -> u32 {
+    (value.clamp(0.0, 1.0) * max as f32).round() as u32
+}
+
+fn snorm(value: f32, max: i32) -> i32 {
+    (value.clamp(-1.0, 1.0) * max as f32).round() as i32
+}
+
+// Vertex data is little endian, like every platform Vulkan runs on.
+
+fn write_u16(out: &mut [u8], value: u16) {
+    out[..2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(out: &mut [u8], value: u32) {
+    out[..4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Rounds to the nearest half float, too large values become infinity.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = (bits >> 16) & 0x8000;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return (sign | 0x7c00 | nan) as u16;
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return (sign | 0x7c00) as u16;
+    }
+    if half_exponent <= 0 {
+        // Subnormal, or zero if it's too small even for that.
+        if half_exponent < -10 {
+            return sign as u16;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let mut half = mantissa >> shift;
+        if (mantissa >> (shift - 1)) & 1 != 0 {
+            half += 1;
+        }
+        return (sign | half) as u16;
+    }
+
+    // A carry out of the mantissa correctly bumps the exponent.
+    let mut half = sign | (half_exponent as u32) << 10 | mantissa >> 13;
+    if mantissa & 0x1000 != 0 {
+        half += 1;
+    }
+    half as u16
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = (half as u32 & 0x8000) << 16;
+    let exponent = (half as u32 >> 10) & 0x1f;
+    let mantissa = half as u32 & 0x3ff;
+
+    match exponent {
+        0 => {
+            let value = mantissa as f32 / (1 << 24) as f32;
+            if sign != 0 {
+                -value
+            } else {
+                value
+            }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | mantissa << 13),
+        _ => f32::from_bits(sign | (exponent + 112) << 23 | mantissa << 13),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(format: AttributeFormat, value: [f32; 4]) -> [f32; 4] {
+        let mut bytes = [0; 16];
+        format.encode(value, &mut bytes);
+        format.decode(&bytes)
+    }
+
+    #[test]
+    fn halves_round_trip_through_floats() {
+        for half in 0..=0xffff_u16 {
+            let value = f16_to_f32(half);
+            if value.is_nan() {
+                assert!(f16_to_f32(f32_to_f16(value)).is_nan());
+            } else {
+                assert_eq!(f32_to_f16(value), half, "{:#06x} read as {}", half, value);
+            }
+        }
+    }
+
+    #[test]
+    fn floats_round_to_the_nearest_half() {
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        // Halfway to the next exponent rounds up into it.
+        assert_eq!(f32_to_f16(1.0 + 2.0_f32.powi(-11)), 0x3c01);
+        assert_eq!(f32_to_f16(2.0 - 2.0_f32.powi(-12)), 0x4000);
+
+        // Subnormals, down to the smallest one and then zero.
+        assert_eq!(f32_to_f16(2.0_f32.powi(-14)), 0x0400);
+        assert_eq!(f32_to_f16(2.0_f32.powi(-15)), 0x0200);
+        assert_eq!(f32_to_f16(2.0_f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(2.0_f32.powi(-26)), 0x0000);
+        assert_eq!(f32_to_f16(-2.0_f32.powi(-26)), 0x8000);
+        assert_eq!(f16_to_f32(0x0001), 2.0_f32.powi(-24));
+        assert_eq!(f16_to_f32(0x83ff), -1023.0 * 2.0_f32.powi(-24));
+    }
+
+    #[test]
+    fn out_of_range_floats_become_infinity() {
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(1e10), 0x7c00);
+        assert_eq!(f32_to_f16(-1e10), 0xfc00);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
+
+        // NaN stays NaN instead of turning into infinity.
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+    }
+
+    #[test]
+    fn snorm_reads_its_most_negative_value_as_minus_one() {
+        let mut bytes = [0x80, 0x81, 0x7f, 0x00];
+        assert_eq!(AttributeFormat::Snorm8x4.decode(&bytes), [-1.0, -1.0, 1.0, 0.0]);
+
+        bytes = [0x00, 0x80, 0x01, 0x80];
+        let value = AttributeFormat::Snorm16x2.decode(&bytes);
+        assert_eq!(value, [-1.0, -1.0, 0.0, 1.0]);
+
+        // Encoding never produces it.
+        assert_eq!(
+            round_trip(AttributeFormat::Snorm8x4, [-2.0, -1.0, 2.0, 0.5]),
+            [-1.0, -1.0, 1.0, 64.0 / 127.0]
+        );
+    }
+
+    #[test]
+    fn snorm10x3_packs_three_10_bit_components_and_a_2_bit_one() {
+        let mut bytes = [0; 4];
+        AttributeFormat::Snorm10x3.encode([1.0, -1.0, 0.0, -1.0], &mut bytes);
+        assert_eq!(read_u32(&bytes), 0x1ff | 0x201 << 10 | 0x3 << 30);
+
+        assert_eq!(
+            round_trip(AttributeFormat::Snorm10x3, [0.5, -0.25, 2.0, 1.0]),
+            [256.0 / 511.0, -128.0 / 511.0, 1.0, 1.0]
+        );
+
+        // The most negative 10 and 2 bit values clamp to -1.
+        let packed: u32 = 0x200 | 0x200 << 10 | 0x200 << 20 | 0x2 << 30;
+        assert_eq!(
+            AttributeFormat::Snorm10x3.decode(&packed.to_le_bytes()),
+            [-1.0, -1.0, -1.0, -1.0]
+        );
+    }
+
+    #[test]
+    fn unorm16_rounds_and_clamps() {
+        let mut bytes = [0; 8];
+        AttributeFormat::Unorm16x4.encode([0.0, 0.5, 1.5, -1.0], &mut bytes);
+        assert_eq!(read_u16(&bytes[0..]), 0);
+        assert_eq!(read_u16(&bytes[2..]), 32768);
+        assert_eq!(read_u16(&bytes[4..]), 65535);
+        assert_eq!(read_u16(&bytes[6..]), 0);
+
+        assert_eq!(
+            AttributeFormat::Unorm16x4.decode(&bytes),
+            [0.0, 32768.0 / 65535.0, 1.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn missing_components_read_as_zero_and_one() {
+        assert_eq!(
+            round_trip(AttributeFormat::Float2, [2.0, 3.0, 4.0, 5.0]),
+            [2.0, 3.0, 0.0, 1.0]
+        );
+        assert_eq!(
+            round_trip(AttributeFormat::Half2, [0.5, -0.5, 4.0, 5.0]),
+            [0.5, -0.5, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn layouts_pack_attributes_back_to_back() {
+        let layout = VertexLayout::new(&[
+            (Semantic::Position, AttributeFormat::Float3),
+            (Semantic::Normal, AttributeFormat::Snorm10x3),
+            (Semantic::Uv0, AttributeFormat::Half2),
+            (Semantic::Color, AttributeFormat::Unorm16x4),
+        ]).unwrap();
+
+        let offsets: Vec<_> = layout
+            .attributes()
+            .iter()
+            .map(|attribute| (attribute.semantic, attribute.offset))
+            .collect();
+        assert_eq!(
+            offsets,
+            [
+                (Semantic::Position, 0),
+                (Semantic::Normal, 12),
+                (Semantic::Uv0, 16),
+                (Semantic::Color, 20),
+            ]
+        );
+        assert_eq!(layout.stride(), 28);
+        assert!(layout.attribute(Semantic::Tangent).is_none());
+
+        let duplicate = VertexLayout::new(&[
+            (Semantic::Uv0, AttributeFormat::Float2),
+            (Semantic::Uv0, AttributeFormat::Half2),
+        ]);
+        assert!(duplicate.is_err());
+    }
+
+    #[test]
+    fn vertex_data_reads_back_what_was_set() {
+        let layout = VertexLayout::new(&[
+            (Semantic::Position, AttributeFormat::Float3),
+            (Semantic::Uv0, AttributeFormat::Unorm8x4),
+        ]).unwrap();
+        let mut data = VertexData::new(layout, 2);
+        data.set(1, Semantic::Position, [1.0, 2.0, 3.0, 4.0]);
+        data.set(1, Semantic::Uv0, [0.0, 1.0, 0.2, 2.0]);
+        // Not in the layout, so ignored.
+        data.set(1, Semantic::Normal, [1.0, 0.0, 0.0, 0.0]);
+
+        assert_eq!(data.len(), 2);
+        assert_eq!(data.bytes().len(), 32);
+        assert_eq!(data.get(0, Semantic::Position), Some([0.0, 0.0, 0.0, 1.0]));
+        assert_eq!(data.get(1, Semantic::Position), Some([1.0, 2.0, 3.0, 1.0]));
+        assert_eq!(data.get(1, Semantic::Uv0), Some([0.0, 1.0, 51.0 / 255.0, 1.0]));
+        assert_eq!(data.get(1, Semantic::Normal), None);
+    }
+}
@@ -0,0 +1,497 @@
+//! Renders scripted scenes through the game's `render` entry point with the
+//! software renderer and compares the frames against the reference images in
+//! `tests/golden`. Run with `XTREME_UPDATE_GOLDEN=1` to (re)write the
+//! references after an intended change.
+//!
+//! Frames and, on failure, diff images are written to `target/golden`.
+
+extern crate cgmath;
+extern crate image;
+extern crate xtreme_game;
+
+use cgmath::Vector3;
+use image::{ImageBuffer, Rgba, RgbaImage};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use xtreme_game::game;
+use xtreme_game::game::asset;
+use xtreme_game::game::component;
+use xtreme_game::game::entity::Entity;
+use xtreme_game::game::state::State;
+use xtreme_game::renderer::Renderer;
+use xtreme_game::render_backends::software::SoftwareRenderer;
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+const UPDATE_ENV: &str = "XTREME_UPDATE_GOLDEN";
+
+/// Largest per-pixel difference that still counts as equal, as a fraction of
+/// the biggest possible YIQ distance. Absorbs rounding differences between
+/// platforms without letting visible changes through.
+const PIXEL_THRESHOLD: f32 = 0.1;
+/// Fraction of pixels allowed to exceed `PIXEL_THRESHOLD`, for edge pixels
+/// whose coverage flips on tiny float differences.
+const MAX_DIFFERENT_PIXELS: f32 = 0.001;
+
+/// A scene is a state to start from and the frames to compare; every frame
+/// up to the last one is rendered so assets load the way they do in game.
+struct Scene {
+    name: &'static str,
+    setup: fn() -> State,
+    frames: &'static [u32],
+}
+
+fn single_entity() -> State {
+    game::init().0
+}
+
+/// Three entities stepping along x and y and away from the viewer, with no
+/// camera, each partly hidden behind the one before it.
+fn overlapping_entities() -> State {
+    let mut state = State::default();
+    for i in 0..3 {
+        let step = i as f32;
+        let mut physics = component::Physics::new();
+        physics.pos = Vector3::new(-0.6 + step * 0.25, -0.4 + step * 0.2, step * 0.2);
+        Entity::new(&mut state)
+            .with_physics(physics)
+            .with_graphics(component::Graphics::new())
+            .build();
+    }
+    state
+}
+
+/// Two entities placed by their `Physics` position, seen from an angle.
+fn perspective_camera() -> State {
+    let mut state = State::default();
+
+    let mut camera_physics = component::Physics::new();
+    camera_physics.pos = Vector3::new(0.6, 0.9, -1.5);
+    let camera =
+        component::Camera::perspective(60.0, 0.1, 10.0).looking_at(Vector3::new(0.25, 0.25, 0.25));
+    Entity::new(&mut state)
+        .with_physics(camera_physics)
+        .with_camera(camera)
+        .build();
+
+    for &x in &[-0.3, 0.4] {
+        let mut physics = component::Physics::new();
+        physics.pos = Vector3::new(x, 0.0, 0.0);
+        Entity::new(&mut state)
+            .with_physics(physics)
+            .with_graphics(component::Graphics::new())
+            .build();
+    }
+
+    state
+}
+
+/// Entities drawn opaque, as a wireframe and alpha blended, the blended one
+/// first so it has to be moved behind the others.
+fn render_states() -> State {
+    let mut state = State::default();
+
+    let mut camera_physics = component::Physics::new();
+    camera_physics.pos = Vector3::new(0.6, 0.9, -1.5);
+    let camera =
+        component::Camera::perspective(60.0, 0.1, 10.0).looking_at(Vector3::new(0.25, 0.25, 0.25));
+    Entity::new(&mut state)
+        .with_physics(camera_physics)
+        .with_camera(camera)
+        .build();
+
+    let materials = [
+        (-0.3, "data/materials/transparent.ron"),
+        (0.05, "data/materials/default.ron"),
+        (0.4, "data/materials/wireframe.ron"),
+    ];
+    for &(x, material) in &materials {
+        let mut physics = component::Physics::new();
+        physics.pos = Vector3::new(x, 0.0, 0.0);
+        let mut graphics = component::Graphics::new();
+        graphics.material = asset::Material::new(Path::new(material));
+        Entity::new(&mut state)
+            .with_physics(physics)
+            .with_graphics(graphics)
+            .build();
+    }
+
+    state
+}
+
+/// A grid of entities sharing one mesh, drawn as instances, every other one
+/// with a tinted material.
+fn instanced_entities() -> State {
+    let mut state = State::default();
+
+    let mut camera_physics = component::Physics::new();
+    camera_physics.pos = Vector3::new(0.0, 2.5, -2.5);
+    let camera =
+        component::Camera::perspective(60.0, 0.1, 10.0).looking_at(Vector3::new(0.0, 0.0, 0.0));
+    Entity::new(&mut state)
+        .with_physics(camera_physics)
+        .with_camera(camera)
+        .build();
+
+    for i in 0..16 {
+        let mut physics = component::Physics::new();
+        physics.pos = Vector3::new((i % 4) as f32 * 0.7 - 1.3, 0.0, (i / 4) as f32 * 0.7 - 1.3);
+        let mut graphics = component::Graphics::new();
+        if i % 2 == 1 {
+            graphics.material = asset::Material::new(Path::new("data/materials/tinted.ron"));
+        }
+        Entity::new(&mut state)
+            .with_physics(physics)
+            .with_graphics(graphics)
+            .build();
+    }
+
+    state
+}
+
+/// Entities lit by an ambient, a directional, a point and a spot light.
+fn lit_entities() -> State {
+    let mut state = State::default();
+
+    let mut camera_physics = component::Physics::new();
+    camera_physics.pos = Vector3::new(0.35, 1.0, -1.6);
+    let camera =
+        component::Camera::perspective(60.0, 0.1, 10.0).looking_at(Vector3::new(0.35, 0.25, 0.25));
+    Entity::new(&mut state)
+        .with_physics(camera_physics)
+        .with_camera(camera)
+        .build();
+
+    for &x in &[-0.5, 0.1, 0.7] {
+        let mut physics = component::Physics::new();
+        physics.pos = Vector3::new(x, 0.0, 0.0);
+        Entity::new(&mut state)
+            .with_physics(physics)
+            .with_graphics(component::Graphics::new())
+            .build();
+    }
+
+    let lights = [
+        (Vector3::new(0.0, 0.0, 0.0), component::Light::ambient(0.15)),
+        (
+            Vector3::new(0.0, 0.0, 0.0),
+            component::Light::directional(Vector3::new(-0.3, -1.0, 0.5))
+                .with_color([1.0, 0.95, 0.8], 0.8),
+        ),
+        (
+            Vector3::new(-0.3, 0.3, -0.4),
+            component::Light::point(1.2).with_color([1.0, 0.3, 0.2], 2.0),
+        ),
+        (
+            Vector3::new(0.95, 1.0, -0.3),
+            component::Light::spot(Vector3::new(0.0, -0.75, 0.55), 3.0, 15.0, 30.0)
+                .with_color([0.3, 0.5, 1.0], 2.0),
+        ),
+    ];
+    for &(pos, ref light) in &lights {
+        let mut physics = component::Physics::new();
+        physics.pos = pos;
+        Entity::new(&mut state)
+            .with_physics(physics)
+            .with_light(light.clone())
+            .build();
+    }
+
+    state
+}
+
+/// Cubes on a floor of cubes, shadowed by a directional light through its
+/// cascades and by a spot light. The floating cube casts no shadow.
+fn shadowed_entities() -> State {
+    let mut state = State::default();
+
+    let mut camera_physics = component::Physics::new();
+    camera_physics.pos = Vector3::new(0.25, 2.5, -2.0);
+    let camera =
+        component::Camera::perspective(60.0, 0.1, 10.0).looking_at(Vector3::new(0.25, 0.0, 0.5));
+    Entity::new(&mut state)
+        .with_physics(camera_physics)
+        .with_camera(camera)
+        .build();
+
+    let mut entity = |pos: Vector3<f32>, cast_shadows: bool| {
+        let mut physics = component::Physics::new();
+        physics.pos = pos;
+        let mut graphics = component::Graphics::new();
+        graphics.cast_shadows = cast_shadows;
+        Entity::new(&mut state)
+            .with_physics(physics)
+            .with_graphics(graphics)
+            .build();
+    };
+    for i in 0..36 {
+        let (x, z) = ((i % 6) as f32 * 0.5 - 1.25, (i / 6) as f32 * 0.5 - 1.0);
+        entity(Vector3::new(x, -0.5, z), true);
+    }
+    entity(Vector3::new(-0.5, 0.0, 0.0), true);
+    entity(Vector3::new(0.5, 0.0, 0.5), true);
+    entity(Vector3::new(-0.9, 0.6, -0.6), false);
+
+    let lights = [
+        (Vector3::new(0.0, 0.0, 0.0), component::Light::ambient(0.2)),
+        (
+            Vector3::new(0.0, 0.0, 0.0),
+            component::Light::directional(Vector3::new(0.6, -1.0, 0.4))
+                .with_color([1.0, 0.95, 0.8], 0.7)
+                .with_shadows(),
+        ),
+        (
+            Vector3::new(1.2, 1.2, -0.6),
+            component::Light::spot(Vector3::new(-0.6, -1.0, 0.7), 4.0, 20.0, 35.0)
+                .with_color([0.3, 0.5, 1.0], 1.5)
+                .with_shadows(),
+        ),
+    ];
+    for &(pos, ref light) in &lights {
+        let mut physics = component::Physics::new();
+        physics.pos = pos;
+        Entity::new(&mut state)
+            .with_physics(physics)
+            .with_light(light.clone())
+            .build();
+    }
+
+    state
+}
+
+#[test]
+fn single_entity_matches_reference() {
+    check_scene(&Scene {
+        name: "single_entity",
+        setup: single_entity,
+        frames: &[0, 2],
+    });
+}
+
+#[test]
+fn overlapping_entities_match_reference() {
+    check_scene(&Scene {
+        name: "overlapping_entities",
+        setup: overlapping_entities,
+        frames: &[0],
+    });
+}
+
+#[test]
+fn perspective_camera_matches_reference() {
+    check_scene(&Scene {
+        name: "perspective_camera",
+        setup: perspective_camera,
+        frames: &[0],
+    });
+}
+
+#[test]
+fn render_states_match_reference() {
+    check_scene(&Scene {
+        name: "render_states",
+        setup: render_states,
+        frames: &[0],
+    });
+}
+
+#[test]
+fn instanced_entities_match_reference() {
+    check_scene(&Scene {
+        name: "instanced_entities",
+        setup: instanced_entities,
+        frames: &[0],
+    });
+}
+
+#[test]
+fn lit_entities_match_reference() {
+    check_scene(&Scene {
+        name: "lit_entities",
+        setup: lit_entities,
+        frames: &[0],
+    });
+}
+
+#[test]
+fn shadowed_entities_match_reference() {
+    check_scene(&Scene {
+        name: "shadowed_entities",
+        setup: shadowed_entities,
+        frames: &[0],
+    });
+}
+
+/// A minimized window's frames draw nothing, and drawing picks up again
+/// once it's restored.
+#[test]
+fn zero_sized_frames_are_skipped() {
+    let mut state = shadowed_entities();
+    let mut renderer = SoftwareRenderer::new(WIDTH, HEIGHT);
+    renderer.update_resolution(0, 0).unwrap();
+    xtreme_game::render(&mut state, &mut renderer).unwrap();
+    assert!(renderer.pixels().is_empty());
+
+    renderer.update_resolution(WIDTH, HEIGHT).unwrap();
+    xtreme_game::render(&mut state, &mut renderer).unwrap();
+
+    let mut expected_state = shadowed_entities();
+    let mut expected = SoftwareRenderer::new(WIDTH, HEIGHT);
+    xtreme_game::render(&mut expected_state, &mut expected).unwrap();
+    assert!(renderer.pixels() == expected.pixels());
+}
+
+fn check_scene(scene: &Scene) {
+    let mut state = (scene.setup)();
+    let mut renderer = SoftwareRenderer::new(WIDTH, HEIGHT);
+    let last_frame = *scene.frames.iter().max().unwrap();
+
+    let mut failures = vec![];
+    for frame in 0..last_frame + 1 {
+        xtreme_game::render(&mut state, &mut renderer).unwrap();
+
+        if scene.frames.contains(&frame) {
+            let name = format!("{}_{}", scene.name, frame);
+            if let Err(err) = check_frame(&name, &renderer) {
+                failures.push(err);
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("{}", failures.join("\n"));
+    }
+}
+
+fn check_frame(name: &str, renderer: &SoftwareRenderer) -> Result<(), String> {
+    let output_path = output_dir().join(format!("{}.png", name));
+    renderer
+        .save_png(&output_path)
+        .map_err(|err| format!("Couldn't save {}: {}", output_path.display(), err))?;
+
+    let reference_path = reference_dir().join(format!("{}.png", name));
+    if env::var_os(UPDATE_ENV).is_some() {
+        renderer
+            .save_png(&reference_path)
+            .map_err(|err| format!("Couldn't save {}: {}", reference_path.display(), err))?;
+        return Ok(());
+    }
+
+    let reference = image::open(&reference_path)
+        .map_err(|err| {
+            format!(
+                "{}: couldn't open reference {} ({}), run with {}=1 to create it",
+                name,
+                reference_path.display(),
+                err,
+                UPDATE_ENV
+            )
+        })?
+        .to_rgba();
+
+    if reference.dimensions() != (renderer.width(), renderer.height()) {
+        return Err(format!(
+            "{}: frame is {}x{} but the reference is {}x{}",
+            name,
+            renderer.width(),
+            renderer.height(),
+            reference.width(),
+            reference.height()
+        ));
+    }
+
+    let frame: RgbaImage =
+        ImageBuffer::from_raw(renderer.width(), renderer.height(), renderer.pixels().to_vec())
+            .unwrap();
+    let (diff, different_pixels) = compare(&frame, &reference);
+
+    let allowed = (MAX_DIFFERENT_PIXELS * (frame.width() * frame.height()) as f32) as u32;
+    if different_pixels > allowed {
+        let diff_path = output_dir().join(format!("{}.diff.png", name));
+        diff.save(&diff_path)
+            .map_err(|err| format!("Couldn't save {}: {}", diff_path.display(), err))?;
+        return Err(format!(
+            "{}: {} pixels differ from {} (at most {} allowed), see {}",
+            name,
+            different_pixels,
+            reference_path.display(),
+            allowed,
+            diff_path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns a diff image, with differing pixels in red over a faded copy of
+/// the reference, and the number of differing pixels.
+fn compare(frame: &RgbaImage, reference: &RgbaImage) -> (RgbaImage, u32) {
+    let mut different_pixels = 0;
+    let diff = ImageBuffer::from_fn(frame.width(), frame.height(), |x, y| {
+        let actual = frame.get_pixel(x, y);
+        let expected = reference.get_pixel(x, y);
+
+        if color_distance(actual, expected) > PIXEL_THRESHOLD {
+            different_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let gray = (luma(expected) * 0.25 + 0.75) * 255.0;
+            Rgba([gray as u8, gray as u8, gray as u8, 255])
+        }
+    });
+
+    (diff, different_pixels)
+}
+
+/// Distance in YIQ space, which follows perceived differences far better
+/// than RGB, normalized to [0, 1]. Colors are blended onto white first so
+/// differences hidden by a zero alpha don't count.
+fn color_distance(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    // Largest possible squared YIQ distance, between black and white.
+    const MAX_DISTANCE: f32 = 35215.0 / (255.0 * 255.0);
+
+    let (ay, ai, aq) = yiq(a);
+    let (by, bi, bq) = yiq(b);
+    let (dy, di, dq) = (ay - by, ai - bi, aq - bq);
+
+    let distance = 0.5053 * dy * dy + 0.299 * di * di + 0.1957 * dq * dq;
+    (distance / MAX_DISTANCE).sqrt()
+}
+
+fn yiq(pixel: &Rgba<u8>) -> (f32, f32, f32) {
+    let [r, g, b] = blend_on_white(pixel);
+    (
+        0.2988953 * r + 0.5866225 * g + 0.1144822 * b,
+        0.595978 * r - 0.2741761 * g - 0.3218019 * b,
+        0.2114702 * r - 0.5226171 * g + 0.3111469 * b,
+    )
+}
+
+fn luma(pixel: &Rgba<u8>) -> f32 {
+    yiq(pixel).0
+}
+
+fn blend_on_white(pixel: &Rgba<u8>) -> [f32; 3] {
+    let alpha = pixel[3] as f32 / 255.0;
+    let blend = |channel: u8| 1.0 + (channel as f32 / 255.0 - 1.0) * alpha;
+    [blend(pixel[0]), blend(pixel[1]), blend(pixel[2])]
+}
+
+fn reference_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+}
+
+fn output_dir() -> PathBuf {
+    let target_dir = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target"));
+    let dir = target_dir.join("golden");
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}